@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use yogi::ir::{CodegenOptions, IRMachine};
+use yogi::parser::YololParser;
+
+// Parsing, compiling and running any source must never panic. Run with
+// `cargo fuzz run parse_compile` from the repository root.
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(program) = YololParser::default().parse(src) {
+        let options = CodegenOptions {
+            check_asserts: true,
+            ..Default::default()
+        };
+        IRMachine::from_ast(options, program).step_repeat(100);
+    }
+});
@@ -0,0 +1,101 @@
+//! Counts the heap allocations made by each test thread, to check code which shouldn't
+//! allocate once warmed up, like [`IRMachine::step`](crate::ir::IRMachine::step).
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+
+struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+thread_local! {
+    static RECORDING: Cell<bool> = const { Cell::new(false) };
+    static SITES: RefCell<Vec<Backtrace>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record() {
+    // off while recording, as capturing a backtrace allocates too
+    if RECORDING.try_with(|r| r.replace(false)).unwrap_or(false) {
+        let site = Backtrace::force_capture();
+        SITES.with(|s| s.borrow_mut().push(site));
+        RECORDING.with(|r| r.set(true));
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Where each allocation `f` made on this thread came from.
+pub fn allocations(f: impl FnOnce()) -> Vec<Backtrace> {
+    RECORDING.with(|r| r.set(true));
+    f();
+    RECORDING.with(|r| r.set(false));
+    SITES.with(|s| std::mem::take(&mut *s.borrow_mut()))
+}
+
+/// Panics, listing where they came from, if `f` allocates.
+#[track_caller]
+pub fn assert_no_allocations(what: &str, f: impl FnOnce()) {
+    let sites = allocations(f);
+    if !sites.is_empty() {
+        let sites: Vec<_> = sites.iter().map(|s| s.to_string()).collect();
+        panic!("{} allocated {} times:\n\n{}", what, sites.len(), sites.join("\n\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{CodegenOptions, IRMachine};
+    use crate::parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn counts_allocations() {
+        assert_eq!(allocations(|| drop(vec![1, 2, 3])).len(), 1);
+        assert!(allocations(|| drop(Vec::<u8>::new())).is_empty());
+    }
+
+    #[test]
+    fn stepping_doesnt_allocate() {
+        // a value register going from a number to a string has to allocate, so these don't
+        // reuse temporaries for both
+        let programs = [
+            ("numbers", ":a=1 b=:a*2+3 c=b/7 d=sqrt c+sin d :out=d%10 goto 1"),
+            ("strings", "s=\"hello\" t=s+\" world\" t-=\"o\" u=t-- :out=t+s+u t+=:n :n++ t-=:n"),
+            ("branches", "if :a>3 then :a=0 else :a++ end if :a==2 then goto 2 end goto 1\n:b=1/0"),
+        ];
+        for (name, src) in programs {
+            let program = YololParser::default().parse(src).unwrap();
+            let options = CodegenOptions { protect_locals: true, ..Default::default() };
+            let mut machine = IRMachine::from_ast(options, program);
+            for _ in 0..20 {
+                machine.step();
+            }
+            assert_no_allocations(name, || {
+                for _ in 0..100 {
+                    machine.step();
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use super::*;
+
+/// Which game patch's rules to follow, for operators whose behaviour changed between them.
+/// Replays should be checked with the rules in force when they were recorded. Only the current
+/// rules are here so far: an older patch gets a profile once its behaviour has been recorded from
+/// the game to test against.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Compat {
+    #[default]
+    Starbase_Latest,
+}
+
+impl Compat {
+    pub fn rem(self, l: Number, r: Number) -> ValueResult<Number> {
+        match self {
+            Compat::Starbase_Latest => l % r,
+        }
+    }
+
+    pub fn abs(self, n: Number) -> Number {
+        match self {
+            Compat::Starbase_Latest => n.abs(),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Float functions for `no_std` builds, from libm. With std the inherent methods of the same
+//! names are used instead, as they take priority over trait methods.
+
+pub(crate) trait Float {
+    fn sqrt(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn round(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn asin(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn log10(self) -> Self;
+}
+
+macro_rules! float {
+    ($ty:ty, $pow:ident, $($name:ident => $libm:ident),*) => {
+        impl Float for $ty {
+            fn powf(self, n: Self) -> Self {
+                libm::$pow(self, n)
+            }
+
+            $(
+                fn $name(self) -> Self {
+                    libm::$libm(self)
+                }
+            )*
+        }
+    };
+}
+
+float!(f64, pow, sqrt => sqrt, round => round, sin => sin, cos => cos, tan => tan, asin => asin,
+    acos => acos, atan => atan, exp => exp, ln => log, log10 => log10);
+float!(f32, powf, sqrt => sqrtf, round => roundf, sin => sinf, cos => cosf, tan => tanf,
+    asin => asinf, acos => acosf, atan => atanf, exp => expf, ln => logf, log10 => log10f);
@@ -1,43 +1,163 @@
-use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
-use std::str::FromStr;
-use std::ops::*;
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::str::FromStr;
+use core::ops::*;
 use thiserror::Error;
+use arrayvec::ArrayVec;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use float::Float;
 pub mod value;
 pub mod ystring;
+pub mod compat;
+pub mod overflow;
+pub mod precision;
+#[cfg(not(feature = "std"))]
+mod float;
+#[cfg(feature = "serde")]
+mod serialize;
 pub use value::*;
 pub use ystring::*;
+pub use compat::*;
+pub use overflow::*;
+pub use precision::*;
+#[cfg(feature = "serde")]
+pub use serialize::decimal;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
 pub struct Number(pub i64);
 
+/// A [`Number`] from a literal, worked out while compiling, so it can go in a `const` or
+/// `static`: `num!(1.5)`, `num!(-0.001)`. More than 3 decimal places is a compile error, rather
+/// than being cut short like when parsing.
+#[macro_export]
+macro_rules! num {
+    (-$n:literal) => {{
+        const N: $crate::arith::Number = $crate::arith::Number::from_literal(stringify!($n), true);
+        N
+    }};
+    ($n:literal) => {{
+        const N: $crate::arith::Number = $crate::arith::Number::from_literal(stringify!($n), false);
+        N
+    }};
+}
+
+/// How [`Number::div_rounded`] rounds a result between two thousandths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+    /// Like Yolol's own division.
+    #[default]
+    TowardZero,
+    Down,
+    Up,
+    /// Halves round away from zero.
+    Nearest,
+}
+
+impl Rounding {
+    fn div(self, n: i128, d: i128) -> i128 {
+        let (q, r) = (n / d, n % d);
+        if r == 0 {
+            return q;
+        }
+        let away = if (n < 0) == (d < 0) { 1 } else { -1 };
+        match self {
+            Rounding::TowardZero => q,
+            Rounding::Down => if away < 0 { q - 1 } else { q },
+            Rounding::Up => if away > 0 { q + 1 } else { q },
+            Rounding::Nearest => if r.abs() * 2 >= d.abs() { q + away } else { q },
+        }
+    }
+}
+
 impl Number {
     const SCALE: i64 = 1000;
     const SCALE_F32: f32 = 1000.0;
     const SCALE_F64: f64 = 1000.0;
     pub const MAX: Number = Number(i64::MAX);
     pub const MIN: Number = Number(i64::MIN);
-    pub const ONE: Number = Number(1 * Self::SCALE);
+    pub const ONE: Number = Number(Self::SCALE);
     pub const ZERO: Number = Number(0);
     const MAX_VAL_F64: f64 = Self::MAX.0 as f64 / Self::SCALE_F64;
     const MIN_VAL_F64: f64 = Self::MIN.0 as f64 / Self::SCALE_F64;
     const MAX_VAL_F32: f32 = Self::MAX_VAL_F64 as f32;
     const MIN_VAL_F32: f32 = Self::MIN_VAL_F64 as f32;
 
-    pub fn new(mut n: f64) -> Self {
+    /// Like `Number::from(n)`, but usable in constants.
+    pub const fn from_int(n: i64) -> Self {
+        Number(n * Self::SCALE)
+    }
+
+    /// `millis` thousandths.
+    pub const fn from_millis(millis: i64) -> Self {
+        Number(millis)
+    }
+
+    /// For [`num!`], from the text of a literal. Panics, so the constant fails to compile, on
+    /// anything but digits with up to 3 decimal places, or a number too big to fit.
+    #[doc(hidden)]
+    pub const fn from_literal(s: &str, negative: bool) -> Self {
+        const fn fits(n: Option<i64>) -> i64 {
+            match n {
+                Some(n) => n,
+                None => panic!("num! literal out of range"),
+            }
+        }
+
+        let bytes = s.as_bytes();
+        let sign = if negative { -1 } else { 1 };
+        let mut millis = 0_i64;
+        // -1 before the decimal point
+        let mut decimals = -1;
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            i += 1;
+            if b == b'_' {
+                continue;
+            } else if b == b'.' && decimals < 0 {
+                decimals = 0;
+                continue;
+            }
+            assert!(b.is_ascii_digit(), "num! takes plain decimal literals");
+            if decimals >= 0 {
+                decimals += 1;
+                assert!(decimals <= 3, "num! literals can't have more than 3 decimal places");
+            }
+            millis = fits(millis.checked_mul(10));
+            millis = fits(millis.checked_add(sign * (b - b'0') as i64));
+        }
+        if decimals < 0 {
+            decimals = 0;
+        }
+        while decimals < 3 {
+            millis = fits(millis.checked_mul(10));
+            decimals += 1;
+        }
+        Number(millis)
+    }
+
+    pub fn new(n: f64) -> Self {
+        Self::checked_new(n).unwrap_or(Number::MIN)
+    }
+
+    /// Like [`Number::new`], but `None` for anything not finite or out of range, where that
+    /// gives [`Number::MIN`].
+    pub fn checked_new(mut n: f64) -> Option<Self> {
         if n.is_finite() && {
             n *= Self::SCALE_F64;
-            n <= Self::MAX_VAL_F64 && n >= Self::MIN_VAL_F64
+            (Self::MIN_VAL_F64..=Self::MAX_VAL_F64).contains(&n)
         } {
-            Number(n as i64)
+            Some(Number(n as i64))
         } else {
-            Number::MIN
+            None
         }
     }
 
     pub fn new_f32(mut n: f32) -> Self {
         if n.is_finite() && {
             n *= Self::SCALE_F32;
-            n <= Self::MAX_VAL_F32 && n >= Self::MIN_VAL_F32
+            (Self::MIN_VAL_F32..=Self::MAX_VAL_F32).contains(&n)
         } {
             Number(n as i64)
         } else {
@@ -57,11 +177,20 @@ impl Number {
         self != Self::ZERO
     }
 
+    /// Appends the number as Yolol prints it, as far as the string has room.
     pub fn stringify_with_buffer(&self, buffer: &mut YString) {
-        let data = buffer.data.as_mut();
+        let digits = self.digits();
+        let room = MAX_STRING_BYTES - buffer.len();
+        buffer.data_mut().extend(digits.into_iter().take(room));
+    }
+
+    /// The number as Yolol prints it, without allocating. The longest is
+    /// `-9223372036854775.808`.
+    pub(crate) fn digits(&self) -> ArrayVec<u8, 21> {
+        let mut data = ArrayVec::new();
         let int = self.0 / Self::SCALE;
         let mut dec = (self.0 % Self::SCALE).unsigned_abs() as u32;
-        let neg = int.is_negative();
+        let neg = self.0.is_negative();
 
         let mut int = int.unsigned_abs();
         let mut rem;
@@ -69,8 +198,8 @@ impl Number {
             rem = (int % 10) as u32;
             int /= 10;
             unsafe {
-                let c = std::char::from_digit(rem, 10)
-                    .unwrap_or_else(|| std::hint::unreachable_unchecked());
+                let c = core::char::from_digit(rem, 10)
+                    .unwrap_or_else(|| core::hint::unreachable_unchecked());
                 data.push_unchecked(c as u8);
             }
             if int == 0 {
@@ -84,27 +213,26 @@ impl Number {
 
         data.reverse();
         if dec == 0 {
-            return;
+            return data;
         }
 
         unsafe { data.push_unchecked(b'.'); }
-        let old_len = data.len();
 
-        for _ in 0..3 {
-            rem = dec % 10;
+        // leading zeros are kept and trailing ones dropped, so 0.05 is "0.05"
+        let mut digits = 3;
+        while dec.is_multiple_of(10) {
             dec /= 10;
+            digits -= 1;
+        }
+        for place in (0..digits).rev() {
+            rem = dec / 10_u32.pow(place) % 10;
             unsafe {
-                let c = std::char::from_digit(rem, 10)
-                    .unwrap_or_else(|| std::hint::unreachable_unchecked());
+                let c = core::char::from_digit(rem, 10)
+                    .unwrap_or_else(|| core::hint::unreachable_unchecked());
                 data.push_unchecked(c as u8);
             }
-            if dec == 0 {
-                break;
-            }
         }
-
-        let len = data.len();
-        data[old_len..len].reverse();
+        data
     }
 
     pub fn stringify(&self) -> YString {
@@ -118,9 +246,13 @@ impl Number {
         Ok(())
     }
 
-    fn round_to_new(mut v: f64) -> Self {
+    fn round_to_new(v: f64) -> Self {
+        Self::checked_round_to_new(v).unwrap_or(Number::MIN)
+    }
+
+    fn checked_round_to_new(mut v: f64) -> Option<Self> {
         v += 5e-5_f64.copysign(v);
-        Self::new(v)
+        Self::checked_new(v)
     }
 
     pub fn pow(self, other: Self) -> Self {
@@ -158,6 +290,31 @@ impl Number {
         }
     }
 
+    /// `e` to the power of `self`, rounded like [`pow`](Self::pow).
+    pub fn exp(self) -> Self {
+        Self::round_to_new(self.as_f64().exp())
+    }
+
+    /// The natural logarithm, rounded like [`pow`](Self::pow). Like [`sqrt`](Self::sqrt), gives
+    /// [`Number::MIN`] where there's no answer, for zero and below.
+    pub fn ln(self) -> Self {
+        Self::round_to_new(self.as_f64().ln())
+    }
+
+    /// The logarithm in base ten, rounded and undefined like [`ln`](Self::ln).
+    pub fn log10(self) -> Self {
+        Self::round_to_new(self.as_f64().log10())
+    }
+
+    /// The logarithm in `base`, giving [`Number::MIN`] for a base of one, or of zero and below.
+    pub fn log(self, base: Self) -> Self {
+        if base.0 <= 0 || base == Number::ONE {
+            Number::MIN
+        } else {
+            Self::round_to_new(self.as_f64().ln() / base.as_f64().ln())
+        }
+    }
+
     pub fn sin(self) -> Self {
         Self::new((self.as_f32().to_radians() as f64).sin())
     }
@@ -201,6 +358,143 @@ impl Number {
             Number(result.wrapping_mul(Number::SCALE))
         }
     }
+
+    /// `self / rhs`, rounded as given. Results outside the range of a number wrap, like Yolol's
+    /// arithmetic does.
+    pub fn div_rounded(self, rhs: Self, rounding: Rounding) -> ValueResult<Self> {
+        if rhs.0 == 0 {
+            return Err(RuntimeErr::DivZero);
+        }
+        Ok(Number(rounding.div(self.0 as i128 * Self::SCALE as i128, rhs.0 as i128) as i64))
+    }
+
+    /// `self * num / den` without overflowing in between, rounded toward zero. Results outside
+    /// the range of a number wrap, like Yolol's arithmetic does.
+    pub fn mul_div(self, num: Self, den: Self) -> ValueResult<Self> {
+        if den.0 == 0 {
+            return Err(RuntimeErr::DivZero);
+        }
+        Ok(Number((self.0 as i128 * num.0 as i128 / den.0 as i128) as i64))
+    }
+
+    /// `percent`% of `self`.
+    pub fn percent(self, percent: Self) -> Self {
+        self.mul_div(percent, 100.into()).unwrap()
+    }
+
+    /// `self + rhs`, or `None` where `+` would wrap.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Number)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Number)
+    }
+
+    /// Computed exactly, so only `None` if the product doesn't fit, where `*` can also wrap
+    /// partway through.
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let exact = self.0 as i128 * rhs.0 as i128 / Self::SCALE as i128;
+        i64::try_from(exact).ok().map(Number)
+    }
+
+    /// `None` when dividing by zero, as well as on overflow.
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        if rhs.0 == 0 {
+            return None;
+        }
+        i64::try_from(self.0 as i128 * Self::SCALE as i128 / rhs.0 as i128).ok().map(Number)
+    }
+
+    /// `None` for a remainder by zero. Nothing else fails.
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        (rhs.0 != 0).then(|| Number(self.0.wrapping_rem(rhs.0)))
+    }
+
+    /// `None` where [`Number::pow`] gives [`Number::MIN`] for a result which doesn't fit or
+    /// doesn't exist, like a fractional power of a negative number.
+    pub fn checked_pow(self, rhs: Self) -> Option<Self> {
+        Self::checked_round_to_new(self.as_f64().powf(rhs.as_f64()))
+    }
+
+    /// Like [`str::parse`], but forgiving the way the game is with text typed into fields:
+    /// whitespace around the number and a leading `+` are ignored, and text with no digits,
+    /// like `""` or `"."`, is 0. See [`NumberFormat::LENIENT`].
+    pub fn parse_lenient(s: &str) -> Result<Self, NumberParseErr> {
+        Self::parse_with(s, NumberFormat::LENIENT)
+    }
+
+    /// Like [`str::parse`], also accepting whatever `format` allows. An exponent moves the
+    /// decimal point before anything is cut off, so `1.2345e1` is `12.345`, then digits past
+    /// the third decimal place are dropped as usual, so `-1e-4` is 0.
+    pub fn parse_with(s: &str, format: NumberFormat) -> Result<Self, NumberParseErr> {
+        let s = if format.trim_whitespace { s.trim() } else { s };
+        match Self::parse_formatted(s, format) {
+            Err(NumberParseErr::Empty | NumberParseErr::NoDigits) if format.empty_as_zero => {
+                Ok(Number::ZERO)
+            },
+            result => result,
+        }
+    }
+
+    fn parse_formatted(s: &str, format: NumberFormat) -> Result<Self, NumberParseErr> {
+        let s = match s.strip_prefix('+') {
+            Some(rest) if format.plus_sign && !rest.starts_with(['+', '-']) => rest,
+            _ => s,
+        };
+        let Some(at) = s.find(['e', 'E']).filter(|_| format.exponent) else {
+            return s.parse();
+        };
+        let (mantissa, exponent) = (&s[..at], &s[at + 1..]);
+        mantissa.parse::<Number>()?;
+
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.is_empty() {
+            return Err(NumberParseErr::EmptyExponent);
+        }
+        let mut shift: i64 = 0;
+        for c in digits.chars() {
+            let d = c.to_digit(10).ok_or(NumberParseErr::UnknownChar(c))?;
+            // anything this far out is either 0 or too big anyway
+            shift = (shift * 10 + d as i64).min(1_000);
+        }
+        if exponent.starts_with('-') {
+            shift = -shift;
+        }
+
+        let neg = mantissa.starts_with('-');
+        let mantissa = mantissa.trim_start_matches('-');
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits: String = int.chars().chain(frac.chars()).collect();
+        let trimmed = digits.trim_start_matches('0');
+        // where the point goes, counting digits from the first which isn't 0
+        let point = int.len() as i64 + shift - (digits.len() - trimmed.len()) as i64;
+        if trimmed.is_empty() || point < -3 {
+            return Ok(Number::ZERO);
+        }
+        if point > 19 {
+            return Err(NumberParseErr::Overflow);
+        }
+
+        let mut shifted = String::with_capacity(trimmed.len() + 24);
+        if neg {
+            shifted.push('-');
+        }
+        if point <= 0 {
+            shifted.push_str("0.");
+            shifted.extend(core::iter::repeat_n('0', -point as usize));
+            shifted.push_str(trimmed);
+        } else if point as usize >= trimmed.len() {
+            shifted.push_str(trimmed);
+            shifted.extend(core::iter::repeat_n('0', point as usize - trimmed.len()));
+        } else {
+            let (int, frac) = trimmed.split_at(point as usize);
+            shifted.push_str(int);
+            shifted.push('.');
+            shifted.push_str(frac);
+        }
+        shifted.parse()
+    }
 }
 
 impl From<bool> for Number {
@@ -215,7 +509,7 @@ impl From<bool> for Number {
 
 impl From<i64> for Number {
     fn from(n: i64) -> Self {
-        Number(n * Self::SCALE)
+        Self::from_int(n)
     }
 }
 
@@ -249,18 +543,68 @@ impl Display for Number {
     }
 }
 
-#[derive(Debug, Error, Clone, Copy)]
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
 pub enum NumberParseErr {
     #[error("Number can't fit in i64")]
     Overflow,
     #[error("Found unknown char '{0:}'")]
     UnknownChar(char),
+    #[error("Number is empty")]
+    Empty,
+    #[error("Number contains whitespace")]
+    Whitespace,
+    #[error("Number starts with '+'")]
+    PlusSign,
+    #[error("Number has no digits")]
+    NoDigits,
+    #[error("Number's exponent has no digits")]
+    EmptyExponent,
+}
+
+/// What [`Number::parse_with`] accepts beyond the literals [`str::parse`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct NumberFormat {
+    /// An exponent after the digits, like `1e3` or `2.5E-2`.
+    pub exponent: bool,
+    /// A leading `+`.
+    pub plus_sign: bool,
+    /// Whitespace around the number.
+    pub trim_whitespace: bool,
+    /// Text with no digits, like `""` or `"."`, which is 0.
+    pub empty_as_zero: bool,
 }
 
+impl NumberFormat {
+    /// What [`Number::parse_lenient`] accepts, which is what the game does with text typed
+    /// into fields.
+    pub const LENIENT: Self = NumberFormat {
+        exponent: false,
+        plus_sign: true,
+        trim_whitespace: true,
+        empty_as_zero: true,
+    };
+}
+
+/// Parses numbers as written in Yolol code: an optional `-`, digits, then optionally a `.`
+/// and more digits, of which only the first 3 count. There must be a digit on one side of the
+/// `.`, so `5.` and `.5` are fine but `.` isn't. Whitespace and a leading `+` are errors, see
+/// [`Number::parse_lenient`] to allow them.
 impl FromStr for Number {
     type Err = NumberParseErr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(NumberParseErr::Empty);
+        }
+        if s.contains(char::is_whitespace) {
+            return Err(NumberParseErr::Whitespace);
+        }
+        if s.starts_with('+') {
+            return Err(NumberParseErr::PlusSign);
+        }
+        if !s.bytes().any(|b| b.is_ascii_digit()) {
+            return Err(NumberParseErr::NoDigits);
+        }
         let neg = s.as_bytes()[0] == b'-';
         let (mut big, small) = if let Some((big, small)) = s.split_once('.') {
             (big.chars(), Some(small.chars()))
@@ -297,8 +641,12 @@ impl FromStr for Number {
             if neg {
                 exp = -exp;
             }
-            for c in small.take(3) {
-                if c.is_ascii_digit() {
+            for (i, c) in small.enumerate() {
+                if !c.is_ascii_digit() {
+                    return Err(NumberParseErr::UnknownChar(c));
+                }
+                // digits past the third are dropped
+                if i < 3 {
                     exp = exp
                         .checked_div(10)
                         .ok_or(NumberParseErr::Overflow)?;
@@ -306,8 +654,6 @@ impl FromStr for Number {
                         .checked_mul(c as i64 - '0' as i64)
                         .ok_or(NumberParseErr::Overflow)?;
                     val = val.checked_add(d).ok_or(NumberParseErr::Overflow)?;
-                } else {
-                    return Err(NumberParseErr::UnknownChar(c));
                 }
             }
         }
@@ -355,6 +701,7 @@ impl Neg for Number {
 impl Mul for Number {
     type Output = Self;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn mul(self, rhs: Self) -> Self::Output {
         Number(self.0.wrapping_mul(rhs.0) / Self::SCALE)
     }
@@ -401,3 +748,178 @@ impl Rem for Number {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use super::*;
+
+    fn num(s: &str) -> Number {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn stringify_fractions() {
+        let cases = [
+            ("-0.999", "-0.999"), ("0.019", "0.019"), ("2.500", "2.5"), ("-3.05", "-3.05"),
+        ];
+        for (n, s) in cases {
+            assert_eq!(num(n).stringify().to_string(), s);
+        }
+        assert_eq!(Number::MIN.stringify().to_string(), "-9223372036854775.808");
+    }
+
+    proptest! {
+        #[test]
+        fn stringify_round_trips(raw in any::<i64>()) {
+            let n = Number(raw);
+            prop_assert_eq!(n.stringify().to_string().parse::<Number>().unwrap(), n);
+        }
+
+        #[test]
+        fn parsing_truncates_extra_decimals(raw in any::<i64>(), extra in "[0-9]{1,6}") {
+            let sign = if raw < 0 { "-" } else { "" };
+            let abs = raw.unsigned_abs();
+            let s = format!("{}{}.{:03}{}", sign, abs / 1000, abs % 1000, extra);
+            prop_assert_eq!(s.parse::<Number>().unwrap(), Number(raw));
+        }
+    }
+
+    #[test]
+    fn parse_policy() {
+        let strict = |s: &str| s.parse::<Number>();
+        assert_eq!(strict(""), Err(NumberParseErr::Empty));
+        assert_eq!(strict(" 1"), Err(NumberParseErr::Whitespace));
+        assert_eq!(strict("1\t"), Err(NumberParseErr::Whitespace));
+        assert_eq!(strict("+1"), Err(NumberParseErr::PlusSign));
+        assert_eq!(strict("."), Err(NumberParseErr::NoDigits));
+        assert_eq!(strict("-"), Err(NumberParseErr::NoDigits));
+        assert_eq!(strict("1-"), Err(NumberParseErr::UnknownChar('-')));
+        assert_eq!(strict("5."), Ok(num("5")));
+        assert_eq!(strict("-.5"), Ok(num("-0.5")));
+        assert_eq!(strict("1.2349"), Ok(num("1.234")));
+        assert_eq!(strict("1.234abc"), Err(NumberParseErr::UnknownChar('a')));
+        assert_eq!(strict("1.23456-"), Err(NumberParseErr::UnknownChar('-')));
+        assert_eq!(strict("1.2.3"), Err(NumberParseErr::UnknownChar('.')));
+
+        let lenient = Number::parse_lenient;
+        assert_eq!(lenient(" +2.5\n"), Ok(num("2.5")));
+        assert_eq!(lenient("  "), Ok(Number::ZERO));
+        assert_eq!(lenient("-."), Ok(Number::ZERO));
+        assert_eq!(lenient("2 5"), Err(NumberParseErr::Whitespace));
+        assert_eq!(lenient("++2"), Err(NumberParseErr::PlusSign));
+
+        let trimming = NumberFormat { trim_whitespace: true, ..Default::default() };
+        assert_eq!(Number::parse_with(" 2 ", trimming), Ok(num("2")));
+        assert_eq!(Number::parse_with(" ", trimming), Err(NumberParseErr::Empty));
+    }
+
+    #[test]
+    fn parses_exponents() {
+        let format = NumberFormat { exponent: true, plus_sign: true, ..Default::default() };
+        let parse = |s| Number::parse_with(s, format);
+        assert_eq!(parse("1e3"), Ok(num("1000")));
+        assert_eq!(parse("2.5E-2"), Ok(num("0.025")));
+        assert_eq!(parse("+1.2345e1"), Ok(num("12.345")));
+        assert_eq!(parse("-0.0125e+2"), Ok(num("-1.25")));
+        assert_eq!(parse("-1e-4"), Ok(Number::ZERO));
+        assert_eq!(parse("0e99999999999"), Ok(Number::ZERO));
+        assert_eq!(parse("9e15"), Ok(num("9000000000000000")));
+        assert_eq!(parse("1e16"), Err(NumberParseErr::Overflow));
+        assert_eq!(parse("12"), Ok(num("12")));
+        assert_eq!(parse("1e"), Err(NumberParseErr::EmptyExponent));
+        assert_eq!(parse("1e2.5"), Err(NumberParseErr::UnknownChar('.')));
+        assert_eq!(parse("e3"), Err(NumberParseErr::Empty));
+        assert_eq!(parse("+-1"), Err(NumberParseErr::PlusSign));
+
+        let plain = |s| Number::parse_with(s, NumberFormat::default());
+        assert_eq!(plain("1e3"), Err(NumberParseErr::UnknownChar('e')));
+        assert_eq!(plain("+1"), Err(NumberParseErr::PlusSign));
+        assert_eq!(plain("1.5"), Ok(num("1.5")));
+    }
+
+    #[test]
+    fn const_construction() {
+        static TABLE: [Number; 5] = [
+            num!(1.5),
+            num!(-0.001),
+            num!(1_000),
+            num!(-9223372036854775.808),
+            Number::from_int(7),
+        ];
+        let min = Number::MIN.to_string();
+        assert_eq!(TABLE.map(|n| n.to_string()), ["1.5", "-0.001", "1000", &min, "7"]);
+        assert_eq!(num!(2.25), num("2.25"));
+        assert_eq!(Number::from_millis(1500), num("1.5"));
+        assert_eq!(Number::from(3), Number::from_int(3));
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let big = num("9000000000000000");
+        assert_eq!(big.checked_add(big), None);
+        assert_eq!(big.checked_add(num("1.5")), Some(num("9000000000000001.5")));
+        assert_eq!((-big).checked_sub(big), None);
+        assert_eq!(Number::MIN.checked_sub(num("-1")), Some(num("-9223372036854774.808")));
+        assert_eq!(big.checked_mul(num("2")), None);
+        assert_eq!(big.checked_mul(num("0.5")), Some(num("4500000000000000")));
+        assert_eq!(big.checked_div(num("0.5")), None);
+        assert_eq!(big.checked_div(num("3")), Some(num("3000000000000000")));
+        assert_eq!(num("1").checked_div(Number::ZERO), None);
+        assert_eq!(num("7").checked_rem(num("2.5")), Some(num("2")));
+        assert_eq!(Number::MIN.checked_rem(num("-0.001")), Some(Number::ZERO));
+        assert_eq!(num("1").checked_rem(Number::ZERO), None);
+        assert_eq!(num("2").checked_pow(num("10")), Some(num("1024")));
+        assert_eq!(num("10").checked_pow(num("16")), None);
+        assert_eq!(num("-8").checked_pow(num("0.5")), None);
+    }
+
+    #[test]
+    fn logarithms() {
+        assert_eq!(num("1").exp(), num("2.718"));
+        assert_eq!(num("-2").exp(), num("0.135"));
+        assert_eq!(num("100").exp(), Number::MIN);
+        assert_eq!(num("10").ln(), num("2.302"));
+        assert_eq!(num("1000").log10(), num("3"));
+        assert_eq!(num("0.01").log10(), num("-2"));
+        assert_eq!(num("8").log(num("2")), num("3"));
+        assert_eq!(num("8").log(num("0.5")), num("-3"));
+        for n in [num("0"), num("-1")] {
+            assert_eq!(n.ln(), Number::MIN);
+            assert_eq!(n.log10(), Number::MIN);
+            assert_eq!(num("8").log(n), Number::MIN);
+        }
+        assert_eq!(num("8").log(num("1")), Number::MIN);
+    }
+
+    #[test]
+    fn rounded_division() {
+        let cases = [
+            (Rounding::TowardZero, "0.666", "-0.666"),
+            (Rounding::Down, "0.666", "-0.667"),
+            (Rounding::Up, "0.667", "-0.666"),
+            (Rounding::Nearest, "0.667", "-0.667"),
+        ];
+        for (rounding, pos, neg) in cases {
+            let div = |l, r| num(l).div_rounded(num(r), rounding).unwrap();
+            assert_eq!(div("2", "3"), num(pos), "{:?}", rounding);
+            assert_eq!(div("-2", "3"), num(neg), "{:?}", rounding);
+            assert_eq!(div("2", "-3"), num(neg), "{:?}", rounding);
+        }
+        assert_eq!(num("0.001").div_rounded(num("2"), Rounding::Nearest).unwrap(), num("0.001"));
+        assert_eq!(num("6").div_rounded(num("3"), Rounding::Up).unwrap(), num("2"));
+        assert!(num("1").div_rounded(Number::ZERO, Rounding::Nearest).is_err());
+        let default = num("2").div_rounded(num("3"), Rounding::default());
+        assert_eq!(default.ok(), (num("2") / num("3")).ok());
+    }
+
+    #[test]
+    fn mul_div_no_overflow() {
+        let big = num("9000000000000");
+        // big * big overflows, even though the result fits
+        assert_eq!(big.mul_div(big, big).unwrap(), big);
+        assert_eq!(num("250").mul_div(num("3"), num("-4")).unwrap(), num("-187.5"));
+        assert!(big.mul_div(big, Number::ZERO).is_err());
+        assert_eq!(num("80").percent(num("12.5")), num("10"));
+    }
+}
@@ -0,0 +1,74 @@
+use super::*;
+
+/// What `+`, `-` and `*` do with results too big or small for a [`Number`]. `++` and `--`
+/// always wrap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OverflowPolicy {
+    /// Wrap around, as the game does.
+    #[default]
+    Wrapping,
+    /// Clamp to [`Number::MIN`] or [`Number::MAX`].
+    Saturating,
+    /// Fail with [`RuntimeErr::Overflow`], skipping the rest of the line.
+    Error,
+}
+
+impl OverflowPolicy {
+    pub const fn can_fail(self) -> bool {
+        matches!(self, OverflowPolicy::Error)
+    }
+
+    /// `checked` as this policy has it, with `wrapped` for where it overflowed and `negative`
+    /// for which way.
+    fn apply(
+        self,
+        checked: Option<Number>,
+        wrapped: Number,
+        negative: bool,
+    ) -> ValueResult<Number> {
+        match (self, checked) {
+            (OverflowPolicy::Wrapping, _) => Ok(wrapped),
+            (_, Some(n)) => Ok(n),
+            (OverflowPolicy::Saturating, None) =>
+                Ok(if negative { Number::MIN } else { Number::MAX }),
+            (OverflowPolicy::Error, None) => Err(RuntimeErr::Overflow),
+        }
+    }
+
+    // a sum or difference can only overflow on the side `l` is on
+    pub fn add(self, l: Number, r: Number) -> ValueResult<Number> {
+        self.apply(l.checked_add(r), l + r, l.0 < 0)
+    }
+
+    pub fn sub(self, l: Number, r: Number) -> ValueResult<Number> {
+        self.apply(l.checked_sub(r), l - r, l.0 < 0)
+    }
+
+    /// Unlike wrapping, which overflows as soon as `l.0 * r.0` does, the others only count a
+    /// product as overflowing if it doesn't fit once scaled back down, as in
+    /// [`Number::checked_mul`].
+    pub fn mul(self, l: Number, r: Number) -> ValueResult<Number> {
+        self.apply(l.checked_mul(r), l * r, (l.0 < 0) != (r.0 < 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let n = |s: &str| s.parse::<Number>().unwrap();
+        let big = n("9000000000000000");
+        assert_eq!(OverflowPolicy::Wrapping.add(big, big).ok(), Some(big + big));
+        assert_eq!(OverflowPolicy::Saturating.add(big, big).ok(), Some(Number::MAX));
+        assert_eq!(OverflowPolicy::Saturating.sub(-big, big).ok(), Some(Number::MIN));
+        let zero_minus_min = OverflowPolicy::Saturating.sub(Number::ZERO, Number::MIN);
+        assert_eq!(zero_minus_min.ok(), Some(Number::MAX));
+        assert_eq!(OverflowPolicy::Saturating.mul(-big, n("2")).ok(), Some(Number::MIN));
+        assert!(matches!(OverflowPolicy::Error.add(big, big), Err(RuntimeErr::Overflow)));
+        assert_eq!(OverflowPolicy::Error.mul(big, n("0.5")).ok(), Some(n("4500000000000000")));
+        assert_eq!(OverflowPolicy::Wrapping.mul(big, n("0.5")).ok(), Some(big * n("0.5")));
+        assert_eq!(OverflowPolicy::Error.sub(n("1.5"), n("2")).ok(), Some(n("-0.5")));
+    }
+}
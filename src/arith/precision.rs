@@ -0,0 +1,151 @@
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use super::*;
+
+/// The operations Yolol computes through floating point, which can land on the wrong side of a
+/// thousandth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatOp {
+    Sqrt,
+    Pow,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+}
+
+impl Display for FloatOp {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            FloatOp::Sqrt => "sqrt",
+            FloatOp::Pow => "^",
+            FloatOp::Sin => "sin",
+            FloatOp::Cos => "cos",
+            FloatOp::Tan => "tan",
+            FloatOp::Asin => "asin",
+            FloatOp::Acos => "acos",
+            FloatOp::Atan => "atan",
+        })
+    }
+}
+
+/// How close to a thousandth an `f64` result has to be to count as landing on it. Doubles
+/// aren't exact, so `sin 210` comes out a hair over -0.5.
+const SNAP: f64 = 1e-9;
+
+/// Like [`Number::new`], but treating results within [`SNAP`] of a thousandth as exact.
+fn snapped(v: f64) -> Number {
+    let scaled = v * 1000.0;
+    let nearest = scaled.round();
+    if (scaled - nearest).abs() < SNAP {
+        Number::new(nearest / 1000.0)
+    } else {
+        Number::new(v)
+    }
+}
+
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = (n as f64).sqrt() as u128;
+    while x * x > n {
+        x -= 1;
+    }
+    while (x + 1) * (x + 1) <= n {
+        x += 1;
+    }
+    x
+}
+
+/// `base ^ exp` for a whole `exp` from 0 to 64 in exact arithmetic, rounded like
+/// [`Number::pow`], or `None` if it doesn't fit.
+fn exact_pow(base: Number, exp: i64) -> Option<Number> {
+    // base.0^exp / 1000^(exp - 1) thousandths, and Number::pow rounds by adding 0.05 of one
+    let magnitude = base.0.unsigned_abs() as u128;
+    let mut num: u128 = 20;
+    for _ in 0..exp {
+        num = num.checked_mul(magnitude)?;
+    }
+    let den = 1000_u128.checked_pow(u32::try_from(exp).ok()?.checked_sub(1)?)?;
+    let thousandths = i64::try_from((num / den + 1) / 20).ok()?;
+    let negative = base.0.is_negative() && exp % 2 == 1;
+    Some(Number(if negative { -thousandths } else { thousandths }))
+}
+
+impl FloatOp {
+    /// The result as Yolol computes it. `rhs` is only used by [`FloatOp::Pow`].
+    pub fn fast(self, lhs: Number, rhs: Number) -> Number {
+        match self {
+            FloatOp::Sqrt => lhs.sqrt(),
+            FloatOp::Pow => lhs.pow(rhs),
+            FloatOp::Sin => lhs.sin(),
+            FloatOp::Cos => lhs.cos(),
+            FloatOp::Tan => lhs.tan(),
+            FloatOp::Asin => lhs.asin(),
+            FloatOp::Acos => lhs.acos(),
+            FloatOp::Atan => lhs.atan(),
+        }
+    }
+
+    /// The result computed as precisely as possible, then rounded like [`FloatOp::fast`].
+    /// Square roots and small whole powers are exact, and the rest are done entirely in `f64`.
+    pub fn precise(self, lhs: Number, rhs: Number) -> Number {
+        let x = lhs.as_f64();
+        match self {
+            FloatOp::Sqrt if lhs.0.is_negative() || lhs.0 >= 9223372036854775000 => Number::MIN,
+            // sqrt(lhs.0 / 1000) * 1000 = sqrt(lhs.0 * 1000), and Number::sqrt rounds by adding
+            // 0.05 of a thousandth
+            FloatOp::Sqrt => Number(((isqrt(lhs.0 as u128 * 1000 * 400) + 1) / 20) as i64),
+            FloatOp::Pow => {
+                let exact = (rhs.0 % 1000 == 0 && (1..=64).contains(&(rhs.0 / 1000)))
+                    .then(|| exact_pow(lhs, rhs.0 / 1000))
+                    .flatten();
+                exact.unwrap_or_else(|| Number::round_to_new(x.powf(rhs.as_f64())))
+            },
+            FloatOp::Sin => snapped(x.to_radians().sin()),
+            FloatOp::Cos => snapped(x.to_radians().cos()),
+            FloatOp::Tan => snapped(x.to_radians().tan()),
+            FloatOp::Asin => snapped(x.asin().to_degrees()),
+            FloatOp::Acos => snapped(x.acos().to_degrees()),
+            FloatOp::Atan => {
+                let atan = x.atan().to_degrees();
+                snapped(if atan == -90.0 { 90.0 } else { atan })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(s: &str) -> Number {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn precise_results() {
+        assert_eq!(FloatOp::Sin.fast(n("90"), Number::ZERO), n("0.999"));
+        assert_eq!(FloatOp::Sin.precise(n("90"), Number::ZERO), n("1"));
+        assert_eq!(FloatOp::Sin.fast(n("210"), Number::ZERO), n("-0.499"));
+        assert_eq!(FloatOp::Sin.precise(n("210"), Number::ZERO), n("-0.5"));
+        assert_eq!(FloatOp::Cos.precise(n("60"), Number::ZERO), n("0.5"));
+        assert_eq!(FloatOp::Sqrt.precise(n("2"), Number::ZERO), n("1.414"));
+        assert_eq!(FloatOp::Sqrt.precise(n("16"), Number::ZERO), n("4"));
+        assert_eq!(FloatOp::Pow.precise(n("1.1"), n("2")), n("1.21"));
+        assert_eq!(FloatOp::Pow.precise(n("-2"), n("3")), n("-8"));
+        assert_eq!(FloatOp::Pow.precise(n("2"), n("0.5")), n("1.414"));
+
+        // they agree away from the edges
+        for i in -2000..2000 {
+            let x = Number(i * 97);
+            for op in [FloatOp::Sqrt, FloatOp::Sin, FloatOp::Cos, FloatOp::Atan] {
+                let (fast, precise) = (op.fast(x, Number::ZERO), op.precise(x, Number::ZERO));
+                let close = (fast.0 - precise.0).abs() <= 1;
+                assert!(close, "{} {:?}: {:?} vs {:?}", op, x, fast, precise);
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use core::fmt::Formatter;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use super::*;
+
+/// As its raw thousandths, so `1.5` is `1500`, which is lossless. See [`decimal`] for `"1.5"`.
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i64::deserialize(deserializer).map(Number)
+    }
+}
+
+/// For `#[serde(with = "yogi::arith::decimal")]` on a [`Number`], writing it as a string like
+/// `"1.5"`, the way Yolol shows it.
+pub mod decimal {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(n: &Number, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(n)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Number, D::Error> {
+        struct Decimal;
+
+        impl Visitor<'_> for Decimal {
+            type Value = Number;
+
+            fn expecting(&self, f: &mut Formatter) -> core::fmt::Result {
+                f.write_str("a decimal number in a string")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Number, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Decimal)
+    }
+}
+
+/// As a string if it's valid UTF-8, or otherwise as bytes.
+impl Serialize for YString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match core::str::from_utf8(self) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => serializer.serialize_bytes(self),
+        }
+    }
+}
+
+struct YStringVisitor;
+
+impl<'de> Visitor<'de> for YStringVisitor {
+    type Value = YString;
+
+    fn expecting(&self, f: &mut Formatter) -> core::fmt::Result {
+        f.write_str("a string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<YString, E> {
+        Ok(YString::from_bytes(s.as_bytes()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<YString, E> {
+        Ok(YString::from_bytes(bytes))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<YString, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(YString::from_bytes(&bytes))
+    }
+}
+
+/// Strings longer than Yolol allows are cut short, like everywhere else.
+impl<'de> Deserialize<'de> for YString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(YStringVisitor)
+    }
+}
+
+/// As whichever it holds, so numbers and strings can be told apart without a tag.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Num(n) => n.serialize(serializer),
+            Value::Str(s) => s.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut Formatter) -> core::fmt::Result {
+                f.write_str("a number's thousandths, a string or bytes")
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+                Ok(Value::Num(Number(n)))
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+                i64::try_from(n)
+                    .map(|n| Value::Num(Number(n)))
+                    .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(n), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+                YStringVisitor.visit_str(s).map(Value::Str)
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Value, E> {
+                YStringVisitor.visit_bytes(bytes).map(Value::Str)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Value, A::Error> {
+                YStringVisitor.visit_seq(seq).map(Value::Str)
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serde_round_trip() {
+        let values = [
+            Value::Num("1.5".parse().unwrap()),
+            Value::Num(Number::MIN),
+            Value::Str("caf\u{e9}".into()),
+            Value::Str(YString::from_bytes(&[0x63, 0xc3])),
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(json, r#"[1500,-9223372036854775808,"café",[99,195]]"#);
+        assert_eq!(serde_json::from_str::<Vec<Value>>(&json).unwrap(), values);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Reading {
+            #[serde(with = "decimal")]
+            speed: Number,
+        }
+        let reading = Reading { speed: "-2.25".parse().unwrap() };
+        let json = serde_json::to_string(&reading).unwrap();
+        assert_eq!(json, r#"{"speed":"-2.25"}"#);
+        assert_eq!(serde_json::from_str::<Reading>(&json).unwrap(), reading);
+        assert!(serde_json::from_str::<Reading>(r#"{"speed":"fast"}"#).is_err());
+    }
+}
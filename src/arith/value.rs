@@ -1,50 +1,62 @@
+use core::cmp::Ordering;
 use super::*;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub enum Value {
     Num(Number),
     Str(YString),
 }
 
 impl Value {
-
+    /// # Safety
+    ///
+    /// The value must be a number.
     pub unsafe fn as_number_unchecked(&self) -> &Number {
         if let Value::Num(n) = self {
             n
         } else if cfg!(debug_assertions) {
-            std::unreachable!()
+            core::unreachable!()
         } else {
-            std::hint::unreachable_unchecked()
+            core::hint::unreachable_unchecked()
         }
     }
 
+    /// # Safety
+    ///
+    /// The value must be a number.
     pub unsafe fn as_number_unchecked_mut(&mut self) -> &mut Number {
         if let Value::Num(n) = self {
             n
         } else if cfg!(debug_assertions) {
-            std::unreachable!()
+            core::unreachable!()
         } else {
-            std::hint::unreachable_unchecked()
+            core::hint::unreachable_unchecked()
         }
     }
 
+    /// # Safety
+    ///
+    /// The value must be a string.
     pub unsafe fn as_ystring_unchecked(&self) -> &YString {
         if let Value::Str(s) = self {
             s
         } else if cfg!(debug_assertions) {
-            std::unreachable!()
+            core::unreachable!()
         } else {
-            std::hint::unreachable_unchecked()
+            core::hint::unreachable_unchecked()
         }
     }
 
+    /// # Safety
+    ///
+    /// The value must be a string.
     pub unsafe fn as_ystring_unchecked_mut(&mut self) -> &mut YString {
         if let Value::Str(s) = self {
             s
         } else if cfg!(debug_assertions) {
-            std::unreachable!()
+            core::unreachable!()
         } else {
-            std::hint::unreachable_unchecked()
+            core::hint::unreachable_unchecked()
         }
     }
 
@@ -56,12 +68,43 @@ impl Value {
     }
 
     pub fn pre_dec(&mut self) -> ValueResult<()> {
+        self.pre_dec_in(StringMode::Bytes)
+    }
+
+    /// Like `+=`, with strings appended as `mode` has it.
+    pub fn add_assign_in(&mut self, other: &Value, mode: StringMode) {
+        match (&mut *self, other) {
+            (Value::Num(l), Value::Str(r)) => {
+                let mut l: YString = l.stringify();
+                l.append_in(r, mode);
+                *self = Value::Str(l);
+            },
+            (Value::Str(l), Value::Str(r)) => l.append_in(r, mode),
+            // numbers print as ASCII, so can't be cut in the middle of a character
+            _ => *self += other,
+        }
+    }
+
+    /// Like [`Value::pre_dec`], with strings shortened as `mode` has it.
+    pub fn pre_dec_in(&mut self, mode: StringMode) -> ValueResult<()> {
         match self {
             Value::Num(n) => {
                 n.pre_dec();
                 Ok(())
             },
-            Value::Str(s) => s.pre_dec(),
+            Value::Str(s) => s.pre_dec_in(mode),
+        }
+    }
+
+    /// Compares values the way Yolol's `<` and friends do, with a number compared to a string
+    /// as if it were stringified. That makes `2 < 10`, `10 < "15"` and `"15" < 2`, so unlike
+    /// [`Ord`] this isn't a total order.
+    pub fn cmp_yolol(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Num(l), Value::Num(r)) => l.cmp(r),
+            (Value::Num(l), Value::Str(r)) => l.digits().as_slice().cmp(r),
+            (Value::Str(l), Value::Num(r)) => l.as_slice().cmp(&r.digits()),
+            (Value::Str(l), Value::Str(r)) => l.cmp(r),
         }
     }
 
@@ -117,6 +160,14 @@ impl Value {
             None
         }
     }
+
+    /// A copy sharing a string's bytes, see [`YString::share`].
+    pub fn share(&self) -> Self {
+        match self {
+            Value::Num(n) => Value::Num(*n),
+            Value::Str(s) => Value::Str(s.share()),
+        }
+    }
 }
 
 impl AddAssign<&'_ Value> for Value {
@@ -129,7 +180,7 @@ impl AddAssign<&'_ Value> for Value {
                 *self = Value::Str(l);
             },
             (Value::Str(l), Value::Num(r)) => {
-                *l += &r.stringify();
+                r.stringify_with_buffer(l);
             },
             (Value::Str(l), Value::Str(r)) => {
                 *l += r;
@@ -148,7 +199,7 @@ impl SubAssign<&'_ Value> for Value {
                 *self = Value::Str(l);
             },
             (Value::Str(l), Value::Num(r)) => {
-                *l -= &r.stringify();
+                l.remove_last_occurrence(&r.digits());
             },
             (Value::Str(l), Value::Str(r)) => {
                 *l -= r;
@@ -157,42 +208,29 @@ impl SubAssign<&'_ Value> for Value {
     }
 }
 
+/// A total order, so values can go in sets, maps and sorted lists. Numbers come before strings,
+/// and otherwise values are ordered as by [`Value::cmp_yolol`]. Yolol itself compares mixed
+/// types as strings, which isn't a total order, so Yolol's `<` uses `cmp_yolol` instead.
 impl PartialOrd for Value {
-    fn partial_cmp(&self, _other: &Self) -> Option<std::cmp::Ordering> {
-        unimplemented!()
-    }
-
-    fn le(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Num(l), Value::Num(r)) => l <= r,
-            (Value::Num(l), Value::Str(r)) => l.stringify() <= *r,
-            (Value::Str(l), Value::Num(r)) => *l <= r.stringify(),
-            (Value::Str(l), Value::Str(r)) => l <= r,
-        }
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    fn lt(&self, other: &Self) -> bool {
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Value::Num(l), Value::Num(r)) => l < r,
-            (Value::Num(l), Value::Str(r)) => l.stringify() < *r,
-            (Value::Str(l), Value::Num(r)) => *l < r.stringify(),
-            (Value::Str(l), Value::Str(r)) => l < r,
+            (Value::Num(_), Value::Str(_)) => Ordering::Less,
+            (Value::Str(_), Value::Num(_)) => Ordering::Greater,
+            (l, r) => l.cmp_yolol(r),
         }
     }
-
-    fn gt(&self, other: &Self) -> bool {
-        other.lt(self)
-    }
-
-    fn ge(&self, other: &Self) -> bool {
-        other.le(self)
-    }
 }
 
 impl Clone for Value {
     fn clone(&self) -> Self {
         match self {
-            Value::Num(n) => Value::Num(n.clone()),
+            Value::Num(n) => Value::Num(*n),
             Value::Str(s) => Value::Str(s.clone()),
         }
     }
@@ -300,6 +338,55 @@ pub enum RuntimeErr {
     DivZero,
     #[error("mod by zero")]
     ModZero,
+    #[error("arithmetic overflow")]
+    Overflow,
 }
 
-pub type ValueResult<T> = Result<T, RuntimeErr>;
\ No newline at end of file
+pub type ValueResult<T> = Result<T, RuntimeErr>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+    use proptest::prelude::*;
+    use crate::parser::{Ident, YololParser};
+    use crate::simple_interp::SimpleInterp;
+    use super::*;
+
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            (-10_i64.pow(15)..10_i64.pow(15)).prop_map(|n| Value::Num(Number(n))),
+            "[a-zA-Z0-9 .:=+-]{0,20}".prop_map(|s| Value::Str(s.into())),
+        ]
+    }
+
+    proptest! {
+        /// Printed values read back as the same value when used as Yolol literals.
+        #[test]
+        fn display_round_trips(v in value()) {
+            let program = YololParser::default().parse(&format!(":v={}", v)).unwrap();
+            let mut interp = SimpleInterp::new(program);
+            interp.step_line();
+            prop_assert_eq!(&interp.values()[&Ident::global("v")], &v);
+        }
+    }
+
+    #[test]
+    fn value_ordering() {
+        let two = Value::Num(2.into());
+        let ten = Value::Num(10.into());
+        let fifteen = Value::Str("15".into());
+        assert!(two.cmp_yolol(&ten).is_lt());
+        assert!(ten.cmp_yolol(&fifteen).is_lt());
+        assert!(fifteen.cmp_yolol(&two).is_lt());
+
+        let sorted: Vec<_> = [fifteen.clone(), ten.clone(), two.clone(), Value::Str("1".into())]
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(sorted, [two.clone(), ten, Value::Str("1".into()), fifteen]);
+
+        let set: HashSet<_> = [two.clone(), Value::Num(2.into()), Value::Str("2".into())].into();
+        assert_eq!(set.len(), 2);
+    }
+}
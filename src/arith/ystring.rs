@@ -1,52 +1,228 @@
-use std::fmt::{Display, Debug, Formatter, Result as FmtResult};
+use core::fmt::{Display, Debug, Formatter, Result as FmtResult};
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::Arc;
 use derive_more::Deref;
 use arrayvec::ArrayVec;
 use super::*;
 
-const MAX_STRING_BYTES: usize = 1024;
+/// The most bytes a string holds, as in the game. Anything past it is dropped, from the end,
+/// by every operation which would grow a string further.
+pub const MAX_STRING_BYTES: usize = 1024;
 
+/// Strings are bytes, which is all that matters for ASCII. Other characters are kept as UTF-8,
+/// so they count as several towards lengths and the 1024 byte limit, and `--` removes a byte at
+/// a time, where the game works on whole characters. [`UnicodePolicy`] picks what to do with
+/// them as strings come in, and [`StringMode`] how the VM works on them after.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deref)]
 pub struct YString {
     #[deref]
-    pub(super) data: Box<ArrayVec<u8, MAX_STRING_BYTES>>,
+    data: Arc<ArrayVec<u8, MAX_STRING_BYTES>>,
 }
 
 impl YString {
+    /// Bytes past the most a string can hold are dropped.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        YString {
+            data: Arc::new(bytes.iter().copied().take(MAX_STRING_BYTES).collect()),
+        }
+    }
+
+    /// Decodes `bytes` as UTF-8, replacing anything invalid with U+FFFD. Characters past the
+    /// most a string can hold are dropped whole.
+    pub fn from_bytes_lossy(bytes: &[u8]) -> Self {
+        let mut data = ArrayVec::new();
+        for c in String::from_utf8_lossy(bytes).chars() {
+            let mut buf = [0; 4];
+            if data.try_extend_from_slice(c.encode_utf8(&mut buf).as_bytes()).is_err() {
+                break;
+            }
+        }
+        YString { data: Arc::new(data) }
+    }
+
+    /// A copy which shares these bytes until either string changes, unlike `clone`, which
+    /// copies them so both can change without allocating again.
+    pub fn share(&self) -> Self {
+        YString { data: self.data.clone() }
+    }
+
+    /// The bytes to change, copied first if they're shared.
+    #[inline]
+    pub(super) fn data_mut(&mut self) -> &mut ArrayVec<u8, MAX_STRING_BYTES> {
+        Arc::make_mut(&mut self.data)
+    }
+
+    /// The length in bytes, which is what the game's rules use for ASCII.
+    #[inline]
+    pub fn byte_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The length in characters, as the game counts them. An invalid UTF-8 sequence counts as
+    /// one, as [`YString::from_bytes_lossy`] would make it.
+    pub fn char_len(&self) -> usize {
+        String::from_utf8_lossy(&self.data).chars().count()
+    }
+
+    /// Applies `policy` to the string, failing on the first character outside of ASCII if it's
+    /// [`UnicodePolicy::Reject`].
+    pub fn apply_policy(&mut self, policy: UnicodePolicy) -> Result<(), NonAscii> {
+        if self.data.is_ascii() {
+            return Ok(());
+        }
+        match policy {
+            UnicodePolicy::Keep => Ok(()),
+            UnicodePolicy::Reject => {
+                let text = String::from_utf8_lossy(&self.data);
+                let (byte, character) = text.char_indices().find(|(_, c)| !c.is_ascii()).unwrap();
+                Err(NonAscii { character, byte })
+            },
+            UnicodePolicy::Replace => {
+                let text = String::from_utf8_lossy(&self.data);
+                let replaced = text.chars().map(|c| if c.is_ascii() { c as u8 } else { b'?' });
+                *self.data_mut() = replaced.collect();
+                Ok(())
+            },
+        }
+    }
+
     #[inline]
     #[allow(unused_must_use)]
     pub fn pre_inc(&mut self) {
-        self.data.try_push(b' ');
+        self.data_mut().try_push(b' ');
     }
 
     #[inline]
     pub fn pre_dec(&mut self) -> ValueResult<()> {
-        if self.data.pop().is_some() {
+        if self.data_mut().pop().is_some() {
             Ok(())
         } else {
             Err(RuntimeErr::EmptyStr)
         }
     }
 
+    /// Where the last character starts, counting an invalid byte as a character of its own.
+    fn last_char_start(&self) -> Option<usize> {
+        let len = self.data.len();
+        let lead = (len.saturating_sub(4)..len).rev().find(|&i| self.data[i] & 0xC0 != 0x80)?;
+        match core::str::from_utf8(&self.data[lead..]) {
+            Ok(s) if s.chars().count() == 1 => Some(lead),
+            _ => Some(len - 1),
+        }
+    }
+
+    /// Like [`YString::pre_dec`], removing the last whole character under [`StringMode::Chars`].
+    pub fn pre_dec_in(&mut self, mode: StringMode) -> ValueResult<()> {
+        match (mode, self.last_char_start()) {
+            (StringMode::Chars, Some(start)) => {
+                self.data_mut().truncate(start);
+                Ok(())
+            },
+            _ => self.pre_dec(),
+        }
+    }
+
+    /// Like [`YString::from_bytes`], dropping a character the limit cuts in two under
+    /// [`StringMode::Chars`].
+    pub fn from_bytes_in(bytes: &[u8], mode: StringMode) -> Self {
+        let mut s = YString::from_bytes(bytes);
+        if bytes.len() > MAX_STRING_BYTES && mode == StringMode::Chars {
+            s.trim_partial_char();
+        }
+        s
+    }
+
+    /// Appends `rhs` like `+=`, dropping a character the limit cuts in two under
+    /// [`StringMode::Chars`].
+    pub fn append_in(&mut self, rhs: &Self, mode: StringMode) {
+        let cut = self.len() + rhs.len() > MAX_STRING_BYTES;
+        *self += rhs;
+        if cut && mode == StringMode::Chars {
+            self.trim_partial_char();
+        }
+    }
+
+    /// Like [`YString::duplicate`], dropping a character the limit cuts in two under
+    /// [`StringMode::Chars`].
+    pub fn duplicate_in(&mut self, mode: StringMode) {
+        let cut = self.len() * 2 > MAX_STRING_BYTES;
+        self.duplicate();
+        if cut && mode == StringMode::Chars {
+            self.trim_partial_char();
+        }
+    }
+
+    /// Under [`StringMode::Chars`], drops a character cut short at the end of a string which
+    /// fills the limit, for strings made without knowing the mode. Cutting a longer string
+    /// leaves them like that, while ones made from text only end early if they were cut.
+    pub fn fit_in(&mut self, mode: StringMode) {
+        if self.len() == MAX_STRING_BYTES && mode == StringMode::Chars {
+            self.trim_partial_char();
+        }
+    }
+
+    /// Drops bytes past `max_len`, for a VM with a lower limit than [`MAX_STRING_BYTES`], along
+    /// with a character that cuts in two under [`StringMode::Chars`].
+    pub fn truncate_in(&mut self, max_len: usize, mode: StringMode) {
+        if self.len() > max_len {
+            self.data_mut().truncate(max_len);
+            if mode == StringMode::Chars {
+                self.trim_partial_char();
+            }
+        }
+    }
+
+    /// Drops a character cut short at the end, as appending past the limit can leave.
+    fn trim_partial_char(&mut self) {
+        let len = self.data.len();
+        let Some(lead) = (len.saturating_sub(3)..len).rev().find(|&i| self.data[i] >= 0xC0) else {
+            return;
+        };
+        if core::str::from_utf8(&self.data[lead..]).is_err_and(|e| e.error_len().is_none()) {
+            self.data_mut().truncate(lead);
+        }
+    }
+
     #[inline]
     pub fn clear(&mut self) {
-        self.data.clear();
+        match Arc::get_mut(&mut self.data) {
+            Some(data) => data.clear(),
+            None => self.data = Default::default(),
+        }
+    }
+
+    /// Removes the last occurrence of `needle`, which is what `-` does to strings. Returns
+    /// whether there was one to remove. An empty needle never matches.
+    pub fn remove_last_occurrence(&mut self, needle: &[u8]) -> bool {
+        if needle.is_empty() {
+            return false;
+        }
+        if let Some(start) = memchr::memmem::rfind(&self.data, needle) {
+            self.data_mut().drain(start..start + needle.len());
+            true
+        } else {
+            false
+        }
     }
 
     #[inline]
     pub fn duplicate(&mut self) {
-        self.data.extend(self.data.clone().into_iter().take(MAX_STRING_BYTES - self.data.len()));
+        let copy = self.data.clone();
+        self.data_mut().extend(copy.iter().copied().take(MAX_STRING_BYTES - copy.len()));
     }
 }
 
 impl Clone for YString {
     fn clone(&self) -> Self {
         YString {
-            data: self.data.clone(),
+            data: Arc::new((*self.data).clone()),
         }
     }
 
     fn clone_from(&mut self, source: &Self) {
-        self.data.clone_from(&source.data);
+        self.data_mut().clone_from(&source.data);
     }
 }
 
@@ -58,35 +234,62 @@ impl Display for YString {
 
 impl<T: Into<String>> From<T> for YString {
     fn from(string: T) -> Self {
-        YString {
-            data: Box::new(string.into().bytes().collect()),
-        }
+        YString::from_bytes(string.into().as_bytes())
     }
 }
 
+/// What to do with characters outside of ASCII, which the game and this crate count
+/// differently (see [`YString`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnicodePolicy {
+    /// Keep them as UTF-8 bytes.
+    #[default]
+    Keep,
+    /// Refuse strings holding them.
+    Reject,
+    /// Replace each with a `?`, so lengths match the game.
+    Replace,
+}
+
+/// How the VM treats strings holding characters outside of ASCII. Comparisons and `-` are the
+/// same either way, since UTF-8 sorts and matches just like the characters it encodes, and the
+/// limit is [`MAX_STRING_BYTES`] bytes in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StringMode {
+    /// Byte by byte, so `--` removes the last byte, and appending past the limit can cut a
+    /// character in two.
+    #[default]
+    Bytes,
+    /// By whole characters, as the game does, so `--` removes the last one, and a character
+    /// which doesn't fit in the limit is dropped.
+    Chars,
+}
+
+/// A character outside of ASCII, refused by [`UnicodePolicy::Reject`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("non-ASCII character {character:?} at byte {byte}")]
+pub struct NonAscii {
+    pub character: char,
+    pub byte: usize,
+}
+
 impl AddAssign<&'_ Self> for YString {
+    #[allow(clippy::suspicious_op_assign_impl)]
     fn add_assign(&mut self, rhs: &Self) {
-        self.data
-            .try_extend_from_slice(&rhs[0..rhs.len().min(MAX_STRING_BYTES - self.len())])
+        let room = MAX_STRING_BYTES - self.len();
+        self.data_mut()
+            .try_extend_from_slice(&rhs[0..rhs.len().min(room)])
             .unwrap_or_else(|_| if cfg!(debug_assertions) {
                 unreachable!()
             } else {
-                unsafe { std::hint::unreachable_unchecked() }
+                unsafe { core::hint::unreachable_unchecked() }
             })
     }
 }
 
 impl SubAssign<&'_ Self> for YString {
     fn sub_assign(&mut self, rhs: &Self) {
-        if self.is_empty() || rhs.is_empty() {
-            return;
-        }
-        for (start, s) in self.data.windows(rhs.len()).enumerate().rev() {
-            if s == rhs.data.as_slice() {
-                self.data.drain(start..start + rhs.len());
-                return;
-            }
-        }
+        self.remove_last_occurrence(rhs);
     }
 }
 
@@ -95,3 +298,113 @@ impl Debug for YString {
         Display::fmt(self, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub(l: &str, r: &str) -> String {
+        let mut l = YString::from(l);
+        l -= &YString::from(r);
+        l.to_string()
+    }
+
+    #[test]
+    fn string_subtraction() {
+        assert_eq!(sub("hello world", "o"), "hello wrld");
+        assert_eq!(sub("abcabc", "abc"), "abc");
+        assert_eq!(sub("aaa", "aa"), "a");
+        assert_eq!(sub("abc", "abc"), "");
+        assert_eq!(sub("abc", "d"), "abc");
+        assert_eq!(sub("abc", "abcd"), "abc");
+        assert_eq!(sub("abc", ""), "abc");
+        assert_eq!(sub("", "a"), "");
+        assert_eq!(sub("ABC", "b"), "ABC");
+
+        let mut s = YString::from("x=1;y=2;x=3");
+        assert!(s.remove_last_occurrence(b"x="));
+        assert_eq!(s.to_string(), "x=1;y=2;3");
+        assert!(!s.remove_last_occurrence(b"z"));
+    }
+
+    #[test]
+    fn unicode() {
+        let s = YString::from("h\u{e9}llo \u{2192}");
+        assert_eq!((s.byte_len(), s.char_len()), (10, 7));
+        let lossy = YString::from_bytes_lossy(b"a\xffb");
+        assert_eq!((lossy.to_string().as_str(), lossy.char_len()), ("a\u{fffd}b", 3));
+        let long = YString::from_bytes_lossy("\u{e9}".repeat(600).as_bytes());
+        assert_eq!(long.byte_len(), MAX_STRING_BYTES);
+
+        let mut replaced = s.clone();
+        replaced.apply_policy(UnicodePolicy::Replace).unwrap();
+        assert_eq!(replaced.to_string(), "h?llo ?");
+        let rejected = s.clone().apply_policy(UnicodePolicy::Reject);
+        assert_eq!(rejected, Err(NonAscii { character: '\u{e9}', byte: 1 }));
+        let mut kept = s.clone();
+        kept.apply_policy(UnicodePolicy::Keep).unwrap();
+        assert_eq!(kept, s);
+    }
+
+    #[test]
+    fn string_modes() {
+        let mut s = YString::from("h\u{e9}\u{2192}");
+        s.pre_dec_in(StringMode::Chars).unwrap();
+        assert_eq!(s.to_string(), "h\u{e9}");
+        s.pre_dec_in(StringMode::Bytes).unwrap();
+        s.pre_dec_in(StringMode::Chars).unwrap();
+        assert_eq!(s.to_string(), "h");
+        let mut invalid = YString::from_bytes(b"a\xe9\x80");
+        invalid.pre_dec_in(StringMode::Chars).unwrap();
+        assert_eq!(invalid.as_slice(), b"a\xe9");
+        assert!(YString::default().pre_dec_in(StringMode::Chars).is_err());
+
+        let long = "\u{5b57}".repeat(400);
+        let mut cut = YString::from(long.as_str());
+        assert_eq!(cut.byte_len(), MAX_STRING_BYTES);
+        cut.fit_in(StringMode::Chars);
+        assert_eq!((cut.byte_len(), cut.char_len()), (1023, 341));
+        cut.fit_in(StringMode::Chars);
+        assert_eq!(cut.byte_len(), 1023);
+        assert_eq!(YString::from_bytes_in(long.as_bytes(), StringMode::Chars), cut);
+        assert_eq!(YString::from_bytes_in(long.as_bytes(), StringMode::Bytes).byte_len(), 1024);
+
+        // only what the limit cut is dropped
+        let mut partial = YString::from_bytes(b"a\xe5\xad");
+        partial.append_in(&YString::from_bytes(b"b\xe5"), StringMode::Chars);
+        assert_eq!(partial.as_slice(), b"a\xe5\xadb\xe5");
+        partial.duplicate_in(StringMode::Chars);
+        assert_eq!(partial.byte_len(), 10);
+        assert_eq!(YString::from_bytes_in(b"\xe5", StringMode::Chars).as_slice(), b"\xe5");
+    }
+
+    #[test]
+    fn capped_length() {
+        let mut s = YString::from("ab".repeat(400));
+        s += &YString::from("cd".repeat(200));
+        assert_eq!(s.byte_len(), MAX_STRING_BYTES);
+        assert_eq!(&s[798..802], b"abcd");
+        s.pre_inc();
+        assert_eq!(s.byte_len(), MAX_STRING_BYTES);
+
+        let mut s = YString::from("x".repeat(1000));
+        s.duplicate();
+        assert_eq!(s.byte_len(), MAX_STRING_BYTES);
+        assert_eq!(YString::from("y".repeat(2000)).byte_len(), MAX_STRING_BYTES);
+    }
+
+    #[test]
+    fn shared_until_changed() {
+        let original = YString::from("abc");
+        assert_ne!(original.clone().as_ptr(), original.as_ptr());
+        let mut copy = original.share();
+        assert_eq!(copy.as_ptr(), original.as_ptr());
+        copy.pre_inc();
+        assert_ne!(copy.as_ptr(), original.as_ptr());
+        assert_eq!((copy.to_string(), original.to_string()), ("abc ".into(), "abc".into()));
+
+        let mut cleared = original.share();
+        cleared.clear();
+        assert_eq!((cleared.byte_len(), original.byte_len()), (0, 3));
+    }
+}
@@ -0,0 +1,26 @@
+use yogi::*;
+
+fn main() -> anyhow::Result<()> {
+    println!("{:<24} {:>12} {:>12} {:>8}  result", "script", "compile", "run", "ticks");
+    let mut failed = 0;
+    for entry in corpus::entries()? {
+        let outcome = corpus::run(&entry)?;
+        let result = match &outcome.result {
+            Ok(()) => "ok".to_string(),
+            Err(failure) => {
+                failed += 1;
+                failure.to_string().lines().next().unwrap_or_default().to_string()
+            },
+        };
+        println!(
+            "{:<24} {:>12?} {:>12?} {:>8}  {}",
+            outcome.name,
+            outcome.compile_time,
+            outcome.run_time,
+            outcome.ticks,
+            result,
+        );
+    }
+    anyhow::ensure!(failed == 0, "{} scripts failed", failed);
+    Ok(())
+}
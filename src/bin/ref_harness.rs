@@ -91,6 +91,7 @@ fn setup_and_bench(
     let mut vm = IRMachine::from_ast(CodegenOptions {
         protect_locals: true,
         protect_globals: true,
+        ..Default::default()
     }, program);
     vm.set_next_line(start_line);
     let outer_iters = max_lines / 1000;
@@ -98,10 +99,7 @@ fn setup_and_bench(
     let mut elapsed_lines = 0;
 
     for vd in vars {
-        let ident = Ident {
-            name: vd.name,
-            global: vd.global,
-        };
+        let ident = Ident::new(&vd.name, vd.global);
         vm.set_ident(&ident, match vd.value {
             DataValue::Number(n) => Value::Num(n.parse()?),
             DataValue::String(s) => Value::Str(s.into()),
@@ -0,0 +1,90 @@
+use std::fs::read_to_string;
+use anyhow::{Context, Result, bail};
+use clap::clap_app;
+use yogi::{arith::Value, parser::{Ident, YololParser}, ir::{IRMachine, Register}};
+use yogi::diagnostics::SpannedError;
+
+/// `name=value`, where a value in quotes is a string and anything else a number.
+fn parse_global(arg: &str) -> Result<(Ident, Value)> {
+    let (name, value) = arg.split_once('=').context("expected NAME=VALUE")?;
+    let ident: Ident = name.trim().parse()?;
+    if !ident.global {
+        bail!("'{}' isn't a global, so needs a leading ':'", name);
+    }
+    let value = value.trim();
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(s) => Value::Str(s.into()),
+        None => Value::Num(value.parse().map_err(|e| anyhow::anyhow!("'{}': {}", value, e))?),
+    };
+    Ok((ident, value))
+}
+
+fn run() -> Result<()> {
+    let matches = clap_app!(yogi =>
+        (about: "Compiles and runs a Yolol script")
+        (@arg FILE: +required "The .yolol file to run")
+        (@arg TICKS: -t --ticks +takes_value "How many lines to run (default 20)")
+        (@arg GLOBAL: -g --global +takes_value +multiple number_of_values(1)
+            "Sets a global before running, like :a=1 or :s=\"text\"")
+        (@arg TRACE: --trace "Prints each line as it runs, with the globals it wrote")
+        (@arg DUMP: --dump "Prints every global once it's finished")
+    ).get_matches();
+
+    let path = matches.value_of("FILE").unwrap();
+    let source = read_to_string(path).with_context(|| format!("couldn't read {}", path))?;
+    let program = YololParser::default().parse(&source).map_err(|e| {
+        match e.downcast_ref::<SpannedError>() {
+            Some(spanned) => {
+                let snippet = spanned.span.snippet(&source);
+                anyhow::anyhow!("{}: {}\n{}", path, spanned, snippet)
+            },
+            None => e,
+        }
+    })?;
+    let mut machine = IRMachine::from_ast(Default::default(), program);
+    for arg in matches.values_of("GLOBAL").into_iter().flatten() {
+        let (ident, value) = parse_global(arg)?;
+        match (machine.ident_register(&ident), &value) {
+            (None, _) => eprintln!("warning: {} isn't used by the script", ident),
+            (Some(Register::Number(_)), Value::Str(_))
+            | (Some(Register::String(_)), Value::Num(_)) => {
+                bail!("{} can't hold {}", ident, value)
+            },
+            _ => machine.set_ident(&ident, value),
+        }
+    }
+    let ticks = matches
+        .value_of("TICKS")
+        .map(str::parse)
+        .transpose()
+        .context("--ticks must be a whole number")?
+        .unwrap_or(20);
+
+    if matches.is_present("TRACE") {
+        for _ in 0..ticks {
+            let result = machine.step_touched();
+            let written: Vec<_> = result.written
+                .iter()
+                .map(|ident| format!("{}={}", ident, machine.get_ident_value(ident)))
+                .collect();
+            let error = if result.errored { " (runtime error)" } else { "" };
+            println!("line {}{}: {}", result.line, error, written.join(" "));
+        }
+    } else {
+        machine.step_repeat(ticks);
+    }
+
+    if matches.is_present("DUMP") {
+        for (ident, value) in machine.idents() {
+            println!("{} = {}", ident, value);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use ir::IRMachine;
+use network::{Network, Scenario, ScenarioFailure};
+use parser::YololParser;
+use super::*;
+
+/// A script from `corpus/manifest.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    /// Relative to the corpus directory.
+    pub file: String,
+    pub source: String,
+    pub license: String,
+    /// A [`Scenario`] checking the script's results.
+    pub scenario: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub name: String,
+    pub compile_time: Duration,
+    pub run_time: Duration,
+    pub ticks: usize,
+    pub result: Result<(), ScenarioFailure>,
+}
+
+pub fn corpus_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("corpus")
+}
+
+pub fn entries() -> Result<Vec<Entry>> {
+    let path = corpus_dir().join("manifest.json");
+    let manifest =
+        fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(serde_json::from_str(&manifest)?)
+}
+
+/// Parses and compiles the script, then runs its scenario. Only errors if the script or
+/// scenario can't be loaded; a failed scenario is part of the [`Outcome`].
+pub fn run(entry: &Entry) -> Result<Outcome> {
+    let src = fs::read_to_string(corpus_dir().join(&entry.file))
+        .with_context(|| format!("reading {}", entry.file))?;
+    let scenario: Scenario =
+        entry.scenario.parse().with_context(|| format!("{} scenario", entry.name))?;
+
+    let start = Instant::now();
+    let program = YololParser::unrestricted()
+        .parse(&src)
+        .with_context(|| format!("parsing {}", entry.name))?;
+    let machine = IRMachine::from_ast(Default::default(), program);
+    let compile_time = start.elapsed();
+
+    let mut network = Network::new();
+    network.add_chip(machine);
+    let start = Instant::now();
+    let result = scenario.run(&mut network);
+    let run_time = start.elapsed();
+
+    Ok(Outcome {
+        name: entry.name.clone(),
+        compile_time,
+        run_time,
+        ticks: network.ticks(),
+        result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_passes() -> Result<()> {
+        let entries = entries()?;
+        assert!(!entries.is_empty());
+        for entry in entries.iter() {
+            assert!(!entry.license.is_empty(), "{} has no license", entry.name);
+            if let Err(failure) = run(entry)?.result {
+                panic!("{} failed: {}", entry.name, failure);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Something worth telling the chip's author about, found while compiling or running it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based, like the lines of a chip.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// A range of bytes in the source, with the 1-based line and column it starts at. Columns
+/// count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(source: &str, start: usize, end: usize) -> Self {
+        let before = &source[..start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            start,
+            end,
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    /// The line the span starts on, with carets under the part of it the span covers:
+    ///
+    /// ```text
+    /// 3 | a = = 1
+    ///   |     ^
+    /// ```
+    pub fn snippet(&self, source: &str) -> String {
+        let line_start = source[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[self.start..].find('\n').map_or(source.len(), |i| self.start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let number = self.line.to_string();
+        let margin = " ".repeat(number.len());
+
+        let mut out = format!("{} | {}\n{} | ", number, text, margin);
+        // tabs stay tabs, so the carets line up however wide they're shown
+        for c in source[line_start..self.start].chars() {
+            out.push(if c == '\t' { '\t' } else { ' ' });
+        }
+        let end = self.end.clamp(self.start, line_start + text.len());
+        let carets = source[self.start..end].chars().count().max(1);
+        out.push_str(&"^".repeat(carets));
+        out
+    }
+}
+
+/// An error at a [`Span`] of the source. Parsing fails with one of these inside the
+/// `anyhow::Error`, so it can be found with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("line {}, column {}: {message}", span.line, span.col)]
+pub struct SpannedError {
+    pub span: Span,
+    pub message: String,
+}
@@ -0,0 +1,78 @@
+//! Rewriting source in one consistent style.
+
+use anyhow::Result;
+use pest::Parser;
+use opt::SourceMap;
+use parser::{Line, Rule, YololParser};
+use super::*;
+
+/// Formats every line the way the parser displays code: keywords in lowercase, names as they
+/// were written, one space between statements and around operators, and only the brackets
+/// precedence needs. Comments are kept, after a single space, and lines stay where they are,
+/// so line numbers and gotos still match. Fails if `source` doesn't parse.
+///
+/// Spacing can push lines past the game's length limit, which [`YololParser::default`]
+/// enforces.
+pub fn format_source(source: &str) -> Result<String> {
+    let mut out = String::with_capacity(source.len());
+    let lines = <YololParser as Parser<_>>::parse(Rule::program, source)?
+        .filter(|pair| pair.as_rule() == Rule::line);
+    for (i, pair) in lines.enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let mut line = Line::parse(pair.clone().into_inner())?;
+        // names display lowercased, as they're matched
+        for stmt in line.stmts.iter_mut() {
+            stmt.for_each_ident_mut(&mut |ident| ident.name = ident.original_name().to_owned());
+        }
+        let start = pair.as_span().start();
+        let code_end = pair
+            .clone()
+            .into_inner()
+            .filter(|p| p.as_rule() == Rule::statement)
+            .last()
+            .map_or(start, |p| p.as_span().end());
+        let code = line.to_string();
+        let comment = pair.as_str()[code_end - start..].trim();
+        out.push_str(&code);
+        if !code.is_empty() && !comment.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(comment);
+    }
+    Ok(out)
+}
+
+/// [`format_source`], also mapping the result back to `source`.
+pub fn format_source_mapped(source: &str) -> Result<(String, SourceMap)> {
+    let formatted = format_source(source)?;
+    let map = SourceMap::between(&formatted, source)?;
+    Ok((formatted, map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_source() {
+        let src = "A=B+ 1*(2)   :X=\"a//b\"+:Y//keep   this\n\n\
+            IF a THEN b++ ELSE goto(1)END//assert: b>0\n//:Out (m/s) speed\n";
+        let formatted = format_source(src).unwrap();
+        assert_eq!(
+            formatted,
+            "A = B + 1 * 2 :X = \"a//b\" + :Y //keep   this\n\n\
+            if a then b++ else goto 1 end //assert: b>0\n//:Out (m/s) speed\n",
+        );
+        let parse = |s: &str| YololParser::unrestricted().parse(s).unwrap();
+        assert_eq!(parse(&formatted), parse(src));
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        assert!(format_source("a = = 1").is_err());
+
+        let (_, map) = format_source_mapped(src).unwrap();
+        let at = |line, col| opt::Position { line, col };
+        assert_eq!(map.original(at(1, 15)), Some(at(1, 14)));
+        assert_eq!(map.generated(at(1, 14)), Some(at(1, 15)));
+    }
+}
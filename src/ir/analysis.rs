@@ -0,0 +1,349 @@
+use cfg::{ControlFlowGraph, EdgeKind};
+use dfg::InstrRef;
+use pass::can_error;
+use super::*;
+
+/// A flag per register, indexed by [`IRMachine::flat_index`].
+pub(super) type Live = Vec<bool>;
+
+/// Which registers might be read later, at every point in the code.
+pub(super) struct Liveness<'a> {
+    pub(super) machine: &'a IRMachine,
+    pub(super) cfg: &'a ControlFlowGraph,
+    /// Registers the host or assertions can read at any point.
+    pub(super) always: Live,
+    /// What's live on entering each section.
+    pub(super) live_in: Vec<Live>,
+}
+
+impl<'a> Liveness<'a> {
+    pub(super) fn new(machine: &'a IRMachine, cfg: &'a ControlFlowGraph) -> Self {
+        let mut always = vec![false; machine.register_total()];
+        let assert_reads = machine.asserts
+            .iter()
+            .flat_map(|a| machine.sections[a.section.0].instrs.iter())
+            .flat_map(|i| i.reads());
+        let results = machine.asserts.iter().map(|a| a.result.into());
+        for reg in machine.idents.values().copied().chain(assert_reads).chain(results) {
+            always[machine.flat_index(reg)] = true;
+        }
+        let mut liveness = Liveness {
+            live_in: vec![always.clone(); machine.sections.len()],
+            machine,
+            cfg,
+            always,
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for section in (0..machine.sections.len()).rev() {
+                let live = liveness.walk(section, &mut |_, _, _, _| ());
+                if live != liveness.live_in[section] {
+                    liveness.live_in[section] = live;
+                    changed = true;
+                }
+            }
+        }
+        liveness
+    }
+
+    fn merge(&self, live: &mut Live, section: Section) {
+        live.iter_mut().zip(self.live_in[section.0].iter()).for_each(|(l, &s)| *l |= s);
+    }
+
+    /// What's live at the end of `section`, including the line register of a `goto`.
+    fn exit(&self, section: usize) -> Live {
+        let machine = self.machine;
+        let mut live = self.always.clone();
+        match machine.sections[section].success {
+            s if s == SUCCESS_NEEDS_FIXING => (),
+            SectionOrLine::Section(s) => self.merge(&mut live, s),
+            SectionOrLine::Line(n) => {
+                live[machine.flat_index(n.into())] = true;
+                for (to, kind) in self.cfg.successors(section) {
+                    if kind == EdgeKind::Goto {
+                        self.merge(&mut live, Section(to));
+                    }
+                }
+            },
+        }
+        live
+    }
+
+    /// What's live on entering `section`. `visit` sees every instruction, last first, with
+    /// what's live after it and whether it's dead.
+    pub(super) fn walk(
+        &self,
+        section: usize,
+        visit: &mut impl FnMut(usize, Instruction, &Live, bool),
+    ) -> Live {
+        let machine = self.machine;
+        let code = &machine.sections[section];
+        let mut live = self.exit(section);
+        for (index, &instr) in code.instrs.iter().enumerate().rev() {
+            match instr.get_section() {
+                // a jump's condition is read before it
+                Some(to) => {
+                    self.merge(&mut live, to);
+                    visit(index, instr, &live, false);
+                    for reg in instr.reads() {
+                        live[machine.flat_index(reg)] = true;
+                    }
+                },
+                None => {
+                    let out = machine.flat_index(instr.modifies().unwrap());
+                    let dead = !live[out] && !can_error(instr, machine.overflow);
+                    visit(index, instr, &live, dead);
+                    if dead {
+                        continue;
+                    }
+                    live[out] = false;
+                    for reg in instr.reads() {
+                        live[machine.flat_index(reg)] = true;
+                    }
+                },
+            }
+        }
+        live
+    }
+}
+
+/// Converts between [`Register`]s and [`IRMachine::flat_index`].
+#[derive(Debug, Clone, Copy)]
+struct Files {
+    numbers: usize,
+    strings: usize,
+}
+
+impl Files {
+    fn new(machine: &IRMachine) -> Self {
+        Files { numbers: machine.numbers.len(), strings: machine.strings.len() }
+    }
+
+    fn index(self, reg: Register) -> usize {
+        match reg {
+            Register::Number(n) => n,
+            Register::String(s) => self.numbers + s,
+            Register::Value(v) => self.numbers + self.strings + v,
+        }
+    }
+
+    fn register(self, index: usize) -> Register {
+        match index {
+            n if n < self.numbers => Register::Number(n),
+            s if s < self.numbers + self.strings => Register::String(s - self.numbers),
+            v => Register::Value(v - self.numbers - self.strings),
+        }
+    }
+
+    fn registers(self, flags: &[bool]) -> Vec<Register> {
+        flags.iter().enumerate().filter(|(_, &f)| f).map(|(i, _)| self.register(i)).collect()
+    }
+}
+
+/// Which registers might be read before they're next written, around every instruction, from
+/// [`IRMachine::live_registers`]. Variables and anything assertions read are always live, as
+/// the host can read them between any two lines.
+#[derive(Debug, Clone)]
+pub struct LiveRegisters {
+    files: Files,
+    /// Per section, what's live before each instruction and at the end.
+    before: Vec<Vec<Live>>,
+    /// Per section, what's live after each instruction, including where it can jump to.
+    after: Vec<Vec<Live>>,
+}
+
+impl LiveRegisters {
+    /// The registers live just before `at`. An index one past the section's last instruction
+    /// is the `goto` ending it.
+    pub fn live_in(&self, at: InstrRef) -> Vec<Register> {
+        self.files.registers(&self.before[at.section][at.index])
+    }
+
+    /// The registers live just after `at`, on any way it can go on.
+    pub fn live_out(&self, at: InstrRef) -> Vec<Register> {
+        self.files.registers(&self.after[at.section][at.index])
+    }
+
+    pub fn is_live_in(&self, at: InstrRef, reg: Register) -> bool {
+        self.before[at.section][at.index][self.files.index(reg)]
+    }
+
+    pub fn is_live_out(&self, at: InstrRef, reg: Register) -> bool {
+        self.after[at.section][at.index][self.files.index(reg)]
+    }
+}
+
+/// Which writes to a register might be the last before each instruction, from
+/// [`IRMachine::reaching_definitions`].
+#[derive(Debug, Clone)]
+pub struct ReachingDefinitions {
+    files: Files,
+    /// Every instruction writing a register, in order, and the register it writes.
+    defs: Vec<(InstrRef, usize)>,
+    /// Per section, which definitions reach each instruction and the end, by index into
+    /// `defs`, followed by a flag per register for its value from before the chip ran.
+    before: Vec<Vec<Vec<bool>>>,
+}
+
+impl ReachingDefinitions {
+    /// The instructions whose write to `reg` might still be there just before `at`, in order.
+    /// An index one past the section's last instruction is the `goto` ending it.
+    pub fn definitions(&self, at: InstrRef, reg: Register) -> Vec<InstrRef> {
+        let index = self.files.index(reg);
+        let reaching = &self.before[at.section][at.index];
+        self.defs
+            .iter()
+            .zip(reaching)
+            .filter(|&(&(_, r), &reaches)| reaches && r == index)
+            .map(|(&(def, _), _)| def)
+            .collect()
+    }
+
+    /// Whether `reg` might still hold what it did before the chip first ran, just before `at`.
+    /// For a variable's register, the host might also have changed it between lines.
+    pub fn reaches_from_start(&self, at: InstrRef, reg: Register) -> bool {
+        self.before[at.section][at.index][self.defs.len() + self.files.index(reg)]
+    }
+}
+
+impl IRMachine {
+    /// Which registers might be read later, around every instruction.
+    pub fn live_registers(&self) -> LiveRegisters {
+        let cfg = self.control_flow_graph();
+        let liveness = Liveness::new(self, &cfg);
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        for (section, code) in self.sections.iter().enumerate() {
+            let len = code.instrs.len();
+            let (mut ins, mut outs) = (vec![Vec::new(); len + 1], vec![Vec::new(); len]);
+            ins[len] = liveness.exit(section);
+            liveness.walk(section, &mut |index, instr, live, dead| {
+                outs[index] = live.clone();
+                // a dead write changes nothing
+                let mut live_in = live.clone();
+                if !dead {
+                    if let Some(out) = instr.modifies() {
+                        live_in[self.flat_index(out)] = false;
+                    }
+                    instr.reads().into_iter().for_each(|r| live_in[self.flat_index(r)] = true);
+                }
+                ins[index] = live_in;
+            });
+            before.push(ins);
+            after.push(outs);
+        }
+        LiveRegisters { files: Files::new(self), before, after }
+    }
+
+    /// Which writes might reach every instruction, following every way control can go,
+    /// including around from the last line to the first.
+    pub fn reaching_definitions(&self) -> ReachingDefinitions {
+        let cfg = self.control_flow_graph();
+        let files = Files::new(self);
+        let mut defs = Vec::new();
+        for (section, code) in self.sections.iter().enumerate() {
+            for (index, instr) in code.instrs.iter().enumerate() {
+                if let Some(reg) = instr.modifies() {
+                    defs.push((InstrRef { section, index }, self.flat_index(reg)));
+                }
+            }
+        }
+        let registers = self.register_total();
+        let mut kills = vec![Vec::new(); registers];
+        for (i, &(_, reg)) in defs.iter().enumerate() {
+            kills[reg].push(i);
+        }
+
+        // the flow into each section, grown until nothing changes
+        let mut entry = vec![vec![false; defs.len() + registers]; self.sections.len()];
+        entry[self.lines[0].0][defs.len()..].iter_mut().for_each(|f| *f = true);
+        let mut before = Vec::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            before.clear();
+            for (section, code) in self.sections.iter().enumerate() {
+                let mut reaching = entry[section].clone();
+                let mut points = Vec::with_capacity(code.instrs.len() + 1);
+                let mut flow = |to: usize, reaching: &Vec<bool>| {
+                    for (e, &r) in entry[to].iter_mut().zip(reaching) {
+                        changed |= r && !*e;
+                        *e |= r;
+                    }
+                };
+                let mut def = defs.partition_point(|(at, _)| at.section < section);
+                for &instr in code.instrs.iter() {
+                    points.push(reaching.clone());
+                    match instr.get_section() {
+                        Some(to) => flow(to.0, &reaching),
+                        None => if let Some(reg) = instr.modifies() {
+                            let reg = self.flat_index(reg);
+                            kills[reg].iter().for_each(|&k| reaching[k] = false);
+                            reaching[defs.len() + reg] = false;
+                            reaching[def] = true;
+                            def += 1;
+                        },
+                    }
+                }
+                match code.success {
+                    s if s == SUCCESS_NEEDS_FIXING => (),
+                    SectionOrLine::Section(s) => flow(s.0, &reaching),
+                    SectionOrLine::Line(_) => {
+                        for (to, kind) in cfg.successors(section) {
+                            if kind == EdgeKind::Goto {
+                                flow(to, &reaching);
+                            }
+                        }
+                    },
+                }
+                points.push(reaching);
+                before.push(points);
+            }
+        }
+        ReachingDefinitions { files, defs, before }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn dataflow() {
+        let src = "a=:x*2 :y=a\nif :x then a=1 end :z=a goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let options = CodegenOptions { protect_locals: true, ..Default::default() };
+        let machine = IRMachine::from_ast(options, program);
+        let dfg = machine.data_flow_graph();
+        let a = machine.ident_register(&Ident::local("a")).unwrap();
+
+        // `:z=a` sees either write to `a`, or what it was before if line 1 failed first
+        let reaching = machine.reaching_definitions();
+        let read = *dfg.uses(a).last().unwrap();
+        assert_eq!(reaching.definitions(read, a), dfg.defs(a));
+        assert!(reaching.reaches_from_start(read, a));
+        // `:y=a` only runs once line 1 has written `a`
+        let read = dfg.uses(a)[0];
+        assert_eq!(reaching.definitions(read, a), &dfg.defs(a)[..1]);
+        assert!(!reaching.reaches_from_start(read, a));
+
+        // variables are always live, and the branch condition only until it's checked
+        let live = machine.live_registers();
+        let (branch, cond) = machine.sections
+            .iter()
+            .enumerate()
+            .find_map(|(section, code)| code.instrs.iter().enumerate().find_map(|(index, i)| {
+                match *i {
+                    Instruction::JumpSectionIf(_, cond) => {
+                        Some((InstrRef { section, index }, AnyReg::from(cond).into()))
+                    },
+                    _ => None,
+                }
+            }))
+            .unwrap();
+        assert!(live.is_live_in(branch, cond));
+        assert!(!live.is_live_out(branch, cond));
+        assert!(live.live_in(branch).contains(&a) && live.live_out(branch).contains(&a));
+    }
+}
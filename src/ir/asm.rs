@@ -0,0 +1,839 @@
+//! Building an [`IRMachine`] instruction by instruction, for targeting it without going through
+//! Yolol source, and a text format for writing such programs by hand.
+//!
+//! Like compiled code, an instruction which can fail moves on to the next line when it does.
+
+use anyhow::{bail, ensure, Context, Result};
+use super::*;
+
+/// A number register of a [`ProgramBuilder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Num(NumReg);
+
+/// A string register of a [`ProgramBuilder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Str(StrReg);
+
+/// A value register of a [`ProgramBuilder`], holding either a number or a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Val(ValReg);
+
+/// A place to jump to, see [`ProgramBuilder::place`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(Section);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, From)]
+pub enum Reg {
+    Num(Num),
+    Str(Str),
+    Val(Val),
+}
+
+impl From<Reg> for AnyReg {
+    fn from(reg: Reg) -> Self {
+        match reg {
+            Reg::Num(Num(n)) => n.into(),
+            Reg::Str(Str(s)) => s.into(),
+            Reg::Val(Val(v)) => v.into(),
+        }
+    }
+}
+
+/// Stands in for the start of the next line until [`ProgramBuilder::build`] knows where it is.
+const NEXT_LINE: Section = Section(!1);
+
+/// Builds an [`IRMachine`] from instructions. Code goes in lines, started with
+/// [`ProgramBuilder::line`], and each line runs on to the next unless it jumps elsewhere.
+///
+/// Binary operations write to `out` without changing their operands, unless `out` is one of
+/// them, and leave it alone if they fail.
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    sections: Vec<SectionCode>,
+    /// The line each section was placed on, or `None` for labels not placed yet.
+    placed: Vec<Option<usize>>,
+    lines: Vec<Section>,
+    /// Where instructions go, once a line has started.
+    current: Option<Section>,
+    numbers: Vec<Number>,
+    strings: Vec<YString>,
+    values: Vec<Value>,
+    idents: AHashMap<Ident, AnyReg>,
+    /// For binary operations writing to their right hand side.
+    scratch: (Option<NumReg>, Option<StrReg>, Option<ValReg>),
+    goto_policy: GotoPolicy,
+    compat: Compat,
+    overflow: OverflowPolicy,
+    string_mode: StringMode,
+    /// [`MAX_STRING_BYTES`] unless set.
+    max_string_len: Option<usize>,
+    unicode: UnicodePolicy,
+    /// The first misuse of the builder, reported by [`ProgramBuilder::build`].
+    error: Option<String>,
+}
+
+macro_rules! unary {
+    ($($(#[$doc:meta])* $name:ident($reg:ident) => $instr:ident;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(&mut self, reg: $reg) {
+                self.emit(Instruction::$instr(reg.0));
+            }
+        )*
+    };
+}
+
+macro_rules! convert {
+    ($($(#[$doc:meta])* $name:ident($from:ident, $to:ident) => $instr:ident;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(&mut self, from: $from, to: $to) {
+                self.emit(Instruction::$instr(from.0, to.0));
+            }
+        )*
+    };
+}
+
+macro_rules! binary {
+    ($(
+        $name:ident($reg:ident, $scratch:ident, $copy:ident, $commutative:literal) => $instr:ident;
+    )*) => {
+        $(
+            pub fn $name(&mut self, l: $reg, r: $reg, out: $reg) {
+                if out == l {
+                    self.emit(Instruction::$instr(out.0, r.0));
+                } else if out == r && $commutative {
+                    self.emit(Instruction::$instr(out.0, l.0));
+                } else if out == r || self.can_fail(Instruction::$instr(l.0, r.0)) {
+                    // so `out` is left alone if it fails
+                    let temp = self.$scratch();
+                    self.emit(Instruction::$copy(l.0, temp));
+                    self.emit(Instruction::$instr(temp, r.0));
+                    self.emit(Instruction::$copy(temp, out.0));
+                } else {
+                    self.emit(Instruction::$copy(l.0, out.0));
+                    self.emit(Instruction::$instr(out.0, r.0));
+                }
+            }
+        )*
+    };
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_goto_policy(&mut self, goto_policy: GotoPolicy) {
+        self.goto_policy = goto_policy;
+    }
+
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    pub fn set_overflow(&mut self, overflow: OverflowPolicy) {
+        self.overflow = overflow;
+    }
+
+    pub fn set_string_mode(&mut self, string_mode: StringMode) {
+        self.string_mode = string_mode;
+    }
+
+    pub fn set_max_string_len(&mut self, max_len: usize) {
+        self.max_string_len = Some(max_len);
+    }
+
+    pub fn set_unicode(&mut self, unicode: UnicodePolicy) {
+        self.unicode = unicode;
+    }
+
+    pub fn new_num(&mut self, value: Number) -> Num {
+        self.numbers.push(value);
+        Num(NumReg(self.numbers.len() - 1))
+    }
+
+    pub fn new_str(&mut self, value: impl Into<YString>) -> Str {
+        self.strings.push(value.into());
+        Str(StrReg(self.strings.len() - 1))
+    }
+
+    pub fn new_val(&mut self, value: impl Into<Value>) -> Val {
+        self.values.push(value.into());
+        Val(ValReg(self.values.len() - 1))
+    }
+
+    /// Makes `reg` the variable `ident`, so it can be read with [`IRMachine::get_ident_value`]
+    /// and the like.
+    pub fn bind(&mut self, ident: Ident, reg: impl Into<Reg>) {
+        self.idents.insert(ident, reg.into().into());
+    }
+
+    fn scratch_num(&mut self) -> NumReg {
+        match self.scratch.0 {
+            Some(n) => n,
+            None => {
+                let reg = self.new_num(Number::default()).0;
+                *self.scratch.0.insert(reg)
+            },
+        }
+    }
+
+    fn scratch_str(&mut self) -> StrReg {
+        match self.scratch.1 {
+            Some(s) => s,
+            None => {
+                let reg = self.new_str("").0;
+                *self.scratch.1.insert(reg)
+            },
+        }
+    }
+
+    fn scratch_val(&mut self) -> ValReg {
+        match self.scratch.2 {
+            Some(v) => v,
+            None => {
+                let reg = self.new_val(Value::default()).0;
+                *self.scratch.2.insert(reg)
+            },
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.error.get_or_insert(message);
+    }
+
+    fn new_section(&mut self, line_start: bool) -> Section {
+        self.sections.push(SectionCode {
+            instrs: Vec::new(),
+            line_start,
+            success: SUCCESS_NEEDS_FIXING,
+        });
+        self.placed.push(None);
+        Section(self.sections.len() - 1)
+    }
+
+    /// Ends the current section by going on to `next`, and carries on in `then`.
+    fn end_section(&mut self, next: SectionOrLine, then: Section) {
+        if let Some(current) = self.current {
+            self.sections[current.0].success = next;
+        }
+        self.placed[then.0] = Some(self.lines.len().saturating_sub(1));
+        self.current = Some(then);
+    }
+
+    fn emit(&mut self, instr: Instruction) {
+        let Some(current) = self.current else {
+            self.fail(format!("'{}' is before the first line", instr));
+            return;
+        };
+        let can_fail = self.can_fail(instr);
+        let code = &mut self.sections[current.0].instrs;
+        code.push(instr);
+        if can_fail {
+            code.push(Instruction::JumpIfError(NEXT_LINE));
+        }
+    }
+
+    fn can_fail(&self, instr: Instruction) -> bool {
+        pass::can_error(instr, self.overflow)
+    }
+
+    /// Starts the next line, which the one before runs on to.
+    pub fn line(&mut self) {
+        let start = self.new_section(true);
+        self.lines.push(start);
+        self.end_section(start.into(), start);
+    }
+
+    pub fn label(&mut self) -> Label {
+        Label(self.new_section(false))
+    }
+
+    /// Carries on from here at `label`, which the code before runs on to. A label can only be
+    /// placed once. Jumping to a label on another line carries on there, without ending the line
+    /// being run.
+    pub fn place(&mut self, label: Label) {
+        if label.0.0 >= self.placed.len() {
+            self.fail(format!("{} is from another builder", label.0));
+        } else if self.placed[label.0.0].is_some() {
+            self.fail(format!("{} was placed twice", label.0));
+        } else if self.current.is_none() {
+            self.fail(format!("{} is before the first line", label.0));
+        } else {
+            self.end_section(label.0.into(), label.0);
+        }
+    }
+
+    /// Jumps to `label`. Code after this can only be reached through another label.
+    pub fn jump(&mut self, label: Label) {
+        let unreachable = self.new_section(false);
+        self.end_section(label.0.into(), unreachable);
+    }
+
+    pub fn jump_if(&mut self, condition: Num, label: Label) {
+        self.emit(Instruction::JumpSectionIf(label.0, condition.0));
+    }
+
+    /// Goes to the 1-based line in `line`, at the end of this one.
+    pub fn goto_line(&mut self, line: Num) {
+        let unreachable = self.new_section(false);
+        self.end_section(SectionOrLine::Line(line.0), unreachable);
+    }
+
+    binary! {
+        add_n(Num, scratch_num, CopyNum, true) => AddNum;
+        sub_n(Num, scratch_num, CopyNum, false) => SubNum;
+        mul(Num, scratch_num, CopyNum, true) => Mul;
+        div(Num, scratch_num, CopyNum, false) => Div;
+        rem(Num, scratch_num, CopyNum, false) => Rem;
+        pow(Num, scratch_num, CopyNum, false) => Pow;
+        and(Num, scratch_num, CopyNum, true) => And;
+        or(Num, scratch_num, CopyNum, true) => Or;
+        add_s(Str, scratch_str, CopyStr, false) => AddStr;
+        sub_s(Str, scratch_str, CopyStr, false) => SubStr;
+        add_v(Val, scratch_val, CopyVal, false) => AddVal;
+        sub_v(Val, scratch_val, CopyVal, false) => SubVal;
+    }
+
+    /// Sets `out` to whether `l == r`.
+    pub fn eq(&mut self, l: Val, r: Val, out: Num) {
+        self.emit(Instruction::Eq(l.0, r.0, out.0));
+    }
+
+    /// Sets `out` to whether `l <= r`.
+    pub fn le(&mut self, l: Val, r: Val, out: Num) {
+        self.emit(Instruction::Le(l.0, r.0, out.0));
+    }
+
+    /// Sets `out` to whether `l < r`.
+    pub fn lt(&mut self, l: Val, r: Val, out: Num) {
+        self.emit(Instruction::Lt(l.0, r.0, out.0));
+    }
+
+    /// Sets `out` to `if_true` if `cond` is truthy, or `if_false` otherwise.
+    pub fn select(&mut self, cond: Num, if_true: Num, if_false: Num, out: Num) {
+        self.emit(Instruction::SelectNum(cond.0, if_true.0, if_false.0, out.0));
+    }
+
+    unary! {
+        neg(Num) => Neg;
+        not_n(Num) => NotNum;
+        /// Sets the register to 1 if it isn't 0.
+        truthy_n(Num) => IsTruthyNum;
+        abs(Num) => Abs;
+        fact(Num) => Fact;
+        sqrt(Num) => Sqrt;
+        sin(Num) => Sin;
+        cos(Num) => Cos;
+        tan(Num) => Tan;
+        asin(Num) => Asin;
+        acos(Num) => Acos;
+        atan(Num) => Atan;
+        exp(Num) => Exp;
+        ln(Num) => Ln;
+        log10(Num) => Log10;
+        inc_n(Num) => IncNum;
+        inc_s(Str) => IncStr;
+        inc_v(Val) => IncVal;
+        dec_n(Num) => DecNum;
+        dec_s(Str) => DecStr;
+        dec_v(Val) => DecVal;
+    }
+
+    convert! {
+        copy_n(Num, Num) => CopyNum;
+        copy_s(Str, Str) => CopyStr;
+        copy_v(Val, Val) => CopyVal;
+        num_to_val(Num, Val) => ValueifyNum;
+        str_to_val(Str, Val) => ValueifyStr;
+        /// Fails if the value is a string.
+        val_to_num(Val, Num) => NumberifyVal;
+        num_to_str(Num, Str) => StringifyNum;
+        val_to_str(Val, Str) => StringifyVal;
+        truthy_v(Val, Num) => IsTruthyVal;
+        not_v(Val, Num) => NotVal;
+    }
+
+    /// Checks the program is complete and only uses handles from this builder, and makes a
+    /// machine to run it, starting on line 1.
+    pub fn build(mut self) -> Result<IRMachine> {
+        if let Some(error) = self.error.take() {
+            bail!(error);
+        }
+        ensure!(!self.lines.is_empty(), "the program has no lines");
+        if let Some(label) = self.placed.iter().position(Option::is_none) {
+            bail!("{} was never placed", Section(label));
+        }
+        // the last line wraps around to the first
+        if let Some(current) = self.current {
+            self.sections[current.0].success = self.lines[0].into();
+        }
+
+        let lines = self.lines.len();
+        for (code, line) in self.sections.iter_mut().zip(self.placed) {
+            let next_line = self.lines[(line.unwrap() + 1) % lines];
+            for instr in code.instrs.iter_mut() {
+                if let Instruction::JumpIfError(s) = instr {
+                    if *s == NEXT_LINE {
+                        *s = next_line;
+                    }
+                }
+            }
+        }
+
+        // handles from another builder could point past the end of this one's
+        let in_range = |reg: AnyReg| match reg {
+            AnyReg::Num(n) => n.0 < self.numbers.len(),
+            AnyReg::Str(s) => s.0 < self.strings.len(),
+            AnyReg::Val(v) => v.0 < self.values.len(),
+        };
+        let sections = self.sections.len();
+        for code in &self.sections {
+            for instr in &code.instrs {
+                ensure!(
+                    instr.relevant().into_iter().all(in_range),
+                    "'{}' uses a register from another builder",
+                    instr,
+                );
+                ensure!(
+                    instr.get_section().is_none_or(|s| s.0 < sections),
+                    "'{}' jumps to a label from another builder",
+                    instr,
+                );
+            }
+            match code.success {
+                SectionOrLine::Line(n) if !in_range(n.into()) =>
+                    bail!("goto_line uses a register from another builder"),
+                SectionOrLine::Section(s) if s.0 >= sections =>
+                    bail!("jump goes to a label from another builder"),
+                _ => (),
+            }
+        }
+        if let Some((ident, _)) = self.idents.iter().find(|(_, reg)| !in_range(**reg)) {
+            bail!("{} is bound to a register from another builder", ident);
+        }
+
+        let max_string_len =
+            self.max_string_len.map_or(MAX_STRING_BYTES, |l| l.min(MAX_STRING_BYTES));
+        let mut strings = self.strings;
+        for s in strings.iter_mut() {
+            s.truncate_in(max_string_len, self.string_mode);
+        }
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            if let Value::Str(s) = v {
+                s.truncate_in(max_string_len, self.string_mode);
+            }
+        }
+
+        Ok(IRMachine {
+            sections: Arc::new(self.sections),
+            current_sect: self.lines[0],
+            line_start: self.lines[0],
+            lines: self.lines,
+            runtime_err: false.into(),
+            goto_policy: self.goto_policy,
+            compat: self.compat,
+            overflow: self.overflow,
+            string_mode: self.string_mode,
+            max_string_len,
+            unicode: self.unicode,
+            asserts: Vec::new(),
+            annotations: Vec::new(),
+            diagnostics: Vec::new(),
+            profile: None,
+            provenance: Default::default(),
+            dynamic_gotos: AHashMap::new(),
+            goto_events: None,
+            divergences: None,
+            numbers: self.numbers.into_iter().map(AtomicRefCell::new).collect(),
+            strings: strings.into_iter().map(AtomicRefCell::new).collect(),
+            values: values.into_iter().map(AtomicRefCell::new).collect(),
+            idents: self.idents,
+            breakpoints: Vec::new(),
+            paused: None,
+            watches: Vec::new(),
+            devices: Vec::new(),
+            pending: Vec::new(),
+            #[cfg(feature = "trace-hooks")]
+            trace_hook: None,
+            #[cfg(feature = "opcode-counts")]
+            opcode_counts: vec![0; instr::OPCODE_NAMES.len()],
+        })
+    }
+}
+
+/// Parses a number or a `"string"` without escapes. [`Scenario`](crate::network::Scenario)s use
+/// it too.
+pub(crate) fn parse_literal(text: &str) -> Result<Value> {
+    if let Some(s) = text.strip_prefix('"') {
+        let s = s.strip_suffix('"').with_context(|| format!("unterminated string {}", text))?;
+        ensure!(!s.contains('"'), "strings can't contain '\"'");
+        Ok(Value::Str(s.into()))
+    } else {
+        ensure!(!text.is_empty(), "missing value");
+        text.parse::<Number>()
+            .map(Value::Num)
+            .map_err(|e| anyhow::anyhow!("bad number '{}': {}", text, e))
+    }
+}
+
+#[derive(Default)]
+struct Assembler {
+    builder: ProgramBuilder,
+    regs: AHashMap<String, Reg>,
+    labels: AHashMap<String, Label>,
+}
+
+impl Assembler {
+    fn reg(&self, name: &str) -> Result<Reg> {
+        self.regs.get(name).copied().with_context(|| format!("no register called '{}'", name))
+    }
+
+    fn num(&self, name: &str) -> Result<Num> {
+        match self.reg(name)? {
+            Reg::Num(n) => Ok(n),
+            _ => bail!("'{}' isn't a number register", name),
+        }
+    }
+
+    fn str(&self, name: &str) -> Result<Str> {
+        match self.reg(name)? {
+            Reg::Str(s) => Ok(s),
+            _ => bail!("'{}' isn't a string register", name),
+        }
+    }
+
+    fn val(&self, name: &str) -> Result<Val> {
+        match self.reg(name)? {
+            Reg::Val(v) => Ok(v),
+            _ => bail!("'{}' isn't a value register", name),
+        }
+    }
+
+    fn label(&mut self, name: &str) -> Label {
+        match self.labels.get(name) {
+            Some(&label) => label,
+            None => {
+                let label = self.builder.label();
+                self.labels.insert(name.to_string(), label);
+                label
+            },
+        }
+    }
+
+    fn declare(&mut self, kind: &str, rest: &str) -> Result<()> {
+        let (name, init) = match rest.split_once('=') {
+            Some((name, init)) => (name.trim(), Some(parse_literal(init.trim())?)),
+            None => (rest.trim(), None),
+        };
+        ensure!(
+            !name.is_empty() && !name.contains(char::is_whitespace),
+            "bad register name '{}'",
+            name,
+        );
+        let reg = match (kind, init) {
+            ("num", None) => self.builder.new_num(Number::default()).into(),
+            ("num", Some(Value::Num(n))) => self.builder.new_num(n).into(),
+            ("str", None) => self.builder.new_str("").into(),
+            ("str", Some(Value::Str(s))) => self.builder.new_str(s).into(),
+            ("val", init) => self.builder.new_val(init.unwrap_or_default()).into(),
+            _ => bail!("can't start a {} register as {}", kind, rest.trim()),
+        };
+        ensure!(self.regs.insert(name.to_string(), reg).is_none(), "'{}' was declared twice", name);
+        Ok(())
+    }
+
+    fn line(&mut self, text: &str) -> Result<()> {
+        if let Some(label) = text.strip_suffix(':') {
+            let label = self.label(label.trim());
+            self.builder.place(label);
+            return Ok(());
+        }
+        let (op, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if matches!(op, "num" | "str" | "val") {
+            return self.declare(op, rest);
+        }
+        let args: Vec<_> = rest.split([',', ' ', '\t']).filter(|a| !a.is_empty()).collect();
+        match (op, args.as_slice()) {
+            ("line", []) => self.builder.line(),
+            ("var", [ident, reg]) => {
+                let global = ident.starts_with(':');
+                let ident = Ident::new(ident.trim_start_matches(':'), global);
+                let reg = self.reg(reg)?;
+                self.builder.bind(ident, reg);
+            },
+            ("jump", [label]) => {
+                let label = self.label(label);
+                self.builder.jump(label);
+            },
+            ("jump_if", [cond, label]) => {
+                let (cond, label) = (self.num(cond)?, self.label(label));
+                self.builder.jump_if(cond, label);
+            },
+            ("goto_line", [n]) => self.builder.goto_line(self.num(n)?),
+            _ => return self.instruction(op, &args),
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, op: &str, args: &[&str]) -> Result<()> {
+        type B = ProgramBuilder;
+        match op {
+            "add_n" => self.call3(B::add_n, args),
+            "sub_n" => self.call3(B::sub_n, args),
+            "mul" => self.call3(B::mul, args),
+            "div" => self.call3(B::div, args),
+            "rem" => self.call3(B::rem, args),
+            "pow" => self.call3(B::pow, args),
+            "and" => self.call3(B::and, args),
+            "or" => self.call3(B::or, args),
+            "add_s" => self.call3(B::add_s, args),
+            "sub_s" => self.call3(B::sub_s, args),
+            "add_v" => self.call3(B::add_v, args),
+            "sub_v" => self.call3(B::sub_v, args),
+            "eq" => self.call3(B::eq, args),
+            "le" => self.call3(B::le, args),
+            "lt" => self.call3(B::lt, args),
+            "select" => self.call4(B::select, args),
+            "neg" => self.call1(B::neg, args),
+            "not_n" => self.call1(B::not_n, args),
+            "truthy_n" => self.call1(B::truthy_n, args),
+            "abs" => self.call1(B::abs, args),
+            "fact" => self.call1(B::fact, args),
+            "sqrt" => self.call1(B::sqrt, args),
+            "sin" => self.call1(B::sin, args),
+            "cos" => self.call1(B::cos, args),
+            "tan" => self.call1(B::tan, args),
+            "asin" => self.call1(B::asin, args),
+            "acos" => self.call1(B::acos, args),
+            "atan" => self.call1(B::atan, args),
+            "inc_n" => self.call1(B::inc_n, args),
+            "inc_s" => self.call1(B::inc_s, args),
+            "inc_v" => self.call1(B::inc_v, args),
+            "dec_n" => self.call1(B::dec_n, args),
+            "dec_s" => self.call1(B::dec_s, args),
+            "dec_v" => self.call1(B::dec_v, args),
+            "copy_n" => self.call2(B::copy_n, args),
+            "copy_s" => self.call2(B::copy_s, args),
+            "copy_v" => self.call2(B::copy_v, args),
+            "num_to_val" => self.call2(B::num_to_val, args),
+            "str_to_val" => self.call2(B::str_to_val, args),
+            "val_to_num" => self.call2(B::val_to_num, args),
+            "num_to_str" => self.call2(B::num_to_str, args),
+            "val_to_str" => self.call2(B::val_to_str, args),
+            "truthy_v" => self.call2(B::truthy_v, args),
+            "not_v" => self.call2(B::not_v, args),
+            _ => bail!("unknown instruction '{}'", op),
+        }
+    }
+
+    fn call1<A: Operand>(&mut self, f: fn(&mut ProgramBuilder, A), args: &[&str]) -> Result<()> {
+        let [a] = args else { bail!("expected 1 operand, found {}", args.len()) };
+        let a = A::lookup(self, a)?;
+        f(&mut self.builder, a);
+        Ok(())
+    }
+
+    fn call2<A: Operand, B: Operand>(
+        &mut self,
+        f: fn(&mut ProgramBuilder, A, B),
+        args: &[&str],
+    ) -> Result<()> {
+        let [a, b] = args else { bail!("expected 2 operands, found {}", args.len()) };
+        let (a, b) = (A::lookup(self, a)?, B::lookup(self, b)?);
+        f(&mut self.builder, a, b);
+        Ok(())
+    }
+
+    fn call3<A: Operand, B: Operand, C: Operand>(
+        &mut self,
+        f: fn(&mut ProgramBuilder, A, B, C),
+        args: &[&str],
+    ) -> Result<()> {
+        let [a, b, c] = args else { bail!("expected 3 operands, found {}", args.len()) };
+        let (a, b, c) = (A::lookup(self, a)?, B::lookup(self, b)?, C::lookup(self, c)?);
+        f(&mut self.builder, a, b, c);
+        Ok(())
+    }
+
+    fn call4<A: Operand, B: Operand, C: Operand, D: Operand>(
+        &mut self,
+        f: fn(&mut ProgramBuilder, A, B, C, D),
+        args: &[&str],
+    ) -> Result<()> {
+        let [a, b, c, d] = args else { bail!("expected 4 operands, found {}", args.len()) };
+        let (a, b) = (A::lookup(self, a)?, B::lookup(self, b)?);
+        let (c, d) = (C::lookup(self, c)?, D::lookup(self, d)?);
+        f(&mut self.builder, a, b, c, d);
+        Ok(())
+    }
+}
+
+trait Operand: Sized {
+    fn lookup(asm: &Assembler, name: &str) -> Result<Self>;
+}
+
+impl Operand for Num {
+    fn lookup(asm: &Assembler, name: &str) -> Result<Self> {
+        asm.num(name)
+    }
+}
+
+impl Operand for Str {
+    fn lookup(asm: &Assembler, name: &str) -> Result<Self> {
+        asm.str(name)
+    }
+}
+
+impl Operand for Val {
+    fn lookup(asm: &Assembler, name: &str) -> Result<Self> {
+        asm.val(name)
+    }
+}
+
+/// Assembles a program written one instruction per line, named and ordered like the methods of
+/// [`ProgramBuilder`] with their operands separated by commas or spaces. Besides those:
+///
+/// - `line` starts the next line, and must come before any code
+/// - `num name`, `str name` or `val name` declares a register, optionally starting with
+///   `= 1.5` or `= "text"`
+/// - `var :name reg` makes a register the given variable
+/// - `name:` places a label, which `jump` and `jump_if` can refer to anywhere
+/// - `#` starts a comment
+pub fn assemble(src: &str) -> Result<IRMachine> {
+    let mut asm = Assembler::default();
+    for (i, text) in src.lines().enumerate() {
+        let text = text.split('#').next().unwrap().trim();
+        if !text.is_empty() {
+            asm.line(text).with_context(|| format!("on line {}: {}", i + 1, text))?;
+        }
+    }
+    asm.builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_text() {
+        let mut machine = assemble(r#"
+            num one = 1
+            num two = 2
+            num zero
+            num n = 10
+            num r
+            str s = "ab"
+            str t = "b"
+            var :n n
+            var :r r
+            var :s s
+
+            line
+                sub_n one, n, n     # writes its right hand side
+                sub_s s, t, s
+                div one, zero, r    # fails, skipping the rest of the line
+                copy_n two, r
+            line
+                jump_if one, skip
+                copy_n two, r
+            skip:
+                add_n r, two, r
+            line
+                goto_line two
+        "#).unwrap();
+
+        machine.step_repeat(2);
+        assert_eq!(machine.get_ident_value(&Ident::global("n")), Value::Num((-9).into()));
+        assert_eq!(machine.get_ident_value(&Ident::global("s")), Value::Str("a".into()));
+        assert_eq!(machine.get_ident_value(&Ident::global("r")), Value::Num(2.into()));
+        machine.step();
+        assert_eq!(machine.get_current_line(), Some(1));
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("r")), Value::Num(4.into()));
+
+        let src = "num c = 0\nnum a = 1\nnum b = 2\nnum r\nvar :r r\nline\nselect c, a, b, r";
+        let mut machine = assemble(src).unwrap();
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("r")), Value::Num(2.into()));
+    }
+
+    #[test]
+    fn builder() {
+        // counts down from 3, then stops on the second line
+        let mut b = ProgramBuilder::new();
+        let (n, one, two) = (b.new_num(3.into()), b.new_num(1.into()), b.new_num(2.into()));
+        let (flag, out) = (b.new_num(Number::default()), b.new_val(Value::Str("counting".into())));
+        b.bind(Ident::global("out"), out);
+        let done = b.label();
+        b.line();
+        b.sub_n(n, one, n);
+        b.copy_n(n, flag);
+        b.not_n(flag);
+        b.jump_if(flag, done);
+        b.goto_line(one);
+        b.place(done);
+        b.num_to_val(n, out);
+        b.line();
+        b.goto_line(two);
+        let mut machine = b.build().unwrap();
+
+        machine.step_repeat(2);
+        assert_eq!(machine.get_ident_value(&Ident::global("out")), Value::Str("counting".into()));
+        machine.step();
+        assert_eq!(machine.get_current_line(), Some(1));
+        assert_eq!(machine.get_ident_value(&Ident::global("out")), Value::Num(0.into()));
+        machine.step();
+        assert_eq!(machine.get_current_line(), Some(1));
+        assert_eq!(machine.get_ident_value(&Ident::global("out")), Value::Num(0.into()));
+    }
+
+    #[test]
+    fn foreign_handles() {
+        let mut other = ProgramBuilder::new();
+        other.new_num(1.into());
+        let (n, s) = (other.new_num(2.into()), other.new_str("x"));
+        let label = (0..5).map(|_| other.label()).last().unwrap();
+
+        let check = |misuse: &dyn Fn(&mut ProgramBuilder), error: &str| {
+            let mut b = ProgramBuilder::new();
+            b.new_num(0.into());
+            b.line();
+            misuse(&mut b);
+            let message = b.build().unwrap_err().to_string();
+            assert!(message.contains(error), "'{}' should contain '{}'", message, error);
+            assert!(message.contains("from another builder"));
+        };
+        check(&|b| b.neg(n), "'number #1 = -(number #1)' uses a register");
+        check(&|b| b.inc_s(s), "uses a register");
+        check(&|b| b.goto_line(n), "goto_line uses a register");
+        check(&|b| b.jump(label), "jump goes to a label");
+        check(&|b| b.bind(Ident::global("n"), n), ":n is bound to a register");
+
+        let mut b = ProgramBuilder::new();
+        b.line();
+        b.place(label);
+        assert!(b.build().unwrap_err().to_string().contains("from another builder"));
+    }
+
+    #[test]
+    fn mistakes() {
+        for (src, error) in [
+            ("", "no lines"),
+            ("num a\nneg a\nline", "before the first line"),
+            ("line\njump nowhere", "never placed"),
+            ("line\na:\na:", "placed twice"),
+            ("num a\nline\nneg b", "no register called 'b'"),
+            ("str a\nline\nneg a", "isn't a number register"),
+            ("num a\nline\nneg a a", "expected 1 operand"),
+            ("line\nfrobnicate", "unknown instruction"),
+            ("num a = \"x\"", "can't start a num register"),
+            ("num a\nnum a", "declared twice"),
+        ] {
+            let message = format!("{:#}", assemble(src).unwrap_err());
+            assert!(message.contains(error), "'{}' failed with '{}'", src, message);
+        }
+    }
+}
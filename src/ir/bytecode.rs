@@ -0,0 +1,409 @@
+use anyhow::{Result, bail, ensure, Context};
+use state::{Reader, write_varint};
+use super::*;
+
+const MAGIC: &[u8] = b"YOGI";
+
+/// Bumped whenever the layout of compiled code changes.
+const BYTECODE_VERSION: u8 = 6;
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    write_varint(out, len as u64);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn write_number(out: &mut Vec<u8>, n: Number) {
+    // zigzag, so small negative numbers are short too
+    write_varint(out, ((n.0 << 1) ^ (n.0 >> 63)) as u64);
+}
+
+fn write_ident(out: &mut Vec<u8>, ident: &Ident) {
+    out.push(ident.global as u8);
+    write_bytes(out, ident.original_name().as_bytes());
+}
+
+impl<'a> Reader<'a> {
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.varint()? as usize)
+    }
+
+    /// An index, which must be below `count`.
+    fn index(&mut self, count: usize, what: &str) -> Result<usize> {
+        let index = self.len()?;
+        ensure!(index < count, "{} #{} doesn't exist", what, index);
+        Ok(index)
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => bail!("bad flag {}", b),
+        }
+    }
+
+    fn number(&mut self) -> Result<Number> {
+        let n = self.varint()?;
+        Ok(Number((n >> 1) as i64 ^ -((n & 1) as i64)))
+    }
+
+    fn string(&mut self) -> Result<&'a str> {
+        let len = self.len()?;
+        std::str::from_utf8(self.bytes(len)?).context("text isn't UTF-8")
+    }
+
+    fn ident(&mut self) -> Result<Ident> {
+        let global = self.bool()?;
+        Ok(Ident::new(self.string()?, global))
+    }
+}
+
+impl IRMachine {
+    /// Saves the compiled program, with the registers as they are, in a compact binary format
+    /// which [`IRMachine::from_bytes`] loads much faster than compiling again. Diagnostics,
+    /// provenance and anything recorded while running aren't included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(BYTECODE_VERSION);
+        out.push(match self.goto_policy {
+            GotoPolicy::Clamp => 0,
+            GotoPolicy::Error => 1,
+            GotoPolicy::Wrap => 2,
+        });
+        out.push(match self.compat {
+            Compat::Starbase_Latest => 1,
+        });
+        out.push(match self.overflow {
+            OverflowPolicy::Wrapping => 0,
+            OverflowPolicy::Saturating => 1,
+            OverflowPolicy::Error => 2,
+        });
+        out.push(match self.string_mode {
+            StringMode::Bytes => 0,
+            StringMode::Chars => 1,
+        });
+        write_len(&mut out, self.max_string_len);
+        out.push(match self.unicode {
+            UnicodePolicy::Keep => 0,
+            UnicodePolicy::Reject => 1,
+            UnicodePolicy::Replace => 2,
+        });
+
+        write_len(&mut out, self.numbers.len());
+        for n in self.numbers.iter() {
+            write_number(&mut out, *n.borrow());
+        }
+        write_len(&mut out, self.strings.len());
+        for s in self.strings.iter() {
+            write_bytes(&mut out, &s.borrow());
+        }
+        write_len(&mut out, self.values.len());
+        for v in self.values.iter() {
+            match &*v.borrow() {
+                Value::Num(n) => {
+                    out.push(0);
+                    write_number(&mut out, *n);
+                },
+                Value::Str(s) => {
+                    out.push(1);
+                    write_bytes(&mut out, s);
+                },
+            }
+        }
+        let mut idents: Vec<_> = self.idents.iter().collect();
+        idents.sort_by_key(|&(ident, _)| ident);
+        write_len(&mut out, idents.len());
+        for (ident, reg) in idents {
+            write_ident(&mut out, ident);
+            let (kind, index) = match *reg {
+                AnyReg::Num(r) => (0, r.0),
+                AnyReg::Str(r) => (1, r.0),
+                AnyReg::Val(r) => (2, r.0),
+            };
+            out.push(kind);
+            write_len(&mut out, index);
+        }
+
+        write_len(&mut out, self.sections.len());
+        for section in self.sections.iter() {
+            out.push(section.line_start as u8);
+            match section.success {
+                SectionOrLine::Section(s) => {
+                    out.push(0);
+                    write_len(&mut out, s.0);
+                },
+                SectionOrLine::Line(r) => {
+                    out.push(1);
+                    write_len(&mut out, r.0);
+                },
+            }
+            write_len(&mut out, section.instrs.len());
+            for instr in section.instrs.iter() {
+                write_len(&mut out, instr.opcode());
+                for operand in instr.operands() {
+                    write_len(&mut out, operand);
+                }
+            }
+        }
+        write_len(&mut out, self.lines.len());
+        for line in self.lines.iter() {
+            write_len(&mut out, line.0);
+        }
+
+        write_len(&mut out, self.asserts.len());
+        for assert in self.asserts.iter() {
+            write_len(&mut out, assert.line.0);
+            write_len(&mut out, assert.section.0);
+            write_len(&mut out, assert.result.0);
+            write_bytes(&mut out, assert.message.as_bytes());
+        }
+        write_len(&mut out, self.annotations.len());
+        for annotation in self.annotations.iter() {
+            write_ident(&mut out, &annotation.field);
+            out.push(annotation.unit.is_some() as u8);
+            if let Some(unit) = &annotation.unit {
+                write_bytes(&mut out, unit.as_bytes());
+            }
+            write_bytes(&mut out, annotation.description.as_bytes());
+        }
+        let mut gotos: Vec<_> = self.dynamic_gotos.iter().collect();
+        gotos.sort_by_key(|(s, _)| s.0);
+        write_len(&mut out, gotos.len());
+        for (section, expr) in gotos {
+            write_len(&mut out, section.0);
+            write_bytes(&mut out, expr.as_bytes());
+        }
+        out
+    }
+
+    /// Loads a program saved by [`IRMachine::to_bytes`], starting on line 1. Fails rather than
+    /// panicking if the bytes are malformed, were saved by another version of the format, or
+    /// hold a string the saved [`UnicodePolicy`] rejects.
+    pub fn from_bytes(bytes: &[u8]) -> Result<IRMachine> {
+        let mut reader = Reader(bytes);
+        ensure!(reader.bytes(MAGIC.len()).ok() == Some(MAGIC), "not compiled Yolol");
+        let version = reader.byte()?;
+        ensure!(version == BYTECODE_VERSION, "unknown bytecode version {}", version);
+        let goto_policy = match reader.byte()? {
+            0 => GotoPolicy::Clamp,
+            1 => GotoPolicy::Error,
+            2 => GotoPolicy::Wrap,
+            b => bail!("unknown goto policy {}", b),
+        };
+        let compat = match reader.byte()? {
+            1 => Compat::Starbase_Latest,
+            b => bail!("unknown compatibility {}", b),
+        };
+        let overflow = match reader.byte()? {
+            0 => OverflowPolicy::Wrapping,
+            1 => OverflowPolicy::Saturating,
+            2 => OverflowPolicy::Error,
+            b => bail!("unknown overflow policy {}", b),
+        };
+        let string_mode = match reader.byte()? {
+            0 => StringMode::Bytes,
+            1 => StringMode::Chars,
+            b => bail!("unknown string mode {}", b),
+        };
+        let max_string_len = reader.len()?;
+        ensure!(max_string_len <= MAX_STRING_BYTES, "strings can't hold {} bytes", max_string_len);
+        let unicode = match reader.byte()? {
+            0 => UnicodePolicy::Keep,
+            1 => UnicodePolicy::Reject,
+            2 => UnicodePolicy::Replace,
+            b => bail!("unknown unicode policy {}", b),
+        };
+        let string = |bytes: &[u8]| -> Result<YString> {
+            let mut s = YString::from_bytes_in(bytes, string_mode);
+            s.truncate_in(max_string_len, string_mode);
+            s.apply_policy(unicode)?;
+            Ok(s)
+        };
+
+        let numbers = (0..reader.len()?)
+            .map(|_| reader.number().map(AtomicRefCell::new))
+            .collect::<Result<Vec<_>>>()?;
+        let strings = (0..reader.len()?)
+            .map(|_| {
+                let len = reader.len()?;
+                Ok(AtomicRefCell::new(string(reader.bytes(len)?)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let values = (0..reader.len()?)
+            .map(|_| {
+                let value = match reader.byte()? {
+                    0 => Value::Num(reader.number()?),
+                    1 => {
+                        let len = reader.len()?;
+                        Value::Str(string(reader.bytes(len)?)?)
+                    },
+                    b => bail!("unknown value type {}", b),
+                };
+                Ok(AtomicRefCell::new(value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut idents = AHashMap::new();
+        for _ in 0..reader.len()? {
+            let ident = reader.ident()?;
+            let reg = match reader.byte()? {
+                0 => AnyReg::Num(NumReg(reader.index(numbers.len(), "number")?)),
+                1 => AnyReg::Str(StrReg(reader.index(strings.len(), "string")?)),
+                2 => AnyReg::Val(ValReg(reader.index(values.len(), "value")?)),
+                b => bail!("unknown register type {}", b),
+            };
+            idents.insert(ident, reg);
+        }
+
+        // sections can jump to ones later on, so they're checked once all are read
+        let mut sections = Vec::new();
+        for _ in 0..reader.len()? {
+            let line_start = reader.bool()?;
+            let success = match reader.byte()? {
+                0 => SectionOrLine::Section(Section(reader.len()?)),
+                1 => SectionOrLine::Line(NumReg(reader.index(numbers.len(), "number")?)),
+                b => bail!("unknown section ending {}", b),
+            };
+            let mut instrs = Vec::new();
+            for _ in 0..reader.len()? {
+                let opcode = reader.len()?;
+                let name = instr::OPCODE_NAMES.get(opcode).context("unknown opcode")?;
+                let mut operands = std::iter::from_fn(|| reader.len().ok());
+                let instr = Instruction::from_operands(opcode, &mut operands)
+                    .with_context(|| format!("{} ends early", name))?;
+                for reg in instr.relevant() {
+                    let (index, count) = match reg {
+                        AnyReg::Num(r) => (r.0, numbers.len()),
+                        AnyReg::Str(r) => (r.0, strings.len()),
+                        AnyReg::Val(r) => (r.0, values.len()),
+                    };
+                    ensure!(index < count, "{} uses missing {}", name, reg);
+                }
+                instrs.push(instr);
+            }
+            sections.push(SectionCode { instrs, line_start, success });
+        }
+        for code in sections.iter() {
+            let targets = code.instrs.iter().filter_map(|i| i.get_section());
+            // sections nothing runs past, like those ending in a goto, are never fixed up
+            let targets = targets.chain(match code.success {
+                s if s == SUCCESS_NEEDS_FIXING => None,
+                SectionOrLine::Section(s) => Some(s),
+                SectionOrLine::Line(_) => None,
+            });
+            for target in targets {
+                ensure!(target.0 < sections.len(), "{} doesn't exist", target);
+            }
+        }
+        let lines = (0..reader.len()?)
+            .map(|_| reader.index(sections.len(), "section").map(Section))
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(!lines.is_empty(), "the program has no lines");
+
+        let asserts = (0..reader.len()?)
+            .map(|_| {
+                Ok(Assertion {
+                    line: Section(reader.index(sections.len(), "section")?),
+                    section: Section(reader.index(sections.len(), "section")?),
+                    result: NumReg(reader.index(numbers.len(), "number")?),
+                    message: reader.string()?.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let annotations = (0..reader.len()?)
+            .map(|_| {
+                Ok(FieldAnnotation {
+                    field: reader.ident()?,
+                    unit: if reader.bool()? { Some(reader.string()?.to_string()) } else { None },
+                    description: reader.string()?.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut dynamic_gotos = AHashMap::new();
+        for _ in 0..reader.len()? {
+            let section = Section(reader.index(sections.len(), "section")?);
+            dynamic_gotos.insert(section, reader.string()?.into());
+        }
+        ensure!(reader.0.is_empty(), "trailing bytes after program");
+
+        Ok(IRMachine {
+            sections: Arc::new(sections),
+            current_sect: lines[0],
+            line_start: lines[0],
+            lines,
+            runtime_err: false.into(),
+            goto_policy,
+            compat,
+            overflow,
+            string_mode,
+            max_string_len,
+            unicode,
+            asserts,
+            annotations,
+            diagnostics: Vec::new(),
+            profile: None,
+            provenance: Default::default(),
+            dynamic_gotos,
+            goto_events: None,
+            divergences: None,
+            numbers,
+            strings,
+            values,
+            idents,
+            breakpoints: Vec::new(),
+            paused: None,
+            watches: Vec::new(),
+            devices: Vec::new(),
+            pending: Vec::new(),
+            #[cfg(feature = "trace-hooks")]
+            trace_hook: None,
+            #[cfg(feature = "opcode-counts")]
+            opcode_counts: vec![0; instr::OPCODE_NAMES.len()],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::super::tests::variables;
+    use super::*;
+
+    #[test]
+    fn bytecode_round_trip() {
+        let src = "// :Speed (m/s) how fast\n:Speed=12.5 s=\"hi\"+:speed // assert: s!=0\n\
+            if :a>3 then :a=0 goto :a+1 else :a++ end :out=s\n:b=:a*-2 goto 2";
+        let options = CodegenOptions { protect_locals: true, ..Default::default() };
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(options, program);
+        let bytes = machine.to_bytes();
+        let mut loaded = IRMachine::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        let listing = |m: &IRMachine| {
+            let mut out = Vec::new();
+            m.print_bytecode(&mut out).unwrap();
+            out
+        };
+        assert_eq!(listing(&loaded), listing(&machine));
+        assert_eq!(loaded.annotations(), machine.annotations());
+        for _ in 0..20 {
+            machine.step();
+            loaded.step();
+            assert_eq!(variables(&loaded), variables(&machine));
+        }
+
+        assert!(IRMachine::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(IRMachine::from_bytes(b"YOGI\x02").is_err());
+        assert!(IRMachine::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        // any truncation or corruption fails cleanly
+        for i in 0..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x55;
+            let _ = IRMachine::from_bytes(&corrupt);
+            let _ = IRMachine::from_bytes(&bytes[..i]);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::atomic::AtomicBool;
+use super::*;
+
+/// Stops [`IRMachine::run_with_cancel`] or [`crate::network::Network::run_with_cancel`] from
+/// another thread. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag, so the token can stop another run.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+impl IRMachine {
+    /// Steps lines until `token` is cancelled, returning how many ran. The token is checked
+    /// before each line, so a line is never left half done.
+    pub fn run_with_cancel(&mut self, token: &CancelToken) -> usize {
+        let mut lines = 0;
+        while !token.is_cancelled() {
+            self.step();
+            lines += 1;
+        }
+        lines
+    }
+}
+
+/// Cancels `token` when the field it's attached to is written `at`, to cancel a run at a known
+/// point in tests.
+#[cfg(test)]
+pub(crate) struct CancelAt {
+    pub token: CancelToken,
+    pub at: Value,
+}
+
+#[cfg(test)]
+impl DeviceField for CancelAt {
+    fn read(&mut self) -> Value {
+        Value::default()
+    }
+
+    fn write(&mut self, value: &Value) {
+        if *value == self.at {
+            self.token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+    use super::*;
+
+    #[test]
+    fn cancel_partway() {
+        let program = YololParser::default().parse(":a++ :c=:a goto 1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        let token = CancelToken::new();
+        token.cancel();
+        assert_eq!(ir_machine.run_with_cancel(&token), 0);
+
+        token.reset();
+        let device = CancelAt { token: token.clone(), at: Value::Num(5.into()) };
+        ir_machine.attach_device(&Ident::global("c"), device);
+        assert_eq!(ir_machine.run_with_cancel(&token), 5);
+        assert_eq!(ir_machine.get_ident_value(&Ident::global("a")), Value::Num(5.into()));
+    }
+}
@@ -0,0 +1,439 @@
+use petgraph::algo::dominators::{Dominators, simple_fast};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::Direction;
+use petgraph::visit::EdgeRef;
+use export::{Attr, Export};
+use super::*;
+
+/// Why control can pass from one section to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// The section ran to its end, moving on within the line or to the next line.
+    Fallthrough,
+    /// A `goto`. Targets which aren't a literal get an edge to every line.
+    Goto,
+    /// An `if` condition was checked, and the branch was taken or not.
+    Branch { taken: bool },
+    /// A runtime error skipped the rest of the line.
+    ErrorSkip,
+}
+
+/// A natural loop, from [`ControlFlowGraph::loops`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    /// The only way into the loop, which dominates all of it.
+    pub header: NodeIndex,
+    /// The nodes jumping back to the header, in order.
+    pub latches: Vec<NodeIndex>,
+    /// Every node in the loop, including the header, in order.
+    pub body: Vec<NodeIndex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CfgNode {
+    pub section: usize,
+    /// The line this section starts, if any.
+    pub line: Option<usize>,
+}
+
+/// The sections of an [`IRMachine`] and the ways control moves between them.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    graph: DiGraph<CfgNode, EdgeKind>,
+    lines: Vec<NodeIndex>,
+}
+
+impl ControlFlowGraph {
+    pub const fn graph(&self) -> &DiGraph<CfgNode, EdgeKind> {
+        &self.graph
+    }
+
+    /// Sections are added in order, so a section's node index is the section's number.
+    pub fn section(&self, section: usize) -> NodeIndex {
+        NodeIndex::new(section)
+    }
+
+    pub fn line_start(&self, line: usize) -> NodeIndex {
+        self.lines[line]
+    }
+
+    pub fn successors(&self, section: usize) -> impl Iterator<Item = (usize, EdgeKind)> + '_ {
+        self.graph
+            .edges(self.section(section))
+            .map(|e| (self.graph[e.target()].section, *e.weight()))
+    }
+
+    /// The immediate dominators of every node, from the start of the first line where the chip
+    /// starts. Sections which can't be reached from there have none.
+    pub fn dominators(&self) -> Dominators<NodeIndex> {
+        simple_fast(&self.graph, self.lines[0])
+    }
+
+    /// The natural loops, by header in order. Edges back to a node dominating where they come
+    /// from make a loop, with those sharing a header merged into one. Running off the last line
+    /// back to the first counts, so most chips are one big loop with any others inside it.
+    pub fn loops(&self) -> Vec<Loop> {
+        let dominators = self.dominators();
+        let mut loops: Vec<Loop> = Vec::new();
+        let mut edges: Vec<_> = self.graph.edge_references().collect();
+        edges.sort_by_key(|e| (e.target(), e.source()));
+        for edge in edges {
+            let (latch, header) = (edge.source(), edge.target());
+            let back = dominators.dominators(latch).is_some_and(|mut d| d.any(|n| n == header));
+            if !back {
+                continue;
+            }
+            if loops.last().is_none_or(|l| l.header != header) {
+                loops.push(Loop { header, latches: Vec::new(), body: vec![header] });
+            }
+            let l = loops.last_mut().unwrap();
+            if l.latches.contains(&latch) {
+                continue;
+            }
+            l.latches.push(latch);
+            // everything reaching the latch without going through the header
+            let mut stack = vec![latch];
+            while let Some(node) = stack.pop() {
+                if !l.body.contains(&node) {
+                    l.body.push(node);
+                    stack.extend(self.graph.neighbors_directed(node, Direction::Incoming));
+                }
+            }
+        }
+        for l in loops.iter_mut() {
+            l.body.sort_unstable();
+        }
+        loops
+    }
+
+    /// The graph as GraphML, for tools like Gephi. Nodes are `s{section}`, with `section` and
+    /// `line` attributes as in [`CfgNode`], and edges have a `kind` and, for branches, `taken`.
+    pub fn to_graphml(&self) -> String {
+        self.export().graphml("cfg")
+    }
+
+    /// The graph as JSON, with a list of `nodes` and `edges`, each with the same attributes
+    /// as [`ControlFlowGraph::to_graphml`].
+    pub fn to_json(&self) -> String {
+        self.export().json()
+    }
+
+    fn export(&self) -> Export {
+        let id = |node: NodeIndex| format!("s{}", self.graph[node].section);
+        let nodes = self.graph.node_indices().map(|node| {
+            let CfgNode { section, line } = self.graph[node];
+            let line = line.map(|l| ("line", Attr::Int(l)));
+            (id(node), [("section", Attr::Int(section))].into_iter().chain(line).collect())
+        });
+        let edges = self.graph.edge_references().map(|e| {
+            let attrs = match *e.weight() {
+                EdgeKind::Fallthrough => vec![("kind", Attr::Str("fallthrough"))],
+                EdgeKind::Goto => vec![("kind", Attr::Str("goto"))],
+                EdgeKind::Branch { taken } => {
+                    vec![("kind", Attr::Str("branch")), ("taken", Attr::Bool(taken))]
+                },
+                EdgeKind::ErrorSkip => vec![("kind", Attr::Str("error_skip"))],
+            };
+            (id(e.source()), id(e.target()), attrs)
+        });
+        Export { nodes: nodes.collect(), edges: edges.collect() }
+    }
+}
+
+impl IRMachine {
+    /// The line a `goto` reading `line` at the end of `section` lands on under the goto
+    /// policy, if it's a literal. `Some(None)` is a literal which always fails, moving on to the
+    /// next line.
+    pub(super) fn const_goto_target(
+        &self,
+        section: &SectionCode,
+        line: NumReg,
+    ) -> Option<Option<usize>> {
+        let mut instrs = section.instrs.iter().rev();
+        // constant folding leaves a copy of the number, rather than it converted there and back
+        let set = instrs.find(|instr| instr.modifies() == Some(line.into()))?;
+        let num = match *set {
+            Instruction::CopyNum(n, _) => n,
+            Instruction::NumberifyVal(val, _) => instrs.find_map(|instr| match *instr {
+                Instruction::ValueifyNum(n, v) if v == val => Some(Some(n)),
+                i if i.modifies() == Some(val.into()) => Some(None),
+                _ => None,
+            })??,
+            _ => return None,
+        };
+        let is_const = self.sections
+            .iter()
+            .flat_map(|s| s.instrs.iter())
+            .all(|i| i.modifies() != Some(num.into()));
+        if !is_const {
+            return None;
+        }
+        Some(self.goto_policy.resolve(*self.num_ref(num).unwrap(), self.lines.len()))
+    }
+
+    /// The line each section belongs to, found by following jumps from each line's start
+    /// without starting another line.
+    fn section_lines(&self) -> Vec<Option<usize>> {
+        let mut owners = vec![None; self.sections.len()];
+        for (line, start) in self.lines.iter().enumerate() {
+            let mut stack = vec![start.0];
+            while let Some(s) = stack.pop() {
+                if owners[s].is_some() {
+                    continue;
+                }
+                owners[s] = Some(line);
+                let code = &self.sections[s];
+                let jumps = code.instrs.iter().filter_map(|i| i.get_section());
+                let success = match code.success {
+                    s if s == SUCCESS_NEEDS_FIXING => None,
+                    SectionOrLine::Section(s) => Some(s),
+                    SectionOrLine::Line(_) => None,
+                };
+                for to in jumps.chain(success) {
+                    if !self.lines.contains(&to) {
+                        stack.push(to.0);
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        let mut graph = DiGraph::with_capacity(self.sections.len(), self.sections.len() * 2);
+        for section in 0..self.sections.len() {
+            graph.add_node(CfgNode {
+                section,
+                line: self.lines.iter().position(|s| s.0 == section),
+            });
+        }
+        let lines: Vec<_> = self.lines.iter().map(|s| NodeIndex::new(s.0)).collect();
+        let owners = self.section_lines();
+
+        for (i, section) in self.sections.iter().enumerate() {
+            let from = NodeIndex::new(i);
+            let mut branches = false;
+            for instr in section.instrs.iter() {
+                match (*instr, instr.get_section()) {
+                    (Instruction::JumpIfError(s), _) => {
+                        graph.add_edge(from, NodeIndex::new(s.0), EdgeKind::ErrorSkip);
+                    },
+                    (_, Some(s)) => {
+                        branches = true;
+                        graph.add_edge(from, NodeIndex::new(s.0), EdgeKind::Branch { taken: true });
+                    },
+                    _ => (),
+                }
+            }
+            match section.success {
+                s if s == SUCCESS_NEEDS_FIXING => (),
+                SectionOrLine::Section(s) => {
+                    let kind = if branches {
+                        EdgeKind::Branch { taken: false }
+                    } else {
+                        EdgeKind::Fallthrough
+                    };
+                    graph.add_edge(from, NodeIndex::new(s.0), kind);
+                },
+                SectionOrLine::Line(n) => match self.const_goto_target(section, n) {
+                    Some(Some(line)) => {
+                        graph.add_edge(from, lines[line], EdgeKind::Goto);
+                    },
+                    Some(None) => if let Some(line) = owners[i] {
+                        let next = lines[(line + 1) % lines.len()];
+                        graph.add_edge(from, next, EdgeKind::ErrorSkip);
+                    },
+                    None => for &line in lines.iter() {
+                        graph.add_edge(from, line, EdgeKind::Goto);
+                    },
+                },
+            }
+        }
+
+        ControlFlowGraph { graph, lines }
+    }
+
+    /// The sections making up `line`, in order, reached from its start without starting
+    /// another line.
+    pub(super) fn line_sections(&self, cfg: &ControlFlowGraph, line: usize) -> Vec<usize> {
+        let start = self.lines[line].0;
+        let mut seen = vec![start];
+        let mut stack = vec![start];
+        while let Some(s) = stack.pop() {
+            for (to, _) in cfg.successors(s) {
+                if cfg.graph()[cfg.section(to)].line.is_none() && !seen.contains(&to) {
+                    seen.push(to);
+                    stack.push(to);
+                }
+            }
+        }
+        seen.sort_unstable();
+        seen
+    }
+
+    /// How many instructions make up each line, counting both sides of every branch.
+    pub fn line_instruction_counts(&self) -> Vec<usize> {
+        let cfg = self.control_flow_graph();
+        (0..self.lines.len())
+            .map(|line| {
+                self.line_sections(&cfg, line)
+                    .iter()
+                    .map(|&s| self.sections[s].instrs.len())
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+    use super::*;
+
+    fn cfg(src: &str) -> ControlFlowGraph {
+        let program = YololParser::unrestricted().parse(src).unwrap();
+        IRMachine::from_ast(Default::default(), program).control_flow_graph()
+    }
+
+    fn kinds_from_line(cfg: &ControlFlowGraph, line: usize) -> Vec<EdgeKind> {
+        let mut kinds = Vec::new();
+        let mut stack = vec![cfg.graph()[cfg.line_start(line)].section];
+        let mut seen = vec![];
+        while let Some(s) = stack.pop() {
+            if seen.contains(&s) {
+                continue;
+            }
+            seen.push(s);
+            for (to, kind) in cfg.successors(s) {
+                kinds.push(kind);
+                if cfg.graph()[cfg.section(to)].line.is_none() {
+                    stack.push(to);
+                }
+            }
+        }
+        kinds
+    }
+
+    #[test]
+    fn edge_kinds() {
+        let cfg = cfg("a=1\ngoto 4\nif a then b=1 else b=2 end\nc=a/b\ngoto a\n");
+
+        assert_eq!(cfg.successors(cfg.graph()[cfg.line_start(0)].section).collect::<Vec<_>>(), [
+            (cfg.graph()[cfg.line_start(1)].section, EdgeKind::Fallthrough),
+        ]);
+        let goto: Vec<_> = cfg.successors(cfg.graph()[cfg.line_start(1)].section).collect();
+        assert!(goto.contains(&(cfg.graph()[cfg.line_start(3)].section, EdgeKind::Goto)));
+        assert_eq!(goto.iter().filter(|(_, k)| *k == EdgeKind::Goto).count(), 1);
+
+        let ite = kinds_from_line(&cfg, 2);
+        assert!(ite.contains(&EdgeKind::Branch { taken: true }));
+        assert!(ite.contains(&EdgeKind::Branch { taken: false }));
+        assert!(!ite.contains(&EdgeKind::Goto));
+
+        let div = kinds_from_line(&cfg, 3);
+        assert!(div.contains(&EdgeKind::ErrorSkip));
+        assert!(div.contains(&EdgeKind::Fallthrough));
+
+        let dynamic = kinds_from_line(&cfg, 4);
+        assert_eq!(dynamic.iter().filter(|&&k| k == EdgeKind::Goto).count(), 20);
+
+        let program = YololParser::unrestricted().parse(":a=1 goto 3").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        OptPipeline::new().add(ConstantFolding).run(&mut machine);
+        let folded = kinds_from_line(&machine.control_flow_graph(), 0);
+        assert_eq!(folded, [EdgeKind::Fallthrough, EdgeKind::Goto]);
+
+        // an if choosing between two numbers doesn't branch once it's a select
+        let program = YololParser::unrestricted().parse("if a then b=1 else b=2 end").unwrap();
+        let options = CodegenOptions { select_numbers: true, ..Default::default() };
+        let machine = IRMachine::from_ast(options, program);
+        assert_eq!(kinds_from_line(&machine.control_flow_graph(), 0), [EdgeKind::Fallthrough]);
+    }
+
+    #[test]
+    fn goto_policies() {
+        let machine = |goto_policy| {
+            let src = format!(":o=a a=5 goto 21{}a=2", "\n".repeat(19));
+            let program = YololParser::default().parse(&src).unwrap();
+            let options = CodegenOptions { goto_policy, ..Default::default() };
+            let mut machine = IRMachine::from_ast(options, program);
+            OptPipeline::new().add(ConstantFolding).add(DeadCodeElimination).run(&mut machine);
+            machine
+        };
+        let wrap = machine(GotoPolicy::Wrap);
+        assert!(kinds_from_line(&wrap.control_flow_graph(), 0).contains(&EdgeKind::Goto));
+        let error = machine(GotoPolicy::Error);
+        let kinds = kinds_from_line(&error.control_flow_graph(), 0);
+        assert!(!kinds.contains(&EdgeKind::Goto) && kinds.contains(&EdgeKind::ErrorSkip));
+
+        // wrapping back to line 1 reads the `a=5` clamping to line 20 would overwrite
+        let mut wrap = wrap;
+        wrap.step();
+        wrap.step();
+        assert_eq!(wrap.get_ident_value(&Ident::global("o")), Value::Num(5.into()));
+    }
+
+    #[test]
+    fn dominators() {
+        let cfg = cfg(":a=1\nif :a then :b=1 else :c=2 end :d=:b\ngoto 1");
+        let dominators = cfg.dominators();
+        let ite = cfg.line_start(1);
+        let arms: Vec<_> = cfg
+            .successors(ite.index())
+            .filter(|(_, kind)| matches!(kind, EdgeKind::Branch { .. }))
+            .map(|(to, _)| cfg.section(to))
+            .collect();
+        assert_eq!(arms.len(), 2);
+        for &arm in arms.iter() {
+            assert_eq!(dominators.immediate_dominator(arm), Some(ite));
+        }
+        // where the arms meet is only reached through the `if`, but neither arm
+        let (join, _) = cfg.successors(arms[0].index()).next().unwrap();
+        assert_eq!(dominators.immediate_dominator(cfg.section(join)), Some(ite));
+        let mut chain = dominators.dominators(cfg.line_start(2)).unwrap();
+        assert!(chain.all(|n| !arms.contains(&n)));
+        assert_eq!(dominators.immediate_dominator(cfg.line_start(0)), None);
+    }
+
+    #[test]
+    fn loops() {
+        let cfg = cfg(":a=1\n:b++ if :b<5 then goto 2 end\n:c=1 goto 4\n:d=1 goto 3");
+        let loops = cfg.loops();
+        let lines = |l: &Loop| -> Vec<usize> {
+            l.body.iter().filter_map(|&n| cfg.graph()[n].line).collect()
+        };
+        // the `goto`s might fail and fall through to the end, back around to the start
+        assert_eq!(loops.len(), 3);
+        assert_eq!(loops[0].header, cfg.line_start(0));
+        assert_eq!(lines(&loops[0]), (0..20).collect::<Vec<_>>());
+        // line 2 counts up to 5, and lines 3 and 4 go back and forth
+        assert_eq!(loops[1].header, cfg.line_start(1));
+        assert_eq!(lines(&loops[1]), [1]);
+        assert!(loops[1].body.len() > 1);
+        assert_eq!(loops[2].header, cfg.line_start(2));
+        assert_eq!(lines(&loops[2]), [2, 3]);
+        assert_eq!(loops[2].latches.len(), 1);
+    }
+
+    #[test]
+    fn exports() {
+        let cfg = cfg("if :a then :b=1 end goto 1");
+        let json: serde_json::Value = serde_json::from_str(&cfg.to_json()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), cfg.graph().node_count());
+        assert_eq!(json["edges"].as_array().unwrap().len(), cfg.graph().edge_count());
+        assert_eq!(json["nodes"][0]["attributes"], serde_json::json!({ "section": 0, "line": 0 }));
+        let branch = json["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["attributes"]["kind"] == "branch" && e["attributes"]["taken"] == true);
+        assert!(branch.is_some());
+
+        let graphml = cfg.to_graphml();
+        let key = r#"<key id="edge_taken" for="edge" attr.name="taken" attr.type="boolean"/>"#;
+        assert!(graphml.contains(key));
+        let node = r#"<data key="node_section">0</data><data key="node_line">0</data>"#;
+        assert!(graphml.contains(&format!(r#"<node id="s0">{}</node>"#, node)));
+        assert_eq!(graphml.matches("<edge ").count(), cfg.graph().edge_count());
+    }
+}
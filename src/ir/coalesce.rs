@@ -0,0 +1,211 @@
+use ahash::AHashSet;
+use cfg::ControlFlowGraph;
+use analysis::Liveness;
+use dfg::{DataFlowGraph, InstrRef};
+use super::*;
+
+/// A [`Pass`] sharing one register between temporaries which are never needed at the same
+/// time, then dropping the registers left unused with [`IRMachine::compact_registers`].
+/// Temporaries copied from one to the other share where they can, which removes the copy.
+/// Protected variables (see [`CodegenOptions`]), constants and anything assertions use keep
+/// their own registers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegisterCoalescing;
+
+impl Pass for RegisterCoalescing {
+    fn name(&self) -> &str {
+        "register coalescing"
+    }
+
+    fn run(
+        &mut self,
+        machine: &mut IRMachine,
+        cfg: &ControlFlowGraph,
+        _: &DataFlowGraph,
+    ) -> PassSummary {
+        let (merged, map) = merge_map(machine, cfg);
+        let mut summary = PassSummary::default();
+        let (numbers, strings) = (machine.numbers.len(), machine.strings.len());
+        let num = |n: &mut NumReg| n.0 = map[n.0];
+        let str = |s: &mut StrReg| s.0 = map[numbers + s.0] - numbers;
+        let val = |v: &mut ValReg| v.0 = map[numbers + strings + v.0] - numbers - strings;
+
+        for section in Arc::make_mut(&mut machine.sections).iter_mut() {
+            for instr in section.instrs.iter_mut() {
+                let before = *instr;
+                instr.get_mut_num_regs().into_iter().for_each(num);
+                instr.get_mut_str_regs().into_iter().for_each(str);
+                instr.get_mut_val_regs().into_iter().for_each(val);
+                summary.instructions_changed += (*instr != before) as usize;
+            }
+            if let SectionOrLine::Line(n) = &mut section.success {
+                num(n);
+            }
+        }
+
+        let mut copies = Vec::new();
+        for (section, code) in machine.sections.iter().enumerate() {
+            for (index, &instr) in code.instrs.iter().enumerate() {
+                use Instruction::*;
+                if matches!(instr, CopyNum(a, b) if a == b)
+                    || matches!(instr, CopyStr(a, b) if a == b)
+                    || matches!(instr, CopyVal(a, b) if a == b)
+                {
+                    copies.push(InstrRef { section, index });
+                }
+            }
+        }
+        for &at in copies.iter().rev() {
+            machine.remove_instruction(at);
+        }
+        summary.instructions_removed = copies.len();
+        if merged > 0 {
+            let removed = machine.compact_registers();
+            summary.notes.push(format!("{} registers merged, {} removed", merged, removed));
+        }
+        summary
+    }
+}
+
+/// How many registers are merged, and the register each is replaced by, both by
+/// [`IRMachine::flat_index`].
+fn merge_map(machine: &IRMachine, cfg: &ControlFlowGraph) -> (usize, Vec<usize>) {
+    let liveness = Liveness::new(machine, cfg);
+    let registers = machine.register_total();
+    let file = |reg: usize| {
+        (reg >= machine.numbers.len()) as usize
+            + (reg >= machine.numbers.len() + machine.strings.len()) as usize
+    };
+
+    // only registers code writes to, outside of assertions
+    let mut candidate = vec![false; registers];
+    let assertions: Vec<_> = machine.asserts.iter().map(|a| a.section.0).collect();
+    let mut fixed = liveness.always.clone();
+    for (section, code) in machine.sections.iter().enumerate() {
+        for reg in code.instrs.iter().filter_map(|i| i.modifies()) {
+            let index = machine.flat_index(reg);
+            candidate[index] = true;
+            fixed[index] |= assertions.contains(&section);
+        }
+    }
+    candidate.iter_mut().zip(fixed).for_each(|(c, f)| *c &= !f);
+
+    let mut interferes = AHashSet::new();
+    let mut copies = Vec::new();
+    for section in 0..machine.sections.len() {
+        liveness.walk(section, &mut |_, instr, live, _| {
+            let Some(out) = instr.modifies().map(|reg| machine.flat_index(reg)) else {
+                return;
+            };
+            let copied = match instr {
+                Instruction::CopyNum(from, _) => Some(machine.flat_index(from.into())),
+                Instruction::CopyStr(from, _) => Some(machine.flat_index(from.into())),
+                Instruction::CopyVal(from, _) => Some(machine.flat_index(from.into())),
+                _ => None,
+            };
+            if let Some(from) = copied {
+                copies.push((from, out));
+            }
+            for (reg, _) in live.iter().enumerate().filter(|&(r, &l)| l && r != out) {
+                // a copy's two registers hold the same thing, so can be one
+                if Some(reg) != copied {
+                    interferes.insert((reg.min(out), reg.max(out)));
+                }
+            }
+        });
+    }
+    // registers starting with a value all hold it at once
+    let start = &liveness.live_in[machine.lines[0].0];
+    let starting: Vec<_> = (0..registers).filter(|&r| start[r] && candidate[r]).collect();
+    for (i, &a) in starting.iter().enumerate() {
+        for &b in starting[i + 1..].iter() {
+            interferes.insert((a, b));
+        }
+    }
+
+    // every group shares the register of its first member, or of the one starting with a value
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for reg in (0..registers).filter(|&r| candidate[r]) {
+        let clash = |other: usize| interferes.contains(&(other.min(reg), other.max(reg)));
+        let fits = |group: &Vec<usize>| {
+            file(group[0]) == file(reg) && !group.iter().any(|&other| clash(other))
+        };
+        let partner = |group: &&mut Vec<usize>| {
+            copies.iter().any(|&(a, b)| {
+                (a == reg && group.contains(&b)) || (b == reg && group.contains(&a))
+            })
+        };
+        let mut fitting: Vec<_> = groups.iter_mut().filter(|g| fits(g)).collect();
+        let group = match fitting.iter().position(partner) {
+            Some(i) => Some(fitting.swap_remove(i)),
+            None => fitting.into_iter().next(),
+        };
+        match group {
+            Some(group) if start[reg] => group.insert(0, reg),
+            Some(group) => group.push(reg),
+            None => groups.push(vec![reg]),
+        }
+    }
+    let mut map: Vec<_> = (0..registers).collect();
+    let mut merged = 0;
+    for group in groups {
+        for &reg in group[1..].iter() {
+            map[reg] = group[0];
+            merged += 1;
+        }
+    }
+    (merged, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::super::tests::assert_matches_simple_interp;
+    use super::*;
+
+    #[test]
+    fn coalesces_registers() {
+        let src = "a=:x*2 b=a+1 c=b*b d=c-:x :y=d+\"!\" e=\"s\"+:x f=e+e :z=f\n\
+            g=:x+1 h=g*3 :w=h+a i=(:x+1)*(:x+2)*(:x+3) :v=i :x++ goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program.clone());
+        let count = |m: &IRMachine| m.number_count() + m.string_count() + m.value_count();
+        let before = count(&machine);
+        let snapshot = machine.snapshot();
+        let report = OptPipeline::new().add(RegisterCoalescing).run(&mut machine);
+        assert!(report[0].1.changed());
+        assert!(count(&machine) < before, "{} registers from {}", count(&machine), before);
+        // the registers moved, so the snapshot no longer lines up with them
+        assert!(machine.restore(&snapshot).is_err());
+
+        let mut simple = SimpleInterp::new(program);
+        for _ in 0..6 {
+            machine.step();
+            simple.step_line();
+            assert_matches_simple_interp(&machine, &simple);
+        }
+    }
+
+    #[test]
+    fn keeps_watches_and_pending() {
+        let src = "a=1 b=a+1 c=b*b :y=c+:x :z=:y*2";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+        machine.watch_global(&Ident::global("z"), move |_, new| {
+            log.lock().unwrap().push(new.to_string());
+        });
+        let x = Ident::global("x");
+        machine.mark_pending(&x);
+        let report = OptPipeline::new().add(RegisterCoalescing).run(&mut machine);
+        assert!(report[0].1.changed());
+
+        assert_eq!(machine.step_or_yield(), StepStatus::Yielded(x.clone()));
+        machine.supply(&x, Value::Num(1.into()));
+        assert_eq!(machine.step_or_yield(), StepStatus::Completed);
+        assert_eq!(*writes.lock().unwrap(), ["10"]);
+    }
+}
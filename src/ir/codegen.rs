@@ -1,10 +1,147 @@
+use anyhow::ensure;
+use pest::Parser;
+use thiserror::Error;
+use diagnostics::Span;
 use parser::*;
 use super::*;
 
+pub mod golden;
+
+/// What a `goto` to a line outside the program does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum GotoPolicy {
+    /// Go to the nearest line, like the game does.
+    #[default]
+    Clamp,
+    /// Cause a runtime error, moving on to the next line.
+    Error,
+    /// Count around from the other end, so `goto 0` goes to the last line.
+    Wrap,
+}
+
+impl GotoPolicy {
+    /// The index of the line `goto target` lands on, or `None` for a runtime error.
+    pub fn resolve(self, target: Number, lines: usize) -> Option<usize> {
+        let target = target.as_f32().floor() as i64;
+        let lines = lines as i64;
+        match self {
+            GotoPolicy::Clamp => Some(target.clamp(1, lines) - 1),
+            GotoPolicy::Error => (1..=lines).contains(&target).then(|| target - 1),
+            GotoPolicy::Wrap => Some((target - 1).rem_euclid(lines)),
+        }.map(|line| line as usize)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CodegenOptions {
     pub protect_locals: bool,
     pub protect_globals: bool,
+    pub goto_policy: GotoPolicy,
+    /// Compile `// assert: expr` comments, reporting any that fail as diagnostics.
+    pub check_asserts: bool,
+    /// Record which statement or expression each instruction came from, see
+    /// [`IRMachine::provenance`].
+    pub provenance: bool,
+    /// Which game patch's rules to run by.
+    pub compat: Compat,
+    /// What `+`, `-` and `*` do when the result doesn't fit.
+    pub overflow: OverflowPolicy,
+    /// Whether `--` and the string length limit work on bytes or whole characters.
+    pub string_mode: StringMode,
+    /// The most bytes a string holds, dropping the rest from the end like the game's limit. Past
+    /// [`MAX_STRING_BYTES`] it's the same as that.
+    pub max_string_len: usize,
+    /// What to do with characters outside of ASCII in strings from outside the program, set by
+    /// [`IRMachine::set_ident`], imported state or loaded bytecode. Literals are up to the
+    /// parser.
+    pub unicode: UnicodePolicy,
+    /// How deeply expressions and `if`s may nest. Lowering recurses through them, so this
+    /// stops generated code from overflowing the stack.
+    pub max_depth: usize,
+    /// Run [`InstructionFusion`] over the compiled code.
+    pub fuse_instructions: bool,
+    /// Pick between two numbers without branching, for `c * a + (1 - c) * b` with `c` a
+    /// comparison and `if c then x = a else x = b end`, with `a` and `b` literals.
+    pub select_numbers: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CodegenError {
+    /// Both 1-based, with `statement` counting the line's top level statements, or 0 for its
+    /// `// assert:` comment.
+    #[error("line {line}, statement {statement}: nested deeper than the limit of {limit}")]
+    ExpressionTooComplex { line: usize, statement: usize, limit: usize },
+}
+
+/// Whether `expr` nests more than `limit` deep, without recursing any deeper than that.
+fn expr_too_deep(expr: &Expr, limit: usize) -> bool {
+    match expr {
+        _ if limit == 0 => true,
+        Expr::Binop(l, _, r) => expr_too_deep(l, limit - 1) || expr_too_deep(r, limit - 1),
+        Expr::Unop(_, e) => expr_too_deep(e, limit - 1),
+        Expr::Ident(_) | Expr::Number(_) | Expr::String(_) | Expr::Incdec(_) => false,
+    }
+}
+
+fn stmt_too_deep(stmt: &Statement, limit: usize) -> bool {
+    match stmt {
+        _ if limit == 0 => true,
+        Statement::Ite(c, t, e) => {
+            expr_too_deep(c, limit)
+                || t.iter().chain(e.iter()).any(|stmt| stmt_too_deep(stmt, limit - 1))
+        },
+        Statement::Goto(e) | Statement::Assign(_, _, e) => expr_too_deep(e, limit),
+        Statement::Incdec(_) => false,
+    }
+}
+
+impl CodegenError {
+    /// Where the statement or assertion the error is about is in `source`, if the program was
+    /// parsed from it.
+    pub fn span(&self, source: &str) -> Option<Span> {
+        let CodegenError::ExpressionTooComplex { line, statement, .. } = *self;
+        let mut parts = <YololParser as Parser<_>>::parse(Rule::program, source)
+            .ok()?
+            .filter(|p| p.as_rule() == Rule::line)
+            .nth(line.checked_sub(1)?)?
+            .into_inner();
+        let pair = match statement {
+            0 => parts.find(|p| p.as_rule() == Rule::assert_comment)?,
+            s => parts.filter(|p| p.as_rule() == Rule::statement).nth(s - 1)?,
+        };
+        Some(Span::new(source, pair.as_span().start(), pair.as_span().end()))
+    }
+}
+
+/// Finds the first statement or assertion in `program` nesting deeper than `limit`.
+fn check_depth(program: &Program, limit: usize) -> Result<(), CodegenError> {
+    for (i, line) in program.iter().enumerate() {
+        let too_deep = |statement| CodegenError::ExpressionTooComplex {
+            line: i + 1,
+            statement,
+            limit,
+        };
+        if let Some(s) = line.iter().position(|stmt| stmt_too_deep(stmt, limit)) {
+            return Err(too_deep(s + 1));
+        }
+        if line.assert.as_ref().is_some_and(|assert| expr_too_deep(assert, limit)) {
+            return Err(too_deep(0));
+        }
+    }
+    Ok(())
+}
+
+/// The source an instruction was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// 1-based, like diagnostics.
+    pub line: usize,
+    /// Which top level statement of the line, from 0, or `None` for an assertion comment.
+    /// [`statement_spans`](crate::parser::statement_spans) finds where it is in the source.
+    pub statement: Option<usize>,
+    /// The innermost statement or expression the instruction was generated for, as printed by
+    /// its `Display`.
+    pub node: Arc<str>,
 }
 
 impl Default for CodegenOptions {
@@ -12,18 +149,114 @@ impl Default for CodegenOptions {
         Self {
             protect_locals: false,
             protect_globals: true,
+            goto_policy: GotoPolicy::Clamp,
+            check_asserts: false,
+            provenance: false,
+            compat: Compat::default(),
+            overflow: OverflowPolicy::default(),
+            string_mode: StringMode::default(),
+            max_string_len: MAX_STRING_BYTES,
+            unicode: UnicodePolicy::default(),
+            max_depth: 256,
+            fuse_instructions: false,
+            select_numbers: false,
+        }
+    }
+}
+
+/// Whether `expr` is always 0 or 1, and evaluating it twice is the same as once.
+fn is_pure_condition(expr: &Expr) -> bool {
+    fn pure(expr: &Expr) -> bool {
+        match expr {
+            Expr::Binop(l, _, r) => pure(l) && pure(r),
+            Expr::Unop(_, e) => pure(e),
+            Expr::Incdec(_) => false,
+            Expr::Ident(_) | Expr::Number(_) | Expr::String(_) => true,
+        }
+    }
+    let boolean = matches!(
+        expr,
+        Expr::Binop(_, Binop::Eq | Binop::Ne | Binop::Lt | Binop::Le | Binop::Gt | Binop::Ge
+            | Binop::And | Binop::Or, _)
+        | Expr::Unop(Unop::Not, _),
+    );
+    boolean && pure(expr)
+}
+
+/// Finds `cond * a + (1 - cond) * b`, either way around, as `(cond, a, b)`. `a` and `b` must be
+/// literals small enough that multiplying them by 1 doesn't overflow, so that it's always one
+/// or the other.
+fn select_pattern(expr: &Expr) -> Option<(&Expr, Number, Number)> {
+    let Expr::Binop(l, Binop::Add, r) = expr else {
+        return None;
+    };
+    fn factors(e: &Expr) -> Option<[(&Expr, &Expr); 2]> {
+        match e {
+            Expr::Binop(x, Binop::Mul, y) => Some([(x, y), (y, x)]),
+            _ => None,
         }
     }
+    let literal = |e: &Expr| match *e {
+        Expr::Number(n) if n.0.checked_mul(1000).is_some() => Some(n),
+        _ => None,
+    };
+    let (l, r) = (factors(l)?, factors(r)?);
+    [(l, r), (r, l)].into_iter().find_map(|(chosen, other)| {
+        chosen.into_iter().find_map(|(cond, a)| {
+            other.into_iter().find_map(|(not_cond, b)| {
+                let inverse = matches!(
+                    not_cond,
+                    Expr::Binop(one, Binop::Sub, c)
+                        if **one == Expr::Number(Number::ONE) && **c == *cond,
+                );
+                if !inverse || !is_pure_condition(cond) {
+                    return None;
+                }
+                Some((cond, literal(a)?, literal(b)?))
+            })
+        })
+    })
+}
+
+/// Finds `if cond then x = a else x = b end` with `a` and `b` number literals, as
+/// `(cond, x, a, b)`.
+fn select_diamond<'a>(
+    cond: &'a Expr,
+    t: &'a [Statement],
+    e: &'a [Statement],
+) -> Option<(&'a Expr, &'a Ident, Number, Number)> {
+    match (t, e) {
+        (
+            [Statement::Assign(x, None, Expr::Number(a))],
+            [Statement::Assign(y, None, Expr::Number(b))],
+        ) if x == y => Some((cond, x, *a, *b)),
+        _ => None,
+    }
+}
+
+/// Whether the register holding `expr` is a temporary, rather than a variable.
+const fn yields_temp(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Ident(_) | Expr::Incdec(_))
 }
 
 struct CodegenData {
     sections: Vec<SectionCode>,
+    asserts: Vec<Assertion>,
+    annotations: Vec<FieldAnnotation>,
     lines: Vec<Section>,
     current_line: usize,
     numbers: Vec<Number>,
     strings: Vec<YString>,
     values: Vec<Value>,
     idents: AHashMap<Ident, ValReg>,
+    free_numbers: Vec<NumReg>,
+    free_values: Vec<ValReg>,
+    /// For each section, where its instructions came from, if recording provenance.
+    provenance: Option<Vec<Vec<Provenance>>>,
+    /// The top level statement of the current line being generated.
+    statement: Option<usize>,
+    /// The target expression of each section ending in a goto to a computed line.
+    dynamic_gotos: AHashMap<Section, Arc<str>>,
     pub options: CodegenOptions,
 }
 
@@ -36,21 +269,74 @@ impl CodegenData {
         }
     }
 
+    /// Attributes every instruction generated since the last call to `node`. Children are
+    /// generated before their parents, so each goes to the innermost node it came from. A node
+    /// only adds to the `section` it's generated into, and sections from `first_new` on, which
+    /// were made while generating it.
+    fn attribute(&mut self, section: Section, first_new: usize, node: String) {
+        let Some(provenance) = &mut self.provenance else {
+            return;
+        };
+        let node = Provenance {
+            line: self.current_line + 1,
+            statement: self.statement,
+            node: node.into(),
+        };
+        provenance.resize_with(self.sections.len(), Vec::new);
+        for s in std::iter::once(section.0).chain(first_new..self.sections.len()) {
+            provenance[s].resize(self.sections[s].instrs.len(), node.clone());
+        }
+    }
+
+    // Temporaries are consumed exactly once by the expression above them, so once that happens
+    // their register goes back on a free list for the next temporary to use.
+    fn temp_num(&mut self) -> NumReg {
+        self.free_numbers.pop().unwrap_or_else(|| {
+            let n = self.numbers.len().into();
+            self.numbers.push(0.into());
+            n
+        })
+    }
+
+    fn temp_val(&mut self) -> ValReg {
+        self.free_values.pop().unwrap_or_else(|| {
+            let v = self.values.len().into();
+            self.values.push(Default::default());
+            v
+        })
+    }
+
+    fn release_num(&mut self, n: NumReg) {
+        debug_assert!(!self.free_numbers.contains(&n), "released {} twice", n);
+        self.free_numbers.push(n);
+    }
+
+    fn release_val(&mut self, v: ValReg) {
+        debug_assert!(!self.free_values.contains(&v), "released {} twice", v);
+        self.free_values.push(v);
+    }
+
     fn add_jmperr(&mut self, section: Section) {
         let line = self.next_line();
         self.sections[section.0].instrs.push(Instruction::JumpIfError(self.lines[line]));
     }
 
+    /// Pushes `instr`, then a jump out of the line if it can fail.
+    fn push_checked(&mut self, section: Section, instr: Instruction) {
+        self.sections[section.0].instrs.push(instr);
+        if pass::can_error(instr, self.options.overflow) {
+            self.add_jmperr(section);
+        }
+    }
+
     fn make_truthy(&mut self, section: Section, r: ValReg) -> NumReg {
-        let n = self.numbers.len().into();
-        self.numbers.push(0.into());
+        let n = self.temp_num();
         self.sections[section.0].instrs.push(Instruction::IsTruthyVal(r, n));
         n
     }
 
     fn numberify(&mut self, section: Section, r: ValReg) -> NumReg {
-        let n = self.numbers.len().into();
-        self.numbers.push(0.into());
+        let n = self.temp_num();
         self.sections[section.0].instrs.push(Instruction::NumberifyVal(r, n));
         self.add_jmperr(section);
         n
@@ -59,14 +345,12 @@ impl CodegenData {
     fn make_val(&mut self, section: Section, r: AnyReg) -> ValReg {
         match r {
             AnyReg::Num(n) => {
-                let v = self.values.len().into();
-                self.values.push(Default::default());
+                let v = self.temp_val();
                 self.sections[section.0].instrs.push(Instruction::ValueifyNum(n, v));
                 v
             },
             AnyReg::Str(s) => {
-                let v = self.values.len().into();
-                self.values.push(Default::default());
+                let v = self.temp_val();
                 self.sections[section.0].instrs.push(Instruction::ValueifyStr(s, v));
                 v
             },
@@ -74,6 +358,7 @@ impl CodegenData {
         }
     }
 
+    /// Doesn't release `l` or `r`, since either may be a variable.
     fn make_arith_binop(&mut self, section: Section, l: ValReg, op: Binop, r: ValReg) -> ValReg {
         let r = self.numberify(section, r);
         let l = self.numberify(section, l);
@@ -82,18 +367,18 @@ impl CodegenData {
             Binop::Div => Instruction::Div(l, r),
             Binop::Mod => Instruction::Rem(l, r),
             Binop::Pow => Instruction::Pow(l, r),
+            Binop::Log => Instruction::Log(l, r),
             _ => unreachable!()
         };
-        self.sections[section.0].instrs.push(instr);
-        if instr.can_runtime_err() {
-            self.add_jmperr(section);
-        }
-        self.make_val(section, l.into())
+        self.push_checked(section, instr);
+        self.release_num(r);
+        let out = self.make_val(section, l.into());
+        self.release_num(l);
+        out
     }
 
     fn make_cmp_binop(&mut self, section: Section, l: ValReg, op: Binop, r: ValReg) -> ValReg {
-        let n = self.numbers.len().into();
-        self.numbers.push(0.into());
+        let n = self.temp_num();
         self.sections[section.0].instrs.push(match op {
             Binop::Eq | Binop::Ne => Instruction::Eq(l, r, n),
             Binop::Le => Instruction::Le(l, r, n),
@@ -105,59 +390,89 @@ impl CodegenData {
         if op == Binop::Ne {
             self.sections[section.0].instrs.push(Instruction::NotNum(n));
         }
-        self.make_val(section, n.into())
+        let out = self.make_val(section, n.into());
+        self.release_num(n);
+        out
     }
 
     fn copy_valreg(&mut self, section: Section, r: ValReg) -> ValReg {
-        let v = self.values.len().into();
-        self.values.push(Default::default());
+        let v = self.temp_val();
         self.sections[section.0].instrs.push(Instruction::CopyVal(r, v));
         v
     }
 
+    /// Gets a temporary holding the value in `v`, only copying if it isn't already a temporary.
+    fn own_valreg(&mut self, section: Section, v: ValReg, is_temp: bool) -> ValReg {
+        if is_temp {
+            v
+        } else {
+            self.copy_valreg(section, v)
+        }
+    }
+
+    fn make_logic_binop(&mut self, section: Section, l: ValReg, op: Binop, r: ValReg) -> ValReg {
+        let rn = self.make_truthy(section, r);
+        self.release_val(r);
+        let ln = self.make_truthy(section, l);
+        self.release_val(l);
+        self.sections[section.0].instrs.push(match op {
+            Binop::And => Instruction::And(ln, rn),
+            Binop::Or => Instruction::Or(ln, rn),
+            _ => unreachable!(),
+        });
+        self.release_num(rn);
+        let out = self.make_val(section, ln.into());
+        self.release_num(ln);
+        out
+    }
+
     fn codegen_from_binop(&mut self, section: Section, l: Expr, op: Binop, r: Expr) -> ValReg {
-        let mut r = self.codegen_from_expr(section, r);
-        let mut l = self.codegen_from_expr(section, l);
-        r = self.copy_valreg(section, r);
-        l = self.copy_valreg(section, l);
+        let (r_is_temp, l_is_temp) = (yields_temp(&r), yields_temp(&l));
+        let r = self.codegen_from_expr(section, r);
+        let l = self.codegen_from_expr(section, l);
+        let r = self.own_valreg(section, r, r_is_temp);
+        let l = self.own_valreg(section, l, l_is_temp);
         match op {
-            Binop::And => {
-                let r = self.make_truthy(section, r);
-                let l = self.make_truthy(section, l);
-                self.sections[section.0].instrs.push(Instruction::And(l, r));
-                self.make_val(section, l.into())
-            },
-            Binop::Or => {
-                let r = self.make_truthy(section, r);
-                let l = self.make_truthy(section, l);
-                self.sections[section.0].instrs.push(Instruction::Or(l, r));
-                self.make_val(section, l.into())
-            },
+            Binop::And | Binop::Or => self.make_logic_binop(section, l, op, r),
             Binop::Add => {
-                self.sections[section.0].instrs.push(Instruction::AddVal(l, r));
+                self.push_checked(section, Instruction::AddVal(l, r));
+                self.release_val(r);
                 l
             },
             Binop::Sub => {
-                self.sections[section.0].instrs.push(Instruction::SubVal(l, r));
+                self.push_checked(section, Instruction::SubVal(l, r));
+                self.release_val(r);
                 l
             },
-            Binop::Mul | Binop::Div | Binop::Mod | Binop::Pow =>
-                self.make_arith_binop(section, l, op, r),
-            Binop::Eq | Binop::Ne | Binop::Le | Binop::Lt | Binop::Ge | Binop::Gt =>
-                self.make_cmp_binop(section, l, op, r),
+            Binop::Mul | Binop::Div | Binop::Mod | Binop::Pow | Binop::Log => {
+                let out = self.make_arith_binop(section, l, op, r);
+                self.release_val(r);
+                self.release_val(l);
+                out
+            },
+            Binop::Eq | Binop::Ne | Binop::Le | Binop::Lt | Binop::Ge | Binop::Gt => {
+                let out = self.make_cmp_binop(section, l, op, r);
+                self.release_val(r);
+                self.release_val(l);
+                out
+            },
         }
     }
 
     fn codegen_from_unop(&mut self, section: Section, op: Unop, r: Expr) -> ValReg {
-        let mut r = self.codegen_from_expr(section, r);
-        r = self.copy_valreg(section, r);
+        let r_is_temp = yields_temp(&r);
+        let r = self.codegen_from_expr(section, r);
+        let r = self.own_valreg(section, r, r_is_temp);
         if op == Unop::Not {
-            let n = self.numbers.len().into();
-            self.numbers.push(0.into());
+            let n = self.temp_num();
             self.sections[section.0].instrs.push(Instruction::NotVal(r, n));
-            return self.make_val(section, n.into());
+            self.release_val(r);
+            let out = self.make_val(section, n.into());
+            self.release_num(n);
+            return out;
         }
         let n = self.numberify(section, r);
+        self.release_val(r);
         let instr = match op {
             Unop::Not => unreachable!(),
             Unop::Neg => Instruction::Neg(n),
@@ -170,17 +485,22 @@ impl CodegenData {
             Unop::Asin => Instruction::Asin(n),
             Unop::Acos => Instruction::Acos(n),
             Unop::Atan => Instruction::Atan(n),
+            Unop::Exp => Instruction::Exp(n),
+            Unop::Ln => Instruction::Ln(n),
+            Unop::Log10 => Instruction::Log10(n),
         };
         self.sections[section.0].instrs.push(instr);
-        self.make_val(section, n.into())
+        let out = self.make_val(section, n.into());
+        self.release_num(n);
+        out
     }
 
     fn get_variable(&mut self, ident: Ident) -> ValReg {
-        self.idents.entry(ident).or_insert_with(|| {
+        *self.idents.entry(ident).or_insert_with(|| {
             let v = ValReg(self.values.len());
             self.values.push(Default::default());
             v
-        }).clone()
+        })
     }
 
     fn codegen_incdec(&mut self, section: Section, incdec: Incdec) -> ValReg {
@@ -194,8 +514,34 @@ impl CodegenData {
         var
     }
 
+    /// Picks one of two number literals by whether `cond` is truthy, without branching.
+    fn codegen_select(&mut self, section: Section, cond: Expr, a: Number, b: Number) -> ValReg {
+        let cond_is_temp = yields_temp(&cond);
+        let cond_val = self.codegen_from_expr(section, cond);
+        let n = self.make_truthy(section, cond_val);
+        if cond_is_temp {
+            self.release_val(cond_val);
+        }
+        let (a_reg, b_reg) = (NumReg(self.numbers.len()), NumReg(self.numbers.len() + 1));
+        self.numbers.extend([a, b]);
+        self.sections[section.0].instrs.push(Instruction::SelectNum(n, a_reg, b_reg, n));
+        let out = self.make_val(section, n.into());
+        self.release_num(n);
+        out
+    }
+
     fn codegen_from_expr(&mut self, section: Section, expr: Expr) -> ValReg {
-        match expr {
+        let node = self.provenance.is_some().then(|| expr.to_string());
+        let first_new = self.sections.len();
+        let select = self.options.select_numbers.then(|| select_pattern(&expr)).flatten();
+        if let Some((cond, a, b)) = select {
+            let out = self.codegen_select(section, cond.clone(), a, b);
+            if let Some(node) = node {
+                self.attribute(section, first_new, node);
+            }
+            return out;
+        }
+        let out = match expr {
             Expr::Binop(l, op, r) => self.codegen_from_binop(section, *l, op, *r),
             Expr::Unop(op, r) => self.codegen_from_unop(section, op, *r),
             Expr::Incdec(incdec) => self.codegen_incdec(section, incdec),
@@ -205,12 +551,18 @@ impl CodegenData {
                 self.numbers.push(n);
                 self.make_val(section, nreg.into())
             },
-            Expr::String(s) => {
+            Expr::String(mut s) => {
+                s.fit_in(self.options.string_mode);
+                s.truncate_in(self.options.max_string_len, self.options.string_mode);
                 let sreg = StrReg(self.strings.len());
                 self.strings.push(s);
                 self.make_val(section, sreg.into())
             },
+        };
+        if let Some(node) = node {
+            self.attribute(section, first_new, node);
         }
+        out
     }
 
     fn new_section(&mut self, line_start: bool) -> Section {
@@ -230,8 +582,12 @@ impl CodegenData {
         t: Vec<Statement>,
         e: Vec<Statement>,
     ) -> Section {
-        let c = self.codegen_from_expr(section, c);
-        let c = self.make_truthy(section, c);
+        let c_is_temp = yields_temp(&c);
+        let c_val = self.codegen_from_expr(section, c);
+        let c = self.make_truthy(section, c_val);
+        if c_is_temp {
+            self.release_val(c_val);
+        }
         let then_link = self.codegen_and_link_stmts(false, t);
         let then_end = if let Some((then_start, then_end)) = then_link {
             self.sections[section.0].instrs.push(Instruction::JumpSectionIf(then_start, c));
@@ -255,10 +611,12 @@ impl CodegenData {
         if let Some(else_end) = else_end {
             self.sections[else_end.0].success = section.into();
         }
+        self.release_num(c);
         section
     }
 
     fn codegen_from_assign(&mut self, section: Section, x: Ident, op: Option<AssignOp>, e: Expr) {
+        let e_is_temp = yields_temp(&e);
         let e = self.codegen_from_expr(section, e);
         let var = self.get_variable(x);
         let instr = match op {
@@ -266,11 +624,15 @@ impl CodegenData {
             Some(AssignOp::Sub) => Instruction::SubVal(var, e),
             Some(op@(AssignOp::Mul | AssignOp::Div | AssignOp::Mod | AssignOp::Pow)) => {
                 let out = self.make_arith_binop(section, var, op.into(), e);
+                self.release_val(out);
                 Instruction::CopyVal(out, var)
             },
             None => Instruction::CopyVal(e, var),
         };
-        self.sections[section.0].instrs.push(instr);
+        self.push_checked(section, instr);
+        if e_is_temp {
+            self.release_val(e);
+        }
     }
 
     fn codegen_from_stmt(
@@ -278,15 +640,37 @@ impl CodegenData {
         stmt: Statement,
         line_start: bool,
     ) -> (Section, Option<Section>) {
+        let first_new = self.sections.len();
         let section = self.new_section(line_start);
-        (section, match stmt {
+        let node = self.provenance.is_some().then(|| stmt.to_string());
+        let end = match stmt {
             Statement::Goto(e) => {
-                let line = self.codegen_from_expr(section, e);
-                let line = self.numberify(section, line);
+                if !matches!(e, Expr::Number(_)) {
+                    self.dynamic_gotos.insert(section, e.to_string().into());
+                }
+                // the line register is read when the section ends, so it's never released
+                let e_is_temp = yields_temp(&e);
+                let line_val = self.codegen_from_expr(section, e);
+                let line = self.numberify(section, line_val);
+                if e_is_temp {
+                    self.release_val(line_val);
+                }
                 self.sections[section.0].success = line.into();
                 None
             },
-            Statement::Ite(c, t, e) => self.codegen_from_ite(section, c, t, e).into(),
+            Statement::Ite(c, t, e) => {
+                let diamond = self.options.select_numbers.then(|| select_diamond(&c, &t, &e));
+                match diamond.flatten() {
+                    Some((c, x, a, b)) => {
+                        let out = self.codegen_select(section, c.clone(), a, b);
+                        let var = self.get_variable(x.clone());
+                        self.sections[section.0].instrs.push(Instruction::CopyVal(out, var));
+                        self.release_val(out);
+                        Some(section)
+                    },
+                    None => self.codegen_from_ite(section, c, t, e).into(),
+                }
+            },
             Statement::Incdec(incdec) => {
                 self.codegen_incdec(section, incdec);
                 Some(section)
@@ -295,7 +679,11 @@ impl CodegenData {
                 self.codegen_from_assign(section, x, op, e);
                 Some(section)
             },
-        })
+        };
+        if let Some(node) = node {
+            self.attribute(section, first_new, node);
+        }
+        (section, end)
     }
 
     fn codegen_and_link_stmts(
@@ -303,9 +691,14 @@ impl CodegenData {
         mut line_start: bool,
         stmts: impl IntoIterator<Item = Statement>,
     ) -> Option<(Section, Option<Section>)> {
+        let top_level = line_start;
         stmts
             .into_iter()
-            .map(|stmt| {
+            .enumerate()
+            .map(|(i, stmt)| {
+                if top_level {
+                    self.statement = Some(i);
+                }
                 let root_end = self.codegen_from_stmt(stmt, line_start);
                 line_start = false;
                 root_end
@@ -325,14 +718,57 @@ impl CodegenData {
         self.codegen_and_link_stmts(true, line.stmts)
     }
 
+    fn codegen_assert(&mut self, expr: Expr) {
+        let node = format!("// assert: {}", expr);
+        let message = format!("assertion failed: {}", expr);
+        let first_new = self.sections.len();
+        let section = self.new_section(false);
+        let is_temp = yields_temp(&expr);
+        let v = self.codegen_from_expr(section, expr);
+        let result = self.make_truthy(section, v);
+        if is_temp {
+            self.release_val(v);
+        }
+        self.release_num(result);
+        self.attribute(section, first_new, node);
+        self.asserts.push(Assertion {
+            line: self.lines[self.current_line],
+            section,
+            result,
+            message,
+        });
+    }
+
+    fn swap_sections(&mut self, a: Section, b: Section) {
+        self.sections.swap(a.0, b.0);
+        if let Some(provenance) = &mut self.provenance {
+            provenance.resize_with(self.sections.len(), Vec::new);
+            provenance.swap(a.0, b.0);
+        }
+        let (from_a, from_b) = (self.dynamic_gotos.remove(&a), self.dynamic_gotos.remove(&b));
+        if let Some(expr) = from_a {
+            self.dynamic_gotos.insert(b, expr);
+        }
+        if let Some(expr) = from_b {
+            self.dynamic_gotos.insert(a, expr);
+        }
+    }
+
     fn codegen_from_program(&mut self, program: Program) {
-        for line in program.lines.into_iter() {
+        for mut line in program.lines.into_iter() {
+            let assert = line.assert.take().filter(|_| self.options.check_asserts);
+            self.annotations.extend(line.annotation.take());
             if let Some((start, end)) = self.codegen_from_line(line) {
                 if let Some(end) = end {
                     debug_assert_eq!(self.sections[end.0].success, SUCCESS_NEEDS_FIXING);
                     self.sections[end.0].success = self.lines[self.next_line()].into();
                 }
-                self.sections.swap(self.lines[self.current_line].0, start.0);
+                self.swap_sections(self.lines[self.current_line], start);
+            }
+
+            self.statement = None;
+            if let Some(assert) = assert {
+                self.codegen_assert(assert);
             }
 
             if self.sections[self.current_line].success == SUCCESS_NEEDS_FIXING {
@@ -348,47 +784,149 @@ impl Default for CodegenData {
     fn default() -> Self {
         CodegenData {
             sections: vec![],
+            asserts: vec![],
+            annotations: vec![],
             lines: vec![],
             current_line: 0,
             numbers: Vec::with_capacity(100),
             strings: Vec::with_capacity(100),
             values: Vec::with_capacity(100),
             idents: AHashMap::with_capacity(100),
+            free_numbers: Vec::with_capacity(20),
+            free_values: Vec::with_capacity(20),
+            provenance: None,
+            statement: None,
+            dynamic_gotos: AHashMap::new(),
             options: Default::default(),
         }
     }
 }
 
 impl IRMachine {
+    /// Panics if the program nests deeper than [`CodegenOptions::max_depth`], see
+    /// [`IRMachine::try_from_ast`].
     pub fn from_ast(options: CodegenOptions, program: parser::Program) -> Self {
+        Self::try_from_ast(options, program).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_from_ast(
+        options: CodegenOptions,
+        program: parser::Program,
+    ) -> Result<Self, CodegenError> {
+        Self::compile(options, program, AHashMap::new())
+    }
+
+    /// Like [`IRMachine::from_ast`], but each variable in `pins` gets the value register at that
+    /// index (see [`IRMachine::ident_register`]), so it stays put when a slightly edited
+    /// program is recompiled. Pinned variables are always protected, even if the program
+    /// doesn't use them. [`IRMachine::compact_registers`] still moves them, so pins only line
+    /// up between programs which haven't been compacted.
+    pub fn compile_with_pinning(
+        options: CodegenOptions,
+        program: parser::Program,
+        pins: impl IntoIterator<Item = (Ident, usize)>,
+    ) -> anyhow::Result<Self> {
+        let mut by_ident = AHashMap::new();
+        let mut by_reg = AHashMap::new();
+        for (ident, reg) in pins {
+            if let Some(other) = by_reg.insert(reg, ident.clone()) {
+                ensure!(
+                    other == ident,
+                    "'{}' and '{}' are both pinned to value #{}",
+                    other,
+                    ident,
+                    reg,
+                );
+            }
+            if let Some(other) = by_ident.insert(ident.clone(), ValReg(reg)) {
+                ensure!(
+                    other.0 == reg,
+                    "'{}' is pinned to both value #{} and value #{}",
+                    ident,
+                    other.0,
+                    reg,
+                );
+            }
+        }
+        Ok(Self::compile(options, program, by_ident)?)
+    }
+
+    fn compile(
+        options: CodegenOptions,
+        program: parser::Program,
+        pins: AHashMap<Ident, ValReg>,
+    ) -> Result<Self, CodegenError> {
+        span!(DEBUG, "compile", lines = program.len());
+        check_depth(&program, options.max_depth)?;
+        let pinned_regs = pins.values().map(|v| v.0 + 1).max().unwrap_or(0);
         let mut codegen = CodegenData {
             sections: vec![SectionCode {
                 instrs: Vec::new(),
                 line_start: true,
                 success: SUCCESS_NEEDS_FIXING,
             }; program.len()],
-            lines: (0..program.len()).map(Section).collect::<Vec<_>>().try_into().unwrap(),
+            lines: (0..program.len()).map(Section).collect(),
+            provenance: options.provenance.then(Vec::new),
             options,
             ..Default::default()
         };
+        codegen.values.resize(pinned_regs, Default::default());
+        // Registers between the pinned ones are free for temporaries
+        let unpinned = (0..pinned_regs)
+            .rev()
+            .map(ValReg)
+            .filter(|v| !pins.values().any(|p| p == v));
+        codegen.free_values.extend(unpinned);
+        codegen.idents.extend(pins.iter().map(|(i, &v)| (i.clone(), v)));
         codegen.codegen_from_program(program);
-        IRMachine {
-            sections: codegen.sections,
+        if let Some(provenance) = &mut codegen.provenance {
+            provenance.resize_with(codegen.sections.len(), Vec::new);
+        }
+        let mut machine = IRMachine {
+            sections: Arc::new(codegen.sections),
             current_sect: codegen.lines[0],
+            line_start: codegen.lines[0],
             lines: codegen.lines,
             runtime_err: false.into(),
+            goto_policy: codegen.options.goto_policy,
+            compat: codegen.options.compat,
+            overflow: codegen.options.overflow,
+            string_mode: codegen.options.string_mode,
+            max_string_len: codegen.options.max_string_len.min(MAX_STRING_BYTES),
+            unicode: codegen.options.unicode,
+            asserts: codegen.asserts,
+            annotations: codegen.annotations,
+            diagnostics: Vec::new(),
+            profile: None,
+            provenance: Arc::new(codegen.provenance.unwrap_or_default()),
+            dynamic_gotos: codegen.dynamic_gotos,
+            goto_events: None,
+            divergences: None,
             numbers: codegen.numbers.into_iter().map(AtomicRefCell::new).collect(),
             strings: codegen.strings.into_iter().map(AtomicRefCell::new).collect(),
             values: codegen.values.into_iter().map(AtomicRefCell::new).collect(),
             idents: codegen.idents
                 .into_iter()
-                .filter(|(i, _)| if i.global {
+                .filter(|(i, _)| pins.contains_key(i) || if i.global {
                     codegen.options.protect_globals
                 } else {
                     codegen.options.protect_locals
                 })
                 .map(|(k, v)| (k, v.into()))
                 .collect(),
+            breakpoints: Vec::new(),
+            paused: None,
+            watches: Vec::new(),
+            devices: Vec::new(),
+            pending: Vec::new(),
+            #[cfg(feature = "trace-hooks")]
+            trace_hook: None,
+            #[cfg(feature = "opcode-counts")]
+            opcode_counts: vec![0; instr::OPCODE_NAMES.len()],
+        };
+        if codegen.options.fuse_instructions {
+            OptPipeline::new().add(InstructionFusion).run(&mut machine);
         }
+        Ok(machine)
     }
 }
\ No newline at end of file
@@ -0,0 +1,271 @@
+//! Golden tests of codegen, comparing compiled code against checked in snapshots.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::fs;
+use super::*;
+
+/// Set to anything to overwrite snapshots with what's compiled now, rather than comparing.
+pub const BLESS_VAR: &str = "YOGI_BLESS";
+
+fn renumber<T: Hash + Eq>(map: &mut AHashMap<T, usize>, key: T) -> usize {
+    let next = map.len();
+    *map.entry(key).or_insert(next)
+}
+
+#[derive(Default)]
+struct Renumbering {
+    sections: AHashMap<Section, usize>,
+    numbers: AHashMap<NumReg, usize>,
+    strings: AHashMap<StrReg, usize>,
+    values: AHashMap<ValReg, usize>,
+}
+
+impl Renumbering {
+    fn instr(&mut self, mut instr: Instruction) -> Instruction {
+        for r in instr.get_mut_num_regs() {
+            r.0 = renumber(&mut self.numbers, *r);
+        }
+        for r in instr.get_mut_str_regs() {
+            r.0 = renumber(&mut self.strings, *r);
+        }
+        for r in instr.get_mut_val_regs() {
+            r.0 = renumber(&mut self.values, *r);
+        }
+        if let Some(s) = instr.get_mut_section() {
+            s.0 = self.sections[s];
+        }
+        instr
+    }
+
+    fn reg(&mut self, reg: AnyReg) -> AnyReg {
+        match reg {
+            AnyReg::Num(r) => NumReg(renumber(&mut self.numbers, r)).into(),
+            AnyReg::Str(r) => StrReg(renumber(&mut self.strings, r)).into(),
+            AnyReg::Val(r) => ValReg(renumber(&mut self.values, r)).into(),
+        }
+    }
+}
+
+/// Sections in the order they're reached, breadth first from the start of each line, followed
+/// by any that can't be reached, like those checking assertions.
+fn section_order(machine: &IRMachine) -> Vec<Section> {
+    let mut order = Vec::with_capacity(machine.sections.len());
+    let mut seen = vec![false; machine.sections.len()];
+    let mut queue: VecDeque<_> = machine.lines.iter().copied().collect();
+    while let Some(section) = queue.pop_front() {
+        if std::mem::replace(&mut seen[section.0], true) {
+            continue;
+        }
+        order.push(section);
+        let code = &machine.sections[section.0];
+        queue.extend(code.instrs.iter().filter_map(|i| i.get_section()));
+        if let SectionOrLine::Section(next) = code.success {
+            if code.success != SUCCESS_NEEDS_FIXING {
+                queue.push_back(next);
+            }
+        }
+    }
+    order.extend((0..seen.len()).filter(|&i| !seen[i]).map(Section));
+    order
+}
+
+/// Dumps the code of a freshly compiled machine, with sections and registers renumbered in
+/// the order they're first used, so the dump only changes when the compiled code does.
+pub fn canonical_dump(machine: &IRMachine) -> String {
+    let order = section_order(machine);
+    let mut map = Renumbering::default();
+    for &section in order.iter() {
+        renumber(&mut map.sections, section);
+    }
+
+    let mut out = String::new();
+    let lines: Vec<_> = machine.lines.iter().map(|s| map.sections[s].to_string()).collect();
+    writeln!(out, "lines: {}", lines.join(", ")).unwrap();
+
+    for &section in order.iter() {
+        let code = &machine.sections[section.0];
+        writeln!(out, "\nsection #{}:", map.sections[&section]).unwrap();
+        for &instr in code.instrs.iter() {
+            writeln!(out, "\t{}", map.instr(instr)).unwrap();
+        }
+        match code.success {
+            _ if code.success == SUCCESS_NEEDS_FIXING => writeln!(out, "\tunreachable"),
+            SectionOrLine::Section(s) => writeln!(out, "\tgoto section #{}", map.sections[&s]),
+            SectionOrLine::Line(n) => writeln!(out, "\tgoto line {}", map.reg(n.into())),
+        }.unwrap();
+    }
+
+    let mut idents: Vec<_> = machine.idents.iter().collect();
+    idents.sort_unstable_by_key(|&(ident, _)| ident);
+    writeln!(out, "\nidents:").unwrap();
+    for (ident, &reg) in idents {
+        writeln!(out, "\t{} is {}", ident, map.reg(reg)).unwrap();
+    }
+
+    let mut constants = Vec::new();
+    for (&reg, &i) in map.numbers.iter() {
+        let value = machine.num_ref(reg).unwrap();
+        if *value != Number::default() {
+            constants.push(((0, i), format!("number #{} = {}", i, *value)));
+        }
+    }
+    for (&reg, &i) in map.strings.iter() {
+        let value = machine.str_ref(reg).unwrap();
+        if !value.is_empty() {
+            constants.push(((1, i), format!("string #{} = \"{}\"", i, *value)));
+        }
+    }
+    for (&reg, &i) in map.values.iter() {
+        let value = machine.val_ref(reg).unwrap();
+        if *value != Value::default() {
+            constants.push(((2, i), format!("value #{} = {}", i, *value)));
+        }
+    }
+    constants.sort();
+    writeln!(out, "\nconstants:").unwrap();
+    for (_, constant) in constants {
+        writeln!(out, "\t{}", constant).unwrap();
+    }
+
+    out
+}
+
+/// A line diff of `expected` against `actual`, with a little context around each change, or
+/// `None` if they're the same.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    const CONTEXT: usize = 2;
+
+    if expected == actual {
+        return None;
+    }
+    let old: Vec<_> = expected.lines().collect();
+    let new: Vec<_> = actual.lines().collect();
+
+    // lcs[i][j] is the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push(('+', new[j]));
+            j += 1;
+        } else {
+            ops.push(('-', old[i]));
+            i += 1;
+        }
+    }
+
+    let changed: Vec<_> = ops.iter().map(|&(op, _)| op != ' ').collect();
+    let mut out = String::new();
+    let mut skipped = false;
+    for (k, &(op, line)) in ops.iter().enumerate() {
+        let near_change = changed[k.saturating_sub(CONTEXT)..(k + CONTEXT + 1).min(ops.len())]
+            .iter()
+            .any(|&c| c);
+        if near_change {
+            if skipped {
+                out.push_str("...\n");
+                skipped = false;
+            }
+            writeln!(out, "{} {}", op, line).unwrap();
+        } else {
+            skipped = true;
+        }
+    }
+    Some(out)
+}
+
+pub fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{}.txt", name))
+}
+
+/// Compares the canonical dump of `machine` to the snapshot called `name`, panicking with a
+/// diff if they differ, or if there's no snapshot. Writes the snapshot instead if
+/// [`BLESS_VAR`] is set.
+pub fn assert_golden(name: &str, machine: &IRMachine) {
+    let path = snapshot_path(name);
+    let actual = canonical_dump(machine);
+    if std::env::var_os(BLESS_VAR).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    assert!(
+        path.exists(),
+        "there's no snapshot at {}, set {} to write it",
+        path.display(),
+        BLESS_VAR,
+    );
+    let expected = fs::read_to_string(&path).unwrap();
+    if let Some(diff) = diff(&expected, &actual) {
+        panic!(
+            "codegen of '{}' doesn't match {}, set {} to update it:\n{}",
+            name,
+            path.display(),
+            BLESS_VAR,
+            diff,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(src: &str) -> IRMachine {
+        let program = YololParser::default().parse(src).unwrap();
+        IRMachine::from_ast(Default::default(), program)
+    }
+
+    #[test]
+    fn golden_snapshots() {
+        assert_golden("if_else", &compile("if :a>1 then :b=\"x\" else :b=2 end\n:c=:b+1 goto 1"));
+        assert_golden("dynamic_goto", &compile("i++ :o=i*2 goto i%3+1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "there's no snapshot")]
+    fn missing_snapshot() {
+        // blessing would write one, so this can't be checked then
+        let blessing = std::env::var_os(BLESS_VAR).is_some();
+        assert!(!blessing, "there's no snapshot to check with {} set", BLESS_VAR);
+        assert_golden("missing", &compile(":a=1"));
+    }
+
+    #[test]
+    fn diff_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni";
+        let new = "a\nB\nc\nd\ne\nf\ng\ni";
+        assert_eq!(diff(old, old), None);
+        assert_eq!(diff(old, new).unwrap(), [
+            "  a",
+            "+ B",
+            "- b",
+            "  c",
+            "  d",
+            "...",
+            "  f",
+            "  g",
+            "- h",
+            "  i",
+            "",
+        ].join("\n"));
+    }
+}
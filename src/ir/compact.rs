@@ -0,0 +1,100 @@
+use super::*;
+
+/// Drops the registers `used` says aren't, returning where each old register moved to.
+fn compact_file<T>(file: &mut Vec<AtomicRefCell<T>>, used: &[bool]) -> Vec<usize> {
+    let mut map = vec![usize::MAX; file.len()];
+    let mut next = 0;
+    for (old, &used) in used.iter().enumerate() {
+        if used {
+            map[old] = next;
+            next += 1;
+        }
+    }
+    let mut old = 0;
+    file.retain(|_| {
+        old += 1;
+        used[old - 1]
+    });
+    file.shrink_to_fit();
+    map
+}
+
+impl IRMachine {
+    /// Removes registers no instruction or variable uses, renumbering the rest densely and
+    /// keeping their values. Passes that remove code leave such holes behind.
+    ///
+    /// This moves registers, including those pinned with [`IRMachine::compile_with_pinning`].
+    /// Returns how many registers were removed.
+    pub fn compact_registers(&mut self) -> usize {
+        span!(DEBUG, "pass", name = "compact_registers");
+        let mut numbers = vec![false; self.numbers.len()];
+        let mut strings = vec![false; self.strings.len()];
+        let mut values = vec![false; self.values.len()];
+        let mut mark = |reg: AnyReg| match reg {
+            AnyReg::Num(n) => numbers[n.0] = true,
+            AnyReg::Str(s) => strings[s.0] = true,
+            AnyReg::Val(v) => values[v.0] = true,
+        };
+        for section in self.sections.iter() {
+            section.instrs.iter().flat_map(|i| i.relevant()).for_each(&mut mark);
+            if let SectionOrLine::Line(n) = section.success {
+                mark(n.into());
+            }
+        }
+        self.idents.values().copied().for_each(&mut mark);
+        self.asserts.iter().for_each(|a| mark(a.result.into()));
+
+        let before = self.numbers.len() + self.strings.len() + self.values.len();
+        let numbers = compact_file(&mut self.numbers, &numbers);
+        let strings = compact_file(&mut self.strings, &strings);
+        let values = compact_file(&mut self.values, &values);
+        let remap = |reg: AnyReg| -> AnyReg {
+            match reg {
+                AnyReg::Num(n) => NumReg(numbers[n.0]).into(),
+                AnyReg::Str(s) => StrReg(strings[s.0]).into(),
+                AnyReg::Val(v) => ValReg(values[v.0]).into(),
+            }
+        };
+
+        for section in Arc::make_mut(&mut self.sections).iter_mut() {
+            for instr in section.instrs.iter_mut() {
+                instr.get_mut_num_regs().into_iter().for_each(|n| n.0 = numbers[n.0]);
+                instr.get_mut_str_regs().into_iter().for_each(|s| s.0 = strings[s.0]);
+                instr.get_mut_val_regs().into_iter().for_each(|v| v.0 = values[v.0]);
+            }
+            if let SectionOrLine::Line(n) = &mut section.success {
+                n.0 = numbers[n.0];
+            }
+        }
+        self.idents.values_mut().for_each(|r| *r = remap(*r));
+        self.asserts.iter_mut().for_each(|a| a.result.0 = numbers[a.result.0]);
+
+        before - (self.numbers.len() + self.strings.len() + self.values.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+    use super::*;
+
+    #[test]
+    fn compaction() {
+        let src = "unused=5 other=\"a\"\n:out=3 goto :out-1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        let count = |m: &IRMachine| m.number_count() + m.string_count() + m.value_count();
+        let before = count(&ir_machine);
+        // what dead code elimination would do, since the locals aren't protected
+        ir_machine[0].instrs.clear();
+
+        let removed = ir_machine.compact_registers();
+        assert!(removed > 0);
+        assert_eq!(count(&ir_machine), before - removed);
+        assert_eq!(ir_machine.compact_registers(), 0);
+
+        ir_machine.step_repeat(3);
+        assert_eq!(ir_machine.get_ident_value(&Ident::global("out")), Value::Num(3.into()));
+        assert_eq!(ir_machine.get_current_line(), Some(1));
+    }
+}
@@ -0,0 +1,110 @@
+use analysis::Liveness;
+use cfg::ControlFlowGraph;
+use dfg::{DataFlowGraph, InstrRef};
+use super::*;
+
+/// A [`Pass`] removing instructions whose results are never read before being overwritten,
+/// on any way the code can go, and code which can never run. The [`DataFlowGraph`] doesn't
+/// follow control flow, so this works out which registers are live along the
+/// [`ControlFlowGraph`] instead. Protected variables (see [`CodegenOptions`]) and anything
+/// assertions read are always live, and instructions which can fail are kept for where
+/// failing sends the line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeadCodeElimination;
+
+impl Pass for DeadCodeElimination {
+    fn name(&self) -> &str {
+        "dead code elimination"
+    }
+
+    fn run(
+        &mut self,
+        machine: &mut IRMachine,
+        cfg: &ControlFlowGraph,
+        _: &DataFlowGraph,
+    ) -> PassSummary {
+        let mut summary = PassSummary::default();
+        // jumps are never removed, so the graph stays right as code goes
+        loop {
+            let dead = Liveness::new(machine, cfg).dead();
+            if dead.is_empty() {
+                return summary;
+            }
+            summary.instructions_removed += dead.len();
+            for &at in dead.iter().rev() {
+                machine.remove_instruction(at);
+            }
+        }
+    }
+}
+
+impl Liveness<'_> {
+    /// Every dead instruction, in order.
+    fn dead(&self) -> Vec<InstrRef> {
+        let machine = self.machine;
+        let mut reachable = vec![false; machine.sections.len()];
+        let mut stack: Vec<_> = machine.lines.iter().map(|s| s.0).collect();
+        while let Some(section) = stack.pop() {
+            if !std::mem::replace(&mut reachable[section], true) {
+                stack.extend(self.cfg.successors(section).map(|(to, _)| to));
+            }
+        }
+        for assert in machine.asserts.iter() {
+            reachable[assert.section.0] = false;
+        }
+
+        let mut dead = Vec::new();
+        for (section, code) in machine.sections.iter().enumerate() {
+            let is_assert = machine.asserts.iter().any(|a| a.section.0 == section);
+            let mut indices = if reachable[section] {
+                let mut indices = Vec::new();
+                self.walk(section, &mut |index, _, _, dead| if dead { indices.push(index) });
+                indices.reverse();
+                indices
+            } else if !is_assert {
+                (0..code.instrs.len()).collect()
+            } else {
+                Vec::new()
+            };
+            dead.extend(indices.drain(..).map(|index| InstrRef { section, index }));
+        }
+        dead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::super::tests::assert_matches_simple_interp;
+    use super::*;
+
+    #[test]
+    fn removes_dead_code() {
+        let src = "a=:x*2 b=a+1 c=\"s\"+b :out=:x+1 if :x then d=a else d=3 end\n\
+            :y=d a=(1+2)*3 c=a :x++ :z=c goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let instructions = |machine: &IRMachine| {
+            machine.sections.iter().map(|s| s.instrs.len()).sum::<usize>()
+        };
+        let mut machine = IRMachine::from_ast(Default::default(), program.clone());
+        let before = instructions(&machine);
+        let report = OptPipeline::new()
+            .add(ConstantFolding)
+            .add(DeadCodeElimination)
+            .run(&mut machine);
+        assert_eq!(report[1].0, "dead code elimination");
+        let removed = report.iter().map(|(_, s)| s.instructions_removed).sum::<usize>();
+        assert_eq!(instructions(&machine), before - removed);
+        // `b` and `c` on line 1 are gone, and only the copy of 9 to `:z` is left of `a` and `c`
+        // on line 2
+        assert!(instructions(&machine) * 2 < before, "{} from {}", instructions(&machine), before);
+
+        let mut simple = SimpleInterp::new(program);
+        for _ in 0..6 {
+            machine.step();
+            simple.step_line();
+            assert_matches_simple_interp(&machine, &simple);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use dfg::InstrRef;
+use super::*;
+
+/// Where [`IRMachine::continue_until_break`] stops, before running it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Breakpoint {
+    /// The start of a 1-based line.
+    Line(usize),
+    /// An instruction, numbered as in [`cfg::ControlFlowGraph`].
+    Instr(InstrRef),
+}
+
+impl IRMachine {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Returns whether the breakpoint was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&b| b != breakpoint);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Whether the machine stopped partway through a line, after [`IRMachine::step_instr`] or a
+    /// breakpoint. [`IRMachine::step`] finishes the line rather than running another.
+    pub fn mid_line(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// The instruction which runs next, or `None` if the next line has none.
+    pub fn next_instr(&self) -> Option<InstrRef> {
+        if let Some(index) = self.paused {
+            return Some(InstrRef { section: self.current_sect.0, index });
+        }
+        let mut section = self.current_sect;
+        loop {
+            let code = &self.sections[section.0];
+            if !code.instrs.is_empty() {
+                return Some(InstrRef { section: section.0, index: 0 });
+            }
+            match code.success {
+                SectionOrLine::Section(s) if !self.sections[s.0].line_start => section = s,
+                _ => return None,
+            }
+        }
+    }
+
+    /// Runs one instruction, starting a line if need be, and returns whether that finished
+    /// the line.
+    pub fn step_instr(&mut self) -> bool {
+        let at = match self.paused {
+            Some(index) => Some(index),
+            None => {
+                if !self.devices.is_empty() {
+                    self.read_devices();
+                }
+                self.line_start = self.current_sect;
+                self.skip_ends(Some(0))
+            },
+        };
+        let next = at.and_then(|index| {
+            #[cfg(feature = "trace-hooks")]
+            self.call_trace_hook(index);
+            let instr = self.sections[self.current_sect.0].instrs[index];
+            #[cfg(feature = "opcode-counts")]
+            {
+                self.opcode_counts[instr.opcode()] += 1;
+            }
+            if let Some(profile) = &mut self.profile {
+                profile.pending += 1;
+            }
+            let watched = self.watched_write(instr);
+            let jump = self.execute_instr(instr);
+            if let Some((watch, old)) = watched {
+                self.notify_watch(watch, old);
+            }
+            match jump {
+                Some(new_sect) => {
+                    self.current_sect = new_sect;
+                    // an error skips to the next line
+                    (!self.sections[new_sect.0].line_start).then_some(0)
+                },
+                None => Some(index + 1),
+            }
+        });
+        self.paused = self.skip_ends(next);
+        if self.paused.is_none() {
+            self.end_line();
+        }
+        self.paused.is_none()
+    }
+
+    /// Follows the ends of sections from the `next` instruction until one with an instruction
+    /// left to run, or `None` where the line ends.
+    fn skip_ends(&mut self, mut next: Option<usize>) -> Option<usize> {
+        while let Some(index) = next {
+            let code = &self.sections[self.current_sect.0];
+            if index < code.instrs.len() {
+                break;
+            }
+            next = match code.success {
+                SectionOrLine::Section(s) => {
+                    self.current_sect = s;
+                    (!self.sections[s.0].line_start).then_some(0)
+                },
+                SectionOrLine::Line(l) => {
+                    self.goto_line(l);
+                    None
+                },
+            };
+        }
+        next
+    }
+
+    /// Runs the rest of the current line, or the next one if it's not partway through one.
+    pub fn step_line(&mut self) {
+        while !self.step_instr() {}
+    }
+
+    /// Runs until the next instruction or line has a breakpoint, which it returns, or until
+    /// `max_lines` lines have finished. It always moves on at least one instruction, so it can
+    /// be called again to carry on from a breakpoint.
+    pub fn continue_until_break(&mut self, max_lines: usize) -> Option<Breakpoint> {
+        let mut lines = 0;
+        while lines < max_lines {
+            if self.step_instr() {
+                lines += 1;
+            }
+            let next = self.next_instr();
+            let at_line = (!self.mid_line())
+                .then(|| self.lines.iter().position(|&s| s == self.current_sect))
+                .flatten();
+            let hit = self.breakpoints.iter().find(|&&b| match b {
+                Breakpoint::Line(line) => at_line == Some(line.wrapping_sub(1)),
+                Breakpoint::Instr(instr) => next == Some(instr),
+            });
+            if hit.is_some() {
+                return hit.copied();
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let src = ":a=1 :b=2 :c=:a+:b\nif :a>5 then :d=1 end :a++\ngoto 2";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program.clone());
+        let mut reference = IRMachine::from_ast(Default::default(), program);
+        let get = |m: &IRMachine, name| m.get_ident_value(&Ident::global(name));
+
+        // stepping instructions one by one does what stepping lines does
+        assert!(!machine.step_instr());
+        assert!(machine.mid_line());
+        assert_eq!(get(&machine, "a"), Value::Num(0.into()));
+        assert!(!machine.step_instr());
+        assert_eq!(get(&machine, "a"), Value::Num(1.into()));
+        assert_eq!(get(&machine, "b"), Value::Num(0.into()));
+        machine.step_line();
+        reference.step();
+        assert!(!machine.mid_line());
+        assert_eq!(get(&machine, "c"), get(&reference, "c"));
+
+        machine.add_breakpoint(Breakpoint::Line(3));
+        assert_eq!(machine.continue_until_break(100), Some(Breakpoint::Line(3)));
+        assert_eq!(machine.get_current_line(), Some(2));
+        assert_eq!(machine.continue_until_break(100), Some(Breakpoint::Line(3)));
+        assert_eq!(get(&machine, "a"), Value::Num(3.into()));
+
+        // break on the instruction setting :d
+        let d = machine.ident_register(&Ident::global("d")).unwrap();
+        let d = machine.data_flow_graph().defs(d)[0];
+        assert!(machine.remove_breakpoint(Breakpoint::Line(3)));
+        machine.add_breakpoint(Breakpoint::Instr(d));
+        assert_eq!(machine.continue_until_break(100), Some(Breakpoint::Instr(d)));
+        assert_eq!(get(&machine, "a"), Value::Num(6.into()));
+        assert_eq!(get(&machine, "d"), Value::Num(0.into()));
+        assert_eq!(machine.next_instr(), Some(d));
+        machine.step();
+        assert_eq!(get(&machine, "d"), Value::Num(1.into()));
+        assert_eq!(get(&machine, "a"), Value::Num(7.into()));
+        assert!(!machine.mid_line());
+        assert_eq!(machine.continue_until_break(5), Some(Breakpoint::Instr(d)));
+
+        machine.remove_breakpoint(Breakpoint::Instr(d));
+        assert_eq!(machine.continue_until_break(5), None);
+    }
+}
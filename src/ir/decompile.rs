@@ -0,0 +1,639 @@
+use ahash::AHashSet;
+use anyhow::{Result, bail, ensure, Context};
+use parser::{AssignOp, Binop, Expr, Incdec, Line, Program, Statement, Unop, YololParser};
+use super::*;
+
+struct Decompiler<'a> {
+    machine: &'a IRMachine,
+    /// Registers which some instruction changes, so can't be replaced by their value.
+    written: AHashSet<Register>,
+    names: AHashMap<Register, Ident>,
+    taken: AHashSet<Ident>,
+    /// The names made up for registers no variable has.
+    temps: AHashSet<Ident>,
+    /// The line being decompiled.
+    line: usize,
+}
+
+impl Decompiler<'_> {
+    fn name(&mut self, reg: AnyReg) -> Ident {
+        let reg = Register::from(reg);
+        if let Some(name) = self.names.get(&reg) {
+            return name.clone();
+        }
+        let name = (self.names.len()..)
+            .map(|i| Ident::local(&format!("_{}", i)))
+            .find(|name| !self.taken.contains(name))
+            .unwrap();
+        self.taken.insert(name.clone());
+        self.temps.insert(name.clone());
+        self.names.insert(reg, name.clone());
+        name
+    }
+
+    /// Reads a register, as its value if it holds a constant.
+    fn expr(&mut self, reg: impl Into<AnyReg>) -> Expr {
+        let reg = reg.into();
+        if self.written.contains(&reg.into()) || self.names.contains_key(&reg.into()) {
+            return Expr::Ident(self.name(reg));
+        }
+        match reg {
+            AnyReg::Num(r) => Expr::Number(*self.machine.num_ref(r).unwrap()),
+            AnyReg::Str(r) => Expr::String(self.machine.str_ref(r).unwrap().clone()),
+            AnyReg::Val(r) => match &*self.machine.val_ref(r).unwrap() {
+                Value::Num(n) => Expr::Number(*n),
+                Value::Str(s) => Expr::String(s.clone()),
+            },
+        }
+    }
+
+    fn assign(&mut self, reg: impl Into<AnyReg>, expr: Expr) -> Statement {
+        Statement::Assign(self.name(reg.into()), None, expr)
+    }
+
+    fn modify(
+        &mut self,
+        reg: impl Into<AnyReg>,
+        op: AssignOp,
+        other: impl Into<AnyReg>,
+    ) -> Statement {
+        let other = self.expr(other);
+        Statement::Assign(self.name(reg.into()), Some(op), other)
+    }
+
+    fn unop(&mut self, reg: NumReg, op: Unop) -> Statement {
+        let expr = Expr::Unop(op, self.expr(reg).into());
+        self.assign(reg, expr)
+    }
+
+    fn binop(&mut self, l: impl Into<AnyReg>, op: Binop, r: impl Into<AnyReg>) -> Expr {
+        Expr::Binop(self.expr(l).into(), op, self.expr(r).into())
+    }
+
+    fn incdec(&mut self, reg: impl Into<AnyReg>, inc: bool) -> Statement {
+        Statement::Incdec(Incdec { ident: self.name(reg.into()), inc })
+    }
+
+    /// Where running on into `section` goes, as statements to finish the line with.
+    fn jump(&mut self, section: Section, depth: usize) -> Result<Vec<Statement>> {
+        if !self.machine.sections[section.0].line_start {
+            return self.section(section, 0, depth + 1);
+        }
+        let lines = &self.machine.lines;
+        let line = lines.iter().position(|&s| s == section).context("jump into a line")?;
+        Ok(if line == (self.line + 1) % lines.len() {
+            Vec::new()
+        } else {
+            vec![Statement::Goto(Expr::Number((line as i64 + 1).into()))]
+        })
+    }
+
+    /// The rest of the line from instruction `start` of `section`. Code both branches of an
+    /// `if` run on to is repeated in each.
+    fn section(&mut self, section: Section, start: usize, depth: usize) -> Result<Vec<Statement>> {
+        ensure!(depth <= self.machine.sections.len(), "line {} loops", self.line + 1);
+        let code = &self.machine.sections[section.0];
+        let mut stmts = Vec::new();
+        for (index, &instr) in code.instrs.iter().enumerate().skip(start) {
+            use Instruction::*;
+            stmts.push(match instr {
+                JumpSectionIf(target, cond) => {
+                    let cond = self.expr(cond);
+                    let taken = self.jump(target, depth)?;
+                    let not_taken = self.section(section, index + 1, depth)?;
+                    stmts.push(Statement::Ite(cond, taken, not_taken));
+                    return Ok(stmts);
+                },
+                JumpIfEq(target, l, r) | JumpIfLe(target, l, r) | JumpIfLt(target, l, r) => {
+                    let op = match instr {
+                        JumpIfEq(..) => Binop::Eq,
+                        JumpIfLe(..) => Binop::Le,
+                        _ => Binop::Lt,
+                    };
+                    let cond = self.binop(l, op, r);
+                    let taken = self.jump(target, depth)?;
+                    let not_taken = self.section(section, index + 1, depth)?;
+                    stmts.push(Statement::Ite(cond, taken, not_taken));
+                    return Ok(stmts);
+                },
+                // Yolol skips to the next line on an error by itself
+                JumpIfError(target) => {
+                    let next = self.machine.lines[(self.line + 1) % self.machine.lines.len()];
+                    ensure!(target == next, "line {} handles errors", self.line + 1);
+                    continue;
+                },
+                CopyNum(from, to) => {
+                    let from = self.expr(from);
+                    self.assign(to, from)
+                },
+                CopyStr(from, to) => {
+                    let from = self.expr(from);
+                    self.assign(to, from)
+                },
+                CopyVal(from, to) => {
+                    let from = self.expr(from);
+                    self.assign(to, from)
+                },
+                ValueifyNum(n, v) => {
+                    let n = self.expr(n);
+                    self.assign(v, n)
+                },
+                ValueifyStr(s, v) => {
+                    let s = self.expr(s);
+                    self.assign(v, s)
+                },
+                // negating twice changes nothing, but fails on a string
+                NumberifyVal(v, n) => {
+                    let v = self.expr(v);
+                    self.assign(n, Expr::Unop(Unop::Neg, Expr::Unop(Unop::Neg, v.into()).into()))
+                },
+                StringifyNum(n, s) => {
+                    let n = self.expr(n);
+                    self.assign(s, Expr::Binop(Expr::from("").into(), Binop::Add, n.into()))
+                },
+                StringifyVal(v, s) => {
+                    let v = self.expr(v);
+                    self.assign(s, Expr::Binop(Expr::from("").into(), Binop::Add, v.into()))
+                },
+                IsTruthyNum(n) => {
+                    let zero = Expr::Number(Number::ZERO);
+                    let expr = Expr::Binop(self.expr(n).into(), Binop::Ne, zero.into());
+                    self.assign(n, expr)
+                },
+                // only `if` has Yolol's idea of truth for strings
+                IsTruthyVal(v, n) => {
+                    let v = self.expr(v);
+                    let yes = self.assign(n, Number::ONE.into());
+                    let no = self.assign(n, Number::ZERO.into());
+                    Statement::Ite(v, vec![yes], vec![no])
+                },
+                NotNum(n) => self.unop(n, Unop::Not),
+                NotVal(v, n) => {
+                    let v = self.expr(v);
+                    self.assign(n, Expr::Unop(Unop::Not, v.into()))
+                },
+                AddNum(a, b) => self.modify(a, AssignOp::Add, b),
+                AddStr(a, b) => self.modify(a, AssignOp::Add, b),
+                AddVal(a, b) => self.modify(a, AssignOp::Add, b),
+                SubNum(a, b) => self.modify(a, AssignOp::Sub, b),
+                SubStr(a, b) => self.modify(a, AssignOp::Sub, b),
+                SubVal(a, b) => self.modify(a, AssignOp::Sub, b),
+                Mul(a, b) => self.modify(a, AssignOp::Mul, b),
+                Div(a, b) => self.modify(a, AssignOp::Div, b),
+                Rem(a, b) => self.modify(a, AssignOp::Mod, b),
+                Pow(a, b) => self.modify(a, AssignOp::Pow, b),
+                Eq(l, r, out) => {
+                    let expr = self.binop(l, Binop::Eq, r);
+                    self.assign(out, expr)
+                },
+                Le(l, r, out) => {
+                    let expr = self.binop(l, Binop::Le, r);
+                    self.assign(out, expr)
+                },
+                Lt(l, r, out) => {
+                    let expr = self.binop(l, Binop::Lt, r);
+                    self.assign(out, expr)
+                },
+                IncNum(n) => self.incdec(n, true),
+                IncStr(s) => self.incdec(s, true),
+                IncVal(v) => self.incdec(v, true),
+                DecNum(n) => self.incdec(n, false),
+                DecStr(s) => self.incdec(s, false),
+                DecVal(v) => self.incdec(v, false),
+                Abs(n) => self.unop(n, Unop::Abs),
+                Fact(n) => self.unop(n, Unop::Fact),
+                Sqrt(n) => self.unop(n, Unop::Sqrt),
+                Sin(n) => self.unop(n, Unop::Sin),
+                Cos(n) => self.unop(n, Unop::Cos),
+                Tan(n) => self.unop(n, Unop::Tan),
+                Asin(n) => self.unop(n, Unop::Asin),
+                Acos(n) => self.unop(n, Unop::Acos),
+                Atan(n) => self.unop(n, Unop::Atan),
+                Exp(n) => self.unop(n, Unop::Exp),
+                Ln(n) => self.unop(n, Unop::Ln),
+                Log10(n) => self.unop(n, Unop::Log10),
+                Log(n, base) => {
+                    let expr = self.binop(n, Binop::Log, base);
+                    self.assign(n, expr)
+                },
+                Neg(n) => self.unop(n, Unop::Neg),
+                And(a, b) => {
+                    let expr = self.binop(a, Binop::And, b);
+                    self.assign(a, expr)
+                },
+                Or(a, b) => {
+                    let expr = self.binop(a, Binop::Or, b);
+                    self.assign(a, expr)
+                },
+                SelectNum(cond, if_true, if_false, out) => {
+                    let cond = self.expr(cond);
+                    let if_true = self.expr(if_true);
+                    let if_false = self.expr(if_false);
+                    let if_true = self.assign(out, if_true);
+                    let if_false = self.assign(out, if_false);
+                    Statement::Ite(cond, vec![if_true], vec![if_false])
+                },
+            });
+        }
+        match code.success {
+            s if s == SUCCESS_NEEDS_FIXING => {
+                bail!("line {} runs into missing code", self.line + 1)
+            },
+            SectionOrLine::Section(s) => stmts.extend(self.jump(s, depth)?),
+            SectionOrLine::Line(l) => stmts.push(Statement::Goto(self.expr(l))),
+        }
+        Ok(stmts)
+    }
+}
+
+/// How many times `expr` reads `ident`.
+fn reads(expr: &Expr, ident: &Ident) -> usize {
+    match expr {
+        Expr::Binop(l, _, r) => reads(l, ident) + reads(r, ident),
+        Expr::Unop(_, e) => reads(e, ident),
+        Expr::Incdec(incdec) => (incdec.ident == *ident) as usize,
+        Expr::Ident(i) => (i == ident) as usize,
+        Expr::Number(_) | Expr::String(_) => 0,
+    }
+}
+
+/// How many times `stmt` reads `ident`, and how many of those are in an expression outside the
+/// branches of an `if`, which a value can be put in place of.
+fn stmt_reads(stmt: &Statement, ident: &Ident) -> (usize, usize) {
+    match stmt {
+        Statement::Goto(e) => (reads(e, ident), reads(e, ident)),
+        Statement::Ite(cond, t, e) => {
+            let nested: usize = t.iter().chain(e).map(|s| stmt_reads(s, ident).0).sum();
+            (reads(cond, ident) + nested, reads(cond, ident))
+        },
+        Statement::Incdec(incdec) => ((incdec.ident == *ident) as usize, 0),
+        Statement::Assign(to, op, e) => {
+            let modified = (op.is_some() && to == ident) as usize;
+            (reads(e, ident) + modified, reads(e, ident))
+        },
+    }
+}
+
+fn replace(expr: &mut Expr, ident: &Ident, value: &Expr) {
+    match expr {
+        Expr::Binop(l, _, r) => {
+            replace(l, ident, value);
+            replace(r, ident, value);
+        },
+        Expr::Unop(_, e) => replace(e, ident, value),
+        Expr::Ident(i) if i == ident => *expr = value.clone(),
+        _ => (),
+    }
+}
+
+/// Whether `expr` always gives a number.
+fn numeric(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Unop(..) => true,
+        Expr::Binop(l, Binop::Add | Binop::Sub, r) => numeric(l) && numeric(r),
+        Expr::Binop(..) => true,
+        _ => false,
+    }
+}
+
+/// Whether `op` only takes numbers, failing on strings.
+fn arithmetic(op: Binop) -> bool {
+    matches!(op, Binop::Mul | Binop::Div | Binop::Mod | Binop::Pow | Binop::Log)
+}
+
+/// Whether `expr` always gives 0 or 1.
+fn boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::Unop(op, _) => *op == Unop::Not,
+        Expr::Binop(_, op, _) => !matches!(op, Binop::Add | Binop::Sub) && !arithmetic(*op),
+        _ => false,
+    }
+}
+
+/// Whether `stmt` sets `ident` whichever way it goes.
+fn defines(stmt: &Statement, ident: &Ident) -> bool {
+    match stmt {
+        Statement::Assign(to, _, _) => to == ident,
+        Statement::Ite(_, t, e) => [t, e].iter().all(|b| b.iter().any(|s| defines(s, ident))),
+        _ => false,
+    }
+}
+
+/// Whether `stmt` only sets temporaries other than `temp`, and none which `value` reads, so
+/// `temp = value` can move past it.
+fn only_sets_temps(
+    stmt: &Statement,
+    temp: &Ident,
+    value: &Expr,
+    temps: &AHashSet<Ident>,
+) -> bool {
+    match stmt {
+        Statement::Assign(to, _, _) => temps.contains(to) && to != temp && reads(value, to) == 0,
+        Statement::Ite(_, t, e) => {
+            t.iter().chain(e).all(|s| only_sets_temps(s, temp, value, temps))
+        },
+        _ => false,
+    }
+}
+
+fn binop(op: AssignOp) -> Binop {
+    match op {
+        AssignOp::Add => Binop::Add,
+        AssignOp::Sub => Binop::Sub,
+        AssignOp::Mul => Binop::Mul,
+        AssignOp::Div => Binop::Div,
+        AssignOp::Mod => Binop::Mod,
+        AssignOp::Pow => Binop::Pow,
+    }
+}
+
+/// Adds to `exposed` the temporaries `stmts` can read before setting, given those `set` already.
+fn exposed_reads(
+    stmts: &[Statement],
+    temps: &AHashSet<Ident>,
+    set: &mut AHashSet<Ident>,
+    exposed: &mut AHashSet<Ident>,
+) {
+    for stmt in stmts {
+        for temp in temps.iter().filter(|t| !set.contains(*t)) {
+            let read = match stmt {
+                Statement::Ite(cond, ..) => reads(cond, temp) > 0,
+                _ => stmt_reads(stmt, temp).0 > 0,
+            };
+            if read {
+                exposed.insert(temp.clone());
+            }
+        }
+        if let Statement::Ite(_, t, e) = stmt {
+            for branch in [t, e] {
+                exposed_reads(branch, temps, &mut set.clone(), exposed);
+            }
+        }
+        set.extend(temps.iter().filter(|t| defines(stmt, t)).cloned());
+    }
+}
+
+/// Where the temporary set by `stmts[i]` can be moved to: the one statement reading it. It
+/// only moves past other temporaries being set, so an error still stops the line before
+/// anything which outlasts it changes.
+fn fold_target(stmts: &[Statement], i: usize, temps: &AHashSet<Ident>) -> Option<usize> {
+    let Statement::Assign(temp, None, value) = &stmts[i] else {
+        return None;
+    };
+    if !temps.contains(temp) {
+        return None;
+    }
+    for (j, stmt) in stmts.iter().enumerate().skip(i + 1) {
+        match stmt_reads(stmt, temp) {
+            (0, _) => (),
+            (1, 1) => {
+                let unread = defines(stmt, temp) || stmts[j + 1..]
+                    .iter()
+                    .find(|s| stmt_reads(s, temp).0 > 0 || defines(s, temp))
+                    .is_none_or(|s| stmt_reads(s, temp).0 == 0);
+                return unread.then_some(j);
+            },
+            _ => return None,
+        }
+        if !only_sets_temps(stmt, temp, value, temps) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Puts temporaries read once back into the expression reading them, so lines read more like
+/// the source they came from.
+fn fold(stmts: &mut Vec<Statement>, temps: &AHashSet<Ident>) {
+    for stmt in stmts.iter_mut() {
+        match stmt {
+            Statement::Ite(_, t, e) => {
+                fold(t, temps);
+                fold(e, temps);
+            },
+            Statement::Assign(to, op @ Some(_), e) if temps.contains(to) => {
+                let r = std::mem::replace(e, Expr::Number(Number::ZERO));
+                let l = Expr::Ident(to.clone()).into();
+                *e = Expr::Binop(l, binop(op.take().unwrap()), r.into());
+            },
+            _ => (),
+        }
+    }
+    loop {
+        let Some((i, j)) = (0..stmts.len())
+            .find_map(|i| fold_target(stmts, i, temps).map(|j| (i, j)))
+        else {
+            // what's left of working out whether something is true
+            let Some(stmt) = stmts.iter_mut().find(|s| truth(s).is_some()) else {
+                return;
+            };
+            let (to, cond) = truth(stmt).unwrap();
+            *stmt = Statement::Assign(to.clone(), None, cond.clone());
+            continue;
+        };
+        let Statement::Assign(temp, _, value) = stmts.remove(i) else {
+            unreachable!()
+        };
+        match &mut stmts[j - 1] {
+            Statement::Goto(e) | Statement::Ite(e, _, _) | Statement::Assign(_, _, e) => {
+                replace(e, &temp, &value)
+            },
+            Statement::Incdec(_) => unreachable!(),
+        }
+    }
+}
+
+/// `if cond then to = 1 else to = 0 end`, where `cond` is already 0 or 1.
+fn truth(stmt: &Statement) -> Option<(&Ident, &Expr)> {
+    let Statement::Ite(cond, t, e) = stmt else {
+        return None;
+    };
+    let [Statement::Assign(to, None, Expr::Number(one))] = &t[..] else {
+        return None;
+    };
+    let [Statement::Assign(other, None, Expr::Number(zero))] = &e[..] else {
+        return None;
+    };
+    (boolean(cond) && to == other && *one == Number::ONE && *zero == Number::ZERO)
+        .then_some((to, cond))
+}
+
+/// Drops `- -x`, which only makes `x` a number, where it's one already or `needs_number` says
+/// the operator taking it would fail on a string anyway.
+fn drop_numberify(expr: &mut Expr, needs_number: bool) {
+    while let Expr::Unop(Unop::Neg, e) = expr {
+        let Expr::Unop(Unop::Neg, inner) = &mut **e else {
+            break;
+        };
+        if !(numeric(inner) || needs_number) {
+            break;
+        }
+        *expr = std::mem::replace(&mut **inner, Expr::Number(Number::ZERO));
+    }
+    match expr {
+        Expr::Binop(l, op, r) => {
+            drop_numberify(l, arithmetic(*op));
+            drop_numberify(r, arithmetic(*op));
+        },
+        Expr::Unop(op, e) => drop_numberify(e, *op != Unop::Not),
+        _ => (),
+    }
+}
+
+fn drop_numberifies(stmts: &mut [Statement]) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Ite(cond, t, e) => {
+                drop_numberify(cond, false);
+                drop_numberifies(t);
+                drop_numberifies(e);
+            },
+            Statement::Goto(e) => drop_numberify(e, false),
+            Statement::Assign(_, op, e) => {
+                let needs = op.is_some_and(|op| !matches!(op, AssignOp::Add | AssignOp::Sub));
+                drop_numberify(e, needs)
+            },
+            Statement::Incdec(_) => (),
+        }
+    }
+}
+
+impl IRMachine {
+    /// Turns the code back into Yolol source, for getting it into the game after changing it,
+    /// with the same number of lines. Variables keep their names, temporaries read once go back
+    /// into the expression reading them, and the rest become locals named `_0`, `_1` and so on.
+    /// Registers start as Yolol variables do, so this should
+    /// be called before running, and comments and the [`GotoPolicy`] aren't kept. Fails on code
+    /// Yolol can't express, like a line looping within itself, which the compiler never makes.
+    ///
+    /// The result can be too long for a chip, which [`IRMachine::decompile_with_warnings`]
+    /// reports. [`IRMachine::decompile_source`] prints it as a chip would hold it.
+    pub fn decompile(&self) -> Result<Program> {
+        let mut decompiler = Decompiler {
+            machine: self,
+            written: self.sections
+                .iter()
+                .flat_map(|s| s.instrs.iter())
+                .filter_map(|i| i.modifies())
+                .map(Register::from)
+                .collect(),
+            names: self.idents.iter().map(|(i, &r)| (r.into(), i.clone())).collect(),
+            taken: self.idents.keys().cloned().collect(),
+            temps: AHashSet::new(),
+            line: 0,
+        };
+        let mut lines = Vec::with_capacity(self.lines.len());
+        for (line, &section) in self.lines.iter().enumerate() {
+            decompiler.line = line;
+            let stmts = decompiler.section(section, 0, 0)?;
+            lines.push(Line { stmts, ..Default::default() });
+        }
+
+        // only temporaries each line sets before reading can be folded away
+        let mut exposed = AHashSet::new();
+        for line in lines.iter() {
+            exposed_reads(line, &decompiler.temps, &mut AHashSet::new(), &mut exposed);
+        }
+        let temps = decompiler.temps.difference(&exposed).cloned().collect();
+        for line in lines.iter_mut() {
+            fold(line, &temps);
+            drop_numberifies(line);
+        }
+        Ok(Program { lines })
+    }
+
+    /// Like [`IRMachine::decompile`], printed with every space the game can do without.
+    pub fn decompile_source(&self) -> Result<String> {
+        let program = self.decompile()?;
+        let used = program.iter().rposition(|line| !line.is_empty()).map_or(0, |i| i + 1);
+        Ok(program[..used].iter().map(opt::compact).collect::<Vec<_>>().join("\n"))
+    }
+
+    /// Like [`IRMachine::decompile`], also warning about every line longer than a chip allows,
+    /// and lines past the last a chip has.
+    pub fn decompile_with_warnings(&self) -> Result<(Program, Vec<Diagnostic>)> {
+        let program = self.decompile()?;
+        let limits = YololParser::default();
+        let mut warnings = Vec::new();
+        for (i, line) in program.iter().enumerate().filter(|(_, line)| !line.is_empty()) {
+            let length = opt::compact(line).len();
+            let message = if length > limits.max_line_length {
+                format!("{} characters long, over the limit of {}", length, limits.max_line_length)
+            } else if i >= limits.max_lines {
+                format!("past the last line of a chip, {}", limits.max_lines)
+            } else {
+                continue;
+            };
+            warnings.push(Diagnostic { severity: Severity::Warning, line: i + 1, message });
+        }
+        Ok((program, warnings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::*;
+
+    #[test]
+    fn decompiles_to_equivalent_source() {
+        let src = "a=:x*2+1 s=\"n=\"+a :out=s if a>5 and :y then :z=1 else :z=2 goto 3 end\n\
+            b=sqrt a c=b! d=b^2 :w=not :y e=-b :v=e+(:x==1) t=\"ab\" t-- :t=t\n\
+            :x++ :y=:x%3 goto 1+(:x>3) \n:end=1";
+        let program = YololParser::default().parse(src).unwrap();
+        for protect_locals in [false, true] {
+            let options = CodegenOptions { protect_locals, ..Default::default() };
+            let machine = IRMachine::from_ast(options, program.clone());
+            assert_eq!(machine.decompile().unwrap().len(), program.len());
+            let text = machine.decompile_source().unwrap();
+
+            let reparsed = YololParser::unrestricted().parse(&text).unwrap();
+            let mut original = SimpleInterp::new(program.clone());
+            let mut copy = SimpleInterp::new(reparsed);
+            for _ in 0..30 {
+                original.step_line();
+                copy.step_line();
+                assert_eq!(copy.line(), original.line(), "{}", text);
+                let globals = |i: &SimpleInterp| {
+                    let mut globals: Vec<_> = i.values()
+                        .iter()
+                        .filter(|(ident, _)| ident.global)
+                        .map(|(ident, v)| (ident.clone(), v.clone()))
+                        .collect();
+                    globals.sort_by(|a, b| a.0.cmp(&b.0));
+                    globals
+                };
+                assert_eq!(globals(&copy), globals(&original), "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn decompiles_compactly() {
+        let src = ":a=:x-(-5) :b=-3*:x\nc=:a*2 :out=\"c=\"+c+\" \"+(c>4) goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program.clone());
+        let text = machine.decompile_source().unwrap();
+        assert_eq!(text, ":a=:x- -5:b=-3*:x\n_9=:a*2:out=\"c=\"+_9+\" \"+4<_9 goto 1");
+        assert!(text.lines().all(|line| line.len() <= 70), "{}", text);
+
+        // temporaries which later lines read are kept
+        let src = "x=:a%7 y=3 if x==y then goto 3 end\n:o=1\n:p=x+y";
+        let program = YololParser::default().parse(src).unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program);
+        let text = machine.decompile_source().unwrap();
+        assert_eq!(text, "_8=:a%7 _9=3 if _8==_9 then goto 3 end\n:o=1\n:p=_8+_9");
+    }
+
+    #[test]
+    fn warns_about_limits() {
+        let long = format!(":a=\"{}\"", "x".repeat(66));
+        let src = format!("{}\n{}:b=1", long, "\n".repeat(20));
+        let program = YololParser::unrestricted().parse(&src).unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program);
+        let (_, warnings) = machine.decompile_with_warnings().unwrap();
+        let lines: Vec<_> = warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, [1, 22], "{:?}", warnings);
+    }
+}
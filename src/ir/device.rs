@@ -0,0 +1,123 @@
+use super::*;
+
+/// A global backed by user code rather than plain storage, like a panel or sensor, attached
+/// with [`IRMachine::attach_device`].
+pub trait DeviceField: Send + Sync {
+    /// The value the field holds as a line starts. Like [`IRMachine::set_ident`], this panics
+    /// if the field's register can't hold it.
+    fn read(&mut self) -> Value;
+
+    /// Called as a line finishes if it left the field holding something other than what
+    /// [`DeviceField::read`] gave.
+    fn write(&mut self, value: &Value);
+}
+
+/// Set by [`IRMachine::attach_device`]. Clones and forks start without any.
+pub(super) struct Device {
+    field: Ident,
+    device: Box<dyn DeviceField>,
+    /// What the device gave when the line started.
+    read: Value,
+}
+
+impl std::fmt::Debug for Device {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Device({})", self.field)
+    }
+}
+
+impl IRMachine {
+    /// Backs `field` with `device`, replacing any device already attached to it. Returns
+    /// false, without attaching it, if the machine doesn't protect `field` (see
+    /// [`CodegenOptions`]).
+    pub fn attach_device(&mut self, field: &Ident, device: impl DeviceField + 'static) -> bool {
+        debug_assert!(field.global, "tried to attach a device to local '{}'", field);
+        if !self.idents.contains_key(field) {
+            return false;
+        }
+        self.detach_device(field);
+        self.devices.push(Device {
+            field: field.clone(),
+            device: Box::new(device),
+            read: Value::default(),
+        });
+        true
+    }
+
+    /// Returns the device backing `field`, if there is one. The field keeps its last value.
+    pub fn detach_device(&mut self, field: &Ident) -> Option<Box<dyn DeviceField>> {
+        let index = self.devices.iter().position(|d| d.field == *field)?;
+        Some(self.devices.remove(index).device)
+    }
+
+    pub(super) fn read_devices(&mut self) {
+        let mut devices = std::mem::take(&mut self.devices);
+        for device in devices.iter_mut() {
+            device.read = device.device.read();
+            self.set_ident(&device.field, device.read.clone());
+        }
+        self.devices = devices;
+    }
+
+    pub(super) fn write_devices(&mut self) {
+        let mut devices = std::mem::take(&mut self.devices);
+        for device in devices.iter_mut() {
+            let value = self.get_ident_value(&device.field);
+            if value != device.read {
+                device.device.write(&value);
+            }
+        }
+        self.devices = devices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use parser::YololParser;
+    use super::*;
+
+    struct Sensor {
+        reading: i64,
+        written: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl DeviceField for Sensor {
+        fn read(&mut self) -> Value {
+            self.reading += 1;
+            Value::Num(self.reading.into())
+        }
+
+        fn write(&mut self, value: &Value) {
+            self.written.lock().unwrap().push(value.clone());
+        }
+    }
+
+    #[test]
+    fn device_fields() {
+        let src = ":out=:sensor*2\n:sensor=0\n:sensor=:sensor goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sensor = Sensor { reading: 0, written: written.clone() };
+        let field = Ident::global("sensor");
+        assert!(machine.attach_device(&field, sensor));
+        assert!(!machine.attach_device(&Ident::global("nope"), Sensor {
+            reading: 0,
+            written: written.clone(),
+        }));
+
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("out")), Value::Num(2.into()));
+        machine.step_line();
+        machine.step();
+        assert_eq!(*written.lock().unwrap(), [Value::Num(0.into())]);
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("out")), Value::Num(8.into()));
+
+        assert!(machine.detach_device(&field).is_some());
+        assert!(machine.clone().devices.is_empty());
+        machine.step_repeat(3);
+        assert_eq!(written.lock().unwrap().len(), 1);
+    }
+}
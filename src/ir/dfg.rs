@@ -0,0 +1,146 @@
+use export::{Attr, Export};
+use super::*;
+
+/// An instruction of an [`IRMachine`], by section and index within it. An index one past the
+/// section's last instruction stands for the computed `goto` ending it, which reads the line
+/// register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrRef {
+    pub section: usize,
+    pub index: usize,
+}
+
+/// Which instructions write and read each register. It doesn't follow control flow, so a
+/// read may see any of the register's definitions, or its value from before the tick.
+#[derive(Debug, Clone, Default)]
+pub struct DataFlowGraph {
+    defs: AHashMap<Register, Vec<InstrRef>>,
+    uses: AHashMap<Register, Vec<InstrRef>>,
+}
+
+impl DataFlowGraph {
+    /// The instructions writing `reg`, in order.
+    pub fn defs(&self, reg: Register) -> &[InstrRef] {
+        self.defs.get(&reg).map_or(&[], Vec::as_slice)
+    }
+
+    /// The instructions reading `reg`, in order.
+    pub fn uses(&self, reg: Register) -> &[InstrRef] {
+        self.uses.get(&reg).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every register read or written by some instruction, in order.
+    pub fn registers(&self) -> impl Iterator<Item = Register> + '_ {
+        let unread = self.defs.keys().filter(|reg| !self.uses.contains_key(reg));
+        let mut registers: Vec<_> = self.uses.keys().chain(unread).copied().collect();
+        registers.sort_unstable();
+        registers.into_iter()
+    }
+
+    /// The graph as GraphML, for tools like Gephi. Registers are nodes `n{index}`, `s{index}`
+    /// or `v{index}` by file, instructions are `i{section}_{index}`, and edges from definitions
+    /// to registers and registers to uses have a `kind` of `def` or `use`.
+    pub fn to_graphml(&self) -> String {
+        self.export().graphml("dfg")
+    }
+
+    /// The graph as JSON, with a list of `nodes` and `edges`, each with the same attributes
+    /// as [`DataFlowGraph::to_graphml`].
+    pub fn to_json(&self) -> String {
+        self.export().json()
+    }
+
+    fn export(&self) -> Export {
+        let mut export = Export::default();
+        let mut instrs: Vec<InstrRef> = self.defs.values().chain(self.uses.values())
+            .flatten()
+            .copied()
+            .collect();
+        instrs.sort_unstable();
+        instrs.dedup();
+        let instr_id = |at: InstrRef| format!("i{}_{}", at.section, at.index);
+        for &at in instrs.iter() {
+            let attrs = vec![
+                ("kind", Attr::Str("instruction")),
+                ("section", Attr::Int(at.section)),
+                ("index", Attr::Int(at.index)),
+            ];
+            export.nodes.push((instr_id(at), attrs));
+        }
+        for reg in self.registers() {
+            let (file, prefix, index) = match reg {
+                Register::Number(i) => ("number", 'n', i),
+                Register::String(i) => ("string", 's', i),
+                Register::Value(i) => ("value", 'v', i),
+            };
+            let id = format!("{}{}", prefix, index);
+            let attrs = vec![
+                ("kind", Attr::Str("register")),
+                ("file", Attr::Str(file)),
+                ("index", Attr::Int(index)),
+            ];
+            export.nodes.push((id.clone(), attrs));
+            for &at in self.defs(reg) {
+                export.edges.push((instr_id(at), id.clone(), vec![("kind", Attr::Str("def"))]));
+            }
+            for &at in self.uses(reg) {
+                export.edges.push((id.clone(), instr_id(at), vec![("kind", Attr::Str("use"))]));
+            }
+        }
+        export
+    }
+}
+
+impl IRMachine {
+    pub fn data_flow_graph(&self) -> DataFlowGraph {
+        let mut dfg = DataFlowGraph::default();
+        for (section, code) in self.sections.iter().enumerate() {
+            for (index, instr) in code.instrs.iter().enumerate() {
+                let at = InstrRef { section, index };
+                for reg in instr.reads() {
+                    dfg.uses.entry(reg.into()).or_default().push(at);
+                }
+                if let Some(reg) = instr.modifies() {
+                    dfg.defs.entry(reg.into()).or_default().push(at);
+                }
+            }
+            if let SectionOrLine::Line(n) = code.success {
+                let at = InstrRef { section, index: code.instrs.len() };
+                dfg.uses.entry(AnyReg::from(n).into()).or_default().push(at);
+            }
+        }
+        dfg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn defs_and_uses() {
+        let program = YololParser::default().parse(":a=1 :b=:a+1 goto :b").unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program);
+        let dfg = machine.data_flow_graph();
+        let a = machine.ident_register(&Ident::global("a")).unwrap();
+        let b = machine.ident_register(&Ident::global("b")).unwrap();
+        assert_eq!(dfg.defs(a).len(), 1);
+        assert_eq!(dfg.defs(b).len(), 1);
+        assert!(!dfg.uses(a).is_empty());
+        assert!(dfg.uses(a)[0] > dfg.defs(a)[0]);
+        // the goto's line register is read when the section ends
+        let goto = dfg
+            .registers()
+            .flat_map(|reg| dfg.uses(reg))
+            .find(|at| at.index == machine.section_len(at.section));
+        assert!(goto.is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&dfg.to_json()).unwrap();
+        let edges = json["edges"].as_array().unwrap();
+        let count = |kind: &str| edges.iter().filter(|e| e["attributes"]["kind"] == kind).count();
+        assert_eq!(count("def"), dfg.registers().map(|r| dfg.defs(r).len()).sum::<usize>());
+        assert_eq!(count("use"), dfg.registers().map(|r| dfg.uses(r).len()).sum::<usize>());
+        assert!(dfg.to_graphml().contains(r#"<data key="node_kind">register</data>"#));
+    }
+}
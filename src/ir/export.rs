@@ -0,0 +1,114 @@
+use std::fmt::Write as _;
+use serde_json::{Map, Value as Json, json};
+
+/// An attribute of a node or edge of an exported graph.
+#[derive(Debug, Clone)]
+pub(super) enum Attr {
+    Int(usize),
+    Bool(bool),
+    Str(&'static str),
+}
+
+impl Attr {
+    const fn graphml_type(&self) -> &'static str {
+        match self {
+            Attr::Int(_) => "int",
+            Attr::Bool(_) => "boolean",
+            Attr::Str(_) => "string",
+        }
+    }
+
+    fn json(&self) -> Json {
+        match *self {
+            Attr::Int(i) => i.into(),
+            Attr::Bool(b) => b.into(),
+            Attr::Str(s) => s.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Attr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Attr::Int(i) => write!(f, "{}", i),
+            Attr::Bool(b) => write!(f, "{}", b),
+            Attr::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+pub(super) type Attrs = Vec<(&'static str, Attr)>;
+
+/// A graph flattened for writing out, with ids made only of letters, digits and `_`, so
+/// nothing needs escaping.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Export {
+    pub(super) nodes: Vec<(String, Attrs)>,
+    pub(super) edges: Vec<(String, String, Attrs)>,
+}
+
+impl Export {
+    /// Every attribute name, with the type of its first value, for nodes then edges.
+    fn keys(&self) -> Vec<(&'static str, &'static str, &'static str)> {
+        let mut keys = Vec::new();
+        let nodes = self.nodes.iter().map(|(_, attrs)| ("node", attrs));
+        let edges = self.edges.iter().map(|(_, _, attrs)| ("edge", attrs));
+        for (of, attrs) in nodes.chain(edges) {
+            for (name, attr) in attrs {
+                if !keys.iter().any(|&(o, n, _)| o == of && n == *name) {
+                    keys.push((of, *name, attr.graphml_type()));
+                }
+            }
+        }
+        keys
+    }
+
+    pub(super) fn graphml(&self, id: &str) -> String {
+        let mut out = String::new();
+        let data = |out: &mut String, of: &str, attrs: &Attrs| {
+            for (name, attr) in attrs {
+                write!(out, "<data key=\"{}_{}\">{}</data>", of, name, attr).unwrap();
+            }
+        };
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(out, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">").unwrap();
+        for (of, name, ty) in self.keys() {
+            writeln!(
+                out,
+                "  <key id=\"{0}_{1}\" for=\"{0}\" attr.name=\"{1}\" attr.type=\"{2}\"/>",
+                of, name, ty,
+            ).unwrap();
+        }
+        writeln!(out, "  <graph id=\"{}\" edgedefault=\"directed\">", id).unwrap();
+        for (id, attrs) in self.nodes.iter() {
+            write!(out, "    <node id=\"{}\">", id).unwrap();
+            data(&mut out, "node", attrs);
+            writeln!(out, "</node>").unwrap();
+        }
+        for (source, target, attrs) in self.edges.iter() {
+            write!(out, "    <edge source=\"{}\" target=\"{}\">", source, target).unwrap();
+            data(&mut out, "edge", attrs);
+            writeln!(out, "</edge>").unwrap();
+        }
+        writeln!(out, "  </graph>").unwrap();
+        writeln!(out, "</graphml>").unwrap();
+        out
+    }
+
+    pub(super) fn json(&self) -> String {
+        let attrs = |attrs: &Attrs| -> Map<String, Json> {
+            attrs.iter().map(|(name, attr)| (name.to_string(), attr.json())).collect()
+        };
+        let nodes: Vec<_> = self.nodes
+            .iter()
+            .map(|(id, a)| json!({ "id": id, "attributes": attrs(a) }))
+            .collect();
+        let edges: Vec<_> = self.edges
+            .iter()
+            .map(|(source, target, a)| {
+                json!({ "source": source, "target": target, "attributes": attrs(a) })
+            })
+            .collect();
+        json!({ "directed": true, "nodes": nodes, "edges": edges }).to_string()
+    }
+}
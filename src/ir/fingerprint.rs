@@ -0,0 +1,91 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use super::*;
+
+/// Renumbers registers in the order they're first used, so lines doing the same thing to
+/// different variables look the same.
+#[derive(Default)]
+struct Renamer {
+    numbers: AHashMap<usize, usize>,
+    strings: AHashMap<usize, usize>,
+    values: AHashMap<usize, usize>,
+}
+
+fn renumber(map: &mut AHashMap<usize, usize>, reg: &mut usize) {
+    let len = map.len();
+    *reg = *map.entry(*reg).or_insert(len);
+}
+
+impl Renamer {
+    fn instr(&mut self, mut instr: Instruction) -> Instruction {
+        for r in instr.get_mut_num_regs() {
+            renumber(&mut self.numbers, &mut r.0);
+        }
+        for r in instr.get_mut_str_regs() {
+            renumber(&mut self.strings, &mut r.0);
+        }
+        for r in instr.get_mut_val_regs() {
+            renumber(&mut self.values, &mut r.0);
+        }
+        instr
+    }
+}
+
+impl IRMachine {
+    /// A hash of the shape of each line's data flow, ignoring which variables and constants it
+    /// uses, or `None` for lines with no code. Lines with the same fingerprint almost certainly
+    /// compute the same way, so can be used to spot copied code.
+    pub fn line_fingerprints(&self) -> Vec<Option<u64>> {
+        let cfg = self.control_flow_graph();
+        (0..self.lines.len())
+            .map(|line| {
+                let sections = self.line_sections(&cfg, line);
+                // jumps out of the line all look alike
+                let local =
+                    |s: Section| Section(sections.iter().position(|&l| l == s.0).unwrap_or(!0));
+                let mut renamer = Renamer::default();
+                let mut hasher = DefaultHasher::new();
+                let mut empty = true;
+                for &s in sections.iter() {
+                    let section = &self.sections[s];
+                    for &instr in section.instrs.iter() {
+                        let mut instr = renamer.instr(instr);
+                        if let Some(s) = instr.get_mut_section() {
+                            *s = local(*s);
+                        }
+                        instr.hash(&mut hasher);
+                        empty = false;
+                    }
+                    match section.success {
+                        SectionOrLine::Section(s) => (0_u8, local(s).0).hash(&mut hasher),
+                        SectionOrLine::Line(mut n) => {
+                            empty = false;
+                            renumber(&mut renamer.numbers, &mut n.0);
+                            (1_u8, n.0).hash(&mut hasher);
+                        },
+                    }
+                }
+                (!empty).then(|| hasher.finish())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn same_shape_same_fingerprint() {
+        let src = ":a=:b*2 :c++\n:x=:y*7 :z++\n:x=:y/7 :z++\n\n\
+                   if :a then goto 1 end\nif :q then goto 1 end";
+        let program = YololParser::default().parse(src).unwrap();
+        let fingerprints = IRMachine::from_ast(Default::default(), program).line_fingerprints();
+        assert_eq!(fingerprints[0], fingerprints[1]);
+        assert_ne!(fingerprints[1], fingerprints[2]);
+        assert_eq!(fingerprints[3], None);
+        assert!(fingerprints[4].is_some());
+        assert_eq!(fingerprints[4], fingerprints[5]);
+    }
+}
@@ -0,0 +1,302 @@
+use petgraph::Direction;
+use cfg::ControlFlowGraph;
+use dfg::{DataFlowGraph, InstrRef};
+use pass::can_error;
+use super::*;
+
+/// A [`Pass`] working out everything which only depends on constants, and rewriting it as a
+/// copy of the result. Branches on constants are resolved, error checks after code which can't
+/// fail are dropped, and writes overwritten before anything reads them are removed.
+///
+/// Values are followed through each section, and into sections only one other jumps to.
+/// Registers belonging to variables are never constant, as the host can change them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &str {
+        "constant folding"
+    }
+
+    fn run(
+        &mut self,
+        machine: &mut IRMachine,
+        cfg: &ControlFlowGraph,
+        _: &DataFlowGraph,
+    ) -> PassSummary {
+        let mut folder = Folder::new(machine);
+        let sections = folder.machine.sections.len();
+        let single_entry: Vec<_> = (0..sections)
+            .map(|s| {
+                let entries = cfg.graph().neighbors_directed(cfg.section(s), Direction::Incoming);
+                !folder.machine.sections[s].line_start && entries.count() == 1
+            })
+            .collect();
+
+        // a section with one way in goes after it, to start with what's known there
+        let mut pending: Vec<_> = (0..sections).collect();
+        let mut entry_states = AHashMap::new();
+        while !pending.is_empty() {
+            let next = pending
+                .iter()
+                .position(|s| !single_entry[*s] || entry_states.contains_key(s))
+                .unwrap_or(0);
+            let section = pending.remove(next);
+            let known = entry_states.remove(&section).unwrap_or_else(|| folder.constants.clone());
+            for (target, known) in folder.fold_section(section, known) {
+                if single_entry[target.0] {
+                    entry_states.insert(target.0, known);
+                }
+            }
+        }
+        folder.summary
+    }
+}
+
+type Known = AHashMap<Register, Value>;
+
+struct Folder<'a> {
+    machine: &'a mut IRMachine,
+    /// Runs single instructions, so they do exactly what they would when running.
+    scratch: IRMachine,
+    /// Registers nothing writes to.
+    constants: Known,
+    numbers: AHashMap<Number, NumReg>,
+    strings: AHashMap<YString, StrReg>,
+    values: AHashMap<Value, ValReg>,
+    summary: PassSummary,
+}
+
+/// The string in a value known for a string register.
+fn string(value: Value) -> YString {
+    match value {
+        Value::Str(s) => s,
+        Value::Num(_) => unreachable!("string register holding a number"),
+    }
+}
+
+impl<'a> Folder<'a> {
+    fn new(machine: &'a mut IRMachine) -> Self {
+        let mut variable: Vec<Register> = machine.sections
+            .iter()
+            .flat_map(|s| s.instrs.iter())
+            .filter_map(|i| i.modifies())
+            .chain(machine.idents.values().copied())
+            .map(Register::from)
+            .collect();
+        variable.sort_unstable();
+        variable.dedup();
+        let registers = (0..machine.numbers.len())
+            .map(|n| AnyReg::from(NumReg(n)))
+            .chain((0..machine.strings.len()).map(|s| StrReg(s).into()))
+            .chain((0..machine.values.len()).map(|v| ValReg(v).into()));
+        let mut folder = Folder {
+            scratch: machine.clone(),
+            constants: AHashMap::new(),
+            numbers: AHashMap::new(),
+            strings: AHashMap::new(),
+            values: AHashMap::new(),
+            summary: PassSummary::default(),
+            machine,
+        };
+        for reg in registers {
+            if variable.binary_search(&reg.into()).is_err() {
+                let value = folder.get(reg);
+                folder.constants.insert(reg.into(), value.clone());
+                match reg {
+                    AnyReg::Num(n) => { folder.numbers.insert(value.as_number().unwrap(), n); },
+                    AnyReg::Str(s) => { folder.strings.insert(string(value), s); },
+                    AnyReg::Val(v) => { folder.values.insert(value, v); },
+                }
+            }
+        }
+        folder
+    }
+
+    fn get(&self, reg: AnyReg) -> Value {
+        match reg {
+            AnyReg::Num(n) => Value::Num(*self.scratch.num_ref(n).unwrap()),
+            AnyReg::Str(s) => Value::Str(self.scratch.str_ref(s).unwrap().clone()),
+            AnyReg::Val(v) => self.scratch.val_ref(v).unwrap().clone(),
+        }
+    }
+
+    /// Runs `instr` with its inputs set to what's known of them, or `None` if it fails.
+    fn evaluate(&self, instr: Instruction, known: &Known) -> Option<Value> {
+        for reg in instr.reads() {
+            let value = known[&reg.into()].clone();
+            match reg {
+                AnyReg::Num(n) => *self.scratch.num_mut(n).unwrap() = value.as_number().unwrap(),
+                AnyReg::Str(s) => *self.scratch.str_mut(s).unwrap() = string(value),
+                AnyReg::Val(v) => *self.scratch.val_mut(v).unwrap() = value,
+            }
+        }
+        self.scratch.execute_instr(instr);
+        if self.scratch.runtime_err.swap(false, Ordering::Relaxed) {
+            return None;
+        }
+        Some(self.get(instr.modifies()?))
+    }
+
+    /// An instruction copying a constant holding `value` to `to`, adding the constant if
+    /// there isn't one yet.
+    fn copy_constant(&mut self, to: AnyReg, value: Value) -> Instruction {
+        match to {
+            AnyReg::Num(to) => {
+                let n = value.as_number().unwrap();
+                let machine = &mut *self.machine;
+                let from = *self.numbers.entry(n).or_insert_with(|| machine.new_num_reg(n));
+                Instruction::CopyNum(from, to)
+            },
+            AnyReg::Str(to) => {
+                let machine = &mut *self.machine;
+                let from = *self.strings
+                    .entry(string(value))
+                    .or_insert_with_key(|s| machine.new_str_reg(s.clone()));
+                Instruction::CopyStr(from, to)
+            },
+            AnyReg::Val(to) => {
+                let machine = &mut *self.machine;
+                let from = *self.values
+                    .entry(value)
+                    .or_insert_with_key(|v| machine.new_val_reg(v.clone()));
+                Instruction::CopyVal(from, to)
+            },
+        }
+    }
+
+    /// Folds one section, starting with what's `known` of the registers, returning what's
+    /// known on jumping to each section it can go on to within the line.
+    fn fold_section(&mut self, section: usize, mut known: Known) -> Vec<(Section, Known)> {
+        let mut exits = Vec::new();
+        // the error flag is always handled before a section ends
+        let mut may_error = false;
+        let mut index = 0;
+        while let Some(&instr) = self.machine.sections[section].instrs.get(index) {
+            let at = InstrRef { section, index };
+            match instr {
+                Instruction::JumpSectionIf(target, cond) => {
+                    match known.get(&AnyReg::from(cond).into()).and_then(Value::as_number) {
+                        Some(cond) if cond.as_bool() => {
+                            let len = self.machine.sections[section].instrs.len();
+                            for index in (index..len).rev() {
+                                self.machine.remove_instruction(InstrRef { section, index });
+                            }
+                            self.machine[section].success = SectionOrLine::Section(target);
+                            self.summary.instructions_removed += len - index;
+                            break;
+                        },
+                        Some(_) => {
+                            self.machine.remove_instruction(at);
+                            self.summary.instructions_removed += 1;
+                            continue;
+                        },
+                        None => exits.push((target, known.clone())),
+                    }
+                },
+                Instruction::JumpIfEq(target, ..)
+                | Instruction::JumpIfLe(target, ..)
+                | Instruction::JumpIfLt(target, ..) => exits.push((target, known.clone())),
+                Instruction::JumpIfError(_) => {
+                    if !may_error {
+                        self.machine.remove_instruction(at);
+                        self.summary.instructions_removed += 1;
+                        continue;
+                    }
+                    may_error = false;
+                },
+                _ => {
+                    let out = instr.modifies().unwrap();
+                    let reads = instr.reads();
+                    let result = match reads.iter().all(|r| known.contains_key(&(*r).into())) {
+                        true => self.evaluate(instr, &known),
+                        false => None,
+                    };
+                    match result {
+                        Some(value) => {
+                            let copies_constant = matches!(
+                                instr,
+                                Instruction::CopyNum(..)
+                                | Instruction::CopyStr(..)
+                                | Instruction::CopyVal(..)
+                            ) && self.constants.contains_key(&reads[0].into());
+                            if !copies_constant {
+                                self.machine[section].instrs[index] =
+                                    self.copy_constant(out, value.clone());
+                                self.summary.instructions_changed += 1;
+                            }
+                            known.insert(out.into(), value);
+                        },
+                        None => {
+                            may_error |= can_error(instr, self.machine.overflow);
+                            known.remove(&out.into());
+                        },
+                    }
+                },
+            }
+            index += 1;
+        }
+        self.remove_overwritten(section);
+        match self.machine.sections[section].success {
+            s if s == SUCCESS_NEEDS_FIXING => (),
+            SectionOrLine::Section(s) => exits.push((s, known)),
+            SectionOrLine::Line(_) => (),
+        }
+        exits
+    }
+
+    /// Removes writes which are overwritten later in the section, with nothing reading them
+    /// and no way out in between.
+    fn remove_overwritten(&mut self, section: usize) {
+        let (instrs, overflow) = (&self.machine.sections[section].instrs, self.machine.overflow);
+        let overwritten: Vec<_> = (0..instrs.len())
+            .filter(|&index| {
+                let instr = instrs[index];
+                let Some(out) = instr.modifies().filter(|_| !can_error(instr, overflow)) else {
+                    return false;
+                };
+                instrs[index + 1..]
+                    .iter()
+                    .find(|i| i.get_section().is_some() || i.relevant().contains(&out))
+                    .is_some_and(|i| i.modifies() == Some(out) && !i.reads().contains(&out))
+            })
+            .collect();
+        for &index in overwritten.iter().rev() {
+            self.machine.remove_instruction(InstrRef { section, index });
+        }
+        self.summary.instructions_removed += overwritten.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::super::tests::assert_matches_simple_interp;
+    use super::*;
+
+    #[test]
+    fn folds_constants() {
+        let src = "a=(1+2)*3 c=\"x\"+a :out=c+(2>1)+sqrt(16) goto 2\n\
+            if 3>2 then :y=a else :y=-a end x=\"ab\"-\"b\" x-- x-- :z=x goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program.clone());
+        let summary = OptPipeline::new().add(ConstantFolding).run(&mut machine).remove(0).1;
+        assert!(summary.changed());
+        // all of line 1 is worked out already
+        let cfg = machine.control_flow_graph();
+        for section in machine.line_sections(&cfg, 0) {
+            for &instr in machine.sections[section].instrs.iter() {
+                let copy = matches!(instr, Instruction::CopyNum(..) | Instruction::CopyVal(..));
+                assert!(copy, "{}", instr);
+            }
+        }
+
+        let mut simple = SimpleInterp::new(program);
+        for _ in 0..4 {
+            machine.step();
+            simple.step_line();
+            assert_matches_simple_interp(&machine, &simple);
+        }
+    }
+}
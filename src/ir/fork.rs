@@ -0,0 +1,133 @@
+use super::*;
+
+impl IRMachine {
+    /// A new instance of the same program, as if just compiled: variables are back to zero and
+    /// it starts on line 1, but the code and constant strings are shared rather than copied, so
+    /// spinning up thousands of the same chip costs little more than their registers. Whether
+    /// profiling, tracing and precision checks are on carries over, with nothing recorded yet,
+    /// as do breakpoints. Like clones, forks start without devices, watches or a trace hook, and
+    /// with no globals pending.
+    pub fn fork(&self) -> Self {
+        // Registers no instruction writes hold constants, unless the host set a variable. Those
+        // share their strings' bytes, and would only copy them if something changed them.
+        let mut fresh = vec![false; self.numbers.len()];
+        let mut fresh_strings = vec![false; self.strings.len()];
+        let mut fresh_values = vec![false; self.values.len()];
+        let written = self.sections
+            .iter()
+            .flat_map(|s| s.instrs.iter())
+            .filter_map(|i| i.modifies());
+        for reg in written.chain(self.idents.values().copied()) {
+            match reg {
+                AnyReg::Num(r) => fresh[r.0] = true,
+                AnyReg::Str(r) => fresh_strings[r.0] = true,
+                AnyReg::Val(r) => fresh_values[r.0] = true,
+            }
+        }
+        fn reset<T: Default>(
+            regs: &[AtomicRefCell<T>],
+            fresh: &[bool],
+            share: fn(&T) -> T,
+        ) -> Vec<AtomicRefCell<T>> {
+            regs.iter()
+                .zip(fresh)
+                .map(|(r, &fresh)| if fresh { T::default() } else { share(&r.borrow()) })
+                .map(AtomicRefCell::new)
+                .collect()
+        }
+
+        Self {
+            sections: self.sections.clone(),
+            lines: self.lines.clone(),
+            current_sect: self.lines[0],
+            line_start: self.lines[0],
+            runtime_err: false.into(),
+            goto_policy: self.goto_policy,
+            compat: self.compat,
+            overflow: self.overflow,
+            string_mode: self.string_mode,
+            max_string_len: self.max_string_len,
+            unicode: self.unicode,
+            asserts: self.asserts.clone(),
+            annotations: self.annotations.clone(),
+            diagnostics: Vec::new(),
+            profile: self.profile.as_ref().map(profile::Profile::cleared),
+            provenance: self.provenance.clone(),
+            dynamic_gotos: self.dynamic_gotos.clone(),
+            goto_events: self.goto_events.as_ref().map(|_| Vec::new()),
+            divergences: self.divergences.as_ref().map(|_| AtomicRefCell::new(Vec::new())),
+            numbers: reset(&self.numbers, &fresh, |&n| n),
+            strings: reset(&self.strings, &fresh_strings, YString::share),
+            values: reset(&self.values, &fresh_values, Value::share),
+            idents: self.idents.clone(),
+            breakpoints: self.breakpoints.clone(),
+            paused: None,
+            watches: Vec::new(),
+            devices: Vec::new(),
+            pending: Vec::new(),
+            #[cfg(feature = "trace-hooks")]
+            trace_hook: None,
+            #[cfg(feature = "opcode-counts")]
+            opcode_counts: vec![0; instr::OPCODE_NAMES.len()],
+        }
+    }
+
+    /// Whether this machine and `other` run the same copy of their code, as after
+    /// [`IRMachine::fork`] or `clone`.
+    pub fn shares_code_with(&self, other: &IRMachine) -> bool {
+        Arc::ptr_eq(&self.sections, &other.sections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::super::tests::variables;
+    use super::*;
+
+    #[test]
+    fn fork_starts_fresh() {
+        let src = "s=\"ab\" n=2.5\n:out=s+n :count++ if :count>2 then goto 3 end goto 2\n:done=1";
+        let compile = || {
+            let options = CodegenOptions { protect_locals: true, ..Default::default() };
+            IRMachine::from_ast(options, YololParser::default().parse(src).unwrap())
+        };
+        let mut machine = compile();
+        machine.set_ident(&Ident::global("count"), Value::Num(10.into()));
+        for _ in 0..5 {
+            machine.step();
+        }
+        let mut fork = machine.fork();
+        assert!(fork.shares_code_with(&machine));
+        assert!(!fork.shares_code_with(&compile()));
+
+        let mut fresh = compile();
+        assert_eq!(variables(&fork), variables(&fresh));
+        for _ in 0..6 {
+            fork.step();
+            fresh.step();
+            assert_eq!(variables(&fork), variables(&fresh));
+        }
+        assert_eq!(fork.get_ident_value(&Ident::global("out")), Value::Str("ab2.5".into()));
+
+        // the "ab" constant is shared, not copied
+        let second = machine.fork();
+        let strings = |m: &IRMachine| -> Vec<_> {
+            let strings = m.strings.iter().map(|s| s.borrow().share());
+            let values = m.values.iter().filter_map(|v| match &*v.borrow() {
+                Value::Str(s) => Some(s.share()),
+                Value::Num(_) => None,
+            });
+            strings.chain(values).filter(|s| s.byte_len() > 0).collect()
+        };
+        let constants = strings(&second);
+        assert_eq!(constants, [YString::from("ab")]);
+        assert!(strings(&machine).iter().any(|s| s.as_ptr() == constants[0].as_ptr()));
+
+        // Changing the code of one leaves the other alone
+        fork.compact_registers();
+        fork[0].instrs.clear();
+        assert!(!fork.shares_code_with(&machine));
+        assert!(!machine[0].instrs.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use analysis::Liveness;
+use cfg::ControlFlowGraph;
+use dfg::{DataFlowGraph, InstrRef};
+use super::*;
+
+/// A [`Pass`] fusing a comparison and the branch on its result into one instruction, saving
+/// dispatching the copies and truth checks between them. `if a < b then` compiles to a
+/// comparison, turning the result into a value, checking that's truthy and then jumping, so
+/// this runs four instructions as one. Registers in between must not be read again.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstructionFusion;
+
+impl Pass for InstructionFusion {
+    fn name(&self) -> &str {
+        "instruction fusion"
+    }
+
+    fn run(
+        &mut self,
+        machine: &mut IRMachine,
+        cfg: &ControlFlowGraph,
+        _: &DataFlowGraph,
+    ) -> PassSummary {
+        let mut fusions = Vec::new();
+        let liveness = Liveness::new(machine, cfg);
+        for (section, code) in machine.sections.iter().enumerate() {
+            let mut fused = Vec::new();
+            liveness.walk(section, &mut |index, instr, live, _| {
+                if let Instruction::JumpSectionIf(..) = instr {
+                    if let Some(fusion) = fuse(&code.instrs[..=index], live, machine) {
+                        fused.push(fusion);
+                    }
+                }
+            });
+            // the walk goes backwards, so later fusions come first
+            fusions.extend(fused.into_iter().map(|(start, len, i)| (section, start, len, i)));
+        }
+
+        let mut summary = PassSummary::default();
+        for &(section, start, len, instr) in fusions.iter() {
+            machine[section].instrs[start] = instr;
+            for index in (start + 1..start + len).rev() {
+                machine.remove_instruction(InstrRef { section, index });
+            }
+            summary.instructions_removed += len - 1;
+            summary.instructions_changed += 1;
+        }
+        summary
+    }
+}
+
+/// Where the comparison feeding the jump ending `instrs` starts, how many instructions from
+/// there on to fuse, and what to replace them with.
+fn fuse(
+    instrs: &[Instruction],
+    live: &analysis::Live,
+    machine: &IRMachine,
+) -> Option<(usize, usize, Instruction)> {
+    let Some(&Instruction::JumpSectionIf(target, mut cond)) = instrs.last() else {
+        return None;
+    };
+    // follow the condition back through anything keeping whether it's truthy
+    let mut index = instrs.len() - 1;
+    let mut value = None;
+    let mut written = Vec::new();
+    loop {
+        index = index.checked_sub(1)?;
+        let instr = instrs[index];
+        if instr.modifies().is_some_and(|reg| live[machine.flat_index(reg)]) {
+            return None;
+        }
+        written.extend(instr.modifies());
+        match (instr, value) {
+            (Instruction::IsTruthyNum(n), None) if n == cond => (),
+            (Instruction::CopyNum(from, to), None) if to == cond => cond = from,
+            (Instruction::IsTruthyVal(v, n), None) if n == cond => value = Some(v),
+            (Instruction::ValueifyNum(n, v), Some(from)) if v == from => {
+                cond = n;
+                value = None;
+            },
+            (Instruction::Eq(l, r, n), None) | (Instruction::Le(l, r, n), None)
+            | (Instruction::Lt(l, r, n), None) if n == cond => {
+                // the chain mustn't change what's compared before the jump reads it
+                if written[..written.len() - 1].iter().any(|w| [l.into(), r.into()].contains(w)) {
+                    return None;
+                }
+                let fused = match instr {
+                    Instruction::Eq(..) => Instruction::JumpIfEq(target, l, r),
+                    Instruction::Le(..) => Instruction::JumpIfLe(target, l, r),
+                    _ => Instruction::JumpIfLt(target, l, r),
+                };
+                return Some((index, instrs.len() - index, fused));
+            },
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::super::tests::assert_matches_simple_interp;
+    use super::*;
+
+    #[test]
+    fn fuses_compare_and_branch() {
+        let src = "i=0 :n=0 :x=5\ni++ if i<:x then :n++ goto 2 end if :n==i then :y=1 end goto 4\n\
+            if :n<=3 then :z=1 end :x--";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut plain = IRMachine::from_ast(Default::default(), program.clone());
+        let options = CodegenOptions { fuse_instructions: true, ..Default::default() };
+        let mut fused = IRMachine::from_ast(options, program.clone());
+        let jumps = |m: &IRMachine| m.sections
+            .iter()
+            .flat_map(|s| s.instrs.iter())
+            .filter(|i| matches!(i, Instruction::JumpIfLt(..) | Instruction::JumpIfEq(..)
+                | Instruction::JumpIfLe(..)))
+            .count();
+        assert_eq!(jumps(&fused), 3);
+        let instructions = |m: &IRMachine| m.sections.iter().map(|s| s.instrs.len()).sum::<usize>();
+        assert_eq!(instructions(&plain), instructions(&fused) + 9);
+
+        let mut simple = SimpleInterp::new(program);
+        for _ in 0..40 {
+            plain.step();
+            fused.step();
+            simple.step_line();
+            assert_matches_simple_interp(&fused, &simple);
+            assert_eq!(fused.get_current_line(), plain.get_current_line());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use dfg::InstrRef;
+use pass::InstrView;
+use super::*;
+
+type HookFn = dyn FnMut(InstrRef, &InstrView) + Send + Sync;
+
+/// Set by [`IRMachine::set_trace_hook`]. Clones and forks start without one.
+pub(super) struct Hook(AtomicRefCell<Box<HookFn>>);
+
+impl std::fmt::Debug for Hook {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("Hook")
+    }
+}
+
+impl IRMachine {
+    /// Calls `hook` before each instruction runs, with where it is (numbered as in
+    /// [`cfg::ControlFlowGraph`]) and what it does. Instructions checking `// assert:`
+    /// comments aren't included.
+    pub fn set_trace_hook(
+        &mut self,
+        hook: impl FnMut(InstrRef, &InstrView) + Send + Sync + 'static,
+    ) {
+        self.trace_hook = Some(Hook(AtomicRefCell::new(Box::new(hook))));
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    pub(super) fn call_trace_hook(&self, index: usize) {
+        if let Some(Hook(hook)) = &self.trace_hook {
+            let at = InstrRef { section: self.current_sect.0, index };
+            let view = self.instruction(at).unwrap();
+            (hook.borrow_mut())(at, &view);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn hook_sees_each_instruction() {
+        let program = YololParser::default().parse(":a=2 :b=:a*3\n:c=1/0 :d=1").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        machine.set_trace_hook(move |at, view| log.lock().unwrap().push((at, view.text.clone())));
+
+        machine.step();
+        let first_line = std::mem::take(&mut *seen.lock().unwrap());
+        assert!(first_line.iter().any(|(_, text)| text.contains('*')));
+        for (at, text) in first_line.iter() {
+            assert_eq!(machine.instruction(*at).unwrap().text, *text);
+        }
+
+        // the error skips the rest of the line, so nothing writes :d
+        machine.step();
+        let d = machine.ident_register(&Ident::global("d")).unwrap();
+        let writes_d = |at: &InstrRef| machine.instruction(*at).unwrap().writes == Some(d);
+        let second_line = std::mem::take(&mut *seen.lock().unwrap());
+        assert!(!second_line.is_empty());
+        assert!(!second_line.iter().any(|(at, _)| writes_d(at)));
+
+        // stepping an instruction at a time reports the same
+        let mut stepped = machine.clone();
+        assert!(stepped.trace_hook.is_none());
+        let log = seen.clone();
+        stepped.set_trace_hook(move |at, view| log.lock().unwrap().push((at, view.text.clone())));
+        stepped.step_line();
+        machine.step();
+        let mut by_instr = std::mem::take(&mut *seen.lock().unwrap());
+        let by_line = by_instr.split_off(by_instr.len() / 2);
+        assert_eq!(by_instr, by_line);
+
+        machine.clear_trace_hook();
+        machine.step();
+        assert!(seen.lock().unwrap().is_empty());
+    }
+}
@@ -34,6 +34,7 @@ impl Display for ValReg {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, From, Into)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(align(8))]
 pub(super) struct Section(pub usize);
 
@@ -91,27 +92,143 @@ pub(super) enum Instruction {
     Neg(NumReg),
     And(NumReg, NumReg),
     Or(NumReg, NumReg),
+    /// `(cond, if_true, if_false, out)`, with `out` set to one or the other by whether `cond`
+    /// is truthy.
+    SelectNum(NumReg, NumReg, NumReg, NumReg),
+    /// Superinstructions, from [`InstructionFusion`](super::InstructionFusion), jumping if
+    /// comparing the values would give true.
+    JumpIfEq(Section, ValReg, ValReg),
+    JumpIfLe(Section, ValReg, ValReg),
+    JumpIfLt(Section, ValReg, ValReg),
+    /// The extensions, from [`YololParser::extensions`](crate::parser::YololParser).
+    Exp(NumReg),
+    Ln(NumReg),
+    Log10(NumReg),
+    /// `(n, base)`, replacing `n` with its logarithm in `base`.
+    Log(NumReg, NumReg),
 }
 
+macro_rules! opcodes {
+    ($($name:ident($($field:ident: $ty:ident),*)),* $(,)?) => {
+        enum Opcode {
+            $($name),*
+        }
+
+        /// The name of each opcode, indexed by [`Instruction::opcode`].
+        pub(super) const OPCODE_NAMES: &[&str] = &[$(stringify!($name)),*];
+
+        impl Instruction {
+            /// Which kind of instruction this is, as an index into [`OPCODE_NAMES`].
+            pub fn opcode(self) -> usize {
+                match self {
+                    $(Instruction::$name(..) => Opcode::$name as usize),*
+                }
+            }
+
+            /// The indices of the registers and sections this uses, in order.
+            pub fn operands(self) -> ArrayVec<usize, 4> {
+                match self {
+                    $(Instruction::$name($($field),*) => [$($field.0),*].into_iter().collect()),*
+                }
+            }
+
+            /// Makes the instruction with this opcode, taking its operands in order, or `None`
+            /// if there's no such opcode or too few operands.
+            pub fn from_operands(
+                opcode: usize,
+                operands: &mut impl Iterator<Item = usize>,
+            ) -> Option<Self> {
+                $(
+                    if opcode == Opcode::$name as usize {
+                        return Some(Instruction::$name($($ty(operands.next()?)),*));
+                    }
+                )*
+                None
+            }
+        }
+    };
+}
+
+opcodes!(
+    JumpSectionIf(a: Section, b: NumReg),
+    JumpIfError(a: Section),
+    CopyNum(a: NumReg, b: NumReg),
+    CopyStr(a: StrReg, b: StrReg),
+    CopyVal(a: ValReg, b: ValReg),
+    ValueifyNum(a: NumReg, b: ValReg),
+    ValueifyStr(a: StrReg, b: ValReg),
+    NumberifyVal(a: ValReg, b: NumReg),
+    StringifyNum(a: NumReg, b: StrReg),
+    StringifyVal(a: ValReg, b: StrReg),
+    IsTruthyNum(a: NumReg),
+    IsTruthyVal(a: ValReg, b: NumReg),
+    NotNum(a: NumReg),
+    NotVal(a: ValReg, b: NumReg),
+    AddNum(a: NumReg, b: NumReg),
+    AddStr(a: StrReg, b: StrReg),
+    AddVal(a: ValReg, b: ValReg),
+    SubNum(a: NumReg, b: NumReg),
+    SubStr(a: StrReg, b: StrReg),
+    SubVal(a: ValReg, b: ValReg),
+    Mul(a: NumReg, b: NumReg),
+    Div(a: NumReg, b: NumReg),
+    Rem(a: NumReg, b: NumReg),
+    Pow(a: NumReg, b: NumReg),
+    Eq(a: ValReg, b: ValReg, c: NumReg),
+    Le(a: ValReg, b: ValReg, c: NumReg),
+    Lt(a: ValReg, b: ValReg, c: NumReg),
+    IncNum(a: NumReg),
+    IncStr(a: StrReg),
+    IncVal(a: ValReg),
+    DecNum(a: NumReg),
+    DecStr(a: StrReg),
+    DecVal(a: ValReg),
+    Abs(a: NumReg),
+    Fact(a: NumReg),
+    Sqrt(a: NumReg),
+    Sin(a: NumReg),
+    Cos(a: NumReg),
+    Tan(a: NumReg),
+    Asin(a: NumReg),
+    Acos(a: NumReg),
+    Atan(a: NumReg),
+    Neg(a: NumReg),
+    And(a: NumReg, b: NumReg),
+    Or(a: NumReg, b: NumReg),
+    SelectNum(a: NumReg, b: NumReg, c: NumReg, d: NumReg),
+    JumpIfEq(a: Section, b: ValReg, c: ValReg),
+    JumpIfLe(a: Section, b: ValReg, c: ValReg),
+    JumpIfLt(a: Section, b: ValReg, c: ValReg),
+    // new opcodes go on the end, so saved bytecode keeps its meaning until the version changes
+    Exp(a: NumReg),
+    Ln(a: NumReg),
+    Log10(a: NumReg),
+    Log(a: NumReg, b: NumReg),
+);
+
 impl Instruction {
-    pub fn reads(self) -> ArrayVec<AnyReg, 2> {
+    pub fn reads(self) -> ArrayVec<AnyReg, 3> {
         use Instruction::*;
 
         match self {
             JumpIfError(_) => ArrayVec::new_const(),
             JumpSectionIf(_, r) | CopyNum(r, _) | ValueifyNum(r, _) | StringifyNum(r, _)
             | IsTruthyNum(r) | NotNum(r) | IncNum(r) | Abs(r) | Fact(r) | Sqrt(r) | Sin(r) | Cos(r)
-            | Tan(r) | Asin(r) | Acos(r) | Atan(r) | Neg(r) | DecNum(r) =>
+            | Tan(r) | Asin(r) | Acos(r) | Atan(r) | Exp(r) | Ln(r) | Log10(r) | Neg(r)
+            | DecNum(r) =>
                 [r.into()].as_ref().try_into().unwrap(),
             CopyStr(r, _) | ValueifyStr(r, _) | IncStr(r) | DecStr(r) =>
                 [r.into()].as_ref().try_into().unwrap(),
             CopyVal(r, _) | NumberifyVal(r, _) | StringifyVal(r, _) | IsTruthyVal(r, _)
             | NotVal(r, _) | IncVal(r) | DecVal(r) => [r.into()].as_ref().try_into().unwrap(),
             AddNum(r1, r2) | SubNum(r1, r2) | Mul(r1, r2) | Div(r1, r2) | Rem(r1, r2) | Pow(r1, r2)
-            | And(r1, r2) | Or(r1, r2) => [r1.into(), r2.into()].into(),
-            SubStr(r1, r2) | AddStr(r1, r2) => [r1.into(), r2.into()].into(),
-            AddVal(r1, r2) | SubVal(r1, r2) | Eq(r1, r2, _) | Le(r1, r2, _) | Lt(r1, r2, _) =>
-                [r1.into(), r2.into()].into(),
+            | Log(r1, r2) | And(r1, r2) | Or(r1, r2) =>
+                [r1.into(), r2.into()].as_ref().try_into().unwrap(),
+            SubStr(r1, r2) | AddStr(r1, r2) => [r1.into(), r2.into()].as_ref().try_into().unwrap(),
+            AddVal(r1, r2) | SubVal(r1, r2) | Eq(r1, r2, _) | Le(r1, r2, _) | Lt(r1, r2, _)
+            | JumpIfEq(_, r1, r2) | JumpIfLe(_, r1, r2) | JumpIfLt(_, r1, r2) =>
+                [r1.into(), r2.into()].as_ref().try_into().unwrap(),
+            SelectNum(c, t, f, _) => [c.into(), t.into(), f.into()].into(),
         }
     }
 
@@ -122,17 +239,19 @@ impl Instruction {
             CopyNum(_, r) | IsTruthyNum(r) | NumberifyVal(_, r) | IsTruthyVal(_, r) | NotNum(r)
             | NotVal(_, r) | AddNum(r, _) | SubNum(r, _) | Mul(r, _) | Div(r, _) | Rem(r, _)
             | Pow(r, _) | Eq(.., r) | Le(.., r) | Lt(.., r) | IncNum(r) | Abs(r) | Fact(r) | Sqrt(r)
-            | Sin(r) | Cos(r) | Tan(r) | Asin(r) | Acos(r) | Atan(r)
-            | Neg(r) | And(r, _) | Or(r, _) | DecNum(r) => Some(r.into()),
+            | Sin(r) | Cos(r) | Tan(r) | Asin(r) | Acos(r) | Atan(r) | Exp(r) | Ln(r) | Log10(r)
+            | Log(r, _) | Neg(r) | And(r, _) | Or(r, _) | DecNum(r) | SelectNum(.., r) =>
+                Some(r.into()),
             StringifyNum(_, r) | CopyStr(_, r) | StringifyVal(_, r) | AddStr(r, _) | SubStr(r, _)
             | IncStr(r) | DecStr(r) => Some(r.into()),
             CopyVal(_, r) | ValueifyNum(_, r) | ValueifyStr(_, r) | AddVal(r, _) | SubVal(r, _)
             | IncVal(r) | DecVal(r) => Some(r.into()),
-            JumpSectionIf(..) | JumpIfError(_) => None,
+            JumpSectionIf(..) | JumpIfError(_) | JumpIfEq(..) | JumpIfLe(..) | JumpIfLt(..) =>
+                None,
         }
     }
 
-    pub fn relevant(self) -> ArrayVec<AnyReg, 3> {
+    pub fn relevant(self) -> ArrayVec<AnyReg, 4> {
         let mut array = ArrayVec::new_const();
         array.extend(self.reads());
         array.extend(self.modifies());
@@ -140,18 +259,35 @@ impl Instruction {
     }
 
     pub const fn get_section(self) -> Option<Section> {
-        if let Instruction::JumpSectionIf(s, _) | Instruction::JumpIfError(s) = self {
+        use Instruction::*;
+
+        if let JumpSectionIf(s, _) | JumpIfError(s) | JumpIfEq(s, ..) | JumpIfLe(s, ..)
+            | JumpIfLt(s, ..) = self
+        {
             Some(s)
         } else {
             None
         }
     }
 
-    fn get_mut_num_regs(&mut self) -> ArrayVec<&mut NumReg, 2> {
+    pub fn get_mut_section(&mut self) -> Option<&mut Section> {
+        use Instruction::*;
+
+        if let JumpSectionIf(s, _) | JumpIfError(s) | JumpIfEq(s, ..) | JumpIfLe(s, ..)
+            | JumpIfLt(s, ..) = self
+        {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut_num_regs(&mut self) -> ArrayVec<&mut NumReg, 4> {
         match self {
             Instruction::JumpSectionIf(_, n) | Instruction::Abs(n) | Instruction::Fact(n)
             | Instruction::Sqrt(n) | Instruction::Sin(n) | Instruction::Cos(n) | Instruction::Tan(n)
             | Instruction::Asin(n) | Instruction::Acos(n) | Instruction::Atan(n)
+            | Instruction::Exp(n) | Instruction::Ln(n) | Instruction::Log10(n)
             | Instruction::Neg(n) | Instruction::IncNum(n) | Instruction::DecNum(n)
             | Instruction::ValueifyNum(n, _) | Instruction::NumberifyVal(_, n)
             | Instruction::StringifyNum(n, _) | Instruction::IsTruthyNum(n)
@@ -160,13 +296,14 @@ impl Instruction {
                 [n].into_iter().collect(),
             Instruction::CopyNum(n1, n2) | Instruction::AddNum(n1, n2) | Instruction::SubNum(n1, n2)
             | Instruction::Mul(n1, n2) | Instruction::Div(n1, n2) | Instruction::Rem(n1, n2)
-            | Instruction::Pow(n1, n2) | Instruction::And(n1, n2) | Instruction::Or(n1, n2) =>
-                [n1, n2].into(),
+            | Instruction::Pow(n1, n2) | Instruction::Log(n1, n2) | Instruction::And(n1, n2)
+            | Instruction::Or(n1, n2) => [n1, n2].into_iter().collect(),
+            Instruction::SelectNum(c, t, f, o) => [c, t, f, o].into(),
             _ => ArrayVec::new_const(),
         }
     }
 
-    fn get_mut_str_regs(&mut self) -> ArrayVec<&mut StrReg, 2> {
+    pub fn get_mut_str_regs(&mut self) -> ArrayVec<&mut StrReg, 2> {
         match self {
             Instruction::ValueifyStr(s, _) | Instruction::StringifyNum(_, s)
             | Instruction::StringifyVal(_, s) | Instruction::IncStr(s) | Instruction::DecStr(s) =>
@@ -177,7 +314,7 @@ impl Instruction {
         }
     }
 
-    fn get_mut_val_regs(&mut self) -> ArrayVec<&mut ValReg, 2> {
+    pub fn get_mut_val_regs(&mut self) -> ArrayVec<&mut ValReg, 2> {
         match self {
             Instruction::ValueifyNum(_, v) | Instruction::ValueifyStr(_, v)
             | Instruction::NumberifyVal(v, _) | Instruction::StringifyVal(v, _)
@@ -185,11 +322,14 @@ impl Instruction {
             | Instruction::DecVal(v) => [v].into_iter().collect(),
             Instruction::CopyVal(v1, v2) | Instruction::AddVal(v1, v2) | Instruction::SubVal(v1, v2)
             | Instruction::Eq(v1, v2, _) | Instruction::Le(v1, v2, _)
-            | Instruction::Lt(v1, v2, _) => [v1, v2].into(),
+            | Instruction::Lt(v1, v2, _) | Instruction::JumpIfEq(_, v1, v2)
+            | Instruction::JumpIfLe(_, v1, v2) | Instruction::JumpIfLt(_, v1, v2) =>
+                [v1, v2].into(),
             _ => ArrayVec::new_const(),
         }
     }
 
+    #[allow(dead_code)]
     pub fn remove_reg(&mut self, reg: AnyReg) {
         match reg {
             AnyReg::Num(n) => {
@@ -222,8 +362,9 @@ impl Instruction {
         }
     }
 
+    #[allow(dead_code)]
     pub fn remove_section(&mut self, section: Section) {
-        if let Instruction::JumpSectionIf(s, _) | Instruction::JumpIfError(s) = self {
+        if let Some(s) = self.get_mut_section() {
             if s.0 > section.0 {
                 s.0 -= 1;
             } else if *s == section {
@@ -240,6 +381,19 @@ impl Instruction {
             | Instruction::Rem(..),
         )
     }
+
+    /// Whether this is `+`, `-` or `*` on numbers, which fail on overflow under
+    /// [`OverflowPolicy::Error`].
+    pub const fn can_overflow(self) -> bool {
+        matches!(
+            self,
+            Instruction::AddNum(..)
+            | Instruction::SubNum(..)
+            | Instruction::Mul(..)
+            | Instruction::AddVal(..)
+            | Instruction::SubVal(..),
+        )
+    }
 }
 
 impl Display for Instruction {
@@ -329,12 +483,28 @@ impl Display for Instruction {
                 write!(f, "{0:} = acos({0:})", n),
             Instruction::Atan(n) =>
                 write!(f, "{0:} = atan({0:})", n),
+            Instruction::Exp(n) =>
+                write!(f, "{0:} = exp({0:})", n),
+            Instruction::Ln(n) =>
+                write!(f, "{0:} = ln({0:})", n),
+            Instruction::Log10(n) =>
+                write!(f, "{0:} = log10({0:})", n),
+            Instruction::Log(n, base) =>
+                write!(f, "{0:} = log({0:}, {1:})", n, base),
             Instruction::Neg(n) =>
                 write!(f, "{0:} = -({0:})", n),
             Instruction::And(l, r) =>
                 write!(f, "{} &= {}", l, r),
             Instruction::Or(l, r) =>
                 write!(f, "{} |= {}", l, r),
+            Instruction::SelectNum(c, t, e, o) =>
+                write!(f, "{} = {} ? {} : {}", o, c, t, e),
+            Instruction::JumpIfEq(s, l, r) =>
+                write!(f, "If {} == {}, jump to {}", l, r, s),
+            Instruction::JumpIfLe(s, l, r) =>
+                write!(f, "If {} <= {}, jump to {}", l, r, s),
+            Instruction::JumpIfLt(s, l, r) =>
+                write!(f, "If {} < {}, jump to {}", l, r, s),
         }
     }
 }
@@ -0,0 +1,227 @@
+use ahash::AHashSet;
+use super::*;
+
+/// The longest a chip can take to pass a change on, from [`IRMachine::worst_case_latency`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChipLatency {
+    /// The 1-based lines it runs along the slowest way, one per tick, ending with the line
+    /// which passes the change on.
+    pub lines: Vec<usize>,
+}
+
+impl ChipLatency {
+    pub fn ticks(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+/// Which registers might hold something derived from the input, indexed by
+/// [`IRMachine::flat_index`].
+type Taint = Vec<bool>;
+
+/// Where the ways through a line end up.
+struct LineEnd {
+    line: usize,
+    taint: Taint,
+    next_lines: Vec<usize>,
+}
+
+struct Analysis<'a> {
+    machine: &'a IRMachine,
+    input: usize,
+    output: usize,
+    /// The lines each line can run on to.
+    next_lines: Vec<Vec<usize>>,
+    done: AHashMap<(usize, Taint), Option<Vec<usize>>>,
+    running: AHashSet<(usize, Taint)>,
+}
+
+impl Analysis<'_> {
+    fn index(&self, reg: AnyReg) -> usize {
+        self.machine.flat_index(reg)
+    }
+
+    fn run_line(&self, line: usize, taint: Taint) -> LineEnd {
+        let mut end = LineEnd { line, taint: vec![false; taint.len()], next_lines: Vec::new() };
+        self.walk(self.machine.lines[line].0, 0, taint, false, &mut end);
+        end.next_lines.sort_unstable();
+        end.next_lines.dedup();
+        end
+    }
+
+    /// Follows every way through the rest of a section. Once a branch depends on the input, so
+    /// does everything written after it.
+    fn walk(
+        &self,
+        section: usize,
+        start: usize,
+        mut taint: Taint,
+        control: bool,
+        end: &mut LineEnd,
+    ) {
+        let code = &self.machine.sections[section];
+        // values just made from numbers, which can't fail to turn back into one
+        let mut numbers = Vec::new();
+        for (index, &instr) in code.instrs.iter().enumerate().skip(start) {
+            let tainted = instr.reads().into_iter().any(|r| taint[self.index(r)]);
+            match instr {
+                Instruction::JumpIfError(target) => {
+                    let cant_fail = matches!(
+                        code.instrs[index - 1],
+                        Instruction::NumberifyVal(v, _) if numbers.contains(&v)
+                    );
+                    if !cant_fail {
+                        self.jump(target, taint.clone(), control, end);
+                    }
+                },
+                _ if instr.get_section().is_some() => {
+                    let control = control || tainted;
+                    self.jump(instr.get_section().unwrap(), taint.clone(), control, end);
+                    return self.walk(section, index + 1, taint, control, end);
+                },
+                _ => if let Some(reg) = instr.modifies() {
+                    let index = self.index(reg);
+                    taint[index] = tainted || control;
+                    match (instr, reg) {
+                        (Instruction::ValueifyNum(_, v), _) => numbers.push(v),
+                        (_, AnyReg::Val(v)) => numbers.retain(|&n| n != v),
+                        _ => (),
+                    }
+                },
+            }
+        }
+        match code.success {
+            s if s == SUCCESS_NEEDS_FIXING => (),
+            SectionOrLine::Section(s) => self.jump(s, taint, control, end),
+            SectionOrLine::Line(n) => {
+                merge(&mut end.taint, &taint);
+                match self.machine.const_goto_target(code, n) {
+                    Some(Some(line)) => end.next_lines.push(line),
+                    Some(None) => end.next_lines.push((end.line + 1) % self.machine.lines.len()),
+                    None => end.next_lines.extend(0..self.machine.lines.len()),
+                }
+            },
+        }
+    }
+
+    fn jump(&self, section: Section, taint: Taint, control: bool, end: &mut LineEnd) {
+        match self.machine.lines.iter().position(|&s| s == section) {
+            Some(line) => {
+                merge(&mut end.taint, &taint);
+                end.next_lines.push(line);
+            },
+            None => self.walk(section.0, 0, taint, control, end),
+        }
+    }
+
+    /// The slowest lines to the output being tainted, starting on `line` with `taint`, or `None`
+    /// if the chip can go on forever without tainting it.
+    fn slowest(&mut self, line: usize, mut taint: Taint) -> Option<Vec<usize>> {
+        taint[self.input] = true;
+        let key = (line, taint);
+        if let Some(done) = self.done.get(&key) {
+            return done.clone();
+        }
+        if !self.running.insert(key.clone()) {
+            return None;
+        }
+        let end = self.run_line(line, key.1.clone());
+        let path = if end.taint[self.output] {
+            Some(vec![line + 1])
+        } else {
+            let mut slowest = Some(Vec::new());
+            for next in self.next_lines[line].clone() {
+                slowest = match (slowest, self.slowest(next, end.taint.clone())) {
+                    (Some(a), Some(b)) => Some(if b.len() > a.len() { b } else { a }),
+                    _ => None,
+                };
+            }
+            slowest.map(|rest| [vec![line + 1], rest].concat())
+        };
+        self.running.remove(&key);
+        self.done.insert(key, path.clone());
+        path
+    }
+}
+
+fn merge(into: &mut Taint, taint: &Taint) {
+    into.iter_mut().zip(taint).for_each(|(a, &b)| *a |= b);
+}
+
+impl IRMachine {
+    /// An upper bound on how many ticks it takes for a change to `input` to reach `output`,
+    /// whichever line the chip is on when it changes and whichever way its branches go. A line
+    /// which might pass the change on counts as doing so. It follows data, and within a line,
+    /// branches on it. `None` if the chip can run forever without passing the change on, or
+    /// either variable isn't protected (see [`CodegenOptions`]).
+    pub fn worst_case_latency(&self, input: &Ident, output: &Ident) -> Option<ChipLatency> {
+        let (&input, &output) = (self.idents.get(input)?, self.idents.get(output)?);
+        if input == output {
+            return Some(ChipLatency { lines: Vec::new() });
+        }
+        let registers = self.register_total();
+        let mut analysis = Analysis {
+            machine: self,
+            input: 0,
+            output: 0,
+            next_lines: Vec::new(),
+            done: AHashMap::new(),
+            running: AHashSet::new(),
+        };
+        analysis.input = analysis.index(input);
+        analysis.output = analysis.index(output);
+        analysis.next_lines = (0..self.lines.len())
+            .map(|line| analysis.run_line(line, vec![false; registers]).next_lines)
+            .collect();
+
+        // the change can come while on any line the chip can get to
+        let mut reachable = vec![0];
+        let mut i = 0;
+        while let Some(&line) = reachable.get(i) {
+            for &next in analysis.next_lines[line].iter() {
+                if !reachable.contains(&next) {
+                    reachable.push(next);
+                }
+            }
+            i += 1;
+        }
+        reachable.sort_unstable();
+        let mut slowest = Vec::new();
+        for line in reachable {
+            let lines = analysis.slowest(line, vec![false; registers])?;
+            if lines.len() > slowest.len() {
+                slowest = lines;
+            }
+        }
+        Some(ChipLatency { lines: slowest })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    fn latency(src: &str) -> Option<Vec<usize>> {
+        let program = YololParser::default().parse(src).unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program);
+        machine
+            .worst_case_latency(&Ident::global("in"), &Ident::global("out"))
+            .map(|latency| latency.lines)
+    }
+
+    #[test]
+    fn worst_case_latency() {
+        assert_eq!(latency(":out=:in goto 1"), Some(vec![1]));
+        // it could have just run line 2
+        assert_eq!(latency("a=:in\n:x=1\n:out=a goto 1"), Some(vec![2, 3, 1, 2, 3]));
+        // the branch depends on the input
+        assert_eq!(latency("if :in>5 then :out=1 else :out=0 end goto 1"), Some(vec![1]));
+        // the temporary holding `:in` on line 1 is overwritten before line 2 reads it
+        assert_eq!(latency("a=:in :b=a a=0\n:out=a goto 1"), None);
+        // it might never take the way which passes the change on
+        assert_eq!(latency("if :go then goto 2 end goto 1\n:out=:in goto 1"), None);
+        // a string makes line 1 fail, falling through to line 2
+        assert_eq!(latency(":out=:in*2\n:x=1 goto 1"), Some(vec![2, 1]));
+    }
+}
@@ -1,18 +1,69 @@
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::io::Write;
+use std::sync::Arc;
 use std::fmt::{Formatter, Display, Result as FmtResult};
-use derive_more::{Index, IndexMut, From, Display};
+use derive_more::{From, Display};
 use atomic_refcell::AtomicRefCell;
 use ahash::AHashMap;
 use arith::*;
-use parser::Ident;
+use parser::{FieldAnnotation, Ident};
+use diagnostics::{Diagnostic, Severity};
 use super::*;
 use instr::*;
-pub use codegen::CodegenOptions;
+pub use codegen::{CodegenError, CodegenOptions, GotoPolicy, Provenance, golden};
+pub use profile::{ProfileReport, LineStats};
+pub use cancel::CancelToken;
+#[cfg(test)]
+pub(crate) use cancel::CancelAt;
+pub use precision::Divergence;
+pub use pass::{InstrView, OptPipeline, Pass, PassSummary};
+pub use state::Snapshot;
+pub use debug::Breakpoint;
+pub use latency::ChipLatency;
+pub use fold::ConstantFolding;
+pub use dce::DeadCodeElimination;
+pub use coalesce::RegisterCoalescing;
+pub use fuse::InstructionFusion;
+pub use touched::LineResult;
+pub use device::DeviceField;
+pub use pending::StepStatus;
+#[cfg(feature = "opcode-counts")]
+pub(crate) use opcodes::sorted_opcode_counts;
 
 mod instr;
 mod codegen;
+mod profile;
+mod compact;
+mod cancel;
+mod precision;
+mod fingerprint;
+mod state;
+mod pass;
+mod fork;
+mod debug;
+mod watch;
+mod bytecode;
+mod decompile;
+mod latency;
+mod fold;
+mod dce;
+mod coalesce;
+mod fuse;
+mod touched;
+mod device;
+mod pending;
+mod export;
+#[cfg(feature = "opcode-counts")]
+mod opcodes;
+#[cfg(feature = "trace-hooks")]
+mod hooks;
+#[cfg(feature = "async")]
+mod tick_async;
+pub mod cfg;
+pub mod dfg;
+pub mod asm;
+pub mod analysis;
 
 const SUCCESS_NEEDS_FIXING: SectionOrLine = SectionOrLine::Section(Section(!0));
 
@@ -23,6 +74,24 @@ enum AnyReg {
     Val(ValReg),
 }
 
+/// A register of an [`IRMachine`], by which register file it's in and its index there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Register {
+    Number(usize),
+    String(usize),
+    Value(usize),
+}
+
+impl From<AnyReg> for Register {
+    fn from(reg: AnyReg) -> Self {
+        match reg {
+            AnyReg::Num(n) => Register::Number(n.0),
+            AnyReg::Str(s) => Register::String(s.0),
+            AnyReg::Val(v) => Register::Value(v.0),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, From)]
 enum SectionOrLine {
     Section(Section),
@@ -45,18 +114,71 @@ pub struct SectionCode {
     success: SectionOrLine,
 }
 
-#[derive(Debug, Index, IndexMut)]
+/// A `goto` to a computed line, reported while [`IRMachine::trace_dynamic_gotos`] is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicGoto {
+    /// The 1-based line the goto is on.
+    pub line: usize,
+    /// The goto's target expression.
+    pub expr: Arc<str>,
+    pub target: Number,
+    /// The 1-based line it went to, or `None` if the [`GotoPolicy`] made it an error.
+    pub landed: Option<usize>,
+}
+
+/// A compiled `// assert: expr` comment, checked after `line` runs.
+#[derive(Debug, Clone)]
+struct Assertion {
+    line: Section,
+    section: Section,
+    result: NumReg,
+    message: String,
+}
+
+type OverflowOp = fn(OverflowPolicy, Number, Number) -> ValueResult<Number>;
+
+#[derive(Debug)]
 pub struct IRMachine {
-    #[index]
-    #[index_mut]
-    sections: Vec<SectionCode>,
+    /// Shared between clones and forks until one of them changes its code.
+    sections: Arc<Vec<SectionCode>>,
     lines: Vec<Section>,
     current_sect: Section,
+    /// Where the line being stepped through started, to move on from if a goto errors.
+    line_start: Section,
     runtime_err: AtomicBool,
+    goto_policy: GotoPolicy,
+    compat: Compat,
+    overflow: OverflowPolicy,
+    string_mode: StringMode,
+    max_string_len: usize,
+    unicode: UnicodePolicy,
+    asserts: Vec<Assertion>,
+    annotations: Vec<FieldAnnotation>,
+    diagnostics: Vec<Diagnostic>,
+    profile: Option<profile::Profile>,
+    /// Empty unless compiled with [`CodegenOptions::provenance`].
+    provenance: Arc<Vec<Vec<Provenance>>>,
+    /// The target expression of each section ending in a goto to a computed line.
+    dynamic_gotos: AHashMap<Section, Arc<str>>,
+    /// Gotos to computed lines the host hasn't taken yet, while tracing them.
+    goto_events: Option<Vec<DynamicGoto>>,
+    /// Float results which would differ if computed precisely, while checking them.
+    divergences: Option<AtomicRefCell<Vec<Divergence>>>,
     numbers: Vec<AtomicRefCell<Number>>,
     strings: Vec<AtomicRefCell<YString>>,
     values: Vec<AtomicRefCell<Value>>,
     idents: AHashMap<Ident, AnyReg>,
+    breakpoints: Vec<Breakpoint>,
+    /// The next instruction of the current section, while stopped partway through a line.
+    paused: Option<usize>,
+    watches: Vec<watch::Watch>,
+    devices: Vec<device::Device>,
+    /// Globals [`IRMachine::step_or_yield`] stops before reading.
+    pending: Vec<Ident>,
+    #[cfg(feature = "trace-hooks")]
+    trace_hook: Option<hooks::Hook>,
+    #[cfg(feature = "opcode-counts")]
+    opcode_counts: Vec<u64>,
 }
 
 macro_rules! reg_fns {
@@ -69,11 +191,19 @@ macro_rules! reg_fns {
         }
 
         fn $ref_name(&self, reg: $reg) -> Option<impl Deref<Target = $val> + '_> {
-            self.$field[reg.0].try_borrow().ok()
+            #[cfg(debug_assertions)]
+            let cell = self.$field.get(reg.0).unwrap_or_else(|| self.bad_register(reg.into()));
+            #[cfg(not(debug_assertions))]
+            let cell = &self.$field[reg.0];
+            cell.try_borrow().ok()
         }
 
         fn $mut_name(&self, reg: $reg) -> Option<impl DerefMut<Target = $val> + '_> {
-            self.$field[reg.0].try_borrow_mut().ok()
+            #[cfg(debug_assertions)]
+            let cell = self.$field.get(reg.0).unwrap_or_else(|| self.bad_register(reg.into()));
+            #[cfg(not(debug_assertions))]
+            let cell = &self.$field[reg.0];
+            cell.try_borrow_mut().ok()
         }
     };
 }
@@ -83,7 +213,84 @@ impl IRMachine {
     reg_fns!(new_str_reg, str_ref, str_mut, StrReg, YString, strings);
     reg_fns!(new_val_reg, val_ref, val_mut, ValReg, Value, values);
 
+    /// Where the machine is, for panics about code that was compiled or transformed wrong.
+    #[cfg(debug_assertions)]
+    fn location(&self) -> String {
+        let line = self.lines.iter().position(|&s| s == self.line_start);
+        format!(
+            "on line {} in {}",
+            line.map_or("?".to_string(), |l| (l + 1).to_string()),
+            self.current_sect,
+        )
+    }
+
+    #[cfg(debug_assertions)]
+    #[cold]
+    fn bad_register(&self, reg: AnyReg) -> ! {
+        let (file, len) = match reg {
+            AnyReg::Num(_) => ("number", self.numbers.len()),
+            AnyReg::Str(_) => ("string", self.strings.len()),
+            AnyReg::Val(_) => ("value", self.values.len()),
+        };
+        let users: Vec<_> = self.sections
+            .get(self.current_sect.0)
+            .into_iter()
+            .flat_map(|s| s.instrs.iter())
+            .filter(|i| i.relevant().contains(&reg))
+            .map(|i| format!("\n\t{}", i))
+            .collect();
+        panic!(
+            "{} is out of bounds {}, there are only {} {} registers. Instructions using it:{}",
+            reg,
+            self.location(),
+            len,
+            file,
+            users.concat(),
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_jump(&self, target: Section, cause: &dyn Display) {
+        if target.0 >= self.sections.len() {
+            panic!(
+                "'{}' {} jumps to {}, but there are only {} sections",
+                cause,
+                self.location(),
+                target,
+                self.sections.len(),
+            );
+        }
+    }
+
+    fn both_nums(&self, v1: ValReg, v2: ValReg) -> bool {
+        let (l, r) = (self.val_ref(v1).unwrap(), self.val_ref(v2).unwrap());
+        matches!((&*l, &*r), (Value::Num(_), Value::Num(_)))
+    }
+
+    /// Sets `n1` to `op` of it and `n2`, or the error flag if that fails.
+    fn num_overflowing(&self, n1: NumReg, n2: NumReg, op: OverflowOp) {
+        let r = *self.num_ref(n2).unwrap();
+        let mut n = self.num_mut(n1).unwrap();
+        match op(self.overflow, *n, r) {
+            Ok(v) => *n = v,
+            Err(_) => self.runtime_err.store(true, Ordering::Relaxed),
+        }
+    }
+
+    /// Like [`IRMachine::num_overflowing`], for values which are both numbers.
+    fn val_overflowing(&self, v1: ValReg, v2: ValReg, op: OverflowOp) {
+        let r = self.val_ref(v2).unwrap().as_number().unwrap();
+        let mut v = self.val_mut(v1).unwrap();
+        match op(self.overflow, v.as_number().unwrap(), r) {
+            Ok(n) => *v = Value::Num(n),
+            Err(_) => self.runtime_err.store(true, Ordering::Relaxed),
+        }
+    }
+
     fn execute_instr(&self, instr: Instruction) -> Option<Section> {
+        if self.divergences.is_some() {
+            self.check_float(instr);
+        }
         match instr {
             Instruction::JumpSectionIf(sect, condition) => {
                 debug_assert_ne!(
@@ -109,9 +316,26 @@ impl IRMachine {
                 );
                 let runtime_err = self.runtime_err.swap(false, Ordering::Relaxed);
                 if runtime_err {
+                    event!(
+                        DEBUG,
+                        line =
+                            self.lines.iter().position(|&s| s == self.line_start).unwrap_or(0) + 1,
+                        "runtime error",
+                    );
                     return Some(sect);
                 }
             },
+            Instruction::JumpIfEq(sect, l, r) => {
+                return (*self.val_ref(l).unwrap() == *self.val_ref(r).unwrap()).then_some(sect);
+            },
+            Instruction::JumpIfLe(sect, l, r) => {
+                let l = &*self.val_ref(l).unwrap();
+                return l.cmp_yolol(&self.val_ref(r).unwrap()).is_le().then_some(sect);
+            },
+            Instruction::JumpIfLt(sect, l, r) => {
+                let l = &*self.val_ref(l).unwrap();
+                return l.cmp_yolol(&self.val_ref(r).unwrap()).is_lt().then_some(sect);
+            },
             Instruction::CopyNum(from, to) => if from != to {
                 *self.num_mut(to).unwrap() = *self.num_ref(from).unwrap();
             },
@@ -143,6 +367,7 @@ impl IRMachine {
                 let mut s = self.str_mut(s).unwrap();
                 s.clear();
                 self.num_ref(n).unwrap().stringify_with_buffer(&mut s);
+                s.truncate_in(self.max_string_len, self.string_mode);
             },
             Instruction::StringifyVal(v, s) => {
                 let mut s = self.str_mut(s).unwrap();
@@ -170,30 +395,53 @@ impl IRMachine {
             Instruction::NotVal(v, n) => {
                 *self.num_mut(n).unwrap() = !&*self.val_ref(v).unwrap();
             },
+            // wrapping is what the plain operators do, so it takes the fast paths below
+            Instruction::AddNum(n1, n2) if self.overflow != OverflowPolicy::Wrapping =>
+                self.num_overflowing(n1, n2, OverflowPolicy::add),
+            Instruction::SubNum(n1, n2) if self.overflow != OverflowPolicy::Wrapping =>
+                self.num_overflowing(n1, n2, OverflowPolicy::sub),
+            Instruction::Mul(n1, n2) if self.overflow != OverflowPolicy::Wrapping =>
+                self.num_overflowing(n1, n2, OverflowPolicy::mul),
+            Instruction::AddVal(v1, v2)
+                if self.overflow != OverflowPolicy::Wrapping && self.both_nums(v1, v2) =>
+                self.val_overflowing(v1, v2, OverflowPolicy::add),
+            Instruction::SubVal(v1, v2)
+                if self.overflow != OverflowPolicy::Wrapping && self.both_nums(v1, v2) =>
+                self.val_overflowing(v1, v2, OverflowPolicy::sub),
             Instruction::AddNum(n1, n2) => if n1 == n2 {
                 let mut n = self.num_mut(n1).unwrap();
-                let n2 = n.clone();
+                let n2 = *n;
                 *n += n2;
             } else {
                 *self.num_mut(n1).unwrap() += *self.num_ref(n2).unwrap();
             },
-            Instruction::AddStr(s1, s2) => if s1 == s2 {
-                self.str_mut(s1).unwrap().duplicate();
-            } else {
-                *self.str_mut(s1).unwrap() += &self.str_ref(s2).unwrap();
+            Instruction::AddStr(s1, s2) => {
+                if s1 == s2 {
+                    self.str_mut(s1).unwrap().duplicate_in(self.string_mode);
+                } else {
+                    let mode = self.string_mode;
+                    self.str_mut(s1).unwrap().append_in(&self.str_ref(s2).unwrap(), mode);
+                }
+                self.str_mut(s1).unwrap().truncate_in(self.max_string_len, self.string_mode);
             },
-            Instruction::AddVal(v1, v2) => if v1 == v2 {
-                match *self.val_mut(v1).unwrap() {
-                    Value::Num(ref mut n) => {
-                        let n2 = n.clone();
-                        *n += n2;
-                    },
-                    Value::Str(ref mut s) => {
-                        s.duplicate();
-                    },
+            Instruction::AddVal(v1, v2) => {
+                if v1 == v2 {
+                    match *self.val_mut(v1).unwrap() {
+                        Value::Num(ref mut n) => {
+                            let n2 = *n;
+                            *n += n2;
+                        },
+                        Value::Str(ref mut s) => {
+                            s.duplicate_in(self.string_mode);
+                        },
+                    }
+                } else {
+                    let mode = self.string_mode;
+                    self.val_mut(v1).unwrap().add_assign_in(&self.val_ref(v2).unwrap(), mode);
+                }
+                if let Value::Str(s) = &mut *self.val_mut(v1).unwrap() {
+                    s.truncate_in(self.max_string_len, self.string_mode);
                 }
-            } else {
-                *self.val_mut(v1).unwrap() += &self.val_ref(v2).unwrap();
             },
             Instruction::SubNum(n1, n2) => if n1 == n2 {
                 *self.num_mut(n1).unwrap() = Number::ZERO;
@@ -220,18 +468,18 @@ impl IRMachine {
             Instruction::Mul(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
                 *n *= n2;
             },
             Instruction::Div(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
                 if let Ok(v) = *n / n2 {
                     *n = v;
@@ -242,11 +490,11 @@ impl IRMachine {
             Instruction::Rem(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
-                if let Ok(v) = *n % n2 {
+                if let Ok(v) = self.compat.rem(*n, n2) {
                     *n = v;
                 } else {
                     self.runtime_err.store(true, Ordering::Relaxed);
@@ -255,9 +503,9 @@ impl IRMachine {
             Instruction::Pow(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
                 n.pow_assign(n2);
             },
@@ -269,36 +517,42 @@ impl IRMachine {
             Instruction::Le(l, r, out) => {
                 let l = &*self.val_ref(l).unwrap();
                 let r = &*self.val_ref(r).unwrap();
-                *self.num_mut(out).unwrap() = (l <= r).into();
+                *self.num_mut(out).unwrap() = l.cmp_yolol(r).is_le().into();
             },
             Instruction::Lt(l, r, out) => {
                 let l = &*self.val_ref(l).unwrap();
                 let r = &*self.val_ref(r).unwrap();
-                *self.num_mut(out).unwrap() = (l < r).into();
+                *self.num_mut(out).unwrap() = l.cmp_yolol(r).is_lt().into();
             },
             Instruction::IncNum(n) => {
                 self.num_mut(n).unwrap().pre_inc();
             },
             Instruction::IncStr(s) => {
-                self.str_mut(s).unwrap().pre_inc();
+                let mut s = self.str_mut(s).unwrap();
+                s.pre_inc();
+                s.truncate_in(self.max_string_len, self.string_mode);
             },
             Instruction::IncVal(v) => {
-                self.val_mut(v).unwrap().pre_inc();
+                let mut v = self.val_mut(v).unwrap();
+                v.pre_inc();
+                if let Value::Str(s) = &mut *v {
+                    s.truncate_in(self.max_string_len, self.string_mode);
+                }
             },
             Instruction::DecNum(n) => {
                 self.num_mut(n).unwrap().pre_dec();
             },
             Instruction::DecStr(s) => {
-                let out = self.str_mut(s).unwrap().pre_dec();
+                let out = self.str_mut(s).unwrap().pre_dec_in(self.string_mode);
                 self.runtime_err.store(out.is_err(), Ordering::Relaxed);
             },
             Instruction::DecVal(v) => {
-                let out = self.val_mut(v).unwrap().pre_dec();
+                let out = self.val_mut(v).unwrap().pre_dec_in(self.string_mode);
                 self.runtime_err.store(out.is_err(), Ordering::Relaxed);
             },
             Instruction::Abs(n) => {
                 let mut n = self.num_mut(n).unwrap();
-                *n = n.abs();
+                *n = self.compat.abs(*n);
             },
             Instruction::Fact(n) => {
                 let mut n = self.num_mut(n).unwrap();
@@ -332,6 +586,23 @@ impl IRMachine {
                 let mut n = self.num_mut(n).unwrap();
                 *n = n.atan();
             },
+            Instruction::Exp(n) => {
+                let mut n = self.num_mut(n).unwrap();
+                *n = n.exp();
+            },
+            Instruction::Ln(n) => {
+                let mut n = self.num_mut(n).unwrap();
+                *n = n.ln();
+            },
+            Instruction::Log10(n) => {
+                let mut n = self.num_mut(n).unwrap();
+                *n = n.log10();
+            },
+            Instruction::Log(n1, n2) => {
+                let base = *self.num_ref(n2).unwrap();
+                let mut n = self.num_mut(n1).unwrap();
+                *n = n.log(base);
+            },
             Instruction::Neg(n) => {
                 let mut n = self.num_mut(n).unwrap();
                 *n = -*n;
@@ -339,21 +610,26 @@ impl IRMachine {
             Instruction::And(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
                 *n = (n.as_bool() && n2.as_bool()).into();
             },
             Instruction::Or(n1, n2) => {
                 let mut n = self.num_mut(n1).unwrap();
                 let n2 = if n1 == n2 {
-                    n.clone()
+                    *n
                 } else {
-                    self.num_ref(n2).unwrap().clone()
+                    *self.num_ref(n2).unwrap()
                 };
                 *n = (n.as_bool() || n2.as_bool()).into();
             },
+            Instruction::SelectNum(c, t, f, out) => {
+                let chosen = if self.num_ref(c).unwrap().as_bool() { t } else { f };
+                let chosen = *self.num_ref(chosen).unwrap();
+                *self.num_mut(out).unwrap() = chosen;
+            },
         };
         None
     }
@@ -363,8 +639,17 @@ impl IRMachine {
         if !FIRST && sect.line_start {
             return false;
         }
-        for &instr in sect.instrs.iter() {
+        for (index, &instr) in sect.instrs.iter().enumerate() {
+            #[cfg(feature = "trace-hooks")]
+            self.call_trace_hook(index);
+            #[cfg(feature = "opcode-counts")]
+            {
+                self.opcode_counts[instr.opcode()] += 1;
+            }
             if let Some(new_sect) = self.execute_instr(instr) {
+                if let Some(profile) = &mut self.profile {
+                    profile.pending += index as u64 + 1;
+                }
                 debug_assert_ne!(
                     new_sect,
                     Section(!0),
@@ -372,10 +657,16 @@ impl IRMachine {
                     self.current_sect.0,
                     sect,
                 );
+                #[cfg(debug_assertions)]
+                self.check_jump(new_sect, &instr);
                 self.current_sect = new_sect;
-                return false;
+                // a taken branch carries on with the line, but an error skips to the next one
+                return !self.sections[new_sect.0].line_start;
             }
         }
+        if let Some(profile) = &mut self.profile {
+            profile.pending += sect.instrs.len() as u64;
+        }
         match sect.success {
             SectionOrLine::Section(s) => {
                 debug_assert_ne!(
@@ -385,19 +676,43 @@ impl IRMachine {
                     self.current_sect.0,
                     sect,
                 );
+                #[cfg(debug_assertions)]
+                self.check_jump(s, &sect.success);
                 self.current_sect = s;
                 true
             },
             SectionOrLine::Line(l) => {
-                let line = self.num_ref(l).unwrap().as_f32() as usize;
-                self.current_sect = self.lines[line.clamp(1, self.lines.len()) - 1];
+                self.goto_line(l);
                 false
             },
         }
     }
 
+    /// Ends the line with a `goto` to the line number in `l`.
+    fn goto_line(&mut self, l: NumReg) {
+        let target = *self.num_ref(l).unwrap();
+        let resolved = self.goto_policy.resolve(target, self.lines.len());
+        if self.goto_events.is_some() {
+            self.record_goto(target, resolved);
+        }
+        let line = resolved.unwrap_or_else(|| {
+            let line = self.lines.iter().position(|&s| s == self.line_start).unwrap();
+            (line + 1) % self.lines.len()
+        });
+        self.current_sect = self.lines[line];
+    }
+
     pub fn step(&mut self) {
+        span!(TRACE, "step");
+        if self.paused.is_some() || !self.watches.is_empty() {
+            self.step_line();
+            return;
+        }
+        if !self.devices.is_empty() {
+            self.read_devices();
+        }
         let mut running = true;
+        self.line_start = self.current_sect;
         self.execute_sect::<true>();
 
         while running {
@@ -409,6 +724,73 @@ impl IRMachine {
                 );
             }
         }
+
+        self.end_line();
+    }
+
+    fn end_line(&mut self) {
+        if !self.devices.is_empty() {
+            self.write_devices();
+        }
+        if !self.asserts.is_empty() {
+            self.check_asserts();
+        }
+        if self.profile.is_some() {
+            self.count_line();
+        }
+    }
+
+    fn check_asserts(&mut self) {
+        let mut failures = Vec::new();
+        for assert in self.asserts.iter().filter(|a| a.line == self.line_start) {
+            // a jump out of an expression only happens on a runtime error
+            let errored = self.sections[assert.section.0]
+                .instrs
+                .iter()
+                .any(|&instr| self.execute_instr(instr).is_some());
+            if errored {
+                failures.push(format!("{}, with a runtime error", assert.message));
+            } else if !self.num_ref(assert.result).unwrap().as_bool() {
+                failures.push(assert.message.clone());
+            }
+        }
+        *self.runtime_err.get_mut() = false;
+
+        let line = self.lines.iter().position(|&s| s == self.line_start).unwrap() + 1;
+        self.diagnostics.extend(failures.into_iter().map(|message| Diagnostic {
+            severity: Severity::Error,
+            line,
+            message,
+        }));
+    }
+
+    /// Start or stop reporting gotos to computed lines, like `goto i*2`, to catch runaway
+    /// gotos. Stopping drops any events which haven't been taken.
+    pub fn trace_dynamic_gotos(&mut self, on: bool) {
+        self.goto_events = on.then(Vec::new);
+    }
+
+    /// Take the gotos to computed lines taken since the last call, while tracing them.
+    pub fn take_goto_events(&mut self) -> Vec<DynamicGoto> {
+        self.goto_events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn record_goto(&mut self, target: Number, resolved: Option<usize>) {
+        let Some(expr) = self.dynamic_gotos.get(&self.current_sect) else {
+            return;
+        };
+        let event = DynamicGoto {
+            line: self.lines.iter().position(|&s| s == self.line_start).unwrap() + 1,
+            expr: expr.clone(),
+            target,
+            landed: resolved.map(|l| l + 1),
+        };
+        self.goto_events.as_mut().unwrap().push(event);
+    }
+
+    /// Take the diagnostics reported since the last call.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
     }
 
     pub fn step_repeat(&mut self, reps: usize) {
@@ -417,22 +799,116 @@ impl IRMachine {
         }
     }
 
+    /// Runs at most `lines` lines, returning whether they all ran rather than stopping early
+    /// at a breakpoint. Calling it again carries on exactly where it stopped. If that was
+    /// partway through a line, the rest of it counts as the first line, so `0` leaves the
+    /// machine where it is.
+    pub fn run_for_lines(&mut self, lines: usize) -> bool {
+        if self.breakpoints.is_empty() {
+            self.step_repeat(lines);
+            return true;
+        }
+        self.continue_until_break(lines).is_none()
+    }
+
     pub fn get_ident_value(&self, ident: &Ident) -> Value {
         match self.idents.get(ident) {
-            Some(&AnyReg::Num(n)) => self.num_ref(n).unwrap().deref().clone().into(),
+            Some(&AnyReg::Num(n)) => (*self.num_ref(n).unwrap().deref()).into(),
             Some(&AnyReg::Str(s)) => self.str_ref(s).unwrap().deref().clone().into(),
             Some(&AnyReg::Val(v)) => self.val_ref(v).unwrap().deref().clone(),
             None => Value::Num(0.into()),
         }
     }
 
+    /// Every protected variable and its value, in [`Ident`] order.
     pub fn idents(&self) -> impl IntoIterator<Item = (&Ident, Value)> + '_ {
-        self.idents
-            .iter()
-            .map(|(s, _)| (s, self.get_ident_value(&s)))
+        let mut idents: Vec<_> = self.idents.keys().collect();
+        idents.sort_unstable();
+        idents.into_iter().map(|s| (s, self.get_ident_value(s)))
+    }
+
+    /// The register holding a variable, if it's protected (see [`CodegenOptions`]). Passes can
+    /// move it, so look it up again after running any.
+    pub fn ident_register(&self, ident: &Ident) -> Option<Register> {
+        self.idents.get(ident).map(|&r| r.into())
+    }
+
+    // Register indices only hold until [`IRMachine::compact_registers`] runs, by itself or as
+    // part of register coalescing. It keeps the registers it doesn't remove in order, so a
+    // count which hasn't changed means nothing has moved.
+
+    pub fn number_count(&self) -> usize {
+        self.numbers.len()
+    }
+
+    pub fn string_count(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn value_count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn number(&self, index: usize) -> Option<Number> {
+        self.numbers.get(index).map(|n| *n.borrow())
+    }
+
+    pub fn string(&self, index: usize) -> Option<impl Deref<Target = YString> + '_> {
+        self.strings.get(index).map(|s| s.borrow())
+    }
+
+    pub fn value(&self, index: usize) -> Option<impl Deref<Target = Value> + '_> {
+        self.values.get(index).map(|v| v.borrow())
+    }
+
+    /// Numbers registers of all three types together, for analyses with a flag per register.
+    fn flat_index(&self, reg: AnyReg) -> usize {
+        match reg {
+            AnyReg::Num(r) => r.0,
+            AnyReg::Str(r) => self.numbers.len() + r.0,
+            AnyReg::Val(r) => self.numbers.len() + self.strings.len() + r.0,
+        }
+    }
+
+    fn register_total(&self) -> usize {
+        self.numbers.len() + self.strings.len() + self.values.len()
+    }
+
+    /// A hash of every register and where the machine is, which only changes if one of those
+    /// does (barring collisions).
+    pub fn state_fingerprint(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = ahash::AHasher::default();
+        for n in self.numbers.iter() {
+            n.borrow().hash(&mut hasher);
+        }
+        for s in self.strings.iter() {
+            s.borrow().hash(&mut hasher);
+        }
+        for v in self.values.iter() {
+            v.borrow().hash(&mut hasher);
+        }
+        self.current_sect.hash(&mut hasher);
+        self.paused.hash(&mut hasher);
+        hasher.finish()
     }
 
+    /// Sets `ident`, if the program uses it, to `val`, which goes through the machine's
+    /// [`UnicodePolicy`] and [`YString::fit_in`] if it's a string. One the policy rejects leaves
+    /// `ident` as it was, see [`IRMachine::try_set_ident`]. Panics if `ident` can't hold `val`.
     pub fn set_ident(&mut self, ident: &Ident, val: Value) {
+        let _ = self.try_set_ident(ident, val);
+    }
+
+    /// Like [`IRMachine::set_ident`], failing if `val` is a string the machine's
+    /// [`UnicodePolicy`] rejects.
+    pub fn try_set_ident(&mut self, ident: &Ident, mut val: Value) -> Result<(), NonAscii> {
+        if let Value::Str(s) = &mut val {
+            s.apply_policy(self.unicode)?;
+            s.fit_in(self.string_mode);
+            s.truncate_in(self.max_string_len, self.string_mode);
+        }
         if let Some(&reg) = self.idents.get(ident) {
             match (reg, val) {
                 (AnyReg::Num(r), Value::Num(n)) => {
@@ -447,6 +923,7 @@ impl IRMachine {
                 (_, _) => panic!("Tried to set '{}' to incorrect type", ident),
             }
         }
+        Ok(())
     }
 
     pub fn print_bytecode(&self, sink: &mut impl Write) -> std::io::Result<()> {
@@ -460,7 +937,9 @@ impl IRMachine {
 
         writeln!(sink, "Globals:")?;
 
-        for (ident, &reg) in self.idents.iter() {
+        let mut idents: Vec<_> = self.idents.iter().collect();
+        idents.sort_unstable_by_key(|&(ident, _)| ident);
+        for (ident, &reg) in idents {
             writeln!(sink, "`{}` is {}", ident, reg)?;
         }
 
@@ -508,6 +987,22 @@ impl IRMachine {
         Ok(())
     }
 
+    /// Where the `instr`th instruction of `section` came from, if compiled with
+    /// [`CodegenOptions::provenance`]. Sections are numbered as in [`cfg::ControlFlowGraph`].
+    pub fn provenance(&self, section: usize, instr: usize) -> Option<&Provenance> {
+        self.provenance.get(section)?.get(instr)
+    }
+
+    /// Every `// :field (unit) description` comment in the program, in line order.
+    pub fn annotations(&self) -> &[FieldAnnotation] {
+        &self.annotations
+    }
+
+    /// The last annotation of `field`, if there is one.
+    pub fn annotation(&self, field: &Ident) -> Option<&FieldAnnotation> {
+        self.annotations.iter().rfind(|a| a.field == *field)
+    }
+
     pub fn get_current_line(&self) -> Option<usize> {
         self.lines.iter().enumerate().find(|(_, &s)| s == self.current_sect).map(|(i, _)| i)
     }
@@ -517,17 +1012,55 @@ impl IRMachine {
     }
 }
 
+impl Index<usize> for IRMachine {
+    type Output = SectionCode;
+
+    fn index(&self, index: usize) -> &SectionCode {
+        &self.sections[index]
+    }
+}
+
+impl IndexMut<usize> for IRMachine {
+    fn index_mut(&mut self, index: usize) -> &mut SectionCode {
+        &mut Arc::make_mut(&mut self.sections)[index]
+    }
+}
+
 impl Clone for IRMachine {
     fn clone(&self) -> Self {
         Self {
             sections: self.sections.clone(),
             lines: self.lines.clone(),
-            current_sect: self.current_sect.clone(),
+            current_sect: self.current_sect,
+            line_start: self.line_start,
             runtime_err: self.runtime_err.load(Ordering::Relaxed).into(),
+            goto_policy: self.goto_policy,
+            compat: self.compat,
+            overflow: self.overflow,
+            string_mode: self.string_mode,
+            max_string_len: self.max_string_len,
+            unicode: self.unicode,
+            asserts: self.asserts.clone(),
+            annotations: self.annotations.clone(),
+            diagnostics: self.diagnostics.clone(),
+            profile: self.profile.clone(),
+            provenance: self.provenance.clone(),
+            dynamic_gotos: self.dynamic_gotos.clone(),
+            goto_events: self.goto_events.clone(),
+            divergences: self.divergences.as_ref().map(|d| AtomicRefCell::new(d.borrow().clone())),
             numbers: self.numbers.clone(),
             strings: self.strings.clone(),
             values: self.values.clone(),
             idents: self.idents.clone(),
+            breakpoints: self.breakpoints.clone(),
+            paused: self.paused,
+            watches: Vec::new(),
+            devices: Vec::new(),
+            pending: self.pending.clone(),
+            #[cfg(feature = "trace-hooks")]
+            trace_hook: None,
+            #[cfg(feature = "opcode-counts")]
+            opcode_counts: self.opcode_counts.clone(),
         }
     }
 
@@ -535,11 +1068,36 @@ impl Clone for IRMachine {
         self.sections.clone_from(&source.sections);
         self.lines.clone_from(&source.lines);
         self.current_sect = source.current_sect;
+        self.line_start = source.line_start;
         *self.runtime_err.get_mut() = source.runtime_err.load(Ordering::Relaxed);
+        self.goto_policy = source.goto_policy;
+        self.compat = source.compat;
+        self.overflow = source.overflow;
+        self.string_mode = source.string_mode;
+        self.max_string_len = source.max_string_len;
+        self.unicode = source.unicode;
+        self.asserts.clone_from(&source.asserts);
+        self.annotations.clone_from(&source.annotations);
+        self.diagnostics.clone_from(&source.diagnostics);
+        self.profile.clone_from(&source.profile);
+        self.provenance.clone_from(&source.provenance);
+        self.dynamic_gotos.clone_from(&source.dynamic_gotos);
+        self.goto_events.clone_from(&source.goto_events);
+        self.divergences =
+            source.divergences.as_ref().map(|d| AtomicRefCell::new(d.borrow().clone()));
         self.numbers.clone_from(&source.numbers);
         self.strings.clone_from(&source.strings);
         self.values.clone_from(&source.values);
         self.idents.clone_from(&source.idents);
+        self.breakpoints.clone_from(&source.breakpoints);
+        self.paused = source.paused;
+        self.watches.clear();
+        self.devices.clear();
+        self.pending.clone_from(&source.pending);
+        #[cfg(feature = "trace-hooks")]
+        self.clear_trace_hook();
+        #[cfg(feature = "opcode-counts")]
+        self.opcode_counts.clone_from(&source.opcode_counts);
     }
 }
 
@@ -550,12 +1108,18 @@ mod tests {
     use super::*;
 
     fn tester(src: &(impl AsRef<str> + ?Sized)) {
+        tester_with(src, Default::default());
+    }
+
+    /// Like [`tester`], compiling with `options` apart from protecting every variable.
+    fn tester_with(src: &(impl AsRef<str> + ?Sized), options: CodegenOptions) {
         let program = YololParser::unrestricted().parse(src.as_ref()).unwrap();
         let mut simple_interp = SimpleInterp::new(program.clone());
         let mut ir_machine = IRMachine::from_ast(
             CodegenOptions {
                 protect_locals: true,
                 protect_globals: true,
+                ..options
             },
             program,
         );
@@ -569,6 +1133,18 @@ mod tests {
         }
     }
 
+    /// Every variable `machine` keeps, with its value.
+    pub(super) fn variables(machine: &IRMachine) -> Vec<(Ident, Value)> {
+        machine.idents().into_iter().map(|(i, v)| (i.clone(), v)).collect()
+    }
+
+    /// Checks every global in `simple` has the same value in `machine`.
+    pub(super) fn assert_matches_simple_interp(machine: &IRMachine, simple: &SimpleInterp) {
+        for (ident, value) in simple.values().iter().filter(|(i, _)| i.global) {
+            assert_eq!(machine.get_ident_value(ident), *value, "{}", ident);
+        }
+    }
+
     #[test]
     fn multiply_huge()
     {
@@ -929,4 +1505,487 @@ goto8"#
     fn many_lines() {
         tester(&("\n".repeat(30) + r#":output="ok" goto30"#));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn temporaries_are_reused() {
+        let src = format!("b=3\n{}goto1", "a=(b*2-1)/(b+1) c=a+b*b-(not a)\n".repeat(10));
+        tester(&src);
+        let program = YololParser::unrestricted().parse(&src).unwrap();
+        let ir_machine = IRMachine::from_ast(Default::default(), program);
+        assert!(ir_machine.values.len() < 10, "used {} value registers", ir_machine.values.len());
+    }
+
+    #[test]
+    fn selects() {
+        let src = "\
+            :x=(:x+1)%5 :a=(:x>2)*5+(1-(:x>2))*-7 :b=(1-(not :x))*2+(not :x)*1.5\n\
+            if :x==1 then :c=4 else :c=8 end :d=(:x>2)*\"s\"+(1-(:x>2))*7 goto 1";
+        let options = CodegenOptions { select_numbers: true, ..Default::default() };
+        tester_with(src, options.clone());
+        let program = YololParser::unrestricted().parse(src).unwrap();
+        let ir_machine = IRMachine::from_ast(options, program);
+        let selects = ir_machine.sections
+            .iter()
+            .flat_map(|s| s.instrs.iter())
+            .filter(|i| matches!(i, Instruction::SelectNum(..)))
+            .count();
+        // not the string one
+        assert_eq!(selects, 3);
+    }
+
+    #[test]
+    fn globals_keep_original_case() {
+        let program = YololParser::unrestricted().parse(":DoorOpen=1 :dooropen+=1 :x=1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        ir_machine.step();
+        let mut globals: Vec<_> = ir_machine
+            .idents()
+            .into_iter()
+            .map(|(i, v)| (i.display_original().to_string(), i.to_string(), v))
+            .collect();
+        globals.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(globals, [
+            (":DoorOpen".to_string(), ":dooropen".to_string(), Value::Num(2.into())),
+            (":x".to_string(), ":x".to_string(), Value::Num(1.into())),
+        ]);
+        assert_eq!(ir_machine.get_ident_value(&Ident::global("DOOROPEN")), Value::Num(2.into()));
+    }
+
+    #[test]
+    fn goto_policies() {
+        let cases = [
+            ("1", [0, 0, 0]),
+            ("20", [19, 19, 19]),
+            ("0", [0, 3, 19]),
+            ("21", [19, 3, 0]),
+            ("-3", [0, 3, 16]),
+            ("2.9", [1, 1, 1]),
+            ("\"x\"", [3, 3, 3]),
+        ];
+        let policies = [GotoPolicy::Clamp, GotoPolicy::Error, GotoPolicy::Wrap];
+
+        for (target, expected) in cases {
+            let src = format!("a=1\nb=1\nc=1 goto {} d=1\n", target);
+            let program = YololParser::unrestricted().parse(&src).unwrap();
+            for (policy, expected) in policies.into_iter().zip(expected) {
+                let mut simple_interp = SimpleInterp::new(program.clone());
+                simple_interp.set_goto_policy(policy);
+                simple_interp.step_lines(3);
+
+                let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+                    goto_policy: policy,
+                    ..Default::default()
+                }, program.clone());
+                ir_machine.step_repeat(3);
+
+                assert_eq!(simple_interp.line(), expected, "goto {} with {:?}", target, policy);
+                assert_eq!(
+                    ir_machine.get_current_line(),
+                    Some(expected),
+                    "goto {} with {:?}",
+                    target,
+                    policy,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compat_profiles() {
+        let src = ":a=10%3.1 :b=10 :b%=3.1 :c=-9223372036854775.807-0.001 :c=abs :c :d=3%0.7";
+        let program = YololParser::unrestricted().parse(src).unwrap();
+        let cases = [
+            (Compat::Starbase_Latest, ["0.7", "0.7", "-9223372036854775.808", "0.2"]),
+        ];
+        for (compat, expected) in cases {
+            let mut simple_interp = SimpleInterp::new(program.clone());
+            simple_interp.set_compat(compat);
+            simple_interp.step_line();
+
+            let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+                compat,
+                ..Default::default()
+            }, program.clone());
+            ir_machine.step();
+
+            for (name, expected) in ["a", "b", "c", "d"].into_iter().zip(expected) {
+                let ident = Ident::global(name);
+                let expected = Value::Num(expected.parse().unwrap());
+                let simple_value = simple_interp.values().get(&ident).cloned().unwrap_or_default();
+                assert_eq!(simple_value, expected, "{} with {:?}", name, compat);
+                let ir_value = ir_machine.get_ident_value(&ident);
+                assert_eq!(ir_value, expected, "{} with {:?}", name, compat);
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let src = "a=9000000000000000 :a=a+a :b=-a*2 :c=a :c-=-a :d=1\n:e=a*0.5";
+        let program = YololParser::unrestricted().parse(src).unwrap();
+        let max = "9223372036854775.807";
+        let min = "-9223372036854775.808";
+        let cases = [
+            (OverflowPolicy::Wrapping, ["-446744073709551.616", "4022215940522.377"]),
+            (OverflowPolicy::Saturating, [max, min]),
+        ];
+        for (overflow, [a, b]) in cases {
+            let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+                overflow,
+                ..Default::default()
+            }, program.clone());
+            ir_machine.step();
+            let get = |name| ir_machine.get_ident_value(&Ident::global(name)).to_string();
+            assert_eq!([get("a"), get("b"), get("c"), get("d")], [a, b, a, "1"], "{:?}", overflow);
+        }
+
+        // the first overflow skips the rest of the line, even once constants are folded
+        let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+            overflow: OverflowPolicy::Error,
+            ..Default::default()
+        }, program);
+        OptPipeline::new().add(ConstantFolding).run(&mut ir_machine);
+        ir_machine.step_repeat(2);
+        let get = |name| ir_machine.get_ident_value(&Ident::global(name)).to_string();
+        assert_eq!([get("a"), get("d"), get("e")], ["0", "0", "4500000000000000"]);
+    }
+
+    #[test]
+    fn string_modes() {
+        let src = ":a=\"\u{5b57}\" :a+=:a :a+=:a :a+=:a :a+=:a :a+=:a :a+=:a :a+=:a :a+=:a :a+=:a\n\
+            :b=\"h\u{e9}\" :b-- :c=\"\" :c-- :d=1";
+        let literal = format!("\n:e=\"{}\"", "\u{5b57}".repeat(342));
+        let program = YololParser::unrestricted().parse(&(src.to_string() + &literal)).unwrap();
+        let cases = [(StringMode::Bytes, 1024, &b"h\xc3"[..]), (StringMode::Chars, 1023, b"h")];
+        for (string_mode, a_len, b) in cases {
+            let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+                string_mode,
+                ..Default::default()
+            }, program.clone());
+            ir_machine.step_repeat(2);
+            let get = |name| ir_machine.get_ident_value(&Ident::global(name));
+            let Value::Str(a) = get("a") else { panic!() };
+            assert_eq!(a.byte_len(), a_len, "{:?}", string_mode);
+            assert_eq!(get("b"), Value::Str(YString::from_bytes(b)), "{:?}", string_mode);
+            // taking from an empty string still fails
+            assert_eq!(get("d"), Value::Num(Number::ZERO), "{:?}", string_mode);
+
+            // strings cut before the machine sees them are fixed up too
+            ir_machine.step();
+            let e_len = |vm: &IRMachine| match vm.get_ident_value(&Ident::global("e")) {
+                Value::Str(e) => e.byte_len(),
+                _ => panic!(),
+            };
+            assert_eq!(e_len(&ir_machine), a_len, "{:?}", string_mode);
+            let long = YString::from("\u{5b57}".repeat(400));
+            ir_machine.set_ident(&Ident::global("e"), Value::Str(long));
+            assert_eq!(e_len(&ir_machine), a_len, "{:?}", string_mode);
+        }
+    }
+
+    #[test]
+    fn max_string_len() {
+        let src = ":a=\"abcdef\" :b=:a+:a :c=\"x\" :c++ :c++ :c++ :d=12345+\"\" :e=\"ab\u{5b57}\"";
+        let program = YololParser::unrestricted().parse(src).unwrap();
+        for (string_mode, e) in [(StringMode::Bytes, &b"ab\xe5"[..]), (StringMode::Chars, b"ab")] {
+            let options = CodegenOptions { string_mode, max_string_len: 3, ..Default::default() };
+            let mut ir_machine = IRMachine::from_ast(options, program.clone());
+            ir_machine.step();
+            // loaded machines keep the limit
+            let mut ir_machine = IRMachine::from_bytes(&ir_machine.to_bytes()).unwrap();
+            ir_machine.step();
+            let get = |name| ir_machine.get_ident_value(&Ident::global(name));
+            assert_eq!(get("a"), Value::Str("abc".into()));
+            assert_eq!(get("b"), Value::Str("abc".into()));
+            assert_eq!(get("c"), Value::Str("x  ".into()));
+            assert_eq!(get("d"), Value::Str("123".into()));
+            assert_eq!(get("e"), Value::Str(YString::from_bytes(e)), "{:?}", string_mode);
+            ir_machine.set_ident(&Ident::global("a"), Value::Str("hello".into()));
+            assert_eq!(ir_machine.get_ident_value(&Ident::global("a")), Value::Str("hel".into()));
+        }
+    }
+
+    #[test]
+    fn unicode_from_outside() {
+        let program = YololParser::default().parse(":a=\"\" :b=\"h\u{e9}\"").unwrap();
+        let machine = |unicode| {
+            let options = CodegenOptions { unicode, ..Default::default() };
+            let mut machine = IRMachine::from_ast(options, program.clone());
+            machine.step();
+            machine
+        };
+        let a = Ident::global("a");
+        let mut replacing = machine(UnicodePolicy::Replace);
+        replacing.set_ident(&a, Value::Str("\u{e9}t\u{e9}".into()));
+        assert_eq!(replacing.get_ident_value(&a), Value::Str("?t?".into()));
+
+        let mut keeping = machine(UnicodePolicy::Keep);
+        keeping.set_ident(&a, Value::Str("\u{e9}".into()));
+        let state = keeping.export_state_string().unwrap();
+        let mut rejecting = machine(UnicodePolicy::Reject);
+        assert!(rejecting.try_set_ident(&a, Value::Str("\u{e9}".into())).is_err());
+        assert!(rejecting.import_state_string(&state).is_err());
+        assert_eq!(rejecting.get_ident_value(&a), Value::Str("".into()));
+        // the literal came from a parser keeping it, so only loading the bytecode refuses it
+        assert!(IRMachine::from_bytes(&rejecting.to_bytes()).is_err());
+        let bytes = machine(UnicodePolicy::Replace).to_bytes();
+        let mut loaded = IRMachine::from_bytes(&bytes).unwrap();
+        loaded.step();
+        let b = Ident::global("b");
+        assert_eq!(loaded.get_ident_value(&b), Value::Str("h?".into()));
+    }
+
+    #[test]
+    fn asserts() {
+        let src = "a=1 // assert: a==1\nb=0 // assert: b==1\nc=1/b // assert: 1/b\n\
+            goto 1 // assert:x";
+        let program = YololParser::default().parse(src).unwrap();
+
+        let mut unchecked = IRMachine::from_ast(Default::default(), program.clone());
+        unchecked.step_repeat(4);
+        assert!(unchecked.take_diagnostics().is_empty());
+
+        let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+            check_asserts: true,
+            ..Default::default()
+        }, program);
+        ir_machine.step_repeat(4);
+        let diagnostics: Vec<_> = ir_machine
+            .take_diagnostics()
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(diagnostics, [
+            "line 2: error: assertion failed: b == 1",
+            "line 3: error: assertion failed: 1 / b, with a runtime error",
+            "line 4: error: assertion failed: x",
+        ]);
+        assert_eq!(ir_machine.get_current_line(), Some(0));
+    }
+
+    #[test]
+    fn register_views() {
+        let program = YololParser::default().parse("a=2 b=\"x\"+a").unwrap();
+        let unprotected = IRMachine::from_ast(Default::default(), program.clone());
+        assert_eq!(unprotected.ident_register(&Ident::local("a")), None);
+
+        let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+            protect_locals: true,
+            ..Default::default()
+        }, program);
+        ir_machine.step();
+
+        let Some(Register::Value(b)) = ir_machine.ident_register(&Ident::local("b")) else {
+            panic!("b isn't in a value register");
+        };
+        assert_eq!(*ir_machine.value(b).unwrap(), Value::Str("x2".into()));
+        assert!(ir_machine.value(ir_machine.value_count()).is_none());
+        assert!((0..ir_machine.number_count()).any(|i| ir_machine.number(i) == Some(2.into())));
+        let x = YString::from("x");
+        assert!((0..ir_machine.string_count()).any(|i| *ir_machine.string(i).unwrap() == x));
+    }
+
+    #[test]
+    fn register_pinning() {
+        let pins = [(Ident::local("speed"), 3), (Ident::global("out"), 0)];
+        let compile = |src: &str| {
+            let program = YololParser::default().parse(src).unwrap();
+            IRMachine::compile_with_pinning(Default::default(), program, pins.clone()).unwrap()
+        };
+        let before = compile("speed=2 :out=speed*3");
+        let mut after = compile("x=1 y=x+1 speed=y*2 :out=speed*3 :other=x");
+        for (ident, reg) in pins.iter() {
+            assert_eq!(before.ident_register(ident), Some(Register::Value(*reg)));
+            assert_eq!(after.ident_register(ident), Some(Register::Value(*reg)));
+        }
+        after.step();
+        assert_eq!(after.get_ident_value(&Ident::local("speed")), Value::Num(4.into()));
+        assert_eq!(after.get_ident_value(&Ident::global("out")), Value::Num(12.into()));
+
+        let program = YololParser::default().parse("a=1").unwrap();
+        let clash = [(Ident::local("a"), 1), (Ident::local("b"), 1)];
+        assert!(IRMachine::compile_with_pinning(Default::default(), program, clash).is_err());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut program = YololParser::default().parse(":a=1 :b=2").unwrap();
+        let deep = (0..1000).fold(Expr::from(Ident::global("a")), |e, _| {
+            Expr::Unop(Unop::Neg, Box::new(e))
+        });
+        let assign = Statement::Assign(Ident::global("b"), None, deep);
+        program.lines[2].stmts = vec![Statement::Goto(1.into()), assign];
+        let options = CodegenOptions { max_depth: 100, ..Default::default() };
+        assert_eq!(
+            IRMachine::try_from_ast(options.clone(), program.clone()).err(),
+            Some(CodegenError::ExpressionTooComplex { line: 3, statement: 2, limit: 100 }),
+        );
+
+        let nested = (0..99).fold(Statement::Goto(1.into()), |s, _| {
+            Statement::Ite(1.into(), vec![s], vec![])
+        });
+        program.lines[2].stmts = vec![nested.clone()];
+        assert!(IRMachine::try_from_ast(options.clone(), program.clone()).is_ok());
+        program.lines[2].stmts = vec![Statement::Ite(1.into(), vec![nested], vec![])];
+        assert!(IRMachine::try_from_ast(options, program).is_err());
+
+        let src = ":a=1\n:b=2 :c=-(-(-:a))";
+        let program = YololParser::default().parse(src).unwrap();
+        let options = CodegenOptions { max_depth: 2, ..Default::default() };
+        let err = IRMachine::try_from_ast(options, program).unwrap_err();
+        let span = err.span(src).unwrap();
+        assert_eq!((span.line, span.col, &src[span.start..span.end]), (2, 6, ":c=-(-(-:a))"));
+    }
+
+    #[test]
+    fn deterministic_listings() {
+        let src = ":z=1 b=2 :a=b c=:z+:q y=\"s\" :m=y+c x=1\n:out=x+:a+:m goto 1";
+        let listing = || {
+            let program = YololParser::default().parse(src).unwrap();
+            let mut machine = IRMachine::from_ast(Default::default(), program);
+            machine.step();
+            let mut bytecode = Vec::new();
+            machine.print_bytecode(&mut bytecode).unwrap();
+            let idents: Vec<_> = machine.idents().into_iter().map(|(i, _)| i.to_string()).collect();
+            let registers: Vec<_> = machine.data_flow_graph().registers().collect();
+            (bytecode, idents, registers)
+        };
+        let first = listing();
+        let (_, idents, _) = &first;
+        assert_eq!(idents, &[":a", ":m", ":out", ":q", ":z"]);
+        for _ in 0..10 {
+            assert!(listing() == first);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "number #999 is out of bounds on line 1 in section #0")]
+    fn bad_register_panic() {
+        let program = YololParser::default().parse("a=1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        ir_machine[0].instrs.insert(0, Instruction::Neg(NumReg(999)));
+        ir_machine.step();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "flag is set, jump to section #99' on line 1 in section #0 jumps to")]
+    fn bad_jump_panic() {
+        let program = YololParser::default().parse("a=1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        ir_machine.runtime_err.store(true, Ordering::Relaxed);
+        ir_machine[0].instrs.insert(0, Instruction::JumpIfError(Section(99)));
+        ir_machine.step();
+    }
+
+    #[test]
+    fn provenance() {
+        let program = YololParser::default().parse("a=1\nif a then b=a*0+2 end :c++").unwrap();
+        let ir_machine = IRMachine::from_ast(CodegenOptions {
+            provenance: true,
+            ..Default::default()
+        }, program.clone());
+
+        let mut found = Vec::new();
+        for (s, section) in ir_machine.sections.iter().enumerate() {
+            for (i, instr) in section.instrs.iter().enumerate() {
+                let p = ir_machine.provenance(s, i).unwrap();
+                match instr {
+                    Instruction::Mul(..)
+                    | Instruction::JumpSectionIf(..)
+                    | Instruction::IncVal(_) => found.push((p.line, p.statement, &*p.node)),
+                    _ => (),
+                }
+            }
+        }
+        found.sort();
+        assert_eq!(found, [
+            (2, Some(0), "a * 0"),
+            (2, Some(0), "if a then b = a * 0 + 2 end"),
+            (2, Some(1), ":c++"),
+        ]);
+
+        let plain = IRMachine::from_ast(Default::default(), program);
+        assert_eq!(plain.provenance(0, 0), None);
+    }
+
+    #[test]
+    fn dynamic_goto_events() {
+        let program = YololParser::default().parse("i++ goto 1+(i>2)*30").unwrap();
+        let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+            goto_policy: GotoPolicy::Error,
+            ..Default::default()
+        }, program);
+        ir_machine.step();
+        assert!(ir_machine.take_goto_events().is_empty());
+
+        ir_machine.trace_dynamic_gotos(true);
+        ir_machine.step_repeat(3);
+        let events: Vec<_> = ir_machine
+            .take_goto_events()
+            .into_iter()
+            .map(|e| (e.line, e.expr.to_string(), e.target, e.landed))
+            .collect();
+        assert_eq!(events, [
+            (1, "1 + (i > 2) * 30".to_string(), Number::from(1), Some(1)),
+            (1, "1 + (i > 2) * 30".to_string(), Number::from(31), None),
+        ]);
+        assert!(ir_machine.take_goto_events().is_empty());
+    }
+
+    #[test]
+    fn branch_finishes_line() {
+        let program = YololParser::default().parse("a=1 if a then b=2 end c=3").unwrap();
+        let mut ir_machine = IRMachine::from_ast(CodegenOptions {
+            protect_locals: true,
+            ..Default::default()
+        }, program);
+        ir_machine.step();
+        assert_eq!(ir_machine.get_ident_value(&Ident::local("b")), Value::Num(2.into()));
+        assert_eq!(ir_machine.get_ident_value(&Ident::local("c")), Value::Num(3.into()));
+        assert_eq!(ir_machine.get_current_line(), Some(1));
+    }
+
+    #[test]
+    fn fingerprint_sees_locals() {
+        // `i` isn't protected, and the machine never leaves line 1
+        let program = YololParser::default().parse("i++ goto 1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        ir_machine.step();
+        let before = ir_machine.state_fingerprint();
+        ir_machine.step();
+        assert_ne!(ir_machine.state_fingerprint(), before);
+    }
+
+    #[test]
+    fn run_for_lines() {
+        let src = "a=1 b=a*2 c=b+a\n:x+=c if :x>20 then :y++ end goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut sliced = IRMachine::from_ast(Default::default(), program.clone());
+        let mut reference = IRMachine::from_ast(Default::default(), program);
+        for _ in 0..5 {
+            assert!(sliced.run_for_lines(3));
+        }
+        reference.step_repeat(15);
+        let x = Ident::global("x");
+        assert_eq!(sliced.get_ident_value(&x), reference.get_ident_value(&x));
+
+        // stopping partway through a line picks up from there
+        let y = Ident::global("y");
+        let y_inc = sliced.data_flow_graph().defs(sliced.ident_register(&y).unwrap())[0];
+        sliced.add_breakpoint(Breakpoint::Instr(y_inc));
+        assert!(!sliced.run_for_lines(3));
+        assert!(sliced.mid_line());
+        let next = sliced.next_instr();
+        assert!(sliced.run_for_lines(0));
+        assert_eq!(sliced.next_instr(), next);
+        assert!(sliced.mid_line());
+        sliced.remove_breakpoint(Breakpoint::Instr(y_inc));
+        assert!(sliced.run_for_lines(4));
+        reference.step_repeat(4);
+        assert_eq!(sliced.get_ident_value(&y), reference.get_ident_value(&y));
+        assert_eq!(sliced.get_ident_value(&x), reference.get_ident_value(&x));
+        assert_eq!(sliced.get_current_line(), reference.get_current_line());
+    }
+}
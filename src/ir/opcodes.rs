@@ -0,0 +1,66 @@
+use super::*;
+
+impl IRMachine {
+    /// How many times each kind of instruction has run, busiest first, leaving out those which
+    /// haven't. Names are those of the bytecode listing's instructions.
+    pub fn opcode_counts(&self) -> Vec<(&'static str, u64)> {
+        sorted_opcode_counts(self.opcode_counts.iter().copied())
+    }
+
+    pub fn reset_opcode_counts(&mut self) {
+        self.opcode_counts.iter_mut().for_each(|c| *c = 0);
+    }
+
+    pub(crate) fn raw_opcode_counts(&self) -> &[u64] {
+        &self.opcode_counts
+    }
+}
+
+/// Names counts indexed by opcode, dropping zeros and sorting the rest busiest first.
+pub(crate) fn sorted_opcode_counts(
+    counts: impl IntoIterator<Item = u64>,
+) -> Vec<(&'static str, u64)> {
+    let mut counts: Vec<_> = instr::OPCODE_NAMES
+        .iter()
+        .copied()
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn counts_opcodes() {
+        let program = YololParser::default().parse(":a++ :b=:a*2 goto 1").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        for _ in 0..10 {
+            machine.step();
+        }
+        let counts = machine.opcode_counts();
+        assert!(!counts.is_empty());
+        assert!(counts.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert!(counts.iter().all(|&(_, count)| count % 10 == 0));
+
+        // stepping one instruction at a time counts the same
+        let mut stepped = machine.fork();
+        for _ in 0..10 {
+            stepped.step_line();
+        }
+        assert_eq!(stepped.opcode_counts(), counts);
+
+        let mut network = crate::network::Network::new();
+        network.add_chip(machine.fork());
+        network.add_chip(machine.fork());
+        network.run(10);
+        let doubled: Vec<_> = counts.iter().map(|&(name, count)| (name, count * 2)).collect();
+        assert_eq!(network.opcode_counts(), doubled);
+        machine.reset_opcode_counts();
+        assert!(machine.opcode_counts().is_empty());
+    }
+}
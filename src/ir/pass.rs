@@ -0,0 +1,279 @@
+use super::*;
+use opt::{Cost, Objective};
+use cfg::ControlFlowGraph;
+use dfg::{DataFlowGraph, InstrRef};
+
+/// What a [`Pass`] did, for the [`OptPipeline`] to report.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PassSummary {
+    pub instructions_removed: usize,
+    pub instructions_added: usize,
+    /// Instructions changed in place, e.g. by [`IRMachine::replace_register`].
+    pub instructions_changed: usize,
+    /// Anything else worth telling the user.
+    pub notes: Vec<String>,
+}
+
+impl PassSummary {
+    pub fn changed(&self) -> bool {
+        self.instructions_removed + self.instructions_added + self.instructions_changed > 0
+    }
+}
+
+/// A rewrite of a compiled program, which may live outside this crate. It sees instructions
+/// through [`IRMachine::instruction`], and changes them with [`IRMachine::remove_instruction`],
+/// [`IRMachine::insert_copy`] and [`IRMachine::replace_register`]. Rewrites must keep the
+/// program doing the same thing, including which variables end up with which values.
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    /// `cfg` and `dfg` are for the machine as it was before the pass started.
+    fn run(
+        &mut self,
+        machine: &mut IRMachine,
+        cfg: &ControlFlowGraph,
+        dfg: &DataFlowGraph,
+    ) -> PassSummary;
+}
+
+/// Runs [`Pass`]es over a machine, in the order they were added.
+#[derive(Default)]
+pub struct OptPipeline {
+    passes: Vec<Box<dyn Pass>>,
+    objective: Option<Objective>,
+}
+
+impl OptPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, pass: impl Pass + 'static) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Undoes any pass which leaves the machine costing more by `objective`, measured with
+    /// [`Cost::of_machine`]. Passes often trade one cost for another: folding constants runs
+    /// fewer instructions, but can take more characters to write out.
+    pub fn objective(&mut self, objective: Objective) -> &mut Self {
+        self.objective = Some(objective);
+        self
+    }
+
+    /// Runs every pass once, returning each one's name and summary.
+    pub fn run(&mut self, machine: &mut IRMachine) -> Vec<(String, PassSummary)> {
+        let objective = self.objective;
+        self.passes
+            .iter_mut()
+            .map(|pass| {
+                span!(DEBUG, "pass", name = pass.name());
+                let before = objective.map(|_| (machine.clone(), Cost::of_machine(machine)));
+                let (cfg, dfg) = (machine.control_flow_graph(), machine.data_flow_graph());
+                let mut summary = pass.run(machine, &cfg, &dfg);
+                if let (Some(objective), Some((old, cost))) = (objective, before) {
+                    if summary.changed() && objective.prefers(&cost, &Cost::of_machine(machine)) {
+                        *machine = old;
+                        let note = format!("undone, as it cost more {:?}", objective);
+                        summary = PassSummary { notes: vec![note], ..Default::default() };
+                    }
+                }
+                (pass.name().to_string(), summary)
+            })
+            .collect()
+    }
+}
+
+/// A read only view of one instruction, from [`IRMachine::instruction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrView {
+    /// As printed by [`IRMachine::print_bytecode`].
+    pub text: String,
+    pub reads: Vec<Register>,
+    pub writes: Option<Register>,
+    /// Whether it can cause a runtime error, skipping the rest of the line.
+    pub can_error: bool,
+    /// The section it jumps to, if it's a jump.
+    pub jumps_to: Option<usize>,
+}
+
+/// Whether `instr` can set the error flag, so removing it could change where the line goes.
+pub(super) fn can_error(instr: Instruction, overflow: OverflowPolicy) -> bool {
+    instr.can_runtime_err()
+        || matches!(instr, Instruction::DecStr(_) | Instruction::DecVal(_))
+        || overflow.can_fail() && instr.can_overflow()
+}
+
+fn any_reg(reg: Register) -> AnyReg {
+    match reg {
+        Register::Number(n) => NumReg(n).into(),
+        Register::String(s) => StrReg(s).into(),
+        Register::Value(v) => ValReg(v).into(),
+    }
+}
+
+impl IRMachine {
+    pub fn section_count(&self) -> usize {
+        self.sections.len()
+    }
+
+    /// How many instructions are in `section`.
+    pub fn section_len(&self, section: usize) -> usize {
+        self.sections[section].instrs.len()
+    }
+
+    pub fn instruction(&self, at: InstrRef) -> Option<InstrView> {
+        let instr = *self.sections.get(at.section)?.instrs.get(at.index)?;
+        Some(InstrView {
+            text: instr.to_string(),
+            reads: instr.reads().into_iter().map(Register::from).collect(),
+            writes: instr.modifies().map(Register::from),
+            can_error: can_error(instr, self.overflow),
+            jumps_to: instr.get_section().map(|s| s.0),
+        })
+    }
+
+    pub fn remove_instruction(&mut self, at: InstrRef) {
+        self[at.section].instrs.remove(at.index);
+        if let Some(provenance) = Arc::make_mut(&mut self.provenance).get_mut(at.section) {
+            provenance.remove(at.index);
+        }
+    }
+
+    /// Inserts a copy from one register to another of the same type before the instruction at
+    /// `at`, or at the end of the section if `at` is one past its last instruction.
+    ///
+    /// Panics if the registers are different types.
+    pub fn insert_copy(&mut self, at: InstrRef, from: Register, to: Register) {
+        let instr = match (any_reg(from), any_reg(to)) {
+            (AnyReg::Num(from), AnyReg::Num(to)) => Instruction::CopyNum(from, to),
+            (AnyReg::Str(from), AnyReg::Str(to)) => Instruction::CopyStr(from, to),
+            (AnyReg::Val(from), AnyReg::Val(to)) => Instruction::CopyVal(from, to),
+            (from, to) => panic!("can't copy {} to {}", from, to),
+        };
+        self[at.section].instrs.insert(at.index, instr);
+        if let Some(provenance) = Arc::make_mut(&mut self.provenance).get_mut(at.section) {
+            let source = provenance.get(at.index.min(provenance.len().saturating_sub(1))).cloned();
+            if let Some(source) = source {
+                provenance.insert(at.index, source);
+            }
+        }
+    }
+
+    /// Makes the instruction at `at` use `to` wherever it used `from`, returning whether it
+    /// used `from`.
+    ///
+    /// Panics if the registers are different types.
+    pub fn replace_register(&mut self, at: InstrRef, from: Register, to: Register) -> bool {
+        let instr = &mut self[at.section].instrs[at.index];
+        let mut replaced = false;
+        match (any_reg(from), any_reg(to)) {
+            (AnyReg::Num(from), AnyReg::Num(to)) => for r in instr.get_mut_num_regs() {
+                if *r == from {
+                    *r = to;
+                    replaced = true;
+                }
+            },
+            (AnyReg::Str(from), AnyReg::Str(to)) => for r in instr.get_mut_str_regs() {
+                if *r == from {
+                    *r = to;
+                    replaced = true;
+                }
+            },
+            (AnyReg::Val(from), AnyReg::Val(to)) => for r in instr.get_mut_val_regs() {
+                if *r == from {
+                    *r = to;
+                    replaced = true;
+                }
+            },
+            (from, to) => panic!("can't replace {} with {}", from, to),
+        }
+        replaced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    /// Removes writes to temporaries which nothing reads.
+    struct DeadCopies;
+
+    impl Pass for DeadCopies {
+        fn name(&self) -> &str {
+            "dead copies"
+        }
+
+        fn run(
+            &mut self,
+            machine: &mut IRMachine,
+            _: &ControlFlowGraph,
+            dfg: &DataFlowGraph,
+        ) -> PassSummary {
+            let variables: Vec<_> = machine
+                .idents()
+                .into_iter()
+                .filter_map(|(ident, _)| machine.ident_register(ident))
+                .collect();
+            let mut dead: Vec<_> = dfg
+                .registers()
+                .filter(|reg| dfg.uses(*reg).is_empty() && !variables.contains(reg))
+                .flat_map(|reg| dfg.defs(reg).iter().copied())
+                .filter(|&at| machine.instruction(at).is_some_and(|i| !i.can_error))
+                .collect();
+            // back to front, so removing one doesn't move the rest
+            dead.sort_by(|a, b| b.cmp(a));
+            for &at in dead.iter() {
+                machine.remove_instruction(at);
+            }
+            PassSummary { instructions_removed: dead.len(), ..Default::default() }
+        }
+    }
+
+    #[test]
+    fn pipeline() {
+        let program = YololParser::default().parse("a=1 :b=2 goto 1").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let mut pipeline = OptPipeline::new();
+        pipeline.add(DeadCopies);
+        let report = pipeline.run(&mut machine);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].0, "dead copies");
+        assert!(report[0].1.changed());
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("b")), Value::Num(2.into()));
+
+        // nothing left to remove
+        assert!(!pipeline.run(&mut machine)[0].1.changed());
+    }
+
+    #[test]
+    fn pipeline_objective() {
+        // folding works out `:o`, but leaves a copy of every constant it found on the way, which
+        // runs quicker than the arithmetic but takes longer to write out
+        let program = YololParser::default()
+            .parse("a=1 b=a*2 c=b+1 if c>2 then :o=c else :o=b end")
+            .unwrap();
+        let machine = IRMachine::from_ast(Default::default(), program);
+        let before = Cost::of_machine(&machine);
+
+        let mut fast = machine.clone();
+        let report = OptPipeline::new()
+            .add(ConstantFolding)
+            .objective(Objective::Instructions)
+            .run(&mut fast);
+        assert!(report[0].1.changed());
+        let after = Cost::of_machine(&fast);
+        assert!(after.instructions < before.instructions);
+        assert!(after.characters > before.characters);
+
+        let mut short = machine.clone();
+        let report = OptPipeline::new()
+            .add(ConstantFolding)
+            .objective(Objective::Characters)
+            .run(&mut short);
+        assert_eq!(report[0].1.notes, ["undone, as it cost more Characters"]);
+        assert_eq!(Cost::of_machine(&short), before);
+    }
+}
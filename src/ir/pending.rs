@@ -0,0 +1,95 @@
+use super::*;
+
+/// What [`IRMachine::step_or_yield`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    /// The line finished.
+    Completed,
+    /// The line stopped just before reading a pending global, and carries on from there once
+    /// [`IRMachine::supply`] gives it a value.
+    Yielded(Ident),
+}
+
+impl IRMachine {
+    /// Marks `field` as waiting on a value from outside, so [`IRMachine::step_or_yield`] stops
+    /// before reading it. Returns false, without marking it, if the machine doesn't protect
+    /// `field` (see [`CodegenOptions`]).
+    pub fn mark_pending(&mut self, field: &Ident) -> bool {
+        debug_assert!(field.global, "tried to mark local '{}' as pending", field);
+        if !self.idents.contains_key(field) {
+            return false;
+        }
+        if !self.is_pending(field) {
+            self.pending.push(field.clone());
+        }
+        true
+    }
+
+    pub fn is_pending(&self, field: &Ident) -> bool {
+        self.pending.contains(field)
+    }
+
+    /// Sets a pending `field` to `value`, so lines reading it can run again.
+    pub fn supply(&mut self, field: &Ident, value: Value) {
+        self.pending.retain(|f| f != field);
+        self.set_ident(field, value);
+    }
+
+    /// Runs the rest of the line, like [`IRMachine::step`], unless it reads a pending global
+    /// first. Stepping normally ignores pending globals. While any are pending, lines run an
+    /// instruction at a time, which is much slower.
+    pub fn step_or_yield(&mut self) -> StepStatus {
+        if self.pending.is_empty() {
+            self.step();
+            return StepStatus::Completed;
+        }
+        loop {
+            if let Some(at) = self.next_instr() {
+                let reads = self.sections[at.section].instrs[at.index].reads();
+                let pending = self.pending.iter().find(|f| reads.contains(&self.idents[*f]));
+                if let Some(field) = pending {
+                    return StepStatus::Yielded(field.clone());
+                }
+            }
+            if self.step_instr() {
+                return StepStatus::Completed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn yields_on_pending() {
+        let src = ":a+=1 :b=:ext*2 :c=3\n:d=:a goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let get = |m: &IRMachine, name| m.get_ident_value(&Ident::global(name));
+        let ext = Ident::global("ext");
+        assert!(machine.mark_pending(&ext));
+        assert!(!machine.mark_pending(&Ident::global("nope")));
+
+        assert_eq!(machine.step_or_yield(), StepStatus::Yielded(ext.clone()));
+        assert!(machine.mid_line());
+        assert_eq!(get(&machine, "a"), Value::Num(1.into()));
+        assert_eq!(machine.step_or_yield(), StepStatus::Yielded(ext.clone()));
+        assert_eq!(get(&machine, "a"), Value::Num(1.into()));
+
+        machine.supply(&ext, Value::Num(5.into()));
+        assert!(!machine.is_pending(&ext));
+        assert_eq!(machine.step_or_yield(), StepStatus::Completed);
+        assert_eq!(get(&machine, "b"), Value::Num(10.into()));
+        assert_eq!(get(&machine, "c"), Value::Num(3.into()));
+
+        // lines which don't read it run while it's pending
+        machine.mark_pending(&ext);
+        assert_eq!(machine.step_or_yield(), StepStatus::Completed);
+        assert_eq!(get(&machine, "d"), Value::Num(1.into()));
+        assert_eq!(machine.step_or_yield(), StepStatus::Yielded(ext));
+        assert_eq!(get(&machine, "a"), Value::Num(2.into()));
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use super::*;
+
+/// An operation done through floating point which gave a different result than computing it
+/// precisely would, reported while [`IRMachine::check_precision`] is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// The 1-based line it happened on.
+    pub line: usize,
+    pub op: FloatOp,
+    pub lhs: Number,
+    /// The exponent, for [`FloatOp::Pow`].
+    pub rhs: Option<Number>,
+    pub fast: Number,
+    pub precise: Number,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "line {}: ", self.line)?;
+        match self.rhs {
+            Some(rhs) => write!(f, "{} {} {}", self.lhs, self.op, rhs)?,
+            None => write!(f, "{} {}", self.op, self.lhs)?,
+        }
+        write!(f, " gave {}, precisely it's {}", self.fast, self.precise)
+    }
+}
+
+impl IRMachine {
+    /// Start or stop recomputing every float based operation precisely alongside the fast
+    /// path, reporting when they disagree. This doesn't change any results, but slows those
+    /// operations down a lot. Stopping drops any reports which haven't been taken.
+    pub fn check_precision(&mut self, on: bool) {
+        self.divergences = on.then(Default::default);
+    }
+
+    /// Take the divergences found since the last call, while checking precision.
+    pub fn take_divergences(&mut self) -> Vec<Divergence> {
+        self.divergences.as_mut().map(|d| std::mem::take(d.get_mut())).unwrap_or_default()
+    }
+
+    /// Records a divergence if `instr` is about to compute something imprecisely.
+    pub(super) fn check_float(&self, instr: Instruction) {
+        let num = |n| *self.num_ref(n).unwrap();
+        let (op, lhs, rhs) = match instr {
+            Instruction::Sqrt(n) => (FloatOp::Sqrt, num(n), None),
+            Instruction::Pow(l, r) => (FloatOp::Pow, num(l), Some(num(r))),
+            Instruction::Sin(n) => (FloatOp::Sin, num(n), None),
+            Instruction::Cos(n) => (FloatOp::Cos, num(n), None),
+            Instruction::Tan(n) => (FloatOp::Tan, num(n), None),
+            Instruction::Asin(n) => (FloatOp::Asin, num(n), None),
+            Instruction::Acos(n) => (FloatOp::Acos, num(n), None),
+            Instruction::Atan(n) => (FloatOp::Atan, num(n), None),
+            _ => return,
+        };
+        let exponent = rhs.unwrap_or_default();
+        let (fast, precise) = (op.fast(lhs, exponent), op.precise(lhs, exponent));
+        if fast != precise {
+            let line = self.lines.iter().position(|&s| s == self.line_start).unwrap() + 1;
+            let divergence = Divergence { line, op, lhs, rhs, fast, precise };
+            self.divergences.as_ref().unwrap().borrow_mut().push(divergence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+    use super::*;
+
+    #[test]
+    fn divergences() {
+        let src = ":a=sin 90 :b=sin 45\n:c=sqrt 2 :d=1.1^2\n:e=cos :x goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program.clone());
+        ir_machine.step_repeat(3);
+        assert!(ir_machine.take_divergences().is_empty());
+
+        let mut checked = IRMachine::from_ast(Default::default(), program);
+        checked.check_precision(true);
+        checked.step_repeat(3);
+        assert_eq!(checked.state_fingerprint(), ir_machine.state_fingerprint());
+        let divergences = checked.take_divergences();
+        assert_eq!(divergences.len(), 1, "{:?}", divergences);
+        assert_eq!(divergences[0].line, 1);
+        assert_eq!(divergences[0].op, FloatOp::Sin);
+        assert_eq!(divergences[0].precise, Number::from(1));
+        assert_eq!(divergences[0].to_string(), "line 1: sin 90 gave 0.999, precisely it's 1");
+        assert!(checked.take_divergences().is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Serialize, Deserialize};
+use super::*;
+
+/// How often a line ran, over every run merged into a [`ProfileReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LineStats {
+    pub total: u64,
+    /// The fewest times the line ran in a single run.
+    pub min: u64,
+    /// The most times the line ran in a single run.
+    pub max: u64,
+    /// How many instructions the line ran, over every time it ran.
+    #[serde(default)]
+    pub instructions: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ProfileReport {
+    /// How many runs were merged into this report.
+    pub runs: u64,
+    pub lines: Vec<LineStats>,
+}
+
+impl ProfileReport {
+    /// A report of a single run, from how many times each line ran.
+    pub fn from_counts(counts: &[u64]) -> Self {
+        ProfileReport {
+            runs: 1,
+            lines: counts
+                .iter()
+                .map(|&n| LineStats { total: n, min: n, max: n, instructions: 0 })
+                .collect(),
+        }
+    }
+
+    fn from_profile(profile: &Profile) -> Self {
+        let mut report = Self::from_counts(&profile.lines);
+        for (line, &n) in report.lines.iter_mut().zip(profile.instructions.iter()) {
+            line.instructions = n;
+        }
+        report
+    }
+
+    /// Adds the runs of `other` to this report. Lines only one report has ran 0 times in the
+    /// runs of the other.
+    pub fn merge(&mut self, other: &ProfileReport) {
+        if self.runs == 0 {
+            self.clone_from(other);
+            return;
+        }
+        if other.runs == 0 {
+            return;
+        }
+        let len = self.lines.len().max(other.lines.len());
+        self.lines.resize(len, LineStats::default());
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            let other = other.lines.get(i).copied().unwrap_or_default();
+            line.total += other.total;
+            line.min = line.min.min(other.min);
+            line.max = line.max.max(other.max);
+            line.instructions += other.instructions;
+        }
+        self.runs += other.runs;
+    }
+
+    /// The mean times each line ran per run.
+    pub fn means(&self) -> Vec<f64> {
+        self.lines
+            .iter()
+            .map(|line| line.total as f64 / self.runs.max(1) as f64)
+            .collect()
+    }
+
+    /// Every line, by number, most instructions run first.
+    pub fn hottest(&self) -> Vec<(usize, LineStats)> {
+        let mut lines: Vec<_> = self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, &line)| (i + 1, line))
+            .collect();
+        lines.sort_by_key(|&(_, line)| std::cmp::Reverse(line.instructions));
+        lines
+    }
+}
+
+/// Counts kept while profiling.
+#[derive(Debug, Clone)]
+pub(super) struct Profile {
+    lines: Vec<u64>,
+    instructions: Vec<u64>,
+    /// Instructions run so far by the line being run.
+    pub(super) pending: u64,
+}
+
+impl Profile {
+    fn new(lines: usize) -> Self {
+        Profile { lines: vec![0; lines], instructions: vec![0; lines], pending: 0 }
+    }
+
+    /// Empty counts for the same program.
+    pub(super) fn cleared(&self) -> Self {
+        Self::new(self.lines.len())
+    }
+}
+
+impl IRMachine {
+    /// Start counting how many times each line runs, clearing any previous counts.
+    pub fn start_profiling(&mut self) {
+        self.profile = Some(Profile::new(self.lines.len()));
+    }
+
+    pub fn stop_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    /// A report of the lines run since profiling started, if it has.
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profile.as_ref().map(ProfileReport::from_profile)
+    }
+
+    pub(super) fn count_line(&mut self) {
+        let line = self.lines.iter().position(|&s| s == self.line_start).unwrap();
+        if let Some(profile) = &mut self.profile {
+            profile.lines[line] += 1;
+            profile.instructions[line] += std::mem::take(&mut profile.pending);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::*;
+    use super::*;
+
+    #[test]
+    fn profile_merge() {
+        let program = YololParser::default().parse("a+=1\nif a>2 then goto 1 end\nb=1").unwrap();
+        let mut report = ProfileReport::default();
+        for steps in [4, 10] {
+            let mut ir_machine = IRMachine::from_ast(Default::default(), program.clone());
+            ir_machine.start_profiling();
+            ir_machine.step_repeat(steps);
+            report.merge(&ir_machine.profile_report().unwrap());
+        }
+
+        assert_eq!(report.runs, 2);
+        assert_eq!(report.lines.len(), 20);
+        // 4 steps: lines 1, 2, 3, 4; 10 steps: 1, 2, 3, 4..20
+        let runs = |line: LineStats| (line.total, line.min, line.max);
+        assert_eq!(runs(report.lines[0]), (2, 1, 1));
+        assert_eq!(runs(report.lines[2]), (2, 1, 1));
+        assert_eq!(report.lines[4], LineStats { total: 1, min: 0, max: 1, instructions: 0 });
+        assert_eq!(report.means()[4], 0.5);
+
+        let json = serde_json::to_string(&report).unwrap();
+        let mut restored: ProfileReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, report);
+        restored.merge(&report);
+        assert_eq!(restored.runs, 4);
+        assert_eq!(restored.lines[4], LineStats { total: 2, min: 0, max: 1, instructions: 0 });
+    }
+
+    #[test]
+    fn profile_instructions() {
+        let src = "a=0\nb=a*a+a*3-a/2 a++ goto 2+(a>3)\nc=a";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        ir_machine.start_profiling();
+        ir_machine.step();
+        let mut stepped = ir_machine.clone();
+        ir_machine.step_repeat(6);
+        let mut lines = 0;
+        while lines < 6 {
+            lines += stepped.step_instr() as usize;
+        }
+
+        let report = ir_machine.profile_report().unwrap();
+        assert_eq!(stepped.profile_report().unwrap(), report);
+        assert_eq!(report.lines[1].total, 4);
+        assert_eq!(report.hottest()[0].0, 2);
+        assert!(report.lines[1].instructions > report.lines[0].instructions * 4);
+        assert_eq!(report.lines[4].instructions, 0);
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Result, bail, ensure, Context};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use super::*;
+
+/// Bumped whenever the layout of exported state changes.
+const STATE_VERSION: u8 = 1;
+
+const GLOBAL: u8 = 1;
+const STRING: u8 = 2;
+
+pub(super) fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Reads bytes of exported state, failing rather than panicking on anything malformed.
+pub(super) struct Reader<'a>(pub(super) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(super) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(len <= self.0.len(), "state ends early");
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(super) fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(super) fn varint(&mut self) -> Result<u64> {
+        let mut n = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        bail!("number too long")
+    }
+}
+
+/// Everything an [`IRMachine`] changes as it runs, from [`IRMachine::snapshot`]. With the
+/// `serde` feature it can be saved, to restore into the same program compiled the same way
+/// after a restart.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    numbers: Vec<Number>,
+    strings: Vec<YString>,
+    values: Vec<Value>,
+    current_sect: Section,
+    line_start: Section,
+    paused: Option<usize>,
+    runtime_err: bool,
+}
+
+impl IRMachine {
+    /// Captures every register, including unprotected variables and temporaries, and where
+    /// the machine is, even partway through a line, to go back to with [`IRMachine::restore`].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            numbers: self.numbers.iter().map(|r| *r.borrow()).collect(),
+            strings: self.strings.iter().map(|r| r.borrow().clone()).collect(),
+            values: self.values.iter().map(|r| r.borrow().clone()).collect(),
+            current_sect: self.current_sect,
+            line_start: self.line_start,
+            paused: self.paused,
+            runtime_err: self.runtime_err.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Rolls back to a [`Snapshot`] of this machine, or of a clone or fork of it. Fails, without
+    /// changing anything, if the registers have been rearranged since.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.check_snapshot(snapshot)?;
+        fn copy<T: Clone>(regs: &mut [AtomicRefCell<T>], from: &[T]) {
+            for (reg, value) in regs.iter_mut().zip(from) {
+                value.clone_into(reg.get_mut());
+            }
+        }
+        copy(&mut self.numbers, &snapshot.numbers);
+        copy(&mut self.strings, &snapshot.strings);
+        copy(&mut self.values, &snapshot.values);
+        self.current_sect = snapshot.current_sect;
+        self.line_start = snapshot.line_start;
+        self.paused = snapshot.paused;
+        *self.runtime_err.get_mut() = snapshot.runtime_err;
+        Ok(())
+    }
+
+    /// Fails if [`IRMachine::restore`] would.
+    pub(crate) fn check_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        // registers only move when some are removed, which changes the counts
+        ensure!(
+            snapshot.numbers.len() == self.numbers.len()
+                && snapshot.strings.len() == self.strings.len()
+                && snapshot.values.len() == self.values.len()
+                && snapshot.current_sect.0 < self.sections.len()
+                && self.lines.contains(&snapshot.line_start),
+            "snapshot is of a different program",
+        );
+        let instrs = self.sections[snapshot.current_sect.0].instrs.len();
+        ensure!(snapshot.paused.is_none_or(|p| p < instrs), "snapshot is paused out of place");
+        Ok(())
+    }
+
+    /// Every protected variable (see [`CodegenOptions`]) and its value, as base64 without
+    /// padding, so it can be stored in a Yolol string. Numbers take a byte or two in most
+    /// cases, and names are stored once. Fails if the result is longer than this machine's
+    /// strings can be, since it couldn't be stored then.
+    pub fn export_state_string(&self) -> Result<String> {
+        let idents: Vec<_> = self.idents().into_iter().collect();
+
+        let mut out = vec![STATE_VERSION];
+        write_varint(&mut out, idents.len() as u64);
+        for (ident, value) in idents {
+            let flags = if ident.global { GLOBAL } else { 0 };
+            let name = ident.original_name().as_bytes();
+            out.push(if let Value::Str(_) = value { flags | STRING } else { flags });
+            write_varint(&mut out, name.len() as u64);
+            out.extend_from_slice(name);
+            match value {
+                // zigzag, so small negative numbers are short too
+                Value::Num(n) => write_varint(&mut out, ((n.0 << 1) ^ (n.0 >> 63)) as u64),
+                Value::Str(s) => {
+                    write_varint(&mut out, s.len() as u64);
+                    out.extend_from_slice(&s);
+                },
+            }
+        }
+        let state = STANDARD_NO_PAD.encode(out);
+        ensure!(
+            state.len() <= self.max_string_len,
+            "the state takes {} bytes, but strings can only hold {}",
+            state.len(),
+            self.max_string_len,
+        );
+        Ok(state)
+    }
+
+    /// Sets variables from [`IRMachine::export_state_string`]. Variables this machine doesn't
+    /// protect are skipped, and nothing is set if the state is malformed, would put a value in
+    /// a register of the wrong type, or holds a string the machine's [`UnicodePolicy`] rejects.
+    pub fn import_state_string(&mut self, state: &str) -> Result<()> {
+        let bytes = STANDARD_NO_PAD.decode(state.trim()).context("state isn't base64")?;
+        let mut reader = Reader(&bytes);
+        let version = reader.byte()?;
+        ensure!(version == STATE_VERSION, "unknown state version {}", version);
+
+        let mut values = Vec::new();
+        for _ in 0..reader.varint()? {
+            let flags = reader.byte()?;
+            let len = reader.varint()? as usize;
+            let name = std::str::from_utf8(reader.bytes(len)?).context("name isn't UTF-8")?;
+            let ident = Ident::new(name, flags & GLOBAL != 0);
+            let value = if flags & STRING != 0 {
+                let len = reader.varint()? as usize;
+                let mut s = YString::from_bytes_in(reader.bytes(len)?, self.string_mode);
+                s.truncate_in(self.max_string_len, self.string_mode);
+                s.apply_policy(self.unicode).with_context(|| format!("in '{}'", ident))?;
+                Value::Str(s)
+            } else {
+                let n = reader.varint()?;
+                Value::Num(Number((n >> 1) as i64 ^ -((n & 1) as i64)))
+            };
+            match (self.idents.get(&ident), &value) {
+                (Some(AnyReg::Num(_)), Value::Str(_)) | (Some(AnyReg::Str(_)), Value::Num(_)) => {
+                    bail!("'{}' can't hold {}", ident, value)
+                },
+                _ => values.push((ident, value)),
+            }
+        }
+        ensure!(reader.0.is_empty(), "trailing bytes after state");
+
+        for (ident, value) in values {
+            self.set_ident(&ident, value);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::super::tests::variables;
+    use super::*;
+
+    #[test]
+    fn snapshot_rollback() {
+        let src = "i++ s+=\"a\" :out=s+i\nif i%3 then goto 1 end goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program.clone());
+        let out = |m: &IRMachine| m.get_ident_value(&Ident::global("out"));
+        machine.step();
+        let snapshot = machine.snapshot();
+        let mut expected = Vec::new();
+        for _ in 0..7 {
+            machine.step();
+            expected.push(out(&machine));
+        }
+
+        let mut fork = machine.fork();
+        for m in [&mut machine, &mut fork] {
+            m.restore(&snapshot).unwrap();
+            let replayed: Vec<_> = (0..7).map(|_| { m.step(); out(m) }).collect();
+            assert_eq!(replayed, expected);
+        }
+
+        let other = YololParser::default().parse(":a=1").unwrap();
+        assert!(IRMachine::from_ast(Default::default(), other).restore(&snapshot).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_serde() {
+        let src = "i++ s+=\"a\" :out=s+i\nif i%3 then goto 1 end goto 1";
+        let compile = || {
+            IRMachine::from_ast(Default::default(), YololParser::default().parse(src).unwrap())
+        };
+        let mut machine = compile();
+        machine.step_repeat(4);
+        machine.step_instr();
+        let json = serde_json::to_string(&machine.snapshot()).unwrap();
+
+        let mut restored = compile();
+        restored.restore(&serde_json::from_str(&json).unwrap()).unwrap();
+        assert!(restored.mid_line());
+        for _ in 0..5 {
+            machine.step();
+            restored.step();
+            assert_eq!(restored.state_fingerprint(), machine.state_fingerprint());
+        }
+        assert!(serde_json::from_str::<Snapshot>("{}").is_err());
+
+        let mut tampered: serde_json::Value = serde_json::from_str(&json).unwrap();
+        tampered["paused"] = 999.into();
+        let tampered = serde_json::from_value(tampered).unwrap();
+        assert!(compile().restore(&tampered).is_err());
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let src = ":Out=-12.5 :msg=\"hi\" n=3 :big=9000000000000 goto 1";
+        let compile = || {
+            let options = CodegenOptions { protect_locals: true, ..Default::default() };
+            IRMachine::from_ast(options, YololParser::default().parse(src).unwrap())
+        };
+        let mut machine = compile();
+        machine.step();
+        let state = machine.export_state_string().unwrap();
+        assert!(state.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/'));
+
+        let mut restored = compile();
+        restored.import_state_string(&state).unwrap();
+        assert_eq!(variables(&restored), variables(&machine));
+        let out = restored.get_ident_value(&Ident::global("out"));
+        assert_eq!(out, Value::Num("-12.5".parse().unwrap()));
+        assert_eq!(restored.export_state_string().unwrap(), state);
+
+        assert!(restored.import_state_string("not base64!").is_err());
+        assert!(restored.import_state_string(&state[..state.len() - 2]).is_err());
+        assert!(restored.import_state_string("AgA").is_err());
+    }
+
+    #[test]
+    fn state_string_limit() {
+        let program = YololParser::default().parse(":s=\"\" goto 1").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let s = Ident::global("s");
+        // 7 bytes besides the string itself, and base64 takes 4 characters for every 3 bytes
+        machine.set_ident(&s, Value::Str("x".repeat(761).as_str().into()));
+        assert_eq!(machine.export_state_string().unwrap().len(), MAX_STRING_BYTES);
+        machine.set_ident(&s, Value::Str("x".repeat(762).as_str().into()));
+        let err = machine.export_state_string().unwrap_err();
+        assert_eq!(err.to_string(), "the state takes 1026 bytes, but strings can only hold 1024");
+    }
+}
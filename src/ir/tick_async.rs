@@ -0,0 +1,100 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use super::*;
+
+/// Returns pending once, waking itself straight away so the executor can run other tasks.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if std::mem::replace(&mut self.0, true) {
+            Poll::Ready(())
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl IRMachine {
+    /// Steps `lines` lines, yielding to the executor whenever at least `yield_every`
+    /// instructions have run since the last yield. Lines always run to completion, and are
+    /// counted as if every branch in them was taken. One the machine stopped partway through
+    /// is finished first, as the first of `lines`, and counted in full.
+    pub async fn tick_async(&mut self, lines: usize, yield_every: usize) {
+        let costs = self.line_instruction_counts();
+        let mut since_yield = 0;
+        for _ in 0..lines {
+            let start = if self.mid_line() { self.line_start } else { self.current_sect };
+            let line = self.lines.iter().position(|&s| s == start);
+            self.step();
+            since_yield += line.map_or(0, |line| costs[line]);
+            if since_yield >= yield_every {
+                since_yield = 0;
+                YieldNow(false).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use crate::parser::*;
+    use super::*;
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Polls `future` to completion, returning how many times it yielded.
+    fn block_on(future: impl Future<Output = ()>) -> usize {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        let mut yields = 0;
+        while future.as_mut().poll(&mut cx).is_pending() {
+            yields += 1;
+        }
+        yields
+    }
+
+    #[test]
+    fn tick_async_yields() {
+        let program = YololParser::default().parse("a+=1 b=a*2 goto 1").unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program.clone());
+        let per_line = ir_machine.line_instruction_counts()[0];
+
+        let yields = block_on(ir_machine.tick_async(10, per_line * 2));
+        assert_eq!(yields, 5);
+
+        let mut reference = IRMachine::from_ast(Default::default(), program);
+        reference.step_repeat(10);
+        assert_eq!(ir_machine.state_fingerprint(), reference.state_fingerprint());
+    }
+
+    #[test]
+    fn tick_async_mid_line() {
+        let src = "a=1 if a then :b=1 :c=2 end :d=3 goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut ir_machine = IRMachine::from_ast(Default::default(), program);
+        // stop inside the `if`
+        for _ in 0..4 {
+            ir_machine.step_instr();
+        }
+        assert!(ir_machine.mid_line());
+        assert_eq!(ir_machine.get_current_line(), None);
+        let mut reference = ir_machine.clone();
+
+        block_on(ir_machine.tick_async(2, 1));
+        reference.step_repeat(2);
+        assert!(!ir_machine.mid_line());
+        assert_eq!(ir_machine.state_fingerprint(), reference.state_fingerprint());
+    }
+}
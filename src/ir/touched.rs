@@ -0,0 +1,85 @@
+use super::*;
+
+/// What a line did, from [`IRMachine::step_touched`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineResult {
+    /// The 1-based line which ran.
+    pub line: usize,
+    /// Protected globals (see [`CodegenOptions`]) the line read, in [`Ident`] order.
+    pub read: Vec<Ident>,
+    /// Protected globals the line wrote, in [`Ident`] order, even if it wrote the value they
+    /// already had.
+    pub written: Vec<Ident>,
+    /// Whether a runtime error ended the line early.
+    pub errored: bool,
+}
+
+impl IRMachine {
+    /// Like [`IRMachine::step`], but reporting which globals the line read and wrote. Lines run
+    /// an instruction at a time, which is much slower.
+    pub fn step_touched(&mut self) -> LineResult {
+        let mut read = Vec::new();
+        let mut written = Vec::new();
+        let mut errored = false;
+        loop {
+            if let Some(at) = self.next_instr() {
+                let instr = self.sections[at.section].instrs[at.index];
+                errored |= matches!(instr, Instruction::JumpIfError(_))
+                    && self.runtime_err.load(Ordering::Relaxed);
+                read.extend(instr.reads());
+                written.extend(instr.modifies());
+            }
+            if self.step_instr() {
+                break;
+            }
+        }
+
+        let globals = |regs: Vec<AnyReg>| {
+            let mut idents: Vec<_> = self.idents
+                .iter()
+                .filter(|(ident, reg)| ident.global && regs.contains(reg))
+                .map(|(ident, _)| ident.clone())
+                .collect();
+            idents.sort_unstable();
+            idents
+        };
+        LineResult {
+            line: self.lines.iter().position(|&s| s == self.line_start).unwrap() + 1,
+            read: globals(read),
+            written: globals(written),
+            errored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn touched_globals() {
+        let src = ":a=:b*2 c=:d\nif :e then :f=1 else :g=1 end\n:h=1/0 :i=1 goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let mut reference = machine.clone();
+        let names = |idents: &[Ident]| {
+            idents.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(" ")
+        };
+
+        let result = machine.step_touched();
+        assert_eq!((result.line, result.errored), (1, false));
+        assert_eq!(names(&result.read), ":b :d");
+        assert_eq!(names(&result.written), ":a");
+        let result = machine.step_touched();
+        assert_eq!(names(&result.read), ":e");
+        assert_eq!(names(&result.written), ":g");
+        let result = machine.step_touched();
+        assert_eq!((result.line, result.errored), (3, true));
+        assert!(result.written.is_empty());
+
+        reference.step_repeat(3);
+        assert_eq!(machine.get_current_line(), reference.get_current_line());
+        assert!(!machine.step_touched().errored);
+    }
+}
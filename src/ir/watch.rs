@@ -0,0 +1,115 @@
+use super::*;
+
+type Callback = dyn FnMut(&Value, &Value) + Send + Sync;
+
+/// Set by [`IRMachine::watch_global`]. Clones and forks start without any. This keeps the
+/// field rather than its register, since passes can move registers.
+pub(super) struct Watch {
+    field: Ident,
+    callback: Box<Callback>,
+}
+
+impl std::fmt::Debug for Watch {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Watch({})", self.field)
+    }
+}
+
+impl IRMachine {
+    /// Calls `callback` with the old and new value every time the program writes `field`, even
+    /// if it's written again later in the line or doesn't change. Returns false, without
+    /// watching, if the machine doesn't protect `field` (see [`CodegenOptions`]).
+    ///
+    /// While anything is watched, lines run an instruction at a time, which is much slower.
+    pub fn watch_global(
+        &mut self,
+        field: &Ident,
+        callback: impl FnMut(&Value, &Value) + Send + Sync + 'static,
+    ) -> bool {
+        debug_assert!(field.global, "tried to watch local '{}'", field);
+        if !self.idents.contains_key(field) {
+            return false;
+        }
+        self.watches.push(Watch {
+            field: field.clone(),
+            callback: Box::new(callback),
+        });
+        true
+    }
+
+    /// Stops every watch on `field`.
+    pub fn unwatch_global(&mut self, field: &Ident) {
+        self.watches.retain(|w| w.field != *field);
+    }
+
+    /// The first watch `instr` writes to, and its value beforehand.
+    pub(super) fn watched_write(&self, instr: Instruction) -> Option<(usize, Value)> {
+        if self.watches.is_empty() {
+            return None;
+        }
+        let written = instr.modifies()?;
+        let watch = self.watches.iter().position(|w| self.idents[&w.field] == written)?;
+        Some((watch, self.get_ident_value(&self.watches[watch].field)))
+    }
+
+    pub(super) fn notify_watch(&mut self, first: usize, old: Value) {
+        let new = self.get_ident_value(&self.watches[first].field);
+        let field = self.watches[first].field.clone();
+        for watch in self.watches[first..].iter_mut().filter(|w| w.field == field) {
+            (watch.callback)(&old, &new);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use parser::YololParser;
+    use super::super::tests::variables;
+    use super::*;
+
+    #[test]
+    fn watching_globals() {
+        let src = ":a=1 :a++ :b=:a*2 :a=:b\n:c=1/0 :a=7\n:a=:a goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let mut reference = machine.clone();
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+        assert!(machine.watch_global(&Ident::global("a"), move |old, new| {
+            log.lock().unwrap().push((old.to_string(), new.to_string()));
+        }));
+        assert!(!machine.watch_global(&Ident::global("nope"), |_, _| {}));
+
+        for _ in 0..4 {
+            machine.step();
+            reference.step();
+        }
+        let writes = std::mem::take(&mut *writes.lock().unwrap());
+        assert_eq!(writes, [
+            ("0", "1"), ("1", "2"), ("2", "4"),
+            ("4", "4"),
+            ("4", "1"), ("1", "2"), ("2", "4"),
+        ].map(|(old, new)| (old.to_string(), new.to_string())));
+        assert_eq!(variables(&machine), variables(&reference));
+
+        machine.unwatch_global(&Ident::global("a"));
+        assert!(machine.watches.is_empty());
+    }
+
+    #[test]
+    fn watching_through_compaction() {
+        let program = YololParser::default().parse("unused=5 other=\"a\"\n:a++ goto 2").unwrap();
+        let mut machine = IRMachine::from_ast(Default::default(), program);
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = writes.clone();
+        machine.watch_global(&Ident::global("a"), move |_, new| {
+            log.lock().unwrap().push(new.to_string());
+        });
+        machine[0].instrs.clear();
+        assert!(machine.compact_registers() > 0);
+
+        machine.step_repeat(3);
+        assert_eq!(*writes.lock().unwrap(), ["1", "2"]);
+    }
+}
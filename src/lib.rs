@@ -1,4 +1,47 @@
-pub mod parser;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+#[macro_use]
+mod trace;
+
 pub mod arith;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "std")]
 pub mod simple_interp;
-pub mod ir;
\ No newline at end of file
+#[cfg(feature = "std")]
+pub mod ir;
+#[cfg(feature = "std")]
+pub mod transpile;
+#[cfg(feature = "std")]
+pub mod network;
+#[cfg(feature = "std")]
+pub mod opt;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod patterns;
+#[cfg(feature = "std")]
+pub mod spec;
+#[cfg(feature = "std")]
+pub mod stdlib;
+#[cfg(feature = "std")]
+pub mod preprocess;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod visit;
+
+#[cfg(test)]
+mod alloc_check;
+
+#[cfg(feature = "corpus")]
+pub mod corpus;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,236 @@
+//! Checks for code which won't fit on a chip or probably doesn't do what was meant.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use anyhow::Result;
+use petgraph::visit::Dfs;
+use diagnostics::{Diagnostic, Severity};
+use ir::{CodegenOptions, ConstantFolding, IRMachine, OptPipeline};
+use parser::*;
+use patterns::Pattern;
+use super::*;
+
+/// What a [`Lint`] found.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintKind {
+    /// The line has more characters than fit on a chip.
+    LineTooLong { length: usize },
+    /// The program runs onto more lines than a chip has, reported on the first line which
+    /// doesn't fit.
+    TooManyLines { lines: usize },
+    /// No way through the program reaches the line.
+    Unreachable,
+    /// A division or `%` by a literal zero, which always fails.
+    DivisionByZero,
+    /// A local assigned on the line but never read anywhere.
+    NeverRead(Ident),
+    /// A global written on the line with a name none of [`LintOptions::global_names`] match.
+    GlobalName(Ident),
+}
+
+impl LintKind {
+    pub const fn severity(&self) -> Severity {
+        match self {
+            LintKind::LineTooLong { .. } | LintKind::TooManyLines { .. } => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let limits = YololParser::default();
+        match self {
+            LintKind::LineTooLong { length } => write!(
+                f,
+                "line is {} characters long, more than the {} which fit",
+                length, limits.max_line_length,
+            ),
+            LintKind::TooManyLines { lines } => write!(
+                f,
+                "program is {} lines long, more than the {} which fit",
+                lines, limits.max_lines,
+            ),
+            LintKind::Unreachable => f.write_str("line can never run"),
+            LintKind::DivisionByZero => f.write_str("division by zero always fails"),
+            LintKind::NeverRead(ident) => write!(f, "'{}' is assigned but never read", ident),
+            LintKind::GlobalName(ident) => {
+                write!(f, "'{}' doesn't follow the naming convention", ident)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Lint {
+    /// 1-based.
+    pub line: usize,
+    pub kind: LintKind,
+}
+
+impl From<Lint> for Diagnostic {
+    fn from(lint: Lint) -> Self {
+        Diagnostic {
+            severity: lint.kind.severity(),
+            line: lint.line,
+            message: lint.kind.to_string(),
+        }
+    }
+}
+
+fn is_zero(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(n) if n.0 == 0)
+}
+
+fn divides_by_zero(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binop(_, Binop::Div | Binop::Mod, r) if is_zero(r) => true,
+        Expr::Binop(l, _, r) => divides_by_zero(l) || divides_by_zero(r),
+        Expr::Unop(_, e) => divides_by_zero(e),
+        Expr::Incdec(_) | Expr::Ident(_) | Expr::Number(_) | Expr::String(_) => false,
+    }
+}
+
+/// Calls `f` on every statement in `stmts`, including those inside `if`s.
+fn for_each_statement(stmts: &[Statement], f: &mut impl FnMut(&Statement)) {
+    for stmt in stmts {
+        f(stmt);
+        if let Statement::Ite(_, t, e) = stmt {
+            for_each_statement(t, f);
+            for_each_statement(e, f);
+        }
+    }
+}
+
+/// Settings for [`lint_with`].
+#[derive(Debug, Clone, Default)]
+pub struct LintOptions {
+    /// Patterns for the names of globals a program writes, without the `:`, like `nav_*`. A
+    /// global must match one of them, unless there are none. Names are lowercase, so patterns
+    /// should be too or ignore case.
+    pub global_names: Vec<Pattern>,
+}
+
+/// Checks `source` for everything a [`LintKind`] describes, by line. Fails if `source` doesn't
+/// parse at all, or nests too deeply to compile.
+pub fn lint(source: &str) -> Result<Vec<Lint>> {
+    lint_with(source, &LintOptions::default())
+}
+
+/// Like [`lint`], with checks set up by `options`.
+pub fn lint_with(source: &str, options: &LintOptions) -> Result<Vec<Lint>> {
+    let limits = YololParser::default();
+    let program = YololParser::unrestricted().parse(source)?;
+    let mut lints = Vec::new();
+
+    let mut lines = 0;
+    for (i, text) in source.lines().enumerate() {
+        let length = text.chars().count();
+        if length > limits.max_line_length {
+            lints.push(Lint { line: i + 1, kind: LintKind::LineTooLong { length } });
+        }
+        if !text.trim().is_empty() {
+            lines = i + 1;
+        }
+    }
+    if lines > limits.max_lines {
+        let kind = LintKind::TooManyLines { lines };
+        lints.push(Lint { line: limits.max_lines + 1, kind });
+    }
+
+    let codegen = CodegenOptions { protect_locals: true, ..Default::default() };
+    let machine = IRMachine::try_from_ast(codegen, program.clone())?;
+    // folding drops the error checks on `goto`s to literals, which can't fail
+    let mut folded = machine.clone();
+    OptPipeline::new().add(ConstantFolding).run(&mut folded);
+    let cfg = folded.control_flow_graph();
+    let mut reachable = Dfs::new(cfg.graph(), cfg.line_start(0));
+    while reachable.next(cfg.graph()).is_some() {}
+    let dfg = machine.data_flow_graph();
+    let never_read = |ident: &Ident| {
+        machine.ident_register(ident).is_some_and(|reg| dfg.uses(reg).is_empty())
+    };
+    let misnamed = |ident: &Ident| {
+        let patterns = &options.global_names;
+        ident.global
+            && !patterns.is_empty()
+            && !patterns.iter().any(|p| p.matches(ident.name.as_bytes()))
+    };
+
+    for (i, line) in program.iter().enumerate() {
+        let mut found = Vec::new();
+        if !line.is_empty() && !reachable.discovered.contains(cfg.line_start(i).index()) {
+            found.push(LintKind::Unreachable);
+        }
+        for_each_statement(line, &mut |stmt| match stmt {
+            Statement::Goto(e) | Statement::Ite(e, ..) => {
+                if divides_by_zero(e) {
+                    found.push(LintKind::DivisionByZero);
+                }
+            },
+            Statement::Assign(ident, op, e) => {
+                let by_zero = matches!(op, Some(AssignOp::Div | AssignOp::Mod)) && is_zero(e);
+                if by_zero || divides_by_zero(e) {
+                    found.push(LintKind::DivisionByZero);
+                }
+                if !ident.global && never_read(ident) {
+                    found.push(LintKind::NeverRead(ident.clone()));
+                }
+                if misnamed(ident) {
+                    found.push(LintKind::GlobalName(ident.clone()));
+                }
+            },
+            Statement::Incdec(Incdec { ident, .. }) => if misnamed(ident) {
+                found.push(LintKind::GlobalName(ident.clone()));
+            },
+        });
+        found.sort_unstable();
+        found.dedup();
+        lints.extend(found.into_iter().map(|kind| Lint { line: i + 1, kind }));
+    }
+    lints.sort_unstable();
+    Ok(lints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lints_source() {
+        let src = format!(
+            "a=1 unused=2 b=a/0 c%=0\n:out=b goto 1\nunreachable=3\n//{}\n{}:x=1",
+            "x".repeat(69),
+            "\n".repeat(17),
+        );
+        let lints: Vec<_> = lint(&src)
+            .unwrap()
+            .into_iter()
+            .map(|l| Diagnostic::from(l).to_string())
+            .collect();
+        assert_eq!(lints, [
+            "line 1: warning: division by zero always fails",
+            "line 1: warning: 'unused' is assigned but never read",
+            "line 3: warning: line can never run",
+            "line 3: warning: 'unreachable' is assigned but never read",
+            "line 4: error: line is 71 characters long, more than the 70 which fit",
+            "line 21: error: program is 22 lines long, more than the 20 which fit",
+            "line 22: warning: line can never run",
+        ]);
+        assert!(lint("a = = 1").is_err());
+        // too deep to compile, with room on the stack to parse it
+        let nested = format!("a={}b{}", "-(".repeat(300), ")".repeat(300));
+        let deep = std::thread::Builder::new().stack_size(64 << 20).spawn(move || lint(&nested));
+        assert!(deep.unwrap().join().unwrap().is_err());
+    }
+
+    #[test]
+    fn global_names() {
+        let options = LintOptions {
+            global_names: vec![Pattern::prefix(b"nav_"), Pattern::new("*_out")],
+        };
+        let src = ":nav_x=1 :pump_out=:speed :speed++ :nav_y=:other";
+        let lints = lint_with(src, &options).unwrap();
+        assert_eq!(lints, [Lint { line: 1, kind: LintKind::GlobalName(Ident::global("speed")) }]);
+        assert!(lint(":speed=1").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::ops::RangeInclusive;
+use super::*;
+
+/// A run of lines in one chip which compute the same way as a run in another, from
+/// [`Network::find_clones`]. Lines are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeClone {
+    pub chip: ChipId,
+    pub lines: RangeInclusive<usize>,
+    pub other_chip: ChipId,
+    pub other_lines: RangeInclusive<usize>,
+}
+
+impl Display for CodeClone {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "lines {}-{} of chip {} \u{2245} lines {}-{} of chip {}",
+            self.lines.start(), self.lines.end(), self.chip.0,
+            self.other_lines.start(), self.other_lines.end(), self.other_chip.0,
+        )
+    }
+}
+
+impl Network {
+    /// Finds runs of at least `min_lines` lines with the same shape (see
+    /// [`IRMachine::line_fingerprints`]) in two different chips, which could be written once and
+    /// shared. Each run is reported whole, rather than also as its shorter parts.
+    pub fn find_clones(&self, min_lines: usize) -> Vec<CodeClone> {
+        let fingerprints: Vec<_> =
+            self.chips.iter().map(|c| c.machine.line_fingerprints()).collect();
+        let mut clones = Vec::new();
+        for (a, fa) in fingerprints.iter().enumerate() {
+            for (b, fb) in fingerprints.iter().enumerate().skip(a + 1) {
+                let same = |i: usize, j: usize| fa[i].is_some() && fa[i] == fb[j];
+                for i in 0..fa.len() {
+                    for j in 0..fb.len() {
+                        // only start at the beginning of a run
+                        if i > 0 && j > 0 && same(i - 1, j - 1) {
+                            continue;
+                        }
+                        let len = (0..)
+                            .take_while(|&k| {
+                                i + k < fa.len() && j + k < fb.len() && same(i + k, j + k)
+                            })
+                            .count();
+                        if len > 0 && len >= min_lines {
+                            clones.push(CodeClone {
+                                chip: ChipId(a),
+                                lines: i + 1..=i + len,
+                                other_chip: ChipId(b),
+                                other_lines: j + 1..=j + len,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        clones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn finds_copied_lines() {
+        let chip = |src: &str| {
+            IRMachine::from_ast(Default::default(), YololParser::default().parse(src).unwrap())
+        };
+        let mut network = Network::new();
+        let a = chip(":a=1\n:b=:a*2 :c=:b/3\nif :c>1 then :d=\"hi\" end\n:e=0 goto 1");
+        let a = network.add_chip(a);
+        let b = chip(":out=\"x\"+:in\n:p=:q*5 :r=:p/2\nif :r>8 then :s=\"yo\" end\ngoto 1");
+        let b = network.add_chip(b);
+        let clones = network.find_clones(2);
+        let expected = CodeClone { chip: a, lines: 2..=3, other_chip: b, other_lines: 2..=3 };
+        assert_eq!(clones, [expected]);
+        assert_eq!(clones[0].to_string(), "lines 2-3 of chip 0 \u{2245} lines 2-3 of chip 1");
+        assert!(network.find_clones(3).is_empty());
+    }
+}
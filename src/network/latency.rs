@@ -0,0 +1,103 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use super::*;
+
+/// How long a change to one field can take to reach another, from
+/// [`Network::worst_case_latency`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latency {
+    pub ticks: usize,
+    /// The chips passing it on, in order.
+    pub hops: Vec<LatencyHop>,
+}
+
+/// One chip passing a change from one field on to another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHop {
+    pub chip: ChipId,
+    pub from: Ident,
+    pub to: Ident,
+    /// The 1-based lines it runs along the slowest way, see [`ir::ChipLatency`].
+    pub lines: Vec<usize>,
+}
+
+impl Network {
+    /// An upper bound on how many ticks it takes for a change to the field `input` to reach
+    /// `output`, going through as many chips as it needs to, and the chips and lines along
+    /// the way. Each chip takes its [`IRMachine::worst_case_latency`], as though it runs
+    /// before the chip it reads from every tick. Chips put to sleep (see
+    /// [`Network::set_sleep_field`]) can take longer. `None` if no chain of chips is sure
+    /// to pass the change on.
+    pub fn worst_case_latency(&self, input: &Ident, output: &Ident) -> Option<Latency> {
+        let mut best: AHashMap<Ident, Latency> = AHashMap::new();
+        best.insert(input.clone(), Latency { ticks: 0, hops: Vec::new() });
+        let mut queue = BinaryHeap::from([(Reverse(0), input.clone())]);
+        while let Some((Reverse(ticks), field)) = queue.pop() {
+            if field == *output {
+                return best.remove(&field);
+            }
+            if best[&field].ticks < ticks {
+                continue;
+            }
+            for (id, chip) in self.chips.iter().enumerate() {
+                if !chip.globals.contains(&field) || self.sleep_field.as_ref() == Some(&field) {
+                    continue;
+                }
+                for to in chip.globals.iter() {
+                    if *to == field || self.sleep_field.as_ref() == Some(to) {
+                        continue;
+                    }
+                    let Some(hop) = chip.machine.worst_case_latency(&field, to) else {
+                        continue;
+                    };
+                    let ticks = ticks + hop.ticks();
+                    if best.get(to).is_some_and(|b| b.ticks <= ticks) {
+                        continue;
+                    }
+                    let mut hops = best[&field].hops.clone();
+                    hops.push(LatencyHop {
+                        chip: ChipId(id),
+                        from: field.clone(),
+                        to: to.clone(),
+                        lines: hop.lines,
+                    });
+                    best.insert(to.clone(), Latency { ticks, hops });
+                    queue.push((Reverse(ticks), to.clone()));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn worst_case_latency_across_chips() {
+        let mut network = Network::new();
+        let chip = |src: &str| {
+            let options = CodegenOptions { protect_globals: true, ..Default::default() };
+            IRMachine::from_ast(options, YololParser::default().parse(src).unwrap())
+        };
+        network.add_chip(chip(":b=:c*2 goto 1"));
+        let first = network.add_chip(chip(":mid=:in\n:x=1 goto 1"));
+        let second = network.add_chip(chip("if :mid>1 then :out=1 end goto 1"));
+
+        let latency = network
+            .worst_case_latency(&Ident::global("in"), &Ident::global("out"))
+            .unwrap();
+        assert_eq!(latency.ticks, 3);
+        let hops: Vec<_> = latency.hops
+            .iter()
+            .map(|h| (h.chip, h.to.clone(), h.lines.clone()))
+            .collect();
+        assert_eq!(hops, [
+            (first, Ident::global("mid"), vec![2, 1]),
+            (second, Ident::global("out"), vec![1]),
+        ]);
+        assert_eq!(network.worst_case_latency(&Ident::global("out"), &Ident::global("in")), None);
+    }
+}
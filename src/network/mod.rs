@@ -0,0 +1,638 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use ahash::{AHashMap, AHashSet};
+use arith::*;
+use ir::{CancelToken, CodegenOptions, IRMachine};
+use parser::{FieldAnnotation, Ident, Program};
+use patterns::Pattern;
+use super::*;
+pub use scenario::{Scenario, ScenarioFailure};
+pub use clones::CodeClone;
+pub use sweep::{Sweep, SweepResult};
+pub use snapshot::NetworkSnapshot;
+pub use latency::{Latency, LatencyHop};
+
+mod scenario;
+mod clones;
+mod sweep;
+mod snapshot;
+mod latency;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChipId(pub usize);
+
+#[derive(Debug, Clone)]
+struct Chip {
+    machine: IRMachine,
+    globals: Vec<Ident>,
+    /// Consecutive ticks ending where they started, counted while the watchdog is on.
+    idle_ticks: usize,
+    /// A moving average of how long a step takes, measured by [`Network::run_frame`].
+    cost: Option<Duration>,
+    /// Ticks left to skip, after writing the sleep field (see [`Network::set_sleep_field`]).
+    asleep: usize,
+}
+
+/// Something that happened while ticking a [`Network`], for the host to react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// The chip went as many ticks as the watchdog allows without changing any variable or
+    /// moving to another line, so it's probably stuck in a loop. Reported once per stall.
+    ChipStalled { chip: ChipId, ticks: usize },
+}
+
+/// What a call to [`Network::run_frame`] did.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrameReport {
+    pub chips_run: usize,
+    /// Whether the frame finished the tick, rather than leaving chips for the next frame.
+    pub tick_completed: bool,
+    /// Chips still to run this tick, since running them would have gone over the budget.
+    pub deferred: Vec<ChipId>,
+}
+
+/// A borrow of a string field, from [`Network::read_str`]. The network can't be changed while
+/// it's held, so the bytes can be read without copying them.
+#[derive(Debug, Clone, Copy)]
+pub struct StrGuard<'a>(&'a YString);
+
+impl<'a> StrGuard<'a> {
+    pub fn as_ystring(&self) -> &'a YString {
+        self.0
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The string as text, if it's valid UTF-8, which string arithmetic doesn't guarantee.
+    pub fn to_str(&self) -> Result<&'a str, std::str::Utf8Error> {
+        std::str::from_utf8(self.0)
+    }
+}
+
+impl std::ops::Deref for StrGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl std::fmt::Display for StrGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self.0, f)
+    }
+}
+
+/// Many chips sharing one set of data fields. Each tick, every chip runs a single line in the
+/// order they were added, seeing the writes of the chips before it.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    chips: Vec<Chip>,
+    fields: AHashMap<Ident, Value>,
+    ticks: usize,
+    watchdog: Option<usize>,
+    events: Vec<NetworkEvent>,
+    /// Writes queued by the host for each field, in the order they apply.
+    inputs: AHashMap<Ident, VecDeque<(usize, Value)>>,
+    /// The next chip to run this tick, if a frame ran out of time part way through.
+    next_chip: usize,
+    /// Fields shared by every namespace, see [`Network::add_chip_in`].
+    ship_fields: AHashSet<Ident>,
+    sleep_field: Option<Ident>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Globals the chip doesn't protect (see [`ir::CodegenOptions`]) aren't connected to the
+    /// network.
+    pub fn add_chip(&mut self, machine: IRMachine) -> ChipId {
+        let globals = machine
+            .idents()
+            .into_iter()
+            .filter(|(ident, _)| ident.global)
+            .map(|(ident, _)| ident.clone())
+            .collect();
+        self.chips.push(Chip {
+            machine,
+            globals,
+            idle_ticks: 0,
+            cost: None,
+            asleep: 0,
+        });
+        ChipId(self.chips.len() - 1)
+    }
+
+    /// Declare a field as ship wide, so chips in every namespace share it.
+    pub fn add_ship_field(&mut self, field: Ident) {
+        debug_assert!(field.global, "'{}' isn't a field", field);
+        self.ship_fields.insert(field);
+    }
+
+    /// The field that `field` means to a chip in `namespace`: itself if it's ship wide, or
+    /// otherwise prefixed with the namespace, so `:speed` in `engine` is `:engine_speed`.
+    pub fn resolve_field(&self, namespace: &str, field: &Ident) -> Ident {
+        if namespace.is_empty() || self.ship_fields.contains(field) {
+            field.clone()
+        } else {
+            Ident::global(&format!("{}_{}", namespace, field.original_name()))
+        }
+    }
+
+    /// Compiles and adds a chip in `namespace`, so its scripts can use short field names
+    /// without clashing with other subsystems. Fields are resolved by
+    /// [`Network::resolve_field`] when compiling, so ship wide fields must be declared first.
+    pub fn add_chip_in(
+        &mut self,
+        namespace: &str,
+        options: CodegenOptions,
+        mut program: Program,
+    ) -> ChipId {
+        program.for_each_ident_mut(|ident| {
+            if ident.global {
+                *ident = self.resolve_field(namespace, ident);
+            }
+        });
+        self.add_chip(IRMachine::from_ast(options, program))
+    }
+
+    pub fn chip(&self, id: ChipId) -> &IRMachine {
+        &self.chips[id.0].machine
+    }
+
+    pub fn chip_mut(&mut self, id: ChipId) -> &mut IRMachine {
+        &mut self.chips[id.0].machine
+    }
+
+    pub fn chip_count(&self) -> usize {
+        self.chips.len()
+    }
+
+    /// Fields which were never written read as 0, like any Yolol variable.
+    pub fn read(&self, field: &Ident) -> Value {
+        debug_assert!(field.global, "tried to read local '{}' from the network", field);
+        self.fields.get(field).cloned().unwrap_or_default()
+    }
+
+    /// How `field` is annotated by the first chip annotating it (see
+    /// [`IRMachine::annotation`]), for labelling it.
+    pub fn annotation(&self, field: &Ident) -> Option<&FieldAnnotation> {
+        self.chips.iter().find_map(|chip| chip.machine.annotation(field))
+    }
+
+    /// [`IRMachine::opcode_counts`] summed over every chip.
+    #[cfg(feature = "opcode-counts")]
+    pub fn opcode_counts(&self) -> Vec<(&'static str, u64)> {
+        let mut totals = Vec::new();
+        for chip in self.chips.iter() {
+            let counts = chip.machine.raw_opcode_counts();
+            totals.resize(counts.len(), 0);
+            totals.iter_mut().zip(counts).for_each(|(t, c)| *t += c);
+        }
+        ir::sorted_opcode_counts(totals)
+    }
+
+    /// Borrows `field` without cloning it, or `None` if it doesn't hold a string.
+    pub fn read_str(&self, field: &Ident) -> Option<StrGuard<'_>> {
+        debug_assert!(field.global, "tried to read local '{}' from the network", field);
+        match self.fields.get(field) {
+            Some(Value::Str(s)) => Some(StrGuard(s)),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, field: Ident, value: Value) {
+        debug_assert!(field.global, "tried to write local '{}' to the network", field);
+        self.fields.insert(field, value);
+    }
+
+    /// Write `value` to `field` just before tick `tick` runs (counting from 0, like
+    /// [`Network::ticks`]), or before the next tick if that's already run. Writes queued for the
+    /// same field and tick apply in the order they were queued, so the last one wins.
+    pub fn queue_write(&mut self, tick: usize, field: Ident, value: Value) {
+        debug_assert!(field.global, "tried to write local '{}' to the network", field);
+        let queue = self.inputs.entry(field).or_default();
+        let at = queue.partition_point(|&(t, _)| t <= tick);
+        queue.insert(at, (tick, value));
+    }
+
+    /// Drop every queued write which hasn't been applied yet.
+    pub fn clear_queued_writes(&mut self) {
+        self.inputs.clear();
+    }
+
+    fn apply_queued_writes(&mut self) {
+        let now = self.ticks;
+        for (field, queue) in self.inputs.iter_mut() {
+            while queue.front().is_some_and(|&(tick, _)| tick <= now) {
+                let (_, value) = queue.pop_front().unwrap();
+                self.fields.insert(field.clone(), value);
+            }
+        }
+        self.inputs.retain(|_, queue| !queue.is_empty());
+    }
+
+    /// Every field anything has written, in [`Ident`] order.
+    pub fn fields(&self) -> impl Iterator<Item = (&Ident, &Value)> + '_ {
+        let mut fields: Vec<_> = self.fields.iter().collect();
+        fields.sort_unstable_by_key(|&(ident, _)| ident);
+        fields.into_iter()
+    }
+
+    /// Every field whose name, without the `:`, matches `name`, in [`Ident`] order. Names are
+    /// lowercase, so the pattern should be too or ignore case.
+    pub fn fields_named<'a>(
+        &'a self,
+        name: &'a Pattern,
+    ) -> impl Iterator<Item = (&'a Ident, &'a Value)> + 'a {
+        self.fields().filter(|(ident, _)| name.matches(ident.name.as_bytes()))
+    }
+
+    /// Every field holding a string matching `value`, in [`Ident`] order.
+    pub fn fields_holding<'a>(
+        &'a self,
+        value: &'a Pattern,
+    ) -> impl Iterator<Item = (&'a Ident, &'a Value)> + 'a {
+        self.fields().filter(|(_, v)| matches!(v, Value::Str(s) if value.matches_ystring(s)))
+    }
+
+    /// Report a [`NetworkEvent::ChipStalled`] when a chip goes `ticks` ticks without changing
+    /// any variable or line, or stop watching with `None`.
+    pub fn set_watchdog(&mut self, ticks: Option<usize>) {
+        self.watchdog = ticks;
+        for chip in self.chips.iter_mut() {
+            chip.idle_ticks = 0;
+        }
+    }
+
+    /// When a chip sets `field` to a positive number, it sleeps through that many of the
+    /// following ticks (rounded up) and the field goes back to 0. The field belongs to each chip
+    /// rather than the network, so chips don't wake or sleep each other. `None` turns it off.
+    ///
+    /// Sleeping chips wake up when this is called.
+    pub fn set_sleep_field(&mut self, field: Option<Ident>) {
+        debug_assert!(field.as_ref().is_none_or(|f| f.global), "the sleep field must be global");
+        self.sleep_field = field;
+        for chip in self.chips.iter_mut() {
+            chip.asleep = 0;
+        }
+    }
+
+    /// How many more ticks the chip will sleep through.
+    pub fn chip_sleep(&self, id: ChipId) -> usize {
+        self.chips[id.0].asleep
+    }
+
+    /// Take the events reported since the last call.
+    pub fn take_events(&mut self) -> Vec<NetworkEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// How many ticks have run so far.
+    pub const fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    /// Returns false if the chip was asleep, so didn't run.
+    fn step_chip(&mut self, id: usize) -> bool {
+        span!(TRACE, "chip", id);
+        let chip = &mut self.chips[id];
+        if chip.asleep > 0 {
+            chip.asleep -= 1;
+            return false;
+        }
+        let sleep_field = self.sleep_field.as_ref();
+        let shared = |global: &&Ident| Some(*global) != sleep_field;
+        for global in chip.globals.iter().filter(shared) {
+            if let Some(value) = self.fields.get(global) {
+                chip.machine.set_ident(global, value.clone());
+            }
+        }
+        if let Some(limit) = self.watchdog {
+            let before = chip.machine.state_fingerprint();
+            chip.machine.step();
+            if chip.machine.state_fingerprint() == before {
+                chip.idle_ticks += 1;
+                if chip.idle_ticks == limit {
+                    event!(WARN, chip = id, ticks = limit, "chip stalled");
+                    self.events.push(NetworkEvent::ChipStalled {
+                        chip: ChipId(id),
+                        ticks: limit,
+                    });
+                }
+            } else {
+                chip.idle_ticks = 0;
+            }
+        } else {
+            chip.machine.step();
+        }
+        if let Some(field) = sleep_field {
+            if let Value::Num(ticks) = chip.machine.get_ident_value(field) {
+                if ticks.0 > 0 {
+                    chip.asleep = ((ticks.0 as u64).div_ceil(1000)) as usize;
+                    chip.machine.set_ident(field, Value::Num(0.into()));
+                }
+            }
+        }
+        for global in chip.globals.iter().filter(shared) {
+            let value = chip.machine.get_ident_value(global);
+            match self.fields.get_mut(global) {
+                Some(field) => *field = value,
+                None => {
+                    self.fields.insert(global.clone(), value);
+                },
+            }
+        }
+        true
+    }
+
+    /// If [`Network::run_frame`] left the tick part way through, only the chips which haven't
+    /// run yet do.
+    pub fn tick(&mut self) {
+        self.run_tick(|network, id| {
+            network.step_chip(id);
+            true
+        });
+    }
+
+    /// Runs the chips left in the current tick with `run_chip`, which returns false instead to
+    /// leave the rest for later. Returns whether the tick completed.
+    fn run_tick(&mut self, mut run_chip: impl FnMut(&mut Self, usize) -> bool) -> bool {
+        span!(DEBUG, "tick", tick = self.ticks);
+        if self.next_chip == 0 && !self.inputs.is_empty() {
+            self.apply_queued_writes();
+        }
+        while self.next_chip < self.chips.len() {
+            if !run_chip(self, self.next_chip) {
+                return false;
+            }
+            self.next_chip += 1;
+        }
+        self.next_chip = 0;
+        self.ticks += 1;
+        true
+    }
+
+    /// Runs chips towards the end of the current tick, stopping before any chip which is
+    /// expected to take the time spent past `budget`, so one host frame doesn't hitch when
+    /// heavy chips line up. The rest run in later frames, before the tick completes.
+    ///
+    /// At least one chip always runs, and at most one tick completes per frame.
+    pub fn run_frame(&mut self, budget: Duration) -> FrameReport {
+        span!(DEBUG, "frame", tick = self.ticks);
+        let start = Instant::now();
+        let mut report = FrameReport::default();
+        report.tick_completed = self.run_tick(|network, id| {
+            let expected = network.chips[id].cost.unwrap_or_default();
+            if report.chips_run > 0 && start.elapsed().saturating_add(expected) > budget {
+                report.deferred = (id..network.chips.len()).map(ChipId).collect();
+                return false;
+            }
+            let step_start = Instant::now();
+            if network.step_chip(id) {
+                let took = step_start.elapsed();
+                let chip = &mut network.chips[id];
+                chip.cost = Some(chip.cost.map_or(took, |cost| (cost * 3 + took) / 4));
+            }
+            report.chips_run += 1;
+            true
+        });
+        report
+    }
+
+    /// How long the chip usually takes to step, once [`Network::run_frame`] has run it.
+    pub fn chip_cost(&self, id: ChipId) -> Option<Duration> {
+        self.chips[id.0].cost
+    }
+
+    pub fn run(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Ticks until `token` is cancelled, returning how many ticks completed. The token is
+    /// checked before each chip steps, and a tick cut short finishes on the next call to
+    /// [`Network::tick`] or the like, as with [`Network::run_frame`].
+    pub fn run_with_cancel(&mut self, token: &CancelToken) -> usize {
+        let mut ticks = 0;
+        let mut run_chip = |network: &mut Self, id| {
+            if token.is_cancelled() {
+                return false;
+            }
+            network.step_chip(id);
+            true
+        };
+        // checked here too, for networks without chips
+        while !token.is_cancelled() && self.run_tick(&mut run_chip) {
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    fn chip(src: &str) -> IRMachine {
+        IRMachine::from_ast(Default::default(), YololParser::default().parse(src).unwrap())
+    }
+
+    #[test]
+    fn chips_share_fields() {
+        let mut network = Network::new();
+        network.add_chip(chip(":a+=1 goto 1"));
+        network.add_chip(chip(":b=:a*2 goto 1"));
+        network.run(3);
+        assert_eq!(network.ticks(), 3);
+        assert_eq!(network.read(&Ident::global("a")), Value::Num(3.into()));
+        assert_eq!(network.read(&Ident::global("b")), Value::Num(6.into()));
+
+        network.write(Ident::global("A"), Value::Num(10.into()));
+        network.tick();
+        assert_eq!(network.read(&Ident::global("b")), Value::Num(22.into()));
+    }
+
+    #[test]
+    fn field_filters() {
+        let mut network = Network::new();
+        network.add_chip(chip(":nav_x=1 :nav_y=\"north\" :door=\"NORTH gate\""));
+        network.run(1);
+        let names = |fields: Vec<(&Ident, &Value)>| {
+            fields.into_iter().map(|(i, _)| i.name.clone()).collect::<Vec<_>>()
+        };
+        let nav = Pattern::prefix(b"nav_");
+        assert_eq!(names(network.fields_named(&nav).collect()), ["nav_x", "nav_y"]);
+        let north = Pattern::new("north*").ignore_case();
+        assert_eq!(names(network.fields_holding(&north).collect()), ["door", "nav_y"]);
+    }
+
+    #[test]
+    fn watchdog() {
+        let mut network = Network::new();
+        network.add_chip(chip(":a+=1 goto 1"));
+        let waiting = network.add_chip(chip("if :go then :b=1 end goto 1"));
+        network.set_watchdog(Some(5));
+
+        network.run(4);
+        assert!(network.take_events().is_empty());
+        network.run(10);
+        assert_eq!(network.take_events(), [NetworkEvent::ChipStalled { chip: waiting, ticks: 5 }]);
+
+        // a write from outside gets it going, then it stalls again
+        network.write(Ident::global("go"), Value::Num(1.into()));
+        network.run(5);
+        assert!(network.take_events().is_empty());
+        network.run(1);
+        assert_eq!(network.take_events(), [NetworkEvent::ChipStalled { chip: waiting, ticks: 5 }]);
+    }
+
+    #[test]
+    fn queued_writes() {
+        let mut network = Network::new();
+        network.add_chip(chip(":seen=:btn :n+=:btn goto 1"));
+        let btn = Ident::global("btn");
+        network.queue_write(5, btn.clone(), Value::Num(0.into()));
+        network.queue_write(3, btn.clone(), Value::Num(1.into()));
+        network.queue_write(3, btn.clone(), Value::Num(2.into()));
+
+        network.run(3);
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(0.into()));
+        network.tick();
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(2.into()));
+        network.run(2);
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(0.into()));
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(4.into()));
+
+        // a tick that's already run applies before the next one
+        network.queue_write(1, btn.clone(), Value::Num(7.into()));
+        network.queue_write(100, btn, Value::Num(8.into()));
+        network.tick();
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(7.into()));
+        network.clear_queued_writes();
+        network.run(200);
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(7.into()));
+    }
+
+    #[test]
+    fn read_str_borrows() {
+        let mut network = Network::new();
+        network.add_chip(chip(":msg=\"hello\" :n=1"));
+        network.tick();
+        let msg = network.read_str(&Ident::global("msg")).unwrap();
+        assert_eq!(msg.as_bytes(), b"hello");
+        assert_eq!(msg.to_str(), Ok("hello"));
+        assert_eq!(msg.to_string(), "hello");
+        assert!(network.read_str(&Ident::global("n")).is_none());
+        assert!(network.read_str(&Ident::global("missing")).is_none());
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut network = Network::new();
+        let counter = network.add_chip(chip(":a++ :c=:a goto 1"));
+        network.add_chip(chip(":b=:a goto 1"));
+        let token = CancelToken::new();
+        token.cancel();
+        assert_eq!(network.run_with_cancel(&token), 0);
+        assert_eq!(network.ticks(), 0);
+
+        // cancelled by the first chip on the third tick, before the second chip runs
+        token.reset();
+        let device = ir::CancelAt { token: token.clone(), at: Value::Num(3.into()) };
+        network.chip_mut(counter).attach_device(&Ident::global("c"), device);
+        assert_eq!(network.run_with_cancel(&token), 2);
+        assert_eq!(network.read(&Ident::global("b")), Value::Num(2.into()));
+        network.tick();
+        assert_eq!(network.ticks(), 3);
+        assert_eq!(network.read(&Ident::global("b")), Value::Num(3.into()));
+    }
+
+    #[test]
+    fn frame_budget() {
+        let mut network = Network::new();
+        let chips: Vec<_> = (0..3).map(|_| network.add_chip(chip(":n++ goto 1"))).collect();
+
+        // only one chip fits each frame, and the tick completes on the last
+        for (i, &id) in chips.iter().enumerate() {
+            let report = network.run_frame(Duration::ZERO);
+            assert_eq!(report.chips_run, 1);
+            assert_eq!(report.deferred, chips[i + 1..]);
+            assert_eq!(report.tick_completed, i == 2);
+            assert!(network.chip_cost(id).is_some());
+        }
+        assert_eq!(network.ticks(), 1);
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(3.into()));
+
+        network.run_frame(Duration::ZERO);
+        network.tick();
+        assert_eq!(network.ticks(), 2);
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(6.into()));
+
+        let report = network.run_frame(Duration::from_secs(60));
+        assert_eq!(report, FrameReport { chips_run: 3, tick_completed: true, deferred: vec![] });
+    }
+
+    #[test]
+    fn sleeping_chips() {
+        let mut network = Network::new();
+        network.set_sleep_field(Some(Ident::global("chipwait")));
+        let sleeper = network.add_chip(chip(":n++ :chipwait=2.5 goto 1"));
+        network.add_chip(chip(":seen=:chipwait goto 1"));
+
+        network.tick();
+        assert_eq!(network.chip_sleep(sleeper), 3);
+        network.run(3);
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(1.into()));
+        assert_eq!(network.chip_sleep(sleeper), 0);
+        network.tick();
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(2.into()));
+        // the other chip never sees it
+        assert_eq!(network.read(&Ident::global("seen")), Value::Num(0.into()));
+
+        network.set_sleep_field(None);
+        network.run(4);
+        assert_eq!(network.read(&Ident::global("n")), Value::Num(6.into()));
+        assert_eq!(network.read(&Ident::global("chipwait")), Value::Num("2.5".parse().unwrap()));
+    }
+
+    #[test]
+    fn field_annotations() {
+        let mut network = Network::new();
+        network.add_chip(chip(":alt+=:climb goto 1 // :alt (m) altitude"));
+        network.add_chip(chip(":climb=1 // :climb (m/s)\n// :alt (ft) ignored"));
+        let annotation = network.annotation(&Ident::global("alt")).unwrap();
+        assert_eq!(annotation.unit.as_deref(), Some("m"));
+        assert_eq!(annotation.description, "altitude");
+        let climb = network.annotation(&Ident::global("climb")).unwrap();
+        assert_eq!(climb.unit.as_deref(), Some("m/s"));
+        assert_eq!(network.annotation(&Ident::global("other")), None);
+    }
+
+    #[test]
+    fn namespaces() {
+        let mut network = Network::new();
+        network.add_ship_field(Ident::global("power"));
+        let program = |src| YololParser::default().parse(src).unwrap();
+        let engine = program(":speed=:power*2 :Status=\"on\"");
+        network.add_chip_in("engine", Default::default(), engine);
+        let pump = program(":speed=:power+1 if :speed then :power=5 end");
+        network.add_chip_in("pump", Default::default(), pump);
+        network.write(Ident::global("power"), Value::Num(3.into()));
+        network.tick();
+
+        assert_eq!(network.read(&Ident::global("engine_speed")), Value::Num(6.into()));
+        assert_eq!(network.read(&Ident::global("pump_speed")), Value::Num(4.into()));
+        assert_eq!(network.read(&Ident::global("power")), Value::Num(5.into()));
+        assert_eq!(network.read(&Ident::global("speed")), Value::default());
+        let status = network.resolve_field("engine", &Ident::global("status"));
+        assert_eq!(status.display_original().to_string(), ":engine_status");
+        assert_eq!(network.read(&status), Value::Str("on".into()));
+    }
+}
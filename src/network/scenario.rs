@@ -0,0 +1,241 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Set(Ident, Value),
+    Expect(Ident, Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    tick: usize,
+    /// The line of the scenario source this step came from, for error messages.
+    line: usize,
+    action: Action,
+}
+
+/// A script of field writes and expectations over time, run against a [`Network`].
+///
+/// Steps are separated by newlines or `;`, and each is either `:field=value` or
+/// `expect :field=value`, where the value is a number or a double quoted string. A step can
+/// start with `tick N:`, and applies at that tick along with the steps after it, until the
+/// next `tick`. Ticks count from when the scenario starts running, and `//` starts a comment.
+///
+/// ```text
+/// tick 0: :btn=1
+/// tick 5: expect :door=1; expect :status="open"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioFailure {
+    pub tick: usize,
+    /// The line of the scenario source holding the failed expectation.
+    pub line: usize,
+    pub field: Ident,
+    pub expected: Value,
+    pub found: Value,
+    /// Every field of the network when the expectation failed, sorted by name.
+    pub fields: Vec<(Ident, Value)>,
+}
+
+impl Display for ScenarioFailure {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        writeln!(
+            f,
+            "scenario line {}, tick {}: expected {} to be {}, but it was {}",
+            self.line,
+            self.tick,
+            self.field.display_original(),
+            self.expected,
+            self.found,
+        )?;
+        write!(f, "fields at tick {}:", self.tick)?;
+        for (field, value) in self.fields.iter() {
+            write!(f, "\n    {} = {}", field.display_original(), value)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScenarioFailure {}
+
+fn parse_field(s: &str) -> Result<(Ident, Value)> {
+    let (field, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected ':field=value', found '{}'", s))?;
+    let field: Ident = field.trim().parse()?;
+    ensure!(field.global, "'{}' isn't a field, since it doesn't start with ':'", field);
+    Ok((field, crate::ir::asm::parse_literal(value.trim())?))
+}
+
+fn parse_step(mut step: &str, tick: &mut Option<usize>, line: usize) -> Result<Step> {
+    if let Some(rest) = step.strip_prefix("tick") {
+        let (n, rest) = rest
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected ':' after the tick number"))?;
+        let n = n.trim().parse().context("bad tick number")?;
+        if let Some(prev) = *tick {
+            ensure!(n >= prev, "tick {} comes after tick {}", n, prev);
+        }
+        *tick = Some(n);
+        step = rest.trim();
+    }
+    let tick = tick.ok_or_else(|| anyhow!("steps must follow a 'tick N:'"))?;
+    let action = if let Some(rest) = step.strip_prefix("expect") {
+        let (field, value) = parse_field(rest)?;
+        Action::Expect(field, value)
+    } else if step.starts_with(':') {
+        let (field, value) = parse_field(step)?;
+        Action::Set(field, value)
+    } else {
+        bail!("expected ':field=value' or 'expect :field=value'")
+    };
+    Ok(Step { tick, line, action })
+}
+
+/// Splits a line of a scenario into its steps, dropping any comment. A `;` or `//` inside a
+/// string is part of the string.
+fn split_steps(line: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut start = 0;
+    let mut in_str = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_str = !in_str,
+            ';' if !in_str => {
+                steps.push(&line[start..i]);
+                start = i + 1;
+            },
+            '/' if !in_str && line[i..].starts_with("//") => {
+                steps.push(&line[start..i]);
+                return steps;
+            },
+            _ => (),
+        }
+    }
+    steps.push(&line[start..]);
+    steps
+}
+
+impl FromStr for Scenario {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+        let mut tick = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let line_no = i + 1;
+            for step in split_steps(line).into_iter().map(str::trim).filter(|s| !s.is_empty()) {
+                let step = parse_step(step, &mut tick, line_no)
+                    .with_context(|| format!("scenario line {}", line_no))?;
+                steps.push(step);
+            }
+        }
+
+        Ok(Scenario { steps })
+    }
+}
+
+impl Scenario {
+    /// Runs the network until the last step, stopping at the first failed expectation.
+    pub fn run(&self, network: &mut Network) -> Result<(), ScenarioFailure> {
+        let start = network.ticks();
+
+        for step in self.steps.iter() {
+            network.run(start + step.tick - network.ticks());
+            match &step.action {
+                Action::Set(field, value) => network.write(field.clone(), value.clone()),
+                Action::Expect(field, expected) => {
+                    let found = network.read(field);
+                    if found != *expected {
+                        let fields: Vec<_> = network
+                            .fields()
+                            .map(|(i, v)| (i.clone(), v.clone()))
+                            .collect();
+                        return Err(ScenarioFailure {
+                            tick: step.tick,
+                            line: step.line,
+                            field: field.clone(),
+                            expected: expected.clone(),
+                            found,
+                            fields,
+                        });
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    fn door_network() -> Network {
+        let mut network = Network::new();
+        let src = "if :Btn then :Door=1 :status=\"open\" end goto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        network.add_chip(IRMachine::from_ast(Default::default(), program));
+        network
+    }
+
+    #[test]
+    fn scenario_passes() -> Result<()> {
+        let scenario: Scenario = "
+            // nothing happens until the button is pressed
+            tick 0: expect :door=0
+            tick 2: :btn=1
+            tick 3: expect :door=1; expect :status=\"open\"
+        ".parse()?;
+        let mut network = door_network();
+        scenario.run(&mut network)?;
+        assert_eq!(network.ticks(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn scenario_failure_output() -> Result<()> {
+        let scenario: Scenario = "tick 0: :btn=1\ntick 1: expect :door=2".parse()?;
+        let failure = scenario.run(&mut door_network()).unwrap_err();
+        assert_eq!(failure.to_string(), [
+            "scenario line 2, tick 1: expected :door to be 2, but it was 1",
+            "fields at tick 1:",
+            "    :btn = 1",
+            "    :Door = 1",
+            "    :status = \"open\"",
+        ].join("\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn scenario_quoted_separators() -> Result<()> {
+        let scenario: Scenario = "tick 0: :s=\"a;b\"; :url=\"http://x\" // set both\n\
+            tick 1: expect :url=\"http://x\"".parse()?;
+        assert_eq!(scenario.steps.iter().map(|s| s.action.clone()).collect::<Vec<_>>(), [
+            Action::Set(Ident::global("s"), Value::Str("a;b".into())),
+            Action::Set(Ident::global("url"), Value::Str("http://x".into())),
+            Action::Expect(Ident::global("url"), Value::Str("http://x".into())),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn scenario_parse_errors() {
+        assert!(":a=1".parse::<Scenario>().is_err());
+        assert!("tick 3: :a=1; tick 2: :a=2".parse::<Scenario>().is_err());
+        assert!("tick 0: a=1".parse::<Scenario>().is_err());
+        assert!("tick 0: expect :a=".parse::<Scenario>().is_err());
+        assert!("tick 0: :a=\"x".parse::<Scenario>().is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{ensure, Result};
+use ir::Snapshot;
+use super::*;
+
+/// Everything a [`Network`] changes as it runs, from [`Network::snapshot`]: every chip, the
+/// fields, how far through a tick it is, and writes queued for later ticks.
+#[derive(Debug, Clone)]
+pub struct NetworkSnapshot {
+    chips: Vec<ChipSnapshot>,
+    fields: AHashMap<Ident, Value>,
+    ticks: usize,
+    next_chip: usize,
+    inputs: AHashMap<Ident, VecDeque<(usize, Value)>>,
+}
+
+#[derive(Debug, Clone)]
+struct ChipSnapshot {
+    machine: Snapshot,
+    idle_ticks: usize,
+    asleep: usize,
+}
+
+impl Network {
+    /// Captures the whole network, to go back to with [`Network::restore`]. Events the host
+    /// hasn't taken and how long chips take to run aren't included.
+    pub fn snapshot(&self) -> NetworkSnapshot {
+        NetworkSnapshot {
+            chips: self.chips
+                .iter()
+                .map(|chip| ChipSnapshot {
+                    machine: chip.machine.snapshot(),
+                    idle_ticks: chip.idle_ticks,
+                    asleep: chip.asleep,
+                })
+                .collect(),
+            fields: self.fields.clone(),
+            ticks: self.ticks,
+            next_chip: self.next_chip,
+            inputs: self.inputs.clone(),
+        }
+    }
+
+    /// Rolls back to a [`NetworkSnapshot`] of this network, or a clone of it. From there, it
+    /// runs exactly as it did after the snapshot, given the same writes from the host. Fails,
+    /// without changing anything, if chips have been added or recompiled since.
+    pub fn restore(&mut self, snapshot: &NetworkSnapshot) -> Result<()> {
+        ensure!(snapshot.chips.len() == self.chips.len(), "snapshot has a different chip count");
+        for (chip, saved) in self.chips.iter().zip(snapshot.chips.iter()) {
+            chip.machine.check_snapshot(&saved.machine)?;
+        }
+        for (chip, saved) in self.chips.iter_mut().zip(snapshot.chips.iter()) {
+            chip.machine.restore(&saved.machine)?;
+            chip.idle_ticks = saved.idle_ticks;
+            chip.asleep = saved.asleep;
+        }
+        self.fields.clone_from(&snapshot.fields);
+        self.ticks = snapshot.ticks;
+        self.next_chip = snapshot.next_chip;
+        self.inputs.clone_from(&snapshot.inputs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn replays_from_snapshot() {
+        let mut network = Network::new();
+        let chip = |src: &str| {
+            IRMachine::from_ast(Default::default(), YololParser::default().parse(src).unwrap())
+        };
+        network.add_chip(chip(":a+=1+:b%3 :s=\"x\"+:a goto 1"));
+        network.add_chip(chip("if :a%4==0 then :b+=:a end :zz=2\ngoto 1"));
+        network.set_sleep_field(Some(Ident::global("zz")));
+        network.queue_write(12, Ident::global("b"), Value::Num(100.into()));
+        network.run(5);
+        network.run_frame(Duration::ZERO);
+
+        let snapshot = network.snapshot();
+        let record = |network: &mut Network| {
+            (0..10)
+                .map(|_| {
+                    network.tick();
+                    network.fields().map(|(f, v)| (f.clone(), v.clone())).collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = record(&mut network);
+        network.restore(&snapshot).unwrap();
+        assert_eq!(network.ticks(), 5);
+        assert_eq!(record(&mut network), expected);
+
+        let mut bigger = network.clone();
+        bigger.add_chip(chip(":c=1"));
+        assert!(bigger.restore(&snapshot).is_err());
+    }
+}
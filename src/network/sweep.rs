@@ -0,0 +1,175 @@
+use std::io::Write;
+use super::*;
+
+/// Runs copies of a [`Network`] from many starting values of some fields, recording others
+/// after a number of ticks, for tuning constants or checking a controller over its whole
+/// input range.
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    inputs: Vec<(Ident, Vec<Value>)>,
+    outputs: Vec<Ident>,
+    ticks: usize,
+    threads: usize,
+}
+
+/// What a [`Sweep`] found, a row per run with the inputs then the outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SweepResult {
+    pub columns: Vec<Ident>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Sweep {
+    /// Runs each copy for `ticks` ticks, on one thread.
+    pub fn new(ticks: usize) -> Self {
+        Sweep {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            ticks,
+            threads: 1,
+        }
+    }
+
+    /// Tries each of `values` for `field`, which is written before the first tick.
+    pub fn input(&mut self, field: Ident, values: impl IntoIterator<Item = Value>) -> &mut Self {
+        self.inputs.push((field, values.into_iter().collect()));
+        self
+    }
+
+    /// Records `field` after the last tick.
+    pub fn output(&mut self, field: Ident) -> &mut Self {
+        self.outputs.push(field);
+        self
+    }
+
+    /// Splits the runs between this many threads. Results come back in the same order.
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Runs every combination of inputs, varying the last input fastest.
+    pub fn grid(&self, network: &Network) -> SweepResult {
+        let runs = self.inputs.iter().map(|(_, values)| values.len()).product();
+        let combos = (0..runs).map(|mut run| {
+            let mut picks = vec![0; self.inputs.len()];
+            for (pick, (_, values)) in picks.iter_mut().zip(self.inputs.iter()).rev() {
+                *pick = run % values.len();
+                run /= values.len();
+            }
+            picks
+        });
+        self.run(network, combos.collect())
+    }
+
+    /// Runs `samples` random combinations of inputs. The same seed picks the same ones.
+    pub fn sample(&self, network: &Network, samples: usize, seed: u64) -> SweepResult {
+        // like the grid, there are no combinations if an input has no values
+        let empty = self.inputs.iter().any(|(_, values)| values.is_empty());
+        let samples = if empty { 0 } else { samples };
+        // xorshift64*, which is plenty for picking from short lists
+        let mut state = seed | 1;
+        let mut next = move || {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        };
+        let combos = (0..samples)
+            .map(|_| {
+                self.inputs
+                    .iter()
+                    .map(|(_, values)| (next() % values.len() as u64) as usize)
+                    .collect()
+            })
+            .collect();
+        self.run(network, combos)
+    }
+
+    fn run(&self, network: &Network, combos: Vec<Vec<usize>>) -> SweepResult {
+        let run_one = |picks: &Vec<usize>| {
+            let mut copy = network.clone();
+            let mut row: Vec<_> = self.inputs
+                .iter()
+                .zip(picks)
+                .map(|((field, values), &pick)| {
+                    copy.write(field.clone(), values[pick].clone());
+                    values[pick].clone()
+                })
+                .collect();
+            copy.run(self.ticks);
+            row.extend(self.outputs.iter().map(|field| copy.read(field)));
+            row
+        };
+        let chunk = combos.len().div_ceil(self.threads).max(1);
+        let rows = std::thread::scope(|scope| {
+            let handles: Vec<_> = combos
+                .chunks(chunk)
+                .map(|combos| scope.spawn(move || combos.iter().map(run_one).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        SweepResult {
+            columns: self.inputs.iter().map(|(f, _)| f).chain(&self.outputs).cloned().collect(),
+            rows,
+        }
+    }
+}
+
+impl SweepResult {
+    /// Writes a header of field names, then a line per row. Strings are quoted.
+    pub fn write_csv(&self, sink: &mut impl Write) -> std::io::Result<()> {
+        let header: Vec<_> = self.columns
+            .iter()
+            .map(|field| field.display_original().to_string())
+            .collect();
+        writeln!(sink, "{}", header.join(","))?;
+        for row in self.rows.iter() {
+            let cells: Vec<_> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Num(n) => n.to_string(),
+                    Value::Str(s) => format!("\"{}\"", s.to_string().replace('"', "\"\"")),
+                })
+                .collect();
+            writeln!(sink, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn sweeps() {
+        let mut network = Network::new();
+        let program = YololParser::default().parse(":y+=:x*:k :s=\"got \"+:y goto 1").unwrap();
+        network.add_chip(IRMachine::from_ast(Default::default(), program));
+        let nums = |ns: &[i64]| ns.iter().map(|&n| Value::Num(n.into())).collect::<Vec<_>>();
+        let mut sweep = Sweep::new(2);
+        sweep
+            .input(Ident::global("x"), nums(&[1, 2]))
+            .input(Ident::global("k"), nums(&[3, 4, 5]))
+            .output(Ident::global("y"))
+            .output(Ident::global("s"));
+
+        let grid = sweep.grid(&network);
+        assert_eq!(grid.rows.len(), 6);
+        assert_eq!(grid.rows[1], [nums(&[1, 4, 8]), vec![Value::Str("got 8".into())]].concat());
+        assert_eq!(grid.rows[5][..3], nums(&[2, 5, 20]));
+        let mut csv = Vec::new();
+        grid.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next(), Some(":x,:k,:y,:s"));
+        assert_eq!(csv.lines().nth(1), Some("1,3,6,\"got 6\""));
+
+        assert_eq!(sweep.clone().threads(4).grid(&network), grid);
+        let sample = sweep.sample(&network, 20, 7);
+        assert_eq!(sample.rows.len(), 20);
+        assert!(sample.rows.iter().all(|row| grid.rows.contains(row)));
+        assert_eq!(sweep.threads(3).sample(&network, 20, 7), sample);
+    }
+}
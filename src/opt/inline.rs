@@ -0,0 +1,209 @@
+use parser::*;
+use super::*;
+use minify::compact;
+use source_map::statement_extents;
+
+/// The line index a `goto` always lands on, or `None` if it isn't a whole number in range.
+fn constant_target(target: &Expr, lines: usize) -> Option<usize> {
+    match target {
+        Expr::Number(n) if n.0 % 1000 == 0 && (1..=lines as i64).contains(&(n.0 / 1000)) => {
+            Some((n.0 / 1000 - 1) as usize)
+        },
+        _ => None,
+    }
+}
+
+fn expr_can_error(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binop(_, Binop::Mul | Binop::Div | Binop::Mod | Binop::Pow | Binop::Log, _) =>
+            true,
+        Expr::Binop(l, _, r) => expr_can_error(l) || expr_can_error(r),
+        Expr::Unop(Unop::Not, e) => expr_can_error(e),
+        Expr::Unop(..) => true,
+        // `--` on an empty string
+        Expr::Incdec(incdec) => !incdec.inc,
+        Expr::Ident(_) | Expr::Number(_) | Expr::String(_) => false,
+    }
+}
+
+/// Whether the statements might cause a runtime error, conservatively assuming any variable
+/// could hold a string.
+fn can_error(stmts: &[Statement]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Statement::Goto(target) => !matches!(target, Expr::Number(_)),
+        Statement::Ite(cond, t, e) => expr_can_error(cond) || can_error(t) || can_error(e),
+        Statement::Incdec(incdec) => !incdec.inc,
+        Statement::Assign(_, None | Some(AssignOp::Add | AssignOp::Sub), expr) =>
+            expr_can_error(expr),
+        Statement::Assign(..) => true,
+    })
+}
+
+/// Adds the line index of every `goto` in `stmts` to `targets`, returning false if any could go
+/// to more than one line.
+fn goto_targets(stmts: &[Statement], lines: usize, targets: &mut Vec<usize>) -> bool {
+    stmts.iter().all(|stmt| match stmt {
+        Statement::Goto(target) => constant_target(target, lines)
+            .map(|line| targets.push(line))
+            .is_some(),
+        Statement::Ite(_, t, e) =>
+            goto_targets(t, lines, targets) && goto_targets(e, lines, targets),
+        _ => true,
+    })
+}
+
+/// The line index `line` ends by jumping to, if its last statement is a constant `goto`.
+fn returns_to(line: &Line, lines: usize) -> Option<usize> {
+    match line.last() {
+        Some(Statement::Goto(target)) => constant_target(target, lines),
+        _ => None,
+    }
+}
+
+/// Finds a line reached only by the `goto` ending one other line, which itself ends by jumping
+/// on, and moves it onto the end of that line. Returns the lines as `(caller, inlined)`.
+fn find_inlinable(program: &Program, max_line_length: usize) -> Option<(usize, usize)> {
+    let lines = program.len();
+    let mut targets = Vec::new();
+    for line in program.iter() {
+        if !goto_targets(line, lines, &mut targets) {
+            // a computed goto could land anywhere
+            return None;
+        }
+    }
+
+    // whether running each line can carry on to the next, by falling off the end or erroring
+    let falls_through: Vec<_> = program
+        .iter()
+        .map(|line| returns_to(line, lines).is_none() || can_error(line))
+        .collect();
+    let mut reachable = vec![false; lines];
+    for i in 0..lines {
+        reachable[i] = i == 0 || targets.contains(&i) || (reachable[i - 1] && falls_through[i - 1]);
+    }
+
+    (1..lines).find_map(|helper| {
+        let line = &program[helper];
+        if reachable[helper - 1] && falls_through[helper - 1] {
+            return None;
+        }
+        if targets.iter().filter(|&&t| t == helper).count() != 1 {
+            return None;
+        }
+        if returns_to(line, lines).is_none() || can_error(line) || line.assert.is_some() {
+            return None;
+        }
+        let caller = (0..lines)
+            .find(|&c| c != helper && returns_to(&program[c], lines) == Some(helper))?;
+        if program[caller].assert.is_some() {
+            return None;
+        }
+
+        let mut merged = program[caller].clone();
+        merged.pop();
+        merged.extend(line.iter().cloned());
+        (compact(&merged).len() <= max_line_length).then_some((caller, helper))
+    })
+}
+
+/// Inlines lines only reached by one `goto`, which end by jumping elsewhere, into the line with
+/// that `goto` whenever the result fits in `max_line_length` characters once minified. The
+/// inlined lines are left empty, so nothing else moves.
+///
+/// The inlined code runs in the same tick as the line that jumped to it rather than the next,
+/// so this only takes lines which can't cause a runtime error, and which nothing can fall into
+/// from the line above. Does nothing to programs with computed gotos.
+pub fn inline_goto_lines(program: &Program, max_line_length: usize) -> Program {
+    inline_goto_lines_mapped(program, max_line_length).0
+}
+
+/// [`inline_goto_lines`], also mapping the result back to `program`.
+pub fn inline_goto_lines_mapped(program: &Program, max_line_length: usize) -> (Program, SourceMap) {
+    span!(DEBUG, "pass", name = "inline_goto_lines");
+    let mut origins = statement_extents(program);
+    let mut program = program.clone();
+    while let Some((caller, helper)) = find_inlinable(&program, max_line_length) {
+        let inlined = std::mem::take(&mut program.lines[helper].stmts);
+        program.lines[caller].pop();
+        program.lines[caller].extend(inlined);
+        let inlined = std::mem::take(&mut origins[helper]);
+        origins[caller].pop();
+        origins[caller].extend(inlined);
+    }
+    let map = SourceMap::new(&program, origins);
+    (program, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use simple_interp::SimpleInterp;
+    use super::*;
+
+    fn program(src: &str) -> Program {
+        YololParser::unrestricted().parse(src).unwrap()
+    }
+
+    #[test]
+    fn inlines_helper_lines() {
+        let src = ":a=1 goto 3\n:b=2 goto 1\n:c=:a+1 goto 4\n:d=:c goto 1";
+        let inlined = inline_goto_lines(&program(src), 70);
+        assert_eq!(inlined, program(":a=1 :c=:a+1 :d=:c goto 1\n:b=2 goto 1\n\n"));
+
+        let mut before = SimpleInterp::new(program(src));
+        let mut after = SimpleInterp::new(inlined);
+        before.step_lines(3);
+        after.step_line();
+        assert_eq!(before.values(), after.values());
+
+        // too long once merged
+        assert_eq!(inline_goto_lines(&program(src), 12), program(src));
+    }
+
+    #[test]
+    fn source_map() {
+        let src = ":a=1 goto 3\n:b=2 goto 1\n:c=:a+1 goto 4\n:d=:c goto 1";
+        let (inlined, map) = inline_goto_lines_mapped(&program(src), 70);
+        let at = |line, col| Position { line, col };
+        assert_eq!(inlined[0].to_string(), ":a = 1 :c = :a + 1 :d = :c goto 1");
+        assert_eq!(map.original(at(1, 1)), Some(at(1, 1)));
+        assert_eq!(map.original(at(1, 8)), Some(at(3, 1)));
+        assert_eq!(map.original(at(1, 18)), Some(at(3, 11)));
+        assert_eq!(map.original(at(1, 28)), Some(at(4, 9)));
+        assert_eq!(map.original(at(2, 1)), Some(at(2, 1)));
+        assert_eq!(map.original(at(3, 1)), None);
+        assert_eq!(map.original(at(1, 50)), None);
+        assert_eq!(map.generated(at(3, 11)), Some(at(1, 18)));
+        // the caller's goto was dropped
+        assert_eq!(map.generated(at(1, 8)), None);
+
+        assert_eq!(SourceMap::identity(&program(src)).then(&map), map);
+        assert_eq!(map.then(&SourceMap::identity(&inlined)), map);
+
+        // columns in the source as written, rather than as printed
+        let spaced = ":a = 1   goto 3\n:b=2 goto 1\n  :c = :a+1 goto 4\n:d=:c goto 1";
+        let printed = SourceMap::printed(&program(spaced), spaced).unwrap();
+        let map = printed.then(&inline_goto_lines_mapped(&program(spaced), 70).1);
+        assert_eq!(map.original(at(1, 8)), Some(at(3, 3)));
+        assert_eq!(map.original(at(1, 18)), Some(at(3, 11)));
+        assert_eq!(map.original(at(1, 28)), Some(at(4, 7)));
+    }
+
+    #[test]
+    fn leaves_reachable_lines() {
+        for src in [
+            // reached by two gotos
+            ":a=1 goto 3\n:b=2 goto 3\n:c=1 goto 1",
+            // reached by falling off the line above
+            "if :x then goto 2 end goto 3\n:b=2\n:c=1 goto 1",
+            // reached when the line above errors
+            "if :x then goto 2 end goto 3\n:b=1/:b goto 1\n:c=1 goto 1",
+            // errors itself, moving on to the next line rather than the caller's
+            ":a=1 goto 3\n:b=2 goto 1\n:c=1/:a goto 1",
+            // might be reached by a computed goto
+            ":a=1 goto 3\n:b=2 goto :a\n:c=1 goto 1",
+        ] {
+            assert_eq!(inline_goto_lines(&program(src), 70), program(src), "{}", src);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use ahash::{AHashMap, AHashSet};
+use anyhow::Result;
+use parser::*;
+use super::*;
+
+/// What [`minify`] made of some source.
+#[derive(Debug, Clone)]
+pub struct Minified {
+    pub source: String,
+    /// The new name of every local. Globals keep theirs.
+    pub names: AHashMap<Ident, Ident>,
+    /// The length of every line which held anything, comments included, before and after.
+    pub lines: Vec<LineBudget>,
+    /// Maps `source` back to what was minified.
+    pub map: SourceMap,
+}
+
+impl Minified {
+    /// Characters saved over the whole program, negative if it grew.
+    pub fn saved(&self) -> isize {
+        self.lines.iter().map(|line| line.before as isize - line.after as isize).sum()
+    }
+}
+
+fn assign_op(op: Binop) -> Option<AssignOp> {
+    Some(match op {
+        Binop::Add => AssignOp::Add,
+        Binop::Sub => AssignOp::Sub,
+        Binop::Mul => AssignOp::Mul,
+        Binop::Div => AssignOp::Div,
+        Binop::Mod => AssignOp::Mod,
+        Binop::Pow => AssignOp::Pow,
+        _ => return None,
+    })
+}
+
+/// Rewrites `a = a op b` as `a op= b`, which does exactly the same.
+fn compound_assigns(stmts: &mut [Statement]) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Ite(_, t, e) => {
+                compound_assigns(t);
+                compound_assigns(e);
+            },
+            Statement::Assign(ident, op @ None, expr) => {
+                let Expr::Binop(l, binop, r) = expr else {
+                    continue;
+                };
+                if let (Expr::Ident(l), Some(new)) = (&**l, assign_op(*binop)) {
+                    if l == ident {
+                        *op = Some(new);
+                        *expr = std::mem::replace(&mut **r, Expr::Number(0.into()));
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Every name which isn't a keyword, shortest first: `a` to `z`, then `aa`, `ab` and so on.
+fn short_names() -> impl Iterator<Item = String> {
+    (0..)
+        .map(|mut i: usize| {
+            let mut name = Vec::new();
+            loop {
+                name.push(b'a' + (i % 26) as u8);
+                if i < 26 {
+                    break;
+                }
+                i = i / 26 - 1;
+            }
+            name.reverse();
+            String::from_utf8(name).unwrap()
+        })
+        .filter(|name| !KEYWORDS.contains(&name.as_str()))
+}
+
+/// Gives each of `locals`, the most used first, the shortest name free. Those which would get a
+/// name longer than their own keep theirs instead, taking it out of the running for the rest.
+fn shorten(locals: &[Ident]) -> AHashMap<Ident, Ident> {
+    let mut kept = AHashSet::new();
+    loop {
+        let mut free = short_names().filter(|name| !kept.contains(name));
+        let mut names = AHashMap::new();
+        let mut keeping = Vec::new();
+        for ident in locals {
+            if kept.contains(&ident.name) {
+                names.insert(ident.clone(), ident.clone());
+                continue;
+            }
+            let name = free.next().unwrap();
+            if name.len() > ident.name.len() {
+                keeping.push(ident.name.clone());
+            }
+            names.insert(ident.clone(), Ident::local(&name));
+        }
+        if keeping.is_empty() {
+            return names;
+        }
+        kept.extend(keeping);
+    }
+}
+
+/// Prints `line` with every space it can do without, checking each removal by parsing the
+/// line again. Spaces between letters and digits stay, since the game reads `ifa` as one name
+/// where the parser here is more lenient. This is how long a line really is on a chip.
+pub(crate) fn compact(line: &Line) -> String {
+    let parses_same = |text: &str| {
+        YololParser::unrestricted()
+            .parse(text)
+            .is_ok_and(|program| program[0].stmts == line.stmts)
+    };
+    let mut text = line.to_string();
+    let mut i = 0;
+    let word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+    while let Some(offset) = text[i..].find(' ') {
+        i += offset;
+        if word(text[..i].chars().next_back()) && word(text[i + 1..].chars().next()) {
+            i += 1;
+            continue;
+        }
+        let candidate = format!("{}{}", &text[..i], &text[i + 1..]);
+        if parses_same(&candidate) {
+            text = candidate;
+        } else {
+            i += 1;
+        }
+    }
+    text
+}
+
+/// Shrinks source to fit more on a chip. Every local gets the shortest name free, the most
+/// used first, unless its own is shorter, `a = a + b` becomes `a += b`, and comments and any
+/// space the parser doesn't need are dropped. Line numbers don't change, so gotos still match.
+/// Fails if `source` doesn't parse.
+///
+/// Globals keep their names, since other chips share them by name.
+pub fn minify(source: &str, max_line_length: usize) -> Result<Minified> {
+    span!(DEBUG, "pass", name = "minify");
+    let mut program = YololParser::unrestricted().parse(source)?;
+    for line in program.iter_mut() {
+        compound_assigns(line);
+    }
+
+    let mut uses = AHashMap::new();
+    program.clone().for_each_ident_mut(|ident| {
+        if !ident.global {
+            *uses.entry(ident.clone()).or_insert(0usize) += 1;
+        }
+    });
+    let mut locals: Vec<_> = uses.into_iter().collect();
+    locals.sort_unstable_by(|(a, a_uses), (b, b_uses)| b_uses.cmp(a_uses).then(a.cmp(b)));
+    let locals: Vec<_> = locals.into_iter().map(|(ident, _)| ident).collect();
+    let names = shorten(&locals);
+    let program = rename(&program, &names)?;
+
+    // the parser pads programs out to a full chip
+    let used = program.iter().rposition(|line| !line.is_empty()).map_or(0, |i| i + 1);
+    let minified: Vec<_> = program[..used].iter().map(compact).collect();
+    let lines = source
+        .lines()
+        .zip(minified.iter())
+        .enumerate()
+        .filter(|(_, (before, _))| !before.is_empty())
+        .map(|(i, (before, after))| LineBudget {
+            line: i + 1,
+            before: before.len(),
+            after: after.len(),
+            fits: after.len() <= max_line_length,
+        })
+        .collect();
+    let minified = minified.join("\n");
+    let map = SourceMap::between(&minified, source)?;
+    Ok(Minified {
+        source: minified,
+        names,
+        lines,
+        map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minifies_source() {
+        let src = "counter = counter + 1   // tick\n\
+            if counter > 10 then counter = 0 else total = total + counter end\n\
+            // just a comment\n\
+            :out = \"a b\" + total * 2 goto 1";
+        let minified = minify(src, 20).unwrap();
+        assert_eq!(
+            minified.source,
+            "a+=1\nif a>10 then a=0 else b+=a end\n\n:out=\"a b\"+b*2 goto 1",
+        );
+        assert_eq!(minified.names[&Ident::local("counter")], Ident::local("a"));
+        assert_eq!(minified.lines, [
+            LineBudget { line: 1, before: 31, after: 4, fits: true },
+            LineBudget { line: 2, before: 65, after: 30, fits: false },
+            LineBudget { line: 3, before: 17, after: 0, fits: true },
+            LineBudget { line: 4, before: 31, after: 21, fits: false },
+        ]);
+        assert_eq!(minified.saved(), 89);
+        assert_eq!(short_names().nth(26).unwrap(), "aa");
+        assert_eq!(short_names().nth(26 * 9 + 5).unwrap(), "ig");
+        let at = |line, col| Position { line, col };
+        assert_eq!(minified.map.original(at(4, 16)), Some(at(4, 26)));
+        assert_eq!(minified.map.generated(at(2, 1)), Some(at(2, 1)));
+
+        let parse = |s: &str| YololParser::unrestricted().parse(s).unwrap();
+        let expected = rename(&parse(src), &minified.names).unwrap();
+        let mut machine = ir::IRMachine::from_ast(Default::default(), parse(&minified.source));
+        let mut reference = ir::IRMachine::from_ast(Default::default(), expected);
+        machine.step_repeat(50);
+        reference.step_repeat(50);
+        let out = Ident::global("out");
+        assert_eq!(machine.get_ident_value(&out), reference.get_ident_value(&out));
+        assert!(minify("a = = 1", 70).is_err());
+    }
+
+    #[test]
+    fn never_lengthens_names() {
+        // 26 locals take every single letter, leaving `q` a longer name than its own
+        let mut src: String = (0..26).map(|i| format!("long{i}=long{i}+1\n")).collect();
+        src.push_str("q=1");
+        let minified = minify(&src, 70).unwrap();
+        assert_eq!(minified.names[&Ident::local("q")], Ident::local("q"));
+        let names: AHashSet<_> = minified.names.values().collect();
+        assert_eq!(names.len(), 27);
+        assert!(minified.lines.iter().all(|line| line.after <= line.before));
+        assert!(minified.saved() > 0);
+    }
+}
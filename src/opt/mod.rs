@@ -0,0 +1,126 @@
+use parser::Program;
+use ir::IRMachine;
+use super::*;
+pub use inline::{inline_goto_lines, inline_goto_lines_mapped};
+pub use source_map::{Position, SourceMap};
+pub use rename::{rename, rename_budget, LineBudget};
+pub use minify::{minify, Minified};
+pub(crate) use minify::compact;
+
+mod inline;
+mod source_map;
+mod rename;
+mod minify;
+
+/// What an optimizer pass should make smaller. Rewrites often trade one for another, e.g.
+/// shortening a line by recomputing an expression instead of storing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Objective {
+    /// Characters of source, which is what limits how much fits on a chip.
+    #[default]
+    Characters,
+    /// Instructions run per tick, estimated from the instructions making up every line.
+    Instructions,
+    /// Lines holding any code.
+    Lines,
+}
+
+/// How expensive a program is by every measure an [`Objective`] can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Cost {
+    /// Characters of every line without any space it doesn't need, as on a chip.
+    pub characters: usize,
+    /// The instructions of every line added up, counting both sides of each branch.
+    pub instructions: usize,
+    pub lines: usize,
+}
+
+impl Cost {
+    pub fn of(program: &Program) -> Self {
+        let characters = program.iter().map(|line| minify::compact(line).len()).sum();
+        let lines = program
+            .iter()
+            .rposition(|line| !line.is_empty())
+            .map_or(0, |i| i + 1);
+        let machine = IRMachine::from_ast(Default::default(), program.clone());
+        let instructions = machine.line_instruction_counts().into_iter().sum();
+        Cost {
+            characters,
+            instructions,
+            lines,
+        }
+    }
+
+    /// The cost of a compiled program, by the source [`IRMachine::decompile`] gives for it, so
+    /// this should be measured before running. Code which doesn't decompile counts as too many
+    /// characters and lines to ever be chosen.
+    pub fn of_machine(machine: &IRMachine) -> Self {
+        let instructions = machine.line_instruction_counts().into_iter().sum();
+        let Ok(program) = machine.decompile() else {
+            return Cost { characters: usize::MAX, instructions, lines: usize::MAX };
+        };
+        let characters = program.iter().map(|line| minify::compact(line).len()).sum();
+        let lines = program
+            .iter()
+            .rposition(|line| !line.is_empty())
+            .map_or(0, |i| i + 1);
+        Cost {
+            characters,
+            instructions,
+            lines,
+        }
+    }
+}
+
+impl Objective {
+    /// Costs compare lexicographically by this key. The other measures break ties, so that a
+    /// rewrite which doesn't help the objective still can't make them worse for nothing.
+    pub const fn key(self, cost: &Cost) -> [usize; 3] {
+        match self {
+            Objective::Characters => [cost.characters, cost.lines, cost.instructions],
+            Objective::Instructions => [cost.instructions, cost.characters, cost.lines],
+            Objective::Lines => [cost.lines, cost.characters, cost.instructions],
+        }
+    }
+
+    /// Whether a pass should keep `candidate` over `current`.
+    pub fn prefers(self, candidate: &Cost, current: &Cost) -> bool {
+        self.key(candidate) < self.key(current)
+    }
+
+    /// Returns whichever program is cheaper, keeping `current` on a tie.
+    pub fn choose(self, current: Program, candidate: Program) -> Program {
+        if self.prefers(&Cost::of(&candidate), &Cost::of(&current)) {
+            candidate
+        } else {
+            current
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    fn program(src: &str) -> Program {
+        YololParser::unrestricted().parse(src).unwrap()
+    }
+
+    #[test]
+    fn objectives_disagree() {
+        // same result, but the first stores `b*b` and the second recomputes it on one line
+        let stored = program("t=b*b\na=t+t c=t");
+        let recomputed = program("a=b*b+b*b c=b*b");
+        let (stored_cost, recomputed_cost) = (Cost::of(&stored), Cost::of(&recomputed));
+        assert_eq!(stored_cost.lines, 2);
+        assert_eq!(recomputed_cost.lines, 1);
+        assert!(recomputed_cost.instructions > stored_cost.instructions);
+
+        assert!(Objective::Lines.prefers(&recomputed_cost, &stored_cost));
+        assert!(Objective::Instructions.prefers(&stored_cost, &recomputed_cost));
+        assert_eq!(Objective::Lines.choose(stored.clone(), recomputed.clone()), recomputed);
+        assert_eq!(Objective::Instructions.choose(stored.clone(), recomputed), stored);
+        assert_eq!(Objective::Characters.choose(stored.clone(), stored.clone()), stored);
+    }
+}
@@ -0,0 +1,112 @@
+use ahash::AHashMap;
+use anyhow::{bail, Result};
+use minify::compact;
+use parser::*;
+use super::*;
+
+/// Renames each variable which is a key of `names` to its value. Renaming a global changes
+/// which field it shares with other chips, so those usually map to themselves. Fails if two
+/// different variables would end up with the same name, which would merge them.
+pub fn rename(program: &Program, names: &AHashMap<Ident, Ident>) -> Result<Program> {
+    span!(DEBUG, "pass", name = "rename");
+    let mut program = program.clone();
+
+    let mut sources: Vec<_> = names.keys().cloned().collect();
+    program.clone().for_each_ident_mut(|ident| sources.push(ident.clone()));
+    let mut owners = AHashMap::new();
+    for source in sources {
+        let target = names.get(&source).unwrap_or(&source).clone();
+        let owner = owners.entry(target.clone()).or_insert_with(|| source.clone());
+        if *owner != source {
+            bail!("renaming would give both `{}` and `{}` the name `{}`", owner, source, target);
+        }
+    }
+
+    program.for_each_ident_mut(|ident| {
+        if let Some(name) = names.get(ident) {
+            *ident = name.clone();
+        }
+    });
+    Ok(program)
+}
+
+/// How long a line is before and after renaming, from [`rename_budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineBudget {
+    /// 1-based.
+    pub line: usize,
+    pub before: usize,
+    pub after: usize,
+    /// Whether the renamed line still fits on a chip.
+    pub fits: bool,
+}
+
+/// Reports the length of every line holding code before and after [`rename`], so a chip copy
+/// with short names can be kept in step with a readable copy, which might not fit. Lines are
+/// measured without any space they don't need, as [`minify`] would leave them. Fails as
+/// [`rename`] does.
+pub fn rename_budget(
+    program: &Program,
+    names: &AHashMap<Ident, Ident>,
+    max_line_length: usize,
+) -> Result<Vec<LineBudget>> {
+    let renamed = rename(program, names)?;
+    Ok(program
+        .iter()
+        .zip(renamed.iter())
+        .enumerate()
+        .filter(|(_, (line, _))| !line.is_empty())
+        .map(|(i, (before, after))| {
+            let after = compact(after).len();
+            LineBudget {
+                line: i + 1,
+                before: compact(before).len(),
+                after,
+                fits: after <= max_line_length,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::YololParser;
+    use super::*;
+
+    #[test]
+    fn budget_after_renaming() {
+        let program = YololParser::default().parse("a=1 b=a*2\n\n:out=b goto 1").unwrap();
+        let names: AHashMap<_, _> = [
+            (Ident::local("a"), Ident::local("counter")),
+            (Ident::local("b"), Ident::local("twiceTheCounter")),
+        ].into_iter().collect();
+
+        let renamed = rename(&program, &names).unwrap();
+        assert_eq!(renamed[0].to_string(), "counter = 1 twicethecounter = counter * 2");
+        assert_eq!(renamed[2].to_string(), ":out = twicethecounter goto 1");
+
+        let budget = rename_budget(&program, &names, 30).unwrap();
+        assert_eq!(budget, [
+            LineBudget { line: 1, before: 9, after: 35, fits: false },
+            LineBudget { line: 3, before: 13, after: 27, fits: true },
+        ]);
+    }
+
+    #[test]
+    fn rename_clashes() {
+        let program = YololParser::default().parse("a=1 b=2 :out=a+b").unwrap();
+        let names = |pairs: &[(&str, &str)]| -> AHashMap<_, _> {
+            pairs.iter().map(|(a, b)| (Ident::local(a), Ident::local(b))).collect()
+        };
+
+        let err = rename(&program, &names(&[("a", "b")])).unwrap_err();
+        assert_eq!(err.to_string(), "renaming would give both `a` and `b` the name `b`");
+        assert!(rename(&program, &names(&[("a", "c"), ("b", "c")])).is_err());
+        assert!(rename(&program, &names(&[("x", "c"), ("y", "c")])).is_err());
+        assert!(rename_budget(&program, &names(&[("a", "b")]), 70).is_err());
+
+        let swapped = rename(&program, &names(&[("a", "b"), ("b", "a")])).unwrap();
+        assert_eq!(swapped[0].to_string(), "b = 1 a = 2 :out = b + a");
+        assert!(rename(&program, &names(&[("a", "a"), ("x", "y")])).is_ok());
+    }
+}
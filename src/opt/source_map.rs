@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use anyhow::{ensure, Result};
+use parser::*;
+use super::*;
+
+/// A place in a program, both 1-based. Columns count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// Where a top level statement starts, and how many characters it takes up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Extent {
+    start: Position,
+    len: usize,
+}
+
+/// Where each statement of a rewritten program came from, so errors in the rewritten program
+/// can point at the code that was written.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourceMap {
+    /// `(generated, original)` for every top level statement, sorted by generated. The two can
+    /// be spaced differently, so only their starts and ends are sure to line up.
+    mappings: Vec<(Extent, Extent)>,
+}
+
+/// Every top level statement in `program` as it's printed, line by line.
+pub(super) fn statement_extents(program: &Program) -> Vec<Vec<Extent>> {
+    program
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            line.iter()
+                .scan(1, |col, stmt| {
+                    let len = stmt.to_string().len();
+                    let start = Position { line: i + 1, col: *col };
+                    *col += len + 1;
+                    Some(Extent { start, len })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Every top level statement in `source` as it's written, line by line.
+fn source_extents(source: &str) -> Result<Vec<Vec<Extent>>> {
+    let extents = statement_spans(source)?
+        .into_iter()
+        .map(|line| {
+            line.into_iter()
+                .map(|span| Extent {
+                    start: Position { line: span.line, col: span.col },
+                    len: source[span.start..span.end].chars().count(),
+                })
+                .collect()
+        })
+        .collect();
+    Ok(extents)
+}
+
+/// The mapping for the statement containing `at`, and how far into it `at` is. `mappings`
+/// must be sorted by the first extent.
+fn find(mappings: &[(Extent, Extent)], at: Position) -> Option<(Extent, usize)> {
+    let i = mappings.partition_point(|(from, _)| from.start <= at).checked_sub(1)?;
+    let (from, to) = mappings[i];
+    if from.start.line != at.line {
+        return None;
+    }
+    let offset = at.col - from.start.col;
+    (offset < from.len).then_some((to, offset))
+}
+
+/// Moves `at` by as much as the statement containing it moved.
+fn translate(mappings: &[(Extent, Extent)], at: Position) -> Option<Position> {
+    let (to, offset) = find(mappings, at)?;
+    Some(Position {
+        line: to.start.line,
+        col: to.start.col + offset.min(to.len.saturating_sub(1)),
+    })
+}
+
+impl SourceMap {
+    /// Maps `program` to itself, as it's printed.
+    pub fn identity(program: &Program) -> Self {
+        Self::new(program, statement_extents(program))
+    }
+
+    /// Maps `generated` to `original`, statement by statement, for rewrites which keep every
+    /// statement where it was, like [`format_source`](crate::format::format_source) and
+    /// [`minify`]. Fails if either doesn't parse, or they have different numbers of
+    /// statements on a line.
+    pub fn between(generated: &str, original: &str) -> Result<Self> {
+        let generated = source_extents(generated)?;
+        let original = source_extents(original)?;
+        let lined_up = generated.iter().zip(original.iter()).all(|(g, o)| g.len() == o.len());
+        // either can have lines without statements at the end
+        let shorter = generated.len().min(original.len());
+        let rest = generated[shorter..].iter().chain(original[shorter..].iter());
+        ensure!(
+            lined_up && rest.flatten().next().is_none(),
+            "the statements of the programs don't line up",
+        );
+        Ok(Self::from_extents(generated, original))
+    }
+
+    /// Maps `program` as it's printed to `source`, which it was parsed from, for starting a
+    /// chain of rewrites with [`SourceMap::then`]. Fails if `source` doesn't parse.
+    pub fn printed(program: &Program, source: &str) -> Result<Self> {
+        let original = source_extents(source)?;
+        let mut generated = statement_extents(program);
+        generated.truncate(original.len());
+        ensure!(
+            generated.iter().zip(original.iter()).all(|(g, o)| g.len() == o.len()),
+            "the program wasn't parsed from the source",
+        );
+        Ok(Self::from_extents(generated, original))
+    }
+
+    /// Maps `program` to `origins`, which holds where each of its top level statements came
+    /// from, line by line.
+    pub(super) fn new(program: &Program, origins: Vec<Vec<Extent>>) -> Self {
+        Self::from_extents(statement_extents(program), origins)
+    }
+
+    fn from_extents(generated: Vec<Vec<Extent>>, original: Vec<Vec<Extent>>) -> Self {
+        let mappings = generated
+            .into_iter()
+            .zip(original)
+            .flat_map(|(generated, original)| generated.into_iter().zip(original))
+            .collect();
+        SourceMap { mappings }
+    }
+
+    /// Where the code at `generated` was written, or `None` if it's not in any statement.
+    pub fn original(&self, generated: Position) -> Option<Position> {
+        translate(&self.mappings, generated)
+    }
+
+    /// Where the code written at `original` ended up, or `None` if it was removed.
+    pub fn generated(&self, original: Position) -> Option<Position> {
+        let mut inverse: Vec<_> = self.mappings.iter().map(|&(g, o)| (o, g)).collect();
+        inverse.sort();
+        translate(&inverse, original)
+    }
+
+    /// A map for running another rewrite, mapped by `later`, after the one this maps.
+    pub fn then(&self, later: &SourceMap) -> SourceMap {
+        let mappings = later
+            .mappings
+            .iter()
+            .filter_map(|&(generated, middle)| {
+                let (original, offset) = find(&self.mappings, middle.start)?;
+                let start = Position { col: original.start.col + offset, ..original.start };
+                Some((generated, Extent { start, len: original.len.saturating_sub(offset) }))
+            })
+            .collect();
+        SourceMap { mappings }
+    }
+
+    /// `(generated, original)` for the start of each statement.
+    pub fn mappings(&self) -> impl Iterator<Item = (Position, Position)> + '_ {
+        self.mappings.iter().map(|&(generated, original)| (generated.start, original.start))
+    }
+}
+
+/// One mapping per line, as `generated -> original`.
+impl Display for SourceMap {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.mappings().try_for_each(|(generated, original)| {
+            writeln!(f, "{} -> {}", generated, original)
+        })
+    }
+}
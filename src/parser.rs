@@ -1,11 +1,14 @@
 use std::ops::*;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
+use std::hash::{Hash, Hasher};
 use anyhow::*;
 use derive_more::{Deref, DerefMut};
-use arith::{Number, YString};
+use arith::{Number, UnicodePolicy, YString};
 use pest::{Parser, iterators::Pair};
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
 use pest_derive::*;
+use diagnostics::{Diagnostic, Severity, Span, SpannedError};
 use super::*;
 
 #[derive(Debug, Parser, Clone)]
@@ -13,6 +16,14 @@ use super::*;
 pub struct YololParser {
     pub max_lines: usize,
     pub max_line_length: usize,
+    /// What to do with string literals holding anything but ASCII.
+    pub unicode: UnicodePolicy,
+    /// Whether to accept the operators the game lacks, `exp(x)`, `ln(x)`, `log10(x)` and
+    /// `log(x, base)`, which need their operands in brackets.
+    pub extensions: bool,
+    /// Whether [`YololParser::parse_with_warnings`] warns about globals a line writes twice
+    /// without reading them between, or a statement reads after changing them.
+    pub field_conflicts: bool,
 }
 
 impl YololParser {
@@ -20,91 +31,475 @@ impl YololParser {
         YololParser {
             max_lines: usize::MAX,
             max_line_length: usize::MAX,
+            unicode: UnicodePolicy::Keep,
+            extensions: false,
+            field_conflicts: false,
         }
     }
 
     pub fn parse(self, s: &str) -> Result<Program> {
+        Ok(self.parse_with_warnings(s)?.0)
+    }
+
+    /// Like [`YololParser::parse`], also warning about number literals with more than 3
+    /// decimal places, which are cut short to fit, and field conflicts if they're turned on.
+    pub fn parse_with_warnings(self, s: &str) -> Result<(Program, Vec<Diagnostic>)> {
         let mut lines = Vec::with_capacity(20);
+        let mut warnings = Vec::new();
+        let mut first_extra = None;
 
-        for line in <YololParser as Parser<_>>::parse(Rule::program, s)? {
+        let pairs = <YololParser as Parser<_>>::parse(Rule::program, s).map_err(pest_error)?;
+        for line in pairs {
             match line.as_rule() {
                 Rule::line => {
-                    let length = line.as_str().trim_end().len();
-                    ensure!(
-                        length <= self.max_line_length,
-                        "Line length too long: {} bytes",
-                        length,
-                    );
-                    lines.push(Line::parse(line.into_inner())?);
+                    // assertions are stripped from exported chips, so they're free
+                    let code = match line.clone().into_inner().last() {
+                        Some(a) if a.as_rule() == Rule::assert_comment =>
+                            &line.as_str()[..a.as_span().start() - line.as_span().start()],
+                        _ => line.as_str(),
+                    };
+                    let length = code.trim_end().len();
+                    if length > self.max_line_length {
+                        let start = line.as_span().start();
+                        let mut over = start + self.max_line_length;
+                        while !s.is_char_boundary(over) {
+                            over -= 1;
+                        }
+                        let span = Span::new(s, over, start + length);
+                        let message = format!("Line length too long: {} bytes", length);
+                        bail!(SpannedError { span, message });
+                    }
+                    if lines.len() == self.max_lines {
+                        first_extra = Some(line.as_span());
+                    }
+                    let literals = line
+                        .clone()
+                        .into_inner()
+                        .flatten()
+                        .filter(|pair| pair.as_rule() == Rule::number);
+                    for literal in literals {
+                        warnings.extend(precision_warning(literal.as_str(), lines.len() + 1));
+                    }
+                    if !self.extensions {
+                        let mut pairs = line.clone().into_inner().flatten();
+                        let extension = |p: &Pair<Rule>| {
+                            matches!(p.as_rule(), Rule::extension_op | Rule::extension_fn)
+                        };
+                        if let Some(op) = pairs.find(extension) {
+                            let message = format!("'{}' is an extension", op.as_str());
+                            return Err(error_at(op.as_span(), message));
+                        }
+                    }
+                    let span = line.as_span();
+                    let mut line = Line::parse(line.into_inner())?;
+                    if self.field_conflicts {
+                        warnings.extend(field_conflicts(&line, lines.len() + 1));
+                    }
+                    if self.unicode != UnicodePolicy::Keep {
+                        let mut error = None;
+                        let mut apply = |s: &mut YString| if error.is_none() {
+                            error = s.apply_policy(self.unicode).err();
+                        };
+                        line.stmts.iter_mut().for_each(|s| s.for_each_string_mut(&mut apply));
+                        line.assert.iter_mut().for_each(|e| e.for_each_string_mut(&mut apply));
+                        if let Some(e) = error {
+                            return Err(error_at(span, e));
+                        }
+                    }
+                    lines.push(line);
                 },
                 Rule::EOI => break,
                 r => unreachable!("parse error in Program: {:?}", r),
             }
         }
 
-        ensure!(
-            lines.len() <= self.max_lines,
-            "Too many lines in program! Found {} of them.",
-            lines.len(),
-        );
-        // ensure the program has at least 20 lines
-        lines.extend(std::iter::repeat(Line::default()).take(20_usize.saturating_sub(lines.len())));
+        if let Some(span) = first_extra {
+            let message = format!("Too many lines in program! Found {} of them.", lines.len());
+            return Err(error_at(span, message));
+        }
+        Ok((Program::new(lines), warnings))
+    }
+
+    /// Like [`YololParser::parse`], but carrying on past errors, for editors showing every
+    /// problem in a program while it's being written. Lines are parsed one at a time. A line
+    /// which doesn't parse keeps the statements before the error, and the rest of the line from
+    /// the first statement after it where everything left parses. Lines over the length limit
+    /// and strings the [`UnicodePolicy`] rejects are reported but kept as written.
+    pub fn parse_recovering(self, s: &str) -> (Program, Vec<SpannedError>) {
+        if let Result::Ok(program) = self.clone().parse(s) {
+            return (program, Vec::new());
+        }
+        let mut lines = Vec::with_capacity(20);
+        let mut errors = Vec::new();
+        let mut offset = 0;
+        for (i, text) in s.split('\n').enumerate() {
+            let start = offset;
+            offset += text.len() + 1;
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let one_line = YololParser { max_lines: usize::MAX, ..self.clone() };
+            let error = match one_line.parse(text) {
+                Result::Ok(program) => {
+                    lines.push(program.lines.into_iter().next().unwrap());
+                    continue;
+                },
+                Err(e) => e.downcast::<SpannedError>().unwrap_or_else(|e| SpannedError {
+                    span: Span::new(text, 0, text.len()),
+                    message: e.to_string(),
+                }),
+            };
+            let mut span = error.span;
+            span.start += start;
+            span.end += start;
+            span.line = i + 1;
+            errors.push(SpannedError { span, ..error });
+            let line = match YololParser::unrestricted().parse(text) {
+                Result::Ok(program) => program.lines.into_iter().next().unwrap(),
+                Err(_) => recover_line(text),
+            };
+            lines.push(line);
+        }
 
-        Ok(Program {
-            lines
+        if lines.len() > self.max_lines {
+            let start = s.split('\n').take(self.max_lines).map(|l| l.len() + 1).sum();
+            let end = s[start..].find('\n').map_or(s.len(), |i| start + i);
+            let message = format!("Too many lines in program! Found {} of them.", lines.len());
+            errors.push(SpannedError { span: Span::new(s, start, end), message });
+        }
+        (Program::new(lines), errors)
+    }
+}
+
+/// Where every top level statement of `s` is, line by line. Fails if `s` doesn't parse,
+/// ignoring the limits a [`YololParser`] enforces.
+pub fn statement_spans(s: &str) -> Result<Vec<Vec<Span>>> {
+    let pairs = <YololParser as Parser<_>>::parse(Rule::program, s).map_err(pest_error)?;
+    let spans = pairs
+        .filter(|pair| pair.as_rule() == Rule::line)
+        .map(|line| {
+            line.into_inner()
+                .filter(|pair| pair.as_rule() == Rule::statement)
+                .map(|stmt| {
+                    // statements end with any spacing before the next
+                    let start = stmt.as_span().start();
+                    Span::new(s, start, start + stmt.as_str().trim_end().len())
+                })
+                .collect()
         })
+        .collect();
+    Ok(spans)
+}
+
+/// The statements of a line which doesn't parse: those before the error, then those from the
+/// first place after it, following a space, where the rest of the line parses.
+fn recover_line(text: &str) -> Line {
+    // without `EOI` the rule matches as many statements as it can
+    let parse = |text| -> Option<(Line, usize)> {
+        let pair = <YololParser as Parser<_>>::parse(Rule::line, text).ok()?.next()?;
+        let end = pair.as_span().end();
+        Some((Line::parse(pair.into_inner()).ok()?, end))
+    };
+    let (mut line, end) = parse(text).unwrap_or_default();
+    let resume = (end + 1..text.len()).filter(|&i| {
+        text.as_bytes()[i - 1].is_ascii_whitespace() && !text.as_bytes()[i].is_ascii_whitespace()
+    });
+    for i in resume {
+        match parse(&text[i..]) {
+            Some((rest, end)) if !rest.is_empty() && text[i + end..].trim().is_empty() => {
+                line.stmts.extend(rest.stmts);
+                line.assert = rest.assert;
+                line.annotation = rest.annotation;
+                break;
+            },
+            _ => (),
+        }
+    }
+    line
+}
+
+/// An error pointing at `span` of the source.
+fn error_at(span: pest::Span, message: impl Display) -> Error {
+    let (line, col) = span.start_pos().line_col();
+    let span = Span { start: span.start(), end: span.end(), line, col };
+    SpannedError { span, message: message.to_string() }.into()
+}
+
+fn pest_error(error: pest::error::Error<Rule>) -> Error {
+    let (start, end) = match error.location {
+        InputLocation::Pos(pos) => (pos, pos),
+        InputLocation::Span(span) => span,
+    };
+    let (line, col) = match error.line_col {
+        LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+    };
+    let rules = |rules: &[Rule]| {
+        rules.iter().map(|r| format!("{:?}", r)).collect::<Vec<_>>().join(", ")
+    };
+    let message = match error.variant {
+        ErrorVariant::ParsingError { positives, .. } if !positives.is_empty() => {
+            format!("expected {}", rules(&positives))
+        },
+        ErrorVariant::ParsingError { negatives, .. } => format!("unexpected {}", rules(&negatives)),
+        ErrorVariant::CustomError { message } => message,
+    };
+    SpannedError { span: Span { start, end, line, col }, message }.into()
+}
+
+/// A warning if `literal` has digits past the third decimal place, which parsing drops.
+fn precision_warning(literal: &str, line: usize) -> Option<Diagnostic> {
+    let (_, decimals) = literal.split_once('.')?;
+    let dropped = decimals.get(3..)?.trim_end_matches('0');
+    if dropped.is_empty() {
+        return None;
+    }
+    let stored: Number = literal.parse().ok()?;
+    let sign = if literal.starts_with('-') { "-" } else { "" };
+    Some(Diagnostic {
+        severity: Severity::Warning,
+        line,
+        message: format!("{} is stored as {}, off by {}0.000{}", literal, stored, sign, dropped),
+    })
+}
+
+/// A read or write of a global, in the order they happen.
+enum Access<'a> {
+    Read(&'a Ident),
+    Write(&'a Ident),
+}
+
+fn expr_accesses<'a>(expr: &'a Expr, out: &mut Vec<Access<'a>>) {
+    match expr {
+        // the right runs first
+        Expr::Binop(l, _, r) => {
+            expr_accesses(r, out);
+            expr_accesses(l, out);
+        },
+        Expr::Unop(_, e) => expr_accesses(e, out),
+        Expr::Ident(ident) => out.push(Access::Read(ident)),
+        Expr::Incdec(Incdec { ident, .. }) => {
+            out.extend([Access::Read(ident), Access::Write(ident)]);
+        },
+        Expr::Number(_) | Expr::String(_) => (),
+    }
+}
+
+/// Writes to globals nothing has read since, with the statements making them, or `None` once a
+/// `goto` has ended the line.
+type Unread<'a> = Option<Vec<(&'a Ident, String)>>;
+
+/// Follows `stmts`, adding to `warnings` for globals written again before anything reads them,
+/// or read by a statement after it changed them. The two sides of an `if` are followed
+/// separately, then what's unread after either is kept.
+fn follow_accesses<'a>(
+    stmts: &'a [Statement],
+    unread: &mut Unread<'a>,
+    warnings: &mut Vec<String>,
+) {
+    for stmt in stmts {
+        let Some(writes) = unread.as_mut() else {
+            return;
+        };
+        let mut accesses = Vec::new();
+        let text = match stmt {
+            Statement::Ite(c, ..) => {
+                expr_accesses(c, &mut accesses);
+                format!("if {}", c)
+            },
+            Statement::Goto(e) => {
+                expr_accesses(e, &mut accesses);
+                stmt.to_string()
+            },
+            Statement::Assign(ident, op, e) => {
+                expr_accesses(e, &mut accesses);
+                if op.is_some() {
+                    accesses.push(Access::Read(ident));
+                }
+                accesses.push(Access::Write(ident));
+                stmt.to_string()
+            },
+            Statement::Incdec(Incdec { ident, .. }) => {
+                accesses.extend([Access::Read(ident), Access::Write(ident)]);
+                stmt.to_string()
+            },
+        };
+        // globals this statement has changed so far
+        let mut changed = Vec::new();
+        for access in accesses {
+            match access {
+                Access::Read(ident) if ident.global => {
+                    if changed.contains(&ident) {
+                        warnings.push(format!(
+                            "{} is read by `{}` after it changed it, so it sees the new value; \
+                            note: the right of each operator is worked out first",
+                            ident, text,
+                        ));
+                    }
+                    writes.retain(|(w, _)| *w != ident);
+                },
+                Access::Write(ident) if ident.global => {
+                    for (_, by) in writes.iter().filter(|(w, _)| *w == ident) {
+                        warnings.push(format!(
+                            "{} written by `{}` is written again by `{}` before anything reads \
+                            it; note: statements run left to right, so the value from `{}` \
+                            replaces the one from `{}`",
+                            ident, by, text, text, by,
+                        ));
+                    }
+                    writes.retain(|(w, _)| *w != ident);
+                    writes.push((ident, text.clone()));
+                    changed.push(ident);
+                },
+                _ => (),
+            }
+        }
+        match stmt {
+            Statement::Goto(_) => *unread = None,
+            Statement::Ite(_, t, e) => {
+                let mut taken = unread.clone();
+                follow_accesses(t, &mut taken, warnings);
+                follow_accesses(e, unread, warnings);
+                match (unread.as_mut(), taken) {
+                    (Some(writes), Some(taken)) => for write in taken {
+                        if !writes.contains(&write) {
+                            writes.push(write);
+                        }
+                    },
+                    (None, taken) => *unread = taken,
+                    (_, None) => (),
+                }
+            },
+            _ => (),
+        }
     }
 }
 
+/// Warnings for globals a line writes twice without reading them between, which loses the
+/// first value, or a statement reads after changing them, on any way through the line.
+fn field_conflicts(line: &Line, number: usize) -> Vec<Diagnostic> {
+    let mut messages = Vec::new();
+    follow_accesses(&line.stmts, &mut Some(Vec::new()), &mut messages);
+    let mut warnings: Vec<Diagnostic> = Vec::new();
+    for message in messages {
+        if !warnings.iter().any(|w| w.message == message) {
+            warnings.push(Diagnostic { severity: Severity::Warning, line: number, message });
+        }
+    }
+    warnings
+}
+
 impl Default for YololParser {
     fn default() -> Self {
         Self {
             max_lines: 20,
             max_line_length: 70,
+            unicode: UnicodePolicy::Keep,
+            extensions: false,
+            field_conflicts: false,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+/// Words which can't name a variable, in lowercase.
+pub(crate) const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "end", "goto", "and", "or", "not", "abs", "sqrt", "sin", "cos", "tan",
+    "asin", "acos", "atan",
+];
+
+/// Names are matched case-insensitively, as in game, but the first spelling is kept around
+/// for display.
+#[derive(Debug, Clone)]
 pub struct Ident {
+    /// The lowercased name, used for matching.
     pub name: String,
     pub global: bool,
+    /// The name as written, if that differs from `name`.
+    original: Option<Box<str>>,
 }
 
 impl Ident {
-   pub fn local(name: &str) -> Self {
+    pub fn new(name: &str, global: bool) -> Self {
+        let lower = name.to_lowercase();
         Ident {
-            name: name.to_lowercase(),
-            global: false,
+            original: (lower != name).then(|| name.into()),
+            name: lower,
+            global,
         }
     }
 
+    pub fn local(name: &str) -> Self {
+        Self::new(name, false)
+    }
+
     pub fn global(name: &str) -> Self {
-        Ident {
-            name: name.to_lowercase(),
-            global: true,
+        Self::new(name, true)
+    }
+
+    /// The name as it was first written, without the `:` of globals.
+    pub fn original_name(&self) -> &str {
+        self.original.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Displays the name as it was first written, rather than lowercased.
+    pub fn display_original(&self) -> impl Display + '_ {
+        struct Original<'a>(&'a Ident);
+
+        impl Display for Original<'_> {
+            fn fmt(&self, f: &mut Formatter) -> FmtResult {
+                if self.0.global {
+                    f.write_str(":")?;
+                }
+
+                f.write_str(self.0.original_name())
+            }
         }
+
+        Original(self)
     }
 
     fn parse<'a>(mut pairs: impl Iterator<Item = Pair<'a, Rule>>) -> Ident {
         let pair = pairs.next().unwrap();
         debug_assert_eq!(pairs.next(), None);
-        Ident {
-            name: match pair.as_rule() {
-                Rule::global_ident => pair.as_str()[1..].to_lowercase(),
-                Rule::local_ident => pair.as_str().to_lowercase(),
-                r => unreachable!("parse error in Ident: {:?}", r),
-            },
-            global: pair.as_rule() == Rule::global_ident,
+        match pair.as_rule() {
+            Rule::global_ident => Self::global(&pair.as_str()[1..]),
+            Rule::local_ident => Self::local(pair.as_str()),
+            r => unreachable!("parse error in Ident: {:?}", r),
         }
     }
 }
 
+impl PartialEq for Ident {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.global == other.global
+    }
+}
+
+impl Eq for Ident {}
+
+impl Hash for Ident {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.global.hash(state);
+    }
+}
+
+/// Locals before globals, then by name, so anything listing variables comes out the same way
+/// every run.
+impl Ord for Ident {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.global, &self.name).cmp(&(other.global, &other.name))
+    }
+}
+
+impl PartialOrd for Ident {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl FromStr for Ident {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let mut ident = <YololParser as Parser<_>>::parse(Rule::ident, s)?;
+        let mut ident = <YololParser as Parser<_>>::parse(Rule::ident, s).map_err(pest_error)?;
         Ok(Self::parse(ident.next().unwrap().into_inner()))
     }
 }
@@ -135,6 +530,8 @@ pub enum Binop {
     Lt,
     Ge,
     Gt,
+    /// The extension `log(n, base)`, parsed with [`YololParser::extensions`].
+    Log,
 }
 
 impl Binop {
@@ -157,6 +554,45 @@ impl Binop {
             s => unreachable!("parse error in Binop: '{}'", s),
         }
     }
+
+    const fn precedence(self) -> u8 {
+        match self {
+            Binop::And => 1,
+            Binop::Or => 2,
+            Binop::Add | Binop::Sub => 4,
+            Binop::Eq | Binop::Ne | Binop::Le | Binop::Lt | Binop::Ge | Binop::Gt => 5,
+            Binop::Mul | Binop::Div | Binop::Mod => 6,
+            Binop::Pow => 7,
+            // written as a call, so never needs brackets
+            Binop::Log => 11,
+        }
+    }
+
+    pub const fn is_extension(self) -> bool {
+        matches!(self, Binop::Log)
+    }
+}
+
+impl Display for Binop {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Binop::And => "and",
+            Binop::Or => "or",
+            Binop::Add => "+",
+            Binop::Sub => "-",
+            Binop::Mul => "*",
+            Binop::Div => "/",
+            Binop::Mod => "%",
+            Binop::Pow => "^",
+            Binop::Eq => "==",
+            Binop::Ne => "!=",
+            Binop::Le => "<=",
+            Binop::Lt => "<",
+            Binop::Ge => ">=",
+            Binop::Gt => ">",
+            Binop::Log => "log",
+        })
+    }
 }
 
 impl From<AssignOp> for Binop {
@@ -185,6 +621,10 @@ pub enum Unop {
     Asin,
     Acos,
     Atan,
+    /// The extensions, parsed with [`YololParser::extensions`].
+    Exp,
+    Ln,
+    Log10,
 }
 
 impl Unop {
@@ -201,9 +641,46 @@ impl Unop {
             "asin" => Unop::Asin,
             "acos" => Unop::Acos,
             "atan" => Unop::Atan,
+            "exp" => Unop::Exp,
+            "ln" => Unop::Ln,
+            "log10" => Unop::Log10,
             s => unreachable!("parse error in Unop: '{}'", s),
         }
     }
+
+    const fn precedence(self) -> u8 {
+        match self {
+            Unop::Not => 3,
+            Unop::Neg => 9,
+            Unop::Fact => 10,
+            _ => 8,
+        }
+    }
+
+    pub const fn is_extension(self) -> bool {
+        matches!(self, Unop::Exp | Unop::Ln | Unop::Log10)
+    }
+}
+
+impl Display for Unop {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Unop::Neg => "-",
+            Unop::Not => "not",
+            Unop::Abs => "abs",
+            Unop::Sqrt => "sqrt",
+            Unop::Fact => "!",
+            Unop::Sin => "sin",
+            Unop::Cos => "cos",
+            Unop::Tan => "tan",
+            Unop::Asin => "asin",
+            Unop::Acos => "acos",
+            Unop::Atan => "atan",
+            Unop::Exp => "exp",
+            Unop::Ln => "ln",
+            Unop::Log10 => "log10",
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -213,6 +690,14 @@ pub struct Incdec {
 }
 
 impl Incdec {
+    pub const fn inc(ident: Ident) -> Self {
+        Incdec { inc: true, ident }
+    }
+
+    pub const fn dec(ident: Ident) -> Self {
+        Incdec { inc: false, ident }
+    }
+
     fn parse(first: Pair<Rule>, second: Pair<Rule>) -> Incdec {
         let inc = match (first.as_str(), second.as_str()) {
             ("++", _) | (_, "++") => true,
@@ -230,6 +715,12 @@ impl Incdec {
     }
 }
 
+impl Display for Incdec {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}{}", if self.inc { "++" } else { "--" }, self.ident)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Expr {
     Binop(Box<Expr>, Binop, Box<Expr>),
@@ -304,6 +795,7 @@ impl Expr {
                 let pair = pair.into_inner().next().unwrap();
                 match pair.as_rule() {
                     Rule::string => Ok(Expr::String({
+                        let span = pair.as_span();
                         let mut new = String::new();
                         let mut escaped = false;
                         let mut unicode = false;
@@ -315,13 +807,13 @@ impl Expr {
                             } else {
                                 unicode = false;
                                 escaped = false;
-                                new.push(parse_codepoint(&unicode_cp));
+                                new.push(parse_codepoint(&unicode_cp, span.clone())?);
                                 unicode_cp.clear();
                                 false
                             } {
                                 unicode_cp.push(c);
                             } else if escaped {
-                                new.push(match c {
+                                let c = match c {
                                     '\\' => '\\',
                                     'b' => '\x08',
                                     'f' => '\x0C',
@@ -333,8 +825,13 @@ impl Expr {
                                         unicode = true;
                                         continue;
                                     },
-                                    _ => unreachable!(),
-                                });
+                                    // not an escape, so the backslash is just a backslash
+                                    c => {
+                                        new.push('\\');
+                                        c
+                                    },
+                                };
+                                new.push(c);
                                 escaped = false;
                             } else if c == '\\' {
                                 escaped = true;
@@ -342,10 +839,25 @@ impl Expr {
                                 new.push(c);
                             }
                         }
+                        if unicode {
+                            new.push(parse_codepoint(&unicode_cp, span.clone())?);
+                        } else if escaped {
+                            new.push('\\');
+                        }
                         new
                     }.into())),
-                    Rule::number => Ok(Expr::Number(pair.as_str().parse()?)),
+                    Rule::number => pair
+                        .as_str()
+                        .parse()
+                        .map(Expr::Number)
+                        .map_err(|e| error_at(pair.as_span(), e)),
                     Rule::ident => Ok(Expr::Ident(Ident::parse(pair.into_inner()))),
+                    Rule::extension_call => {
+                        let mut pairs = pair.into_inner().skip(1);
+                        let n = Expr::parse(pairs.next().unwrap())?;
+                        let base = Expr::parse(pairs.next().unwrap())?;
+                        Ok(Expr::Binop(n.into(), Binop::Log, base.into()))
+                    },
                     _ => Expr::parse(pair),
                 }
             },
@@ -354,8 +866,120 @@ impl Expr {
     }
 }
 
-fn parse_codepoint(_s: &str) -> char {
-    todo!("Haven't added unicde codepoint parsing yet. Someday, if yolol even supports it!")
+fn parse_codepoint(s: &str, span: pest::Span) -> Result<char> {
+    u32::from_str_radix(s, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| error_at(span, format!("invalid unicode escape '\\u{}'", s)))
+}
+
+impl Expr {
+    /// Calls `f` on every identifier in the expression.
+    pub fn for_each_ident_mut(&mut self, f: &mut impl FnMut(&mut Ident)) {
+        match self {
+            Expr::Binop(l, _, r) => {
+                l.for_each_ident_mut(f);
+                r.for_each_ident_mut(f);
+            },
+            Expr::Unop(_, e) => e.for_each_ident_mut(f),
+            Expr::Incdec(Incdec { ident, .. }) | Expr::Ident(ident) => f(ident),
+            Expr::Number(_) | Expr::String(_) => (),
+        }
+    }
+
+    /// Calls `f` on every string literal in the expression.
+    pub fn for_each_string_mut(&mut self, f: &mut impl FnMut(&mut YString)) {
+        match self {
+            Expr::Binop(l, _, r) => {
+                l.for_each_string_mut(f);
+                r.for_each_string_mut(f);
+            },
+            Expr::Unop(_, e) => e.for_each_string_mut(f),
+            Expr::String(s) => f(s),
+            Expr::Incdec(_) | Expr::Ident(_) | Expr::Number(_) => (),
+        }
+    }
+
+    const fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binop(_, op, _) => op.precedence(),
+            Expr::Unop(op, _) => op.precedence(),
+            Expr::Incdec(_) | Expr::Ident(_) | Expr::Number(_) | Expr::String(_) => 11,
+        }
+    }
+
+    fn fmt_child(&self, f: &mut Formatter, parens: bool) -> FmtResult {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Expr::Binop(l, Binop::Log, r) => write!(f, "log({}, {})", l, r),
+            Expr::Binop(l, op, r) => {
+                let prec = op.precedence();
+                // `^` is right associative, everything else is left associative
+                let (l_parens, r_parens) = if *op == Binop::Pow {
+                    (l.precedence() <= prec, r.precedence() < prec)
+                } else {
+                    (l.precedence() < prec, r.precedence() <= prec)
+                };
+                l.fmt_child(f, l_parens)?;
+                write!(f, " {} ", op)?;
+                r.fmt_child(f, r_parens)
+            },
+            Expr::Unop(Unop::Fact, e) => {
+                e.fmt_child(f, e.precedence() <= Unop::Fact.precedence())?;
+                f.write_str("!")
+            },
+            Expr::Unop(Unop::Neg, e) => {
+                let inner = if e.precedence() < Unop::Neg.precedence() {
+                    format!("({})", e)
+                } else {
+                    e.to_string()
+                };
+                if inner.starts_with('-') {
+                    write!(f, "- {}", inner)
+                } else {
+                    write!(f, "-{}", inner)
+                }
+            },
+            Expr::Unop(op, e) if op.is_extension() => write!(f, "{}({})", op, e),
+            Expr::Unop(op, e) => {
+                // `not` takes everything from addition up, the keywords only take negations
+                let min = if *op == Unop::Not { Unop::Not } else { Unop::Abs }.precedence();
+                if e.precedence() < min {
+                    write!(f, "{}({})", op, e)
+                } else {
+                    write!(f, "{} {}", op, e)
+                }
+            },
+            Expr::Incdec(incdec) => write!(f, "{}", incdec),
+            Expr::Ident(ident) => write!(f, "{}", ident),
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::String(s) => {
+                f.write_str("\"")?;
+                for c in s.to_string().chars() {
+                    match c {
+                        '\\' => f.write_str("\\\\")?,
+                        '\x08' => f.write_str("\\b")?,
+                        '\x0C' => f.write_str("\\f")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        '"' => f.write_str("\\\"")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            },
+        }
+    }
 }
 
 impl<T: Into<Number>> From<T> for Expr {
@@ -418,6 +1042,29 @@ macro_rules! unop_impl {
 unop_impl!(Not, not, Not);
 unop_impl!(Neg, neg, Neg);
 
+/// For building expressions in code, rather than formatting source and parsing it. Arithmetic,
+/// `and` and `or` also work through operators, like `a + b`, `a & b` and `a | b`, as do `-`
+/// and `not` through `-a` and `!a`.
+impl Expr {
+    pub fn local(name: &str) -> Expr {
+        Expr::Ident(Ident::local(name))
+    }
+
+    pub fn global(name: &str) -> Expr {
+        Expr::Ident(Ident::global(name))
+    }
+
+    /// `self op rhs`, for operators Rust doesn't have, like `a.binop(Binop::Lt, 5)`.
+    pub fn binop(self, op: Binop, rhs: impl Into<Expr>) -> Expr {
+        Expr::Binop(self.into(), op, Box::new(rhs.into()))
+    }
+
+    /// `op self`, or `self!` for [`Unop::Fact`].
+    pub fn unop(self, op: Unop) -> Expr {
+        Expr::Unop(op, self.into())
+    }
+}
+
 #[derive(Debug, Copy, PartialEq, Eq, Clone)]
 pub enum AssignOp {
     Add,
@@ -445,6 +1092,12 @@ impl AssignOp {
     }
 }
 
+impl Display for AssignOp {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}=", Binop::from(*self))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Statement {
     Goto(Expr),
@@ -454,6 +1107,34 @@ pub enum Statement {
 }
 
 impl Statement {
+    /// Calls `f` on every identifier in the statement, including nested ones.
+    pub fn for_each_ident_mut(&mut self, f: &mut impl FnMut(&mut Ident)) {
+        match self {
+            Statement::Goto(e) => e.for_each_ident_mut(f),
+            Statement::Ite(c, t, e) => {
+                c.for_each_ident_mut(f);
+                t.iter_mut().chain(e.iter_mut()).for_each(|s| s.for_each_ident_mut(f));
+            },
+            Statement::Incdec(Incdec { ident, .. }) => f(ident),
+            Statement::Assign(ident, _, e) => {
+                f(ident);
+                e.for_each_ident_mut(f);
+            },
+        }
+    }
+
+    /// Calls `f` on every string literal in the statement, including nested ones.
+    pub fn for_each_string_mut(&mut self, f: &mut impl FnMut(&mut YString)) {
+        match self {
+            Statement::Goto(e) | Statement::Assign(_, _, e) => e.for_each_string_mut(f),
+            Statement::Ite(c, t, e) => {
+                c.for_each_string_mut(f);
+                t.iter_mut().chain(e.iter_mut()).for_each(|s| s.for_each_string_mut(f));
+            },
+            Statement::Incdec(_) => (),
+        }
+    }
+
     fn parse_goto<'a>(mut pairs: impl Iterator<Item = Pair<'a, Rule>>) -> Result<Statement> {
         let pair = pairs.next().unwrap();
         debug_assert_eq!(pairs.next(), None);
@@ -464,7 +1145,7 @@ impl Statement {
         let condition = Expr::parse(pairs.next().unwrap())?;
         let mut then = Vec::with_capacity(4);
 
-        while let Some(pair) = pairs.next() {
+        for pair in pairs.by_ref() {
             if pair.as_rule() == Rule::else_kw {
                 break
             } else {
@@ -516,44 +1197,185 @@ impl Statement {
     }
 }
 
+/// For building statements in code.
+impl Statement {
+    /// `ident = expr`
+    pub fn assign(ident: Ident, expr: impl Into<Expr>) -> Self {
+        Statement::Assign(ident, None, expr.into())
+    }
+
+    /// `ident op= expr`
+    pub fn assign_op(ident: Ident, op: AssignOp, expr: impl Into<Expr>) -> Self {
+        Statement::Assign(ident, Some(op), expr.into())
+    }
+
+    pub fn goto(line: impl Into<Expr>) -> Self {
+        Statement::Goto(line.into())
+    }
+
+    /// `if cond then .. else .. end`, leaving out `else` if `otherwise` is empty.
+    pub fn ite(
+        cond: impl Into<Expr>,
+        then: impl IntoIterator<Item = Statement>,
+        otherwise: impl IntoIterator<Item = Statement>,
+    ) -> Self {
+        Statement::Ite(cond.into(), then.into_iter().collect(), otherwise.into_iter().collect())
+    }
+}
+
 impl From<Incdec> for Statement {
     fn from(i: Incdec) -> Self {
         Statement::Incdec(i)
     }
 }
 
+fn fmt_stmts(f: &mut Formatter, stmts: &[Statement]) -> FmtResult {
+    stmts.iter().try_for_each(|stmt| write!(f, " {}", stmt))
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Statement::Goto(e) => write!(f, "goto {}", e),
+            Statement::Ite(c, t, e) => {
+                write!(f, "if {} then", c)?;
+                fmt_stmts(f, t)?;
+                if !e.is_empty() {
+                    f.write_str(" else")?;
+                    fmt_stmts(f, e)?;
+                }
+                f.write_str(" end")
+            },
+            // postfix, as `a = b --c` would read back as `a = b - -c`
+            Statement::Incdec(Incdec { inc, ident }) => {
+                write!(f, "{}{}", ident, if *inc { "++" } else { "--" })
+            },
+            Statement::Assign(ident, op, e) => match op {
+                Some(op) => write!(f, "{} {} {}", ident, op, e),
+                None => write!(f, "{} = {}", ident, e),
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Default, Deref, DerefMut)]
 pub struct Line {
     #[deref]
+    #[deref_mut]
     pub stmts: Vec<Statement>,
+    /// From an `// assert: expr` comment, checked after the line runs when compiled with
+    /// [`crate::ir::CodegenOptions::check_asserts`].
+    pub assert: Option<Expr>,
+    pub annotation: Option<FieldAnnotation>,
+}
+
+/// From a `// :field (unit) description` comment, describing a global for dashboards and
+/// other tools. The unit can be left empty as `()`, but the brackets are needed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FieldAnnotation {
+    pub field: Ident,
+    pub unit: Option<String>,
+    pub description: String,
+}
+
+impl FieldAnnotation {
+    fn parse<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> FieldAnnotation {
+        let mut annotation = FieldAnnotation {
+            field: Ident::global(""),
+            unit: None,
+            description: String::new(),
+        };
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::global_ident => annotation.field = Ident::global(&pair.as_str()[1..]),
+                Rule::unit => {
+                    let unit = pair.as_str().trim();
+                    annotation.unit = (!unit.is_empty()).then(|| unit.to_string());
+                },
+                Rule::description => annotation.description = pair.as_str().trim().to_string(),
+                r => unreachable!("parse error in FieldAnnotation: {:?}", r),
+            }
+        }
+        annotation
+    }
 }
 
 impl Line {
-    fn parse<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> Result<Line> {
+    pub(crate) fn parse<'a>(pairs: impl Iterator<Item = Pair<'a, Rule>>) -> Result<Line> {
         let mut stmts = Vec::with_capacity(20);
+        let mut assert = None;
+        let mut annotation = None;
 
         for stmt in pairs {
             match stmt.as_rule() {
                 Rule::statement => {
                     stmts.push(Statement::parse(stmt.into_inner())?);
                 },
+                Rule::assert_comment => {
+                    assert = Some(Expr::parse(stmt.into_inner().next().unwrap())?);
+                },
+                Rule::field_comment => {
+                    annotation = Some(FieldAnnotation::parse(stmt.into_inner()));
+                },
                 Rule::EOI => break,
                 r => unreachable!("parse error in Line: {:?}", r),
             }
         }
 
         Ok(Line {
-            stmts
+            stmts,
+            assert,
+            annotation,
         })
     }
 }
 
+impl FromIterator<Statement> for Line {
+    fn from_iter<I: IntoIterator<Item = Statement>>(stmts: I) -> Self {
+        Line { stmts: stmts.into_iter().collect(), ..Default::default() }
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if let [first, rest@..] = self.stmts.as_slice() {
+            write!(f, "{}", first)?;
+            fmt_stmts(f, rest)?;
+        }
+
+        FmtResult::Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Deref, DerefMut)]
 pub struct Program {
     #[deref]
     pub lines: Vec<Line>,
 }
 
+impl Program {
+    /// A program of `lines`, padded out with empty ones to 20 lines like parsed programs.
+    pub fn new(lines: impl IntoIterator<Item = Line>) -> Self {
+        let mut lines: Vec<_> = lines.into_iter().collect();
+        lines.extend(std::iter::repeat_n(Line::default(), 20_usize.saturating_sub(lines.len())));
+        Program { lines }
+    }
+
+    /// Calls `f` on every identifier in the program, including those in assert and field
+    /// comments.
+    pub fn for_each_ident_mut(&mut self, mut f: impl FnMut(&mut Ident)) {
+        for line in self.lines.iter_mut() {
+            line.stmts.iter_mut().for_each(|s| s.for_each_ident_mut(&mut f));
+            if let Some(assert) = &mut line.assert {
+                assert.for_each_ident_mut(&mut f);
+            }
+            if let Some(annotation) = &mut line.annotation {
+                f(&mut annotation.field);
+            }
+        }
+    }
+}
+
 impl Default for Program {
     fn default() -> Self {
         Program {
@@ -577,6 +1399,210 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn assert_comment_test() -> Result<()> {
+        let program = YololParser::default().parse(&format!(
+            "a=1 // assert: a == 1 and {}a\nb=2 // assert: not really an assertion",
+            "a+".repeat(40),
+        ))?;
+        assert_eq!(program[0].stmts, vec![Statement::Assign(Ident::local("a"), None, 1.into())]);
+        assert!(program[0].assert.is_some());
+        assert_eq!(program[1].assert, None);
+        Ok(())
+    }
+
+    #[test]
+    fn field_comment_test() -> Result<()> {
+        let src = ":alt=1 // :Alt (meters) current altitude\n// :fuel ( ) remaining\n// :x()\n\
+            // a :b\na=1 // :door=1 :b=2\n// :fuel remaining";
+        let program = YololParser::default().parse(src)?;
+        assert_eq!(program[0].annotation, Some(FieldAnnotation {
+            field: Ident::global("alt"),
+            unit: Some("meters".to_string()),
+            description: "current altitude".to_string(),
+        }));
+        assert_eq!(program[0].annotation.as_ref().unwrap().field.original_name(), "Alt");
+        assert_eq!(program[1].annotation, Some(FieldAnnotation {
+            field: Ident::global("fuel"),
+            unit: None,
+            description: "remaining".to_string(),
+        }));
+        assert_eq!(program[2].annotation.as_ref().unwrap().unit, None);
+        assert_eq!(program[3].annotation, None);
+        // commented out code, or no brackets
+        assert_eq!(program[4].annotation, None);
+        assert_eq!(program[5].annotation, None);
+        Ok(())
+    }
+
+    #[test]
+    fn precision_warnings() -> Result<()> {
+        let src = "a=0.12345 b=1.5\n\nc=-2.0001 d=3.1000 // assert: c < 0.9999";
+        let (program, warnings) = YololParser::default().parse_with_warnings(src)?;
+        assert_eq!(program, YololParser::default().parse(src)?);
+        let warnings: Vec<_> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(warnings, [
+            "line 1: warning: 0.12345 is stored as 0.123, off by 0.00045",
+            "line 3: warning: -2.0001 is stored as -2, off by -0.0001",
+            "line 3: warning: 0.9999 is stored as 0.999, off by 0.0009",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn field_conflict_warnings() -> Result<()> {
+        let src = ":a=1 :b=:a+1 :a=2 :a++\nif :c then :d=1 else :d=2 end :d=3\n\
+            :e=1 goto 1 :e=2\n:g=1 if :c then :g=2 end :g=3";
+        let parser = YololParser { field_conflicts: true, ..Default::default() };
+        let (_, warnings) = parser.parse_with_warnings(src)?;
+        let warnings: Vec<_> = warnings.iter().map(|w| w.to_string()).collect();
+        let note = |first: &str, second: &str| format!(
+            "before anything reads it; note: statements run left to right, so the value from \
+            `{}` replaces the one from `{}`", second, first,
+        );
+        assert_eq!(warnings, [
+            format!("line 2: warning: :d written by `:d = 2` is written again by `:d = 3` {}",
+                note(":d = 2", ":d = 3")),
+            format!("line 2: warning: :d written by `:d = 1` is written again by `:d = 3` {}",
+                note(":d = 1", ":d = 3")),
+            format!("line 4: warning: :g written by `:g = 1` is written again by `:g = 2` {}",
+                note(":g = 1", ":g = 2")),
+            format!("line 4: warning: :g written by `:g = 1` is written again by `:g = 3` {}",
+                note(":g = 1", ":g = 3")),
+            format!("line 4: warning: :g written by `:g = 2` is written again by `:g = 3` {}",
+                note(":g = 2", ":g = 3")),
+        ]);
+        assert!(YololParser::default().parse_with_warnings(src)?.1.is_empty());
+
+        // every way through this line would be 2^40 of them
+        let ifs = "if :c then :d=1 end ".repeat(40);
+        let parser = YololParser { field_conflicts: true, ..YololParser::unrestricted() };
+        assert_eq!(parser.parse_with_warnings(&ifs)?.1.len(), 1);
+
+        // the right of `+` runs first, so the left sees the incremented value
+        let src = ":b=:a+:a++ :c+=:c-- :e=:d++ :f=:d";
+        let parser = YololParser { field_conflicts: true, ..Default::default() };
+        let warnings: Vec<_> = parser.parse_with_warnings(src)?.1
+            .iter().map(|w| w.to_string()).collect();
+        let read = |field: &str, by: &str| format!(
+            "line 1: warning: {} is read by `{}` after it changed it, so it sees the new value; \
+            note: the right of each operator is worked out first", field, by,
+        );
+        assert_eq!(warnings, [read(":a", ":b = :a + ++:a"), read(":c", ":c += --:c")]);
+        Ok(())
+    }
+
+    #[test]
+    fn error_spans() {
+        let span = |src: &str, parser: YololParser| {
+            let err = parser.parse(src).unwrap_err();
+            let err = err.downcast_ref::<SpannedError>().unwrap().clone();
+            (err.to_string(), err.span.snippet(src))
+        };
+        let (message, snippet) = span(":a=1\n\t:b = = 2", YololParser::default());
+        assert_eq!(message, "line 2, column 7: expected expression_not");
+        assert_eq!(snippet, "2 | \t:b = = 2\n  | \t     ^");
+
+        // the limit is in bytes, so it can fall in the middle of a character
+        let long = YololParser { max_line_length: 7, ..Default::default() };
+        let (message, snippet) = span("a=1\nb=\"caf\u{e9}\" c=1\n", long);
+        assert_eq!(message, "line 2, column 7: Line length too long: 13 bytes");
+        assert_eq!(snippet, "2 | b=\"caf\u{e9}\" c=1\n  |       ^^^^^^");
+
+        let few = YololParser { max_lines: 2, ..Default::default() };
+        let (message, _) = span("a=1\nb=2\nc=3\n", few);
+        assert!(message.starts_with("line 3, column 1: Too many lines"), "{}", message);
+        let (message, _) = span("a=\"\\u110000\"", YololParser::default());
+        assert_eq!(message, "line 1, column 3: invalid unicode escape '\\u110000'");
+    }
+
+    #[test]
+    fn builds_programs() {
+        let a = || Ident::local("a");
+        let program = Program::new([
+            [Statement::assign(a(), Expr::global("in") * 2.into())].into_iter().collect(),
+            Line::from_iter([
+                Statement::ite(
+                    Expr::local("a").binop(Binop::Ge, 10) & !Expr::global("stop"),
+                    [Statement::assign_op(a(), AssignOp::Mod, 3), Incdec::inc(a()).into()],
+                    [],
+                ),
+                Statement::assign(Ident::global("out"), Expr::local("a").unop(Unop::Sqrt)),
+                Statement::goto(1),
+            ]),
+        ]);
+        let src = "a = :in * 2\n\
+            if a >= 10 and not :stop then a %= 3 a++ end :out = sqrt a goto 1";
+        assert_eq!(program, YololParser::default().parse(src).unwrap());
+        assert_eq!(program.len(), 20);
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let src = ":a=1 :b=* 2 :c=3\n:d=\"x\"\nif :e then\n:f=2 goto 1";
+        let (program, errors) = YololParser::default().parse_recovering(src);
+        let lines: Vec<_> = program.iter().take(4).map(|l| l.to_string()).collect();
+        assert_eq!(lines, [":a = 1 :c = 3", ":d = \"x\"", "", ":f = 2 goto 1"]);
+        let at: Vec<_> = errors.iter().map(|e| (e.span.line, e.span.col, e.span.start)).collect();
+        assert_eq!(at, [(1, 9, 8), (3, 11, 34)]);
+
+        let long = YololParser { max_line_length: 5, max_lines: 2, ..Default::default() };
+        let (program, errors) = long.parse_recovering(":a=1\n:b=2 :c=3\n:d=4");
+        assert_eq!(program[1].len(), 2);
+        assert_eq!(program[2].to_string(), ":d = 4");
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, [
+            "line 2, column 6: Line length too long: 9 bytes",
+            "line 3, column 1: Too many lines in program! Found 3 of them.",
+        ]);
+
+        let (program, errors) = YololParser::default().parse_recovering(":d=\"x\"");
+        assert!(errors.is_empty());
+        assert_eq!(program.len(), 20);
+    }
+
+    #[test]
+    fn unicode_policy() -> Result<()> {
+        let src = ":a=\"caf\u{e9}\"\nif 1 then :b=\"\\u2192\" end";
+        let parser = |unicode| YololParser { unicode, ..Default::default() };
+        let err = parser(UnicodePolicy::Reject).parse(src).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "line 1, column 1: non-ASCII character '\u{e9}' at byte 3",
+        );
+        let program = parser(UnicodePolicy::Replace).parse(src)?;
+        assert_eq!(program[0].to_string(), ":a = \"caf?\"");
+        assert_eq!(program[1].to_string(), "if 1 then :b = \"?\" end");
+        assert_eq!(parser(UnicodePolicy::Keep).parse(src)?, YololParser::default().parse(src)?);
+        Ok(())
+    }
+
+    #[test]
+    fn extensions() -> Result<()> {
+        let src = ":a=ln(:b)*2 :c=exp (1)+LOG10(100) ln=2 exp=ln+exp\n:d=log(:b*0.8, 2)^2 log=1";
+        let err = YololParser::default().parse(src).unwrap_err();
+        assert_eq!(err.to_string(), "line 1, column 4: 'ln' is an extension");
+        let err = YololParser::default().parse(":d=log(8, 2)").unwrap_err();
+        assert_eq!(err.to_string(), "line 1, column 4: 'log' is an extension");
+        let parser = YololParser { extensions: true, ..Default::default() };
+        let program = parser.clone().parse(src)?;
+        let printed = program[0].to_string();
+        assert_eq!(printed, ":a = ln(:b) * 2 :c = exp(1) + log10(100) ln = 2 exp = ln + exp");
+        assert_eq!(program[1].to_string(), ":d = log(:b * 0.8, 2) ^ 2 log = 1");
+        let printed = format!("{}\n{}", printed, program[1]);
+        assert_eq!(parser.parse(&printed)?, program);
+
+        let mut machine = crate::ir::IRMachine::from_ast(Default::default(), program);
+        machine.set_ident(&Ident::global("b"), Number::from(10).into());
+        machine.step();
+        let get = |name| machine.get_ident_value(&Ident::global(name)).to_string();
+        assert_eq!(get("a"), "4.604");
+        assert_eq!(get("c"), "4.718");
+        machine.step();
+        assert_eq!(machine.get_ident_value(&Ident::global("d")).to_string(), "9");
+        Ok(())
+    }
+
     #[test]
     fn simple_comment_test() -> Result<()> {
         let program = YololParser::default().parse("// WOW!
@@ -658,7 +1684,7 @@ mod tests {
                 ident: Ident::local("_"),
             }.into(),
             vec![Statement::Ite(Ident::local("x").into(),
-                vec![Statement::Assign(Ident::local("x").into(), AssignOp::Pow.into(), 2.into())],
+                vec![Statement::Assign(Ident::local("x"), AssignOp::Pow.into(), 2.into())],
                 vec![],
             )],
             vec![],
@@ -720,4 +1746,76 @@ mod tests {
         )]);
         Ok(())
     }
+
+    #[test]
+    fn display_round_trip() -> Result<()> {
+        let program = YololParser::unrestricted().parse(r#"
+        y=a+b+c+(d+e) z=a-(b-c) w=(a^b)^c^d
+        x=not (a and b or c) x=(not a)+1 x=-(-a) x=abs (a+b) x=(a!)+(-b)!
+        if a==2 then goto x++ :o="hi\n\\x" else y-=--x end
+        :x=sqrt 3! b=(1==2)>3 c=1 or 2 and 3 d=1 or (2 and 3) e=-2^2 f=-(2^2)
+        "#)?;
+        for line in program.iter() {
+            let printed = line.to_string();
+            let reparsed = YololParser::unrestricted().parse(&printed)?;
+            assert_eq!(reparsed[0], *line, "printed as `{}`", printed);
+        }
+
+        // `a=b` then `--c` mustn't print as `a = b --c`, which is `a = b - -c`
+        let mut line = YololParser::unrestricted().parse("a=b")?.lines.remove(0);
+        line.extend(YololParser::unrestricted().parse("--c")?.lines.remove(0).stmts);
+        let reparsed = YololParser::unrestricted().parse(&line.to_string())?;
+        assert_eq!(reparsed[0], line, "printed as `{}`", line);
+        Ok(())
+    }
+
+    #[test]
+    fn string_escapes() -> Result<()> {
+        let program = YololParser::default().parse(r#"a="\u41\u263a!" b="\q\""#)?;
+        let strings: Vec<_> = program[0]
+            .stmts
+            .iter()
+            .map(|s| match s {
+                Statement::Assign(_, _, Expr::String(s)) => s.to_string(),
+                s => panic!("expected a string assignment, found {}", s),
+            })
+            .collect();
+        assert_eq!(strings, ["A\u{263a}!", "\\q\\"]);
+        assert!(YololParser::default().parse(r#"a="\u""#).is_err());
+        assert!(YololParser::default().parse(r#"a="\uzz""#).is_err());
+        assert!(YololParser::default().parse(r#"a="\u110000""#).is_err());
+        Ok(())
+    }
+
+    /// Parses, compiles and runs lots of random token soup, none of which should panic.
+    #[test]
+    fn no_panics_on_arbitrary_input() {
+        const TOKENS: &[&str] = &[
+            "a", ":b", "c", "1", "0", "2.5", "-1", "9223372036854775.807", "\"s\"", "\"\\u41\"",
+            "\"\\q\"", "\"\\\"", "+", "-", "*", "/", "%", "^", "==", "!=", "<", ">=", "and", "or",
+            "not", "abs", "sqrt", "sin", "acos", "!", "++", "--", "(", ")", "if", "then", "else",
+            "end", "goto", "=", "+=", "-=", "/=", "%=", "^=", " ", " ", " ", "\n", "//",
+            "// assert: ", "\\", "\"", "字",
+        ];
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+        for _ in 0..3000 {
+            let len = next() % 40;
+            let src: String = (0..len).map(|_| TOKENS[next() % TOKENS.len()]).collect();
+            for parser in [YololParser::default(), YololParser::unrestricted()] {
+                if let std::result::Result::Ok(program) = parser.parse(&src) {
+                    let options = crate::ir::CodegenOptions {
+                        check_asserts: true,
+                        ..Default::default()
+                    };
+                    crate::ir::IRMachine::from_ast(options, program).step_repeat(30);
+                }
+            }
+        }
+    }
 }
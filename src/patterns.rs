@@ -0,0 +1,178 @@
+use crate::arith::YString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Byte(u8),
+    /// `?`
+    AnyByte,
+    /// `*`
+    AnyRun,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Shape {
+    Exact(Vec<u8>),
+    Prefix(Vec<u8>),
+    Suffix(Vec<u8>),
+    Contains(Vec<u8>),
+    General(Vec<Token>),
+}
+
+/// A compiled glob pattern, matching bytes directly so [`YString`]s never need converting.
+///
+/// `*` matches any run of bytes, `?` matches any one byte, and `\` makes the next byte
+/// literal. Patterns that are only a prefix, suffix or substring check are matched as such.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    shape: Shape,
+    ignore_case: bool,
+}
+
+fn literal(tokens: &[Token]) -> Option<Vec<u8>> {
+    tokens
+        .iter()
+        .map(|t| if let Token::Byte(b) = t { Some(*b) } else { None })
+        .collect()
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::with_capacity(pattern.len());
+        let mut bytes = pattern.bytes();
+        while let Some(b) = bytes.next() {
+            tokens.push(match b {
+                b'*' if tokens.last() == Some(&Token::AnyRun) => continue,
+                b'*' => Token::AnyRun,
+                b'?' => Token::AnyByte,
+                b'\\' => Token::Byte(bytes.next().unwrap_or(b'\\')),
+                b => Token::Byte(b),
+            });
+        }
+
+        let shape = match tokens.as_slice() {
+            [Token::AnyRun, mid@.., Token::AnyRun] => literal(mid).map(Shape::Contains),
+            [Token::AnyRun, rest@..] => literal(rest).map(Shape::Suffix),
+            [rest@.., Token::AnyRun] => literal(rest).map(Shape::Prefix),
+            all => literal(all).map(Shape::Exact),
+        }.unwrap_or(Shape::General(tokens));
+
+        Pattern {
+            shape,
+            ignore_case: false,
+        }
+    }
+
+    /// Matches any string starting with `prefix`, with no wildcards.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        Pattern {
+            shape: Shape::Prefix(prefix.to_vec()),
+            ignore_case: false,
+        }
+    }
+
+    /// Compare ASCII letters case-insensitively, like field names are.
+    pub fn ignore_case(mut self) -> Self {
+        self.ignore_case = true;
+        self
+    }
+
+    fn eq(&self, a: &[u8], b: &[u8]) -> bool {
+        if self.ignore_case {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
+        }
+    }
+
+    pub fn matches(&self, s: &[u8]) -> bool {
+        match &self.shape {
+            Shape::Exact(e) => self.eq(s, e),
+            Shape::Prefix(p) => s.len() >= p.len() && self.eq(&s[..p.len()], p),
+            Shape::Suffix(p) => s.len() >= p.len() && self.eq(&s[s.len() - p.len()..], p),
+            Shape::Contains(p) if p.is_empty() => true,
+            Shape::Contains(p) if self.ignore_case => s.windows(p.len()).any(|w| self.eq(w, p)),
+            Shape::Contains(p) => memchr::memmem::find(s, p).is_some(),
+            Shape::General(tokens) => self.matches_general(tokens, s),
+        }
+    }
+
+    pub fn matches_ystring(&self, s: &YString) -> bool {
+        self.matches(s)
+    }
+
+    // Greedy, backtracking only to the last `*`, which is enough since a later `*` can always
+    // absorb whatever an earlier one would have.
+    fn matches_general(&self, tokens: &[Token], s: &[u8]) -> bool {
+        let (mut t, mut i) = (0, 0);
+        let mut star = None;
+        while i < s.len() {
+            match tokens.get(t) {
+                Some(Token::AnyRun) => {
+                    star = Some((t, i));
+                    t += 1;
+                },
+                Some(Token::AnyByte) => {
+                    t += 1;
+                    i += 1;
+                },
+                Some(&Token::Byte(b)) if self.eq(&[b], &s[i..=i]) => {
+                    t += 1;
+                    i += 1;
+                },
+                _ => if let Some((star_t, star_i)) = star {
+                    t = star_t + 1;
+                    i = star_i + 1;
+                    star = Some((star_t, star_i + 1));
+                } else {
+                    return false;
+                },
+            }
+        }
+        tokens[t..].iter().all(|&t| t == Token::AnyRun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matching() {
+        let cases = [
+            ("door", "door", true),
+            ("door", "doors", false),
+            ("door*", "door_left", true),
+            ("door*", "dor", false),
+            ("*_out", "pump_out", true),
+            ("*_out", "pump_in", false),
+            ("*mid*", "a_mid_b", true),
+            ("*mid*", "a_mod_b", false),
+            ("*", "", true),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("a*bc", "abcbc", true),
+            ("\\*lit", "*lit", true),
+            ("\\*lit", "xlit", false),
+        ];
+        for (pattern, s, expected) in cases {
+            assert_eq!(
+                Pattern::new(pattern).matches_ystring(&s.into()),
+                expected,
+                "'{}' against '{}'",
+                pattern,
+                s,
+            );
+        }
+    }
+
+    #[test]
+    fn prefix_and_case() {
+        assert!(Pattern::prefix(b"nav_").matches(b"nav_heading"));
+        assert!(!Pattern::prefix(b"nav_").matches(b"NAV_heading"));
+        assert!(Pattern::prefix(b"nav_").ignore_case().matches(b"NAV_heading"));
+        assert!(Pattern::new("*_Speed").ignore_case().matches(b"engine_speed"));
+        assert!(Pattern::new("e?g*_SPEED").ignore_case().matches(b"engine_speed"));
+    }
+}
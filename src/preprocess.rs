@@ -0,0 +1,439 @@
+//! A preprocessor run over source before parsing, for sharing code within and between chips
+//! without copying it by hand. Directives go on lines of their own:
+//!
+//! ```text
+//! #include "physics"
+//! #macro clamp(x, lo, hi)
+//! t=x t+=(lo-t)*(t<lo) t-=(t-hi)*(t>hi) x=t
+//! #end
+//! @clamp(:throttle, 0, 1) goto 1
+//! ```
+//!
+//! `@name(args)` is replaced by the body of the macro, with each parameter replaced by the
+//! argument's source. Bodies with several lines are joined onto the line they're called from,
+//! so lines keep their numbers for `goto`s and a runtime error skips the whole call. Locals
+//! in a body which aren't parameters get a prefix unique to the call, like `_clamp1_t`, so they
+//! can't clash with the caller's or another call's. Globals are left alone, and comments in
+//! bodies are dropped. Included sources are registered with [`Preprocessor::add_include`].
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use ahash::AHashMap;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use diagnostics::SpannedError;
+use opt::Position;
+use parser::{Program, YololParser, KEYWORDS};
+use super::*;
+
+/// How deeply macros can call each other, to catch one calling itself.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    includes: AHashMap<String, String>,
+}
+
+/// Where a piece of preprocessed source was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// The include it's from, or `None` for the source being preprocessed.
+    pub file: Option<String>,
+    /// 1-based, counting characters as written.
+    pub position: Position,
+    /// The macro call it was expanded from, if it's from a macro body.
+    pub called_at: Option<Box<Origin>>,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}:{}", self.file.as_deref().unwrap_or("<source>"), self.position)?;
+        match &self.called_at {
+            Some(call) => write!(f, ", expanded from {}", call),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Source with every directive carried out, from [`Preprocessor::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preprocessed {
+    pub source: String,
+    /// `(generated, origin)` for every token, in order.
+    mappings: Vec<(Position, Origin)>,
+}
+
+impl Preprocessed {
+    /// Where the code at `generated` in [`Preprocessed::source`] was written, or `None` if its
+    /// line is empty. Past the end of a line is just after its last token, and columns within a
+    /// renamed local are only approximate.
+    pub fn origin(&self, generated: Position) -> Option<Origin> {
+        let i = self.mappings.partition_point(|(from, ..)| *from <= generated).checked_sub(1)?;
+        let (from, origin) = &self.mappings[i];
+        (from.line == generated.line).then(|| Origin {
+            position: Position {
+                line: origin.position.line,
+                col: origin.position.col + (generated.col - from.col),
+            },
+            ..origin.clone()
+        })
+    }
+
+    /// Parses the source, saying where the code was written if it fails to.
+    pub fn parse(&self, parser: YololParser) -> Result<Program> {
+        parser.parse(&self.source).map_err(|error| {
+            let at = error.downcast_ref::<SpannedError>().map(|e| Position {
+                line: e.span.line,
+                col: e.span.col,
+            });
+            match at.and_then(|at| self.origin(at)) {
+                Some(origin) => error.context(format!("written at {}", origin)),
+                None => error,
+            }
+        })
+    }
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `source` available to `#include "name"`.
+    pub fn add_include(&mut self, name: &str, source: &str) -> &mut Self {
+        self.includes.insert(name.to_string(), source.to_string());
+        self
+    }
+
+    pub fn run(&self, source: &str) -> Result<Preprocessed> {
+        let mut expander = Expander {
+            includes: &self.includes,
+            macros: AHashMap::new(),
+            calls: 0,
+            including: Vec::new(),
+            lines: Vec::new(),
+        };
+        expander.file(None, source)?;
+
+        let mut text = Vec::with_capacity(expander.lines.len());
+        let mut mappings = Vec::new();
+        for (i, line) in expander.lines.into_iter().enumerate() {
+            let mut col = 1;
+            let mut s = String::new();
+            for token in line {
+                let len = token.text.chars().count();
+                mappings.push((Position { line: i + 1, col }, token.origin));
+                s.push_str(&token.text);
+                col += len;
+            }
+            text.push(s);
+        }
+        Ok(Preprocessed { source: text.join("\n"), mappings })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    origin: Origin,
+}
+
+impl Token {
+    fn is(&self, text: &str) -> bool {
+        self.text == text
+    }
+
+    fn is_space(&self) -> bool {
+        self.text.chars().all(char::is_whitespace)
+    }
+
+    fn is_local(&self) -> bool {
+        let mut chars = self.text.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && !KEYWORDS.contains(&self.text.to_ascii_lowercase().as_str())
+    }
+}
+
+/// Splits a line into tokens, each knowing where it was written. `origin` is for the start of
+/// the line.
+fn tokenize(line: &str, origin: &Origin) -> Vec<Token> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let c = chars[start];
+        let len_while = |from: usize, f: &dyn Fn(char) -> bool| {
+            chars[from..].iter().take_while(|&&c| f(c)).count()
+        };
+        let len = match c {
+            '/' if chars.get(start + 1) == Some(&'/') => chars.len() - start,
+            '"' => {
+                let mut end = start + 1;
+                while end < chars.len() && chars[end] != '"' {
+                    end += if chars[end] == '\\' { 2 } else { 1 };
+                }
+                (end + 1).min(chars.len()) - start
+            },
+            ':' | '@' => 1 + len_while(start + 1, &word),
+            c if c.is_whitespace() => len_while(start, &|c| c.is_whitespace()),
+            c if word(c) => len_while(start, &word),
+            _ => 1,
+        };
+        tokens.push(Token {
+            text: chars[start..start + len].iter().collect(),
+            origin: Origin {
+                position: Position { line: origin.position.line, col: start + 1 },
+                ..origin.clone()
+            },
+        });
+        start += len;
+    }
+    tokens
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(String, Origin)>,
+}
+
+struct Expander<'a> {
+    includes: &'a AHashMap<String, String>,
+    macros: AHashMap<String, Macro>,
+    /// How many macro calls have been expanded, to number their locals.
+    calls: usize,
+    /// The includes being read, innermost last.
+    including: Vec<String>,
+    lines: Vec<Vec<Token>>,
+}
+
+impl Expander<'_> {
+    fn file(&mut self, file: Option<&str>, source: &str) -> Result<()> {
+        let mut lines = source.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let origin = Origin {
+                file: file.map(str::to_string),
+                position: Position { line: i + 1, col: 1 },
+                called_at: None,
+            };
+            let directive = line.trim();
+            if let Some(header) = directive.strip_prefix("#macro") {
+                let (name, params) = header
+                    .trim()
+                    .strip_suffix(')')
+                    .and_then(|h| h.split_once('('))
+                    .with_context(|| format!("{}: expected `#macro name(params)`", origin))?;
+                let params: Vec<_> = params
+                    .split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                let mut body = Vec::new();
+                loop {
+                    let (j, line) = lines
+                        .next()
+                        .with_context(|| format!("{}: macro `{}` has no `#end`", origin, name))?;
+                    if line.trim() == "#end" {
+                        break;
+                    }
+                    let position = Position { line: j + 1, col: 1 };
+                    body.push((line.to_string(), Origin { position, ..origin.clone() }));
+                }
+                let name = name.trim().to_string();
+                ensure!(
+                    !self.macros.contains_key(&name),
+                    "{}: macro `{}` defined twice",
+                    origin,
+                    name,
+                );
+                self.macros.insert(name, Macro { params, body });
+            } else if let Some(name) = directive.strip_prefix("#include") {
+                let name = name.trim().trim_matches('"');
+                let source = self.includes
+                    .get(name)
+                    .with_context(|| format!("{}: nothing to include called `{}`", origin, name))?;
+                let cycle = self.including.iter().any(|i| i == name);
+                ensure!(!cycle, "{}: `{}` includes itself", origin, name);
+                self.including.push(name.to_string());
+                self.file(Some(name), source)?;
+                self.including.pop();
+            } else if directive.starts_with('#') {
+                bail!("{}: unknown directive `{}`", origin, directive);
+            } else {
+                let tokens = tokenize(line, &origin);
+                let line = self.expand(tokens, 0)?;
+                self.lines.push(line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands the macro calls in a line.
+    fn expand(&mut self, tokens: Vec<Token>, depth: usize) -> Result<Vec<Token>> {
+        let mut line = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            let Some(name) = token.text.strip_prefix('@') else {
+                line.push(token);
+                continue;
+            };
+            while tokens.next_if(Token::is_space).is_some() {}
+            ensure!(
+                tokens.next().is_some_and(|t| t.is("(")),
+                "{}: expected `(` after `@{}`",
+                token.origin,
+                name,
+            );
+            let mut args = vec![Vec::new()];
+            let mut nesting = 0;
+            loop {
+                let arg = tokens
+                    .next()
+                    .ok_or_else(|| anyhow!("{}: `@{}(` isn't closed", token.origin, name))?;
+                match arg.text.as_str() {
+                    ")" if nesting == 0 => break,
+                    "," if nesting == 0 => args.push(Vec::new()),
+                    "(" | ")" => {
+                        nesting += if arg.is("(") { 1 } else { -1 };
+                        args.last_mut().unwrap().push(arg);
+                    },
+                    _ => args.last_mut().unwrap().push(arg),
+                }
+            }
+            let body = self.call(name, args, token.origin, depth)?;
+            line.extend(body);
+        }
+        Ok(line)
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        mut args: Vec<Vec<Token>>,
+        call_site: Origin,
+        depth: usize,
+    ) -> Result<Vec<Token>> {
+        ensure!(depth < MAX_DEPTH, "{}: macros call each other too deeply", call_site);
+        let mac = self.macros
+            .get(name)
+            .with_context(|| format!("{}: no macro called `{}`", call_site, name))?;
+        for arg in args.iter_mut() {
+            let end = arg.iter().rposition(|t| !t.is_space()).map_or(0, |i| i + 1);
+            arg.truncate(end);
+            let start = arg.iter().position(|t| !t.is_space()).unwrap_or(end);
+            arg.drain(..start);
+        }
+        if args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        ensure!(
+            args.len() == mac.params.len(),
+            "{}: `{}` takes {} arguments, but was given {}",
+            call_site,
+            name,
+            mac.params.len(),
+            args.len(),
+        );
+
+        self.calls += 1;
+        let prefix = format!("_{}{}_", name, self.calls);
+        let mut tokens: Vec<Token> = Vec::new();
+        for (line, origin) in mac.body.iter() {
+            let origin = Origin { called_at: Some(Box::new(call_site.clone())), ..origin.clone() };
+            // the body's lines are joined by a space
+            while tokens.last().is_some_and(Token::is_space) {
+                tokens.pop();
+            }
+            if !tokens.is_empty() {
+                tokens.push(Token { text: " ".into(), origin: origin.clone() });
+            }
+            for mut token in tokenize(line, &origin) {
+                if token.text.starts_with("//") {
+                    continue;
+                }
+                if !token.is_local() {
+                    tokens.push(token);
+                    continue;
+                }
+                match mac.params.iter().position(|p| *p == token.text) {
+                    // anything longer than a token is bracketed, to keep it whole
+                    Some(i) if args[i].len() > 1 => {
+                        let bracket = |text: &str| Token { text: text.into(), ..token.clone() };
+                        tokens.push(bracket("("));
+                        tokens.extend(args[i].iter().cloned());
+                        tokens.push(bracket(")"));
+                    },
+                    Some(i) => tokens.extend(args[i].iter().cloned()),
+                    None => {
+                        token.text.insert_str(0, &prefix);
+                        tokens.push(token);
+                    },
+                }
+            }
+        }
+        while tokens.last().is_some_and(Token::is_space) {
+            tokens.pop();
+        }
+        self.expand(tokens, depth + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use simple_interp::SimpleInterp;
+    use parser::Ident;
+    use arith::Value;
+    use super::*;
+
+    #[test]
+    fn expands_macros() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_include("lib", "#macro clamp(x, lo, hi)\n\
+            t=x t+=(lo-t)*(t<lo) // no lower than lo\n\
+            t-=(t-hi)*(t>hi) x=t\n\
+            #end");
+        let source = "#include \"lib\"\n\
+            #macro twice(x)\n\
+            @clamp(x, 0, 10) x*=2\n\
+            #end\n\
+            t=\"mine\" a=7 b=-3 @twice(a) :a=a @clamp(b, 0, 5) :b=b :c=t\n\
+            :n+=1 goto 2";
+        let preprocessed = preprocessor.run(source).unwrap();
+        assert_eq!(preprocessed.source, "t=\"mine\" a=7 b=-3 _clamp2_t=a \
+            _clamp2_t+=(0-_clamp2_t)*(_clamp2_t<0) \
+            _clamp2_t-=(_clamp2_t-10)*(_clamp2_t>10) a=_clamp2_t a*=2 :a=a \
+            _clamp3_t=b _clamp3_t+=(0-_clamp3_t)*(_clamp3_t<0) \
+            _clamp3_t-=(_clamp3_t-5)*(_clamp3_t>5) b=_clamp3_t :b=b :c=t\n\
+            :n+=1 goto 2");
+
+        let program = preprocessed.parse(YololParser::unrestricted()).unwrap();
+        let mut interp = SimpleInterp::new(program);
+        interp.step_lines(3);
+        let get = |name| interp.values().get(&Ident::global(name)).cloned();
+        // `goto 2` still lands on the line written second
+        assert_eq!(get("n"), Some(Value::Num(2.into())));
+        assert_eq!(get("a"), Some(Value::Num(14.into())));
+        assert_eq!(get("b"), Some(Value::Num(0.into())));
+        assert_eq!(get("c"), Some(Value::Str("mine".into())));
+
+        // partway through `clamp`, called from `twice`
+        let origin = preprocessed.origin(Position { line: 1, col: 72 }).unwrap();
+        assert_eq!(origin.to_string(), "lib:3:3, expanded from <source>:3:1, \
+            expanded from <source>:5:19");
+
+        // a call stays inside an `if` around it
+        let guarded = "#include \"lib\"\nif :x then @clamp(:x, 0, 1) end";
+        let guarded = preprocessor.run(guarded).unwrap();
+        assert!(guarded.parse(YololParser::unrestricted()).is_ok());
+
+        let error = preprocessor.run("@twice(1)").unwrap_err();
+        assert!(error.to_string().contains("no macro called `twice`"), "{}", error);
+        let error = Preprocessor::new()
+            .run("#macro bad(x)\nx=x+\n#end\na=1 @bad(b)")
+            .unwrap()
+            .parse(YololParser::default())
+            .unwrap_err();
+        let message = format!("{:#}", error);
+        let expected = "written at <source>:2:5, expanded from <source>:4:5";
+        assert!(message.contains(expected), "{}", message);
+    }
+}
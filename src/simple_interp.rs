@@ -1,8 +1,10 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use anyhow::Result;
 use super::*;
 use parser::*;
 use arith::*;
 use ahash::AHashMap;
+use ir::GotoPolicy;
 
 #[derive(Debug, Clone, Copy)]
 enum ExecuteErr {
@@ -28,11 +30,68 @@ impl From<RuntimeErr> for ExecuteErr {
     }
 }
 
+/// One plain-English sentence describing an executed statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Narration {
+    /// The 1-based line the statement is on.
+    pub line: usize,
+    pub text: String,
+}
+
+impl Display for Narration {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "line {}: {}", self.line, self.text)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NarrationOptions {
+    /// The most sentences kept for a single executed line, the rest are dropped.
+    pub max_per_line: usize,
+    /// The 1-based lines to narrate, or `None` to narrate every line.
+    pub lines: Option<Vec<usize>>,
+}
+
+impl Default for NarrationOptions {
+    fn default() -> Self {
+        Self {
+            max_per_line: usize::MAX,
+            lines: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Narrator {
+    options: NarrationOptions,
+    said_this_line: usize,
+    sentences: Vec<Narration>,
+}
+
+impl Narrator {
+    fn say(narrator: &mut Option<Narrator>, line: usize, text: impl FnOnce() -> String) {
+        if let Some(narrator) = narrator {
+            let line = line + 1;
+            let wanted = narrator.options.lines.as_ref().is_none_or(|lines| lines.contains(&line));
+            if wanted && narrator.said_this_line < narrator.options.max_per_line {
+                narrator.said_this_line += 1;
+                narrator.sentences.push(Narration {
+                    line,
+                    text: text(),
+                });
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SimpleInterp {
     values: AHashMap<Ident, Value>,
     line: usize,
     ast: Program,
+    narrator: Option<Narrator>,
+    goto_policy: GotoPolicy,
+    compat: Compat,
 }
 
 impl From<Program> for SimpleInterp {
@@ -41,6 +100,9 @@ impl From<Program> for SimpleInterp {
             values: AHashMap::default(),
             line: 0,
             ast,
+            narrator: None,
+            goto_policy: GotoPolicy::Clamp,
+            compat: Compat::default(),
         }
     }
 }
@@ -62,11 +124,15 @@ impl SimpleInterp {
         Ok(entry.clone())
     }
 
-    fn eval_expr(values: &mut AHashMap<Ident, Value>, expr: &Expr) -> ExecuteResult<Value> {
+    fn eval_expr(
+        values: &mut AHashMap<Ident, Value>,
+        compat: Compat,
+        expr: &Expr,
+    ) -> ExecuteResult<Value> {
         match expr {
             &Expr::Binop(ref l, op, ref r) => {
-                let r = Self::eval_expr(values, r)?;
-                let mut l = Self::eval_expr(values, l)?;
+                let r = Self::eval_expr(values, compat, r)?;
+                let mut l = Self::eval_expr(values, compat, l)?;
                 Ok(match op {
                     Binop::And => Value::Num((l.as_bool() && r.as_bool()).into()),
                     Binop::Or => Value::Num((l.as_bool() || r.as_bool()).into()),
@@ -86,23 +152,26 @@ impl SimpleInterp {
                         ExecuteErr::from_option(l.as_number())?
                         / ExecuteErr::from_option(r.as_number())?
                     )?.into(),
-                    Binop::Mod => (
-                        ExecuteErr::from_option(l.as_number())?
-                        % ExecuteErr::from_option(r.as_number())?
+                    Binop::Mod => compat.rem(
+                        ExecuteErr::from_option(l.as_number())?,
+                        ExecuteErr::from_option(r.as_number())?,
                     )?.into(),
                     Binop::Pow => ExecuteErr::from_option(l.as_number())?
                         .pow(ExecuteErr::from_option(r.as_number())?)
                         .into(),
+                    Binop::Log => ExecuteErr::from_option(l.as_number())?
+                        .log(ExecuteErr::from_option(r.as_number())?)
+                        .into(),
                     Binop::Eq => Value::Num((l == r).into()),
                     Binop::Ne => Value::Num((l != r).into()),
-                    Binop::Le => Value::Num((l <= r).into()),
-                    Binop::Lt => Value::Num((l < r).into()),
-                    Binop::Ge => Value::Num((l >= r).into()),
-                    Binop::Gt => Value::Num((l > r).into()),
+                    Binop::Le => Value::Num(l.cmp_yolol(&r).is_le().into()),
+                    Binop::Lt => Value::Num(l.cmp_yolol(&r).is_lt().into()),
+                    Binop::Ge => Value::Num(l.cmp_yolol(&r).is_ge().into()),
+                    Binop::Gt => Value::Num(l.cmp_yolol(&r).is_gt().into()),
                 })
             },
             &Expr::Unop(op, ref expr) => {
-                let val = Self::eval_expr(values, expr)?;
+                let val = Self::eval_expr(values, compat, expr)?;
                 if op == Unop::Not {
                     return Ok((!val).into());
                 }
@@ -110,7 +179,7 @@ impl SimpleInterp {
                 Ok(match op {
                     Unop::Neg => -n,
                     Unop::Not => unreachable!(),
-                    Unop::Abs => n.abs(),
+                    Unop::Abs => compat.abs(n),
                     Unop::Sqrt => n.sqrt(),
                     Unop::Fact => n.fact(),
                     Unop::Sin => n.sin(),
@@ -119,37 +188,64 @@ impl SimpleInterp {
                     Unop::Asin => n.asin(),
                     Unop::Acos => n.acos(),
                     Unop::Atan => n.atan(),
+                    Unop::Exp => n.exp(),
+                    Unop::Ln => n.ln(),
+                    Unop::Log10 => n.log10(),
                 }.into())
             },
             Expr::Incdec(incdec) => Self::eval_incdec(values, incdec),
             Expr::Ident(ident) => Ok(values.entry(ident.clone()).or_default().clone()),
-            Expr::Number(n) => Ok(n.clone().into()),
+            Expr::Number(n) => Ok((*n).into()),
             Expr::String(s) => Ok(s.clone().into()),
         }
     }
 
     fn step_stmt(
         line: usize,
+        goto: (GotoPolicy, usize),
+        compat: Compat,
         values: &mut AHashMap<Ident, Value>,
+        narrator: &mut Option<Narrator>,
         stmt: &Statement,
     ) -> ExecuteResult<()> {
         match stmt {
             Statement::Goto(expr) => {
-                let number = ExecuteErr::from_option(Self::eval_expr(values, expr)?.as_number())?;
-                let line = number.as_f32().floor().clamp(1.0, 20.0) as usize;
-                Err(ExecuteErr::Goto(line - 1))
+                let number = Self::eval_expr(values, compat, expr)?.as_number();
+                let number = ExecuteErr::from_option(number)?;
+                let (policy, lines) = goto;
+                let target = policy.resolve(number, lines);
+                Narrator::say(narrator, line, || match target {
+                    Some(target) => format!("jump to line {} (goto {})", target + 1, expr),
+                    None => format!("line {} is out of range (goto {})", number, expr),
+                });
+                Err(target.map_or(ExecuteErr::RuntimeErr, ExecuteErr::Goto))
             },
             Statement::Ite(i, t, e) => {
-                let stmts = if Self::eval_expr(values, i)?.as_bool() {
+                let condition = Self::eval_expr(values, compat, i)?.as_bool();
+                Narrator::say(narrator, line, || if condition {
+                    format!("{} is true, so run the then branch", i)
+                } else {
+                    format!("{} is false, so run the else branch", i)
+                });
+                let stmts = if condition {
                     t
                 } else {
                     e
                 };
-                Self::step_stmts(line, values, stmts)
+                Self::step_stmts(line, goto, compat, values, narrator, stmts)
+            },
+            Statement::Incdec(incdec) => {
+                let val = Self::eval_incdec(values, incdec)?;
+                Narrator::say(narrator, line, || format!(
+                    "{} {} ({})",
+                    if incdec.inc { "increment" } else { "decrement" },
+                    incdec.ident,
+                    val,
+                ));
+                Ok(())
             },
-            Statement::Incdec(incdec) => Self::eval_incdec(values, incdec).map(|_| ()),
             Statement::Assign(id, op, expr) => {
-                let val = Self::eval_expr(values, expr)?;
+                let val = Self::eval_expr(values, compat, expr)?;
                 let entry = values
                     .entry(id.clone())
                     .or_default();
@@ -166,14 +262,30 @@ impl SimpleInterp {
                     },
                     Some(AssignOp::Div) => ExecuteErr::from_option(entry.as_number_mut())?
                         .div_assign(ExecuteErr::from_option(val.as_number())?)?,
-                    Some(AssignOp::Mod) => ExecuteErr::from_option(entry.as_number_mut())?
-                        .rem_assign(ExecuteErr::from_option(val.as_number())?)?,
+                    Some(AssignOp::Mod) => {
+                        let entry = ExecuteErr::from_option(entry.as_number_mut())?;
+                        *entry = compat.rem(*entry, ExecuteErr::from_option(val.as_number())?)?;
+                    },
                     Some(AssignOp::Pow) => ExecuteErr::from_option(entry.as_number_mut())?
                         .pow_assign(ExecuteErr::from_option(val.as_number())?),
                     None => {
                         *entry = val;
                     },
                 }
+                Narrator::say(narrator, line, || {
+                    let new = &values[id];
+                    match op {
+                        None => format!("set {} to {} ({})", id, expr, new),
+                        Some(AssignOp::Add) => format!("add {} to {} ({})", expr, id, new),
+                        Some(AssignOp::Sub) => format!("subtract {} from {} ({})", expr, id, new),
+                        Some(AssignOp::Mul) => format!("multiply {} by {} ({})", id, expr, new),
+                        Some(AssignOp::Div) => format!("divide {} by {} ({})", id, expr, new),
+                        Some(AssignOp::Mod) =>
+                            format!("set {0:} to {0:} modulo {1:} ({2:})", id, expr, new),
+                        Some(AssignOp::Pow) =>
+                            format!("raise {} to the power of {} ({})", id, expr, new),
+                    }
+                });
                 Ok(())
             },
         }
@@ -181,24 +293,79 @@ impl SimpleInterp {
 
     fn step_stmts(
         line: usize,
+        goto: (GotoPolicy, usize),
+        compat: Compat,
         values: &mut AHashMap<Ident, Value>,
+        narrator: &mut Option<Narrator>,
         stmts: &[Statement],
     ) -> ExecuteResult<()> {
         for stmt in stmts {
-            Self::step_stmt(line, values, stmt)?;
+            Self::step_stmt(line, goto, compat, values, narrator, stmt)?;
         }
 
         Ok(())
     }
 
     pub fn step_line(&mut self) {
+        span!(TRACE, "step_line", line = self.line + 1);
         let line = &self.ast[self.line];
-        self.line = match Self::step_stmts(self.line, &mut self.values, line) {
-            Ok(_) | Err(ExecuteErr::RuntimeErr) => self.line + 1,
+        if let Some(narrator) = &mut self.narrator {
+            narrator.said_this_line = 0;
+        }
+        let goto = (self.goto_policy, self.ast.len());
+        let next_line = (self.line + 1) % self.ast.len();
+        let result = Self::step_stmts(
+            self.line,
+            goto,
+            self.compat,
+            &mut self.values,
+            &mut self.narrator,
+            line,
+        );
+        self.line = match result {
+            Ok(_) => next_line,
+            Err(ExecuteErr::RuntimeErr) => {
+                event!(DEBUG, line = self.line + 1, "runtime error");
+                Narrator::say(&mut self.narrator, self.line, || {
+                    "runtime error, so skip the rest of the line".to_string()
+                });
+                next_line
+            },
             Err(ExecuteErr::Goto(line)) => line,
         };
     }
 
+    pub fn set_goto_policy(&mut self, goto_policy: GotoPolicy) {
+        self.goto_policy = goto_policy;
+    }
+
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    /// Start describing every executed statement. Sentences build up until taken with
+    /// [`SimpleInterp::take_narration`].
+    pub fn narrate(&mut self, options: NarrationOptions) {
+        self.narrator = Some(Narrator {
+            options,
+            said_this_line: 0,
+            sentences: Vec::new(),
+        });
+    }
+
+    /// Stop describing executed statements, dropping any sentences not yet taken.
+    pub fn stop_narrating(&mut self) {
+        self.narrator = None;
+    }
+
+    /// Take the sentences built up since the last call.
+    pub fn take_narration(&mut self) -> Vec<Narration> {
+        self.narrator
+            .as_mut()
+            .map(|narrator| std::mem::take(&mut narrator.sentences))
+            .unwrap_or_default()
+    }
+
     pub fn step_lines(&mut self, lines: usize) {
         for _ in 0..lines {
             self.step_line();
@@ -590,4 +757,35 @@ goto8"#
     fn many_lines() {
         tester(true, &("\n".repeat(30) + r#":output="ok" goto30"#));
     }
+
+    #[test]
+    fn narration() {
+        let src = "a=1 b=a+2 if b>2 then a++ end\nb/=0 b=3\ngoto 1";
+        let program = YololParser::default().parse(src).unwrap();
+        let mut interp = SimpleInterp::new(program);
+        interp.narrate(Default::default());
+        interp.step_lines(3);
+        let narration = interp
+            .take_narration()
+            .into_iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(narration, vec![
+            "line 1: set a to 1 (1)",
+            "line 1: set b to a + 2 (3)",
+            "line 1: b > 2 is true, so run the then branch",
+            "line 1: increment a (2)",
+            "line 2: runtime error, so skip the rest of the line",
+            "line 3: jump to line 1 (goto 1)",
+        ]);
+
+        interp.narrate(NarrationOptions {
+            max_per_line: 1,
+            lines: Some(vec![1]),
+        });
+        interp.step_lines(3);
+        let narration = interp.take_narration();
+        assert_eq!(narration.len(), 1);
+        assert_eq!(narration[0].text, "set a to 1 (1)");
+    }
 }
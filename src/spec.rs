@@ -0,0 +1,190 @@
+//! Yolol's semantics, as a table of expressions and what they evaluate to, for checking any
+//! implementation against.
+//!
+//! Expressions can use the variables `s`, which starts as `"ab"`, and `n`, which starts as 5.
+
+use arith::Value;
+use parser::YololParser;
+use super::*;
+
+/// What evaluating an expression does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Evaluates to the value of this Yolol literal.
+    Value(&'static str),
+    /// Causes a runtime error.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Case {
+    pub expr: &'static str,
+    pub outcome: Outcome,
+}
+
+const fn ok(expr: &'static str, value: &'static str) -> Case {
+    Case { expr, outcome: Outcome::Value(value) }
+}
+
+const fn err(expr: &'static str) -> Case {
+    Case { expr, outcome: Outcome::Error }
+}
+
+pub const CASES: &[Case] = &[
+    // numbers have 3 decimal places, and arithmetic truncates toward zero
+    ok("1+2", "3"),
+    ok("0.1+0.2", "0.3"),
+    ok("5-7", "-2"),
+    ok("2*3.5", "7"),
+    ok("0.001*0.5", "0"),
+    ok("7/2", "3.5"),
+    ok("1/3", "0.333"),
+    ok("-1/3", "-0.333"),
+    err("1/0"),
+    ok("7%3", "1"),
+    ok("-7%3", "-1"),
+    ok("7%-3", "1"),
+    ok("10%3.1", "0.7"),
+    err("7%0"),
+    ok("2^10", "1024"),
+    ok("2^0.5", "1.414"),
+    ok("-n", "-5"),
+    // comparisons give 1 or 0
+    ok("1==1", "1"),
+    ok("1!=1", "0"),
+    ok("1<2", "1"),
+    ok("2<=2", "1"),
+    ok("3>2", "1"),
+    ok("2>=3", "0"),
+    // strings
+    ok("\"a\"+\"b\"", "\"ab\""),
+    ok("\"a\"+2", "\"a2\""),
+    ok("1+\"a\"", "\"1a\""),
+    ok("\"abcb\"-\"b\"", "\"abc\""),
+    ok("\"abc\"-\"x\"", "\"abc\""),
+    ok("\"a1\"-1", "\"a\""),
+    ok("1-\"1\"", "\"\""),
+    err("\"a\"*2"),
+    err("2*\"a\""),
+    err("\"a\"/2"),
+    err("\"a\"%2"),
+    err("\"a\"^2"),
+    err("-\"a\""),
+    ok("\"a\"==\"a\"", "1"),
+    ok("\"a\"==\"A\"", "0"),
+    ok("\"1\"==1", "0"),
+    ok("\"a\"<\"b\"", "1"),
+    ok("\"10\"<9", "1"),
+    ok("++s", "\"ab \""),
+    ok("--s", "\"a\""),
+    err("--(\"\")"),
+    // in expressions, postfix increments and decrements apply before the value is used, like
+    // prefix ones
+    ok("n++", "6"),
+    ok("++n", "6"),
+    ok("n-- * 2", "8"),
+    // logic treats 0 as false, and everything else as true
+    ok("1 and 0", "0"),
+    ok("2 and 3", "1"),
+    ok("0 or 0", "0"),
+    ok("0 or -1", "1"),
+    ok("not 0", "1"),
+    ok("not 0.5", "0"),
+    // functions take degrees
+    ok("abs -3", "3"),
+    ok("sqrt 16", "4"),
+    ok("sqrt 2", "1.414"),
+    ok("3!", "6"),
+    ok("0!", "1"),
+    ok("sin 0", "0"),
+    ok("cos 0", "1"),
+    ok("tan 45", "1"),
+    ok("asin 1", "90"),
+    ok("acos 1", "0"),
+    ok("atan 1", "45"),
+    err("abs \"a\""),
+    err("sqrt \"a\""),
+    err("\"a\"!"),
+    // precedence
+    ok("2+3*4", "14"),
+    ok("-2^2", "4"),
+    ok("1+2==3", "1"),
+    ok("not 1==2", "1"),
+];
+
+/// Whether `outcome` is `result`, where `None` is a runtime error.
+pub fn matches(outcome: Outcome, result: Option<&Value>) -> bool {
+    match (outcome, result) {
+        (Outcome::Error, None) => true,
+        (Outcome::Value(literal), Some(value)) => {
+            let expected = format!("r={}", literal);
+            let program = YololParser::unrestricted().parse(&expected).unwrap();
+            match &program[0].stmts[..] {
+                [parser::Statement::Assign(_, None, parser::Expr::Number(n))] =>
+                    *value == Value::Num(*n),
+                [parser::Statement::Assign(_, None, parser::Expr::String(s))] =>
+                    *value == Value::Str(s.clone()),
+                _ => panic!("'{}' isn't a literal", literal),
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Evaluates every case with `eval`, which returns the value of an expression or `None` for a
+/// runtime error, returning a message for each case it gets wrong.
+pub fn check(mut eval: impl FnMut(&str) -> Option<Value>) -> Vec<String> {
+    CASES
+        .iter()
+        .filter_map(|case| {
+            let result = eval(case.expr);
+            (!matches(case.outcome, result.as_ref())).then(|| format!(
+                "{} should be {}, but was {}",
+                case.expr,
+                match case.outcome {
+                    Outcome::Value(v) => v,
+                    Outcome::Error => "an error",
+                },
+                result.map_or("an error".to_string(), |v| v.to_string()),
+            ))
+        })
+        .collect()
+}
+
+/// A program evaluating `expr` into `:r`, which is left as `"!err"` if it errors.
+pub fn program(expr: &str) -> parser::Program {
+    let src = format!("s=\"ab\" n=5 :r=\"!err\" :r={}", expr);
+    YololParser::unrestricted().parse(&src).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::Ident;
+    use simple_interp::SimpleInterp;
+    use ir::IRMachine;
+    use super::*;
+
+    fn result(value: Value) -> Option<Value> {
+        (value != Value::Str("!err".into())).then_some(value)
+    }
+
+    #[test]
+    fn simple_interp_spec() {
+        let failures = check(|expr| {
+            let mut interp = SimpleInterp::new(program(expr));
+            interp.step_line();
+            result(interp.values()[&Ident::global("r")].clone())
+        });
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn ir_spec() {
+        let failures = check(|expr| {
+            let mut machine = IRMachine::from_ast(Default::default(), program(expr));
+            machine.step();
+            result(machine.get_ident_value(&Ident::global("r")))
+        });
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Generators for common Yolol routines. Those which can be made either as short or as fast as
+//! they can be go by an [`Objective`]: [`Objective::Characters`] makes the shortest code, and
+//! the others make code which never loops, taking the same ticks whatever the input.
+//! Temporaries are locals named `_t`, `_p` and `_i`, and every line fits on a chip.
+
+use std::fmt::Write;
+use crate::opt::Objective;
+use crate::parser::YololParser;
+
+/// Sets `output` to the square root of the non-negative `input`, rounded down. There's only one
+/// way to write this, as `sqrt` is both the shortest and fastest way to start. `sqrt` rounds to
+/// 3 decimal places, which goes up to a whole number just below large squares, so the result
+/// is corrected for that. `output` can't be `input`.
+pub fn isqrt(input: &str, output: &str) -> String {
+    format!("{o}=sqrt {i} {o}-={o}%1 {o}-={o}*{o}>{i}", i = input, o = output)
+}
+
+/// Sets `output` to the string `input` with copies of `pad` in front, up to `width` characters.
+/// The code goes on its own lines, starting with the 1-based `line`, as it finishes a line by
+/// causing a runtime error. `pad` can't be a quote or a newline. Fast code grows with `width`,
+/// going on to more lines, each taking a tick. The pads are written out on the first line, so
+/// `width` can't be much over 40.
+pub fn left_pad(
+    input: &str,
+    output: &str,
+    width: usize,
+    pad: char,
+    objective: Objective,
+    line: usize,
+) -> String {
+    padded(input, input, output, width, pad, objective, line)
+}
+
+/// Sets `output` to the number `input` as a string, with spaces in front up to `width`
+/// characters. Placed like [`left_pad`].
+pub fn fixed_width(
+    input: &str,
+    output: &str,
+    width: usize,
+    objective: Objective,
+    line: usize,
+) -> String {
+    padded(&format!("\"\"+{}", input), input, output, width, ' ', objective, line)
+}
+
+fn padded(
+    text: &str,
+    input: &str,
+    output: &str,
+    width: usize,
+    pad: char,
+    objective: Objective,
+    line: usize,
+) -> String {
+    assert!(pad != '"' && pad != '\n', "can't pad with {:?}", pad);
+    if width == 0 {
+        return format!("{}=\"\"+{}", output, input);
+    }
+
+    // Each character of the text removes a pad, until `_t--` runs out of characters or `_p--`
+    // runs out of pads and errors. Pads are taken from their own string, as taking them from
+    // the output would take pads in the input too.
+    let pads: String = std::iter::repeat_n(pad, width).collect();
+    let mut code = format!("_p=\"{}\" {}=_p+{} _t={}", pads, output, input, text);
+    let unpad = format!("_t-- _p-- {}=_p+{}", output, input);
+    match objective {
+        Objective::Characters => {
+            write!(code, " _i=0\n{} _i++ goto {}-(_i<{})", unpad, line + 2, width).unwrap();
+        },
+        Objective::Instructions | Objective::Lines => {
+            // once a line is full the rest carry on a tick at a time, the error skipping each
+            let max = YololParser::default().max_line_length;
+            for _ in 0..width {
+                let last = code.lines().last().unwrap_or_default();
+                let separator = if last.len() + 1 + unpad.len() > max { '\n' } else { ' ' };
+                write!(code, "{}{}", separator, unpad).unwrap();
+            }
+        },
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arith::Value;
+    use crate::ir::{CodegenOptions, IRMachine};
+    use crate::parser::{Ident, YololParser};
+    use crate::simple_interp::SimpleInterp;
+    use super::*;
+
+    /// Runs `setup` on line 1 then `routine(2)`, returning `:out` from both interpreters and
+    /// the ticks it took.
+    fn run(setup: &str, routine: impl Fn(usize) -> String) -> (Value, usize) {
+        let routine = routine(2);
+        assert!(routine.lines().all(|line| line.len() <= 70), "{}", routine);
+        let done = routine.lines().count() + 2;
+        let src = format!("{}\n{}\n:done=1 goto {}", setup, routine, done);
+        let program = YololParser::unrestricted().parse(&src).unwrap();
+
+        let mut simple = SimpleInterp::new(program.clone());
+        let options = CodegenOptions { protect_globals: true, ..Default::default() };
+        let mut machine = IRMachine::from_ast(options, program);
+        let mut ticks = 0;
+        while machine.get_ident_value(&Ident::global("done")) != Value::Num(1.into()) {
+            machine.step();
+            simple.step_line();
+            ticks += 1;
+            assert!(ticks < 1000, "{} never finished", src);
+        }
+        let out = machine.get_ident_value(&Ident::global("out"));
+        assert_eq!(simple.values().get(&Ident::global("out")), Some(&out), "{}", src);
+        (out, ticks)
+    }
+
+    #[test]
+    fn routines_compute_the_same_either_way() {
+        let objectives = [Objective::Characters, Objective::Instructions];
+        for n in [0, 1, 2, 15, 16, 17, 99, 999_999, 1_000_000, 123_456_789] {
+            let root = (n as f64).sqrt().floor() as i64;
+            let (out, _) = run(&format!(":n={}", n), |_| isqrt(":n", ":out"));
+            assert_eq!(out, Value::Num(root.into()), "isqrt {}", n);
+        }
+
+        let strings = ["", "a", "abc", "a b", "a_b", "abcdef"];
+        for (s, width) in strings.iter().flat_map(|s| [0, 1, 4, 9].map(|w| (s, w))) {
+            let expected = format!("{:_>width$}", s, width = width);
+            let padded = |objective| {
+                let setup = format!(":s=\"{}\"", s);
+                run(&setup, |line| left_pad(":s", ":out", width, '_', objective, line))
+            };
+            let (short, short_ticks) = padded(Objective::Characters);
+            let (fast, fast_ticks) = padded(Objective::Instructions);
+            assert_eq!(short, Value::Str(expected.as_str().into()), "{:?} to {}", s, width);
+            assert_eq!(fast, short);
+            let fast_code = left_pad(":s", ":out", width, '_', Objective::Instructions, 2);
+            assert_eq!(fast_ticks, fast_code.lines().count() + 2);
+            if s.len() >= width {
+                assert!(fast_ticks <= short_ticks);
+            }
+        }
+
+        for (n, expected) in [("5", "    5"), ("-1.5", " -1.5"), ("123456", "123456")] {
+            for objective in objectives {
+                let setup = format!(":n={}", n);
+                let (out, _) = run(&setup, |line| fixed_width(":n", ":out", 5, objective, line));
+                assert_eq!(out, Value::Str(expected.into()));
+            }
+        }
+        assert!(left_pad("s", "o", 30, ' ', Objective::Characters, 1).len()
+            < left_pad("s", "o", 30, ' ', Objective::Instructions, 1).len());
+    }
+}
@@ -0,0 +1,18 @@
+//! Spans and events for embedders using `tracing`, which compile to nothing unless the
+//! `tracing` feature is on.
+
+/// Enters a span until the end of the enclosing block, e.g. `span!(DEBUG, "tick", tick = 3)`.
+macro_rules! span {
+    ($level:ident, $name:expr $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $name $(, $($fields)*)?).entered();
+    };
+}
+
+/// Records an event, e.g. `event!(DEBUG, line = 2, "runtime error")`.
+macro_rules! event {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($args)*);
+    };
+}
@@ -0,0 +1,625 @@
+use std::fmt::Write;
+use parser::*;
+use arith::*;
+use super::*;
+
+/// The language a program gets transpiled to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A `createChip()` function, with numbers as 64 bit `BigInt`s.
+    JavaScript,
+    /// A chunk returning a `create_chip()` function, needing Lua 5.3+ for 64 bit integers.
+    Lua,
+}
+
+// Both helpers mirror `arith` op for op, including the quirks. Numbers are the raw scaled
+// integer, so `1.5` is `1500`. Strings are capped at 1024 units, which are UTF-16 code units in
+// JavaScript and bytes in Lua.
+const JS_HELPERS: &str = r#"
+  const MIN = -(2n ** 63n);
+  const MAX_F = 9223372036854775807 / 1000;
+  const MIN_F = -9223372036854775808 / 1000;
+  const MAX_LEN = 1024;
+  const f32 = Math.fround;
+  const RAD = f32(f32(Math.PI) / 180);
+  const DEG = f32(180 / f32(Math.PI));
+  class YololError extends Error {}
+  const fail = () => { throw new YololError(); };
+  const wrap = (n) => BigInt.asIntN(64, n);
+  const isNum = (x) => typeof x === "bigint";
+  const num = (x) => (isNum(x) ? x : fail());
+  const bool = (b) => (b ? 1000n : 0n);
+  const fromF64 = (n) => {
+    n *= 1000;
+    return Number.isFinite(n) && n >= MIN_F && n <= MAX_F ? BigInt(Math.trunc(n)) : MIN;
+  };
+  const fromF32 = (n) => {
+    n = f32(n * 1000);
+    return Number.isFinite(n) && n >= f32(MIN_F) && n <= f32(MAX_F) ? BigInt(Math.trunc(n)) : MIN;
+  };
+  const roundToNew = (v) => fromF64(v + (v < 0 || Object.is(v, -0) ? -5e-5 : 5e-5));
+  const asF64 = (n) => Number(n) / 1000;
+  const asF32 = (n) => f32(f32(Number(n)) / 1000);
+  const stringify = (n) => {
+    const int = n / 1000n;
+    const dec = n % 1000n;
+    if (dec === 0n) return int.toString();
+    const sign = n < 0n && int === 0n ? "-" : "";
+    const digits = (dec < 0n ? -dec : dec).toString().padStart(3, "0").replace(/0+$/, "");
+    return sign + int + "." + digits;
+  };
+  const str = (x) => (isNum(x) ? stringify(x) : x);
+  const cap = (s) => (s.length > MAX_LEN ? s.slice(0, MAX_LEN) : s);
+  const get = (v, k) => (k in v ? v[k] : 0n);
+  const add = (l, r) => (isNum(l) && isNum(r) ? wrap(l + r) : cap(str(l) + str(r)));
+  const sub = (l, r) => {
+    if (isNum(l) && isNum(r)) return wrap(l - r);
+    l = str(l);
+    r = str(r);
+    const i = l.lastIndexOf(r);
+    return r === "" || i < 0 ? l : l.slice(0, i) + l.slice(i + r.length);
+  };
+  const mul = (l, r) => wrap(num(l) * num(r)) / 1000n;
+  const div = (l, r) => {
+    l = num(l);
+    r = num(r);
+    return r === 0n ? fail() : wrap(wrap(l * 1000n) / r);
+  };
+  const mod = (l, r) => {
+    l = num(l);
+    r = num(r);
+    return r === 0n ? fail() : l % r;
+  };
+  const pow = (l, r) => roundToNew(Math.pow(asF64(num(l)), asF64(num(r))));
+  const truthy = (x) => isNum(x) && x !== 0n;
+  const and = (l, r) => bool(truthy(l) && truthy(r));
+  const or = (l, r) => bool(truthy(l) || truthy(r));
+  const eq = (l, r) => bool(isNum(l) === isNum(r) && l === r);
+  const ne = (l, r) => bool(!(isNum(l) === isNum(r) && l === r));
+  const lt = (l, r) => bool(isNum(l) && isNum(r) ? l < r : str(l) < str(r));
+  const le = (l, r) => bool(isNum(l) && isNum(r) ? l <= r : str(l) <= str(r));
+  const gt = (l, r) => lt(r, l);
+  const ge = (l, r) => le(r, l);
+  const not = (x) => bool(isNum(x) && x === 0n);
+  const neg = (x) => wrap(-num(x));
+  const abs = (x) => (num(x) < 0n ? wrap(-x) : x);
+  const sqrt = (x) =>
+    (num(x) < 0n || x >= 9223372036854775000n ? MIN : roundToNew(Math.sqrt(asF64(x))));
+  const exp = (x) => roundToNew(Math.exp(asF64(num(x))));
+  const ln = (x) => roundToNew(Math.log(asF64(num(x))));
+  const log10 = (x) => roundToNew(Math.log10(asF64(num(x))));
+  const log = (x, b) =>
+    (num(b) <= 0n || b === 1000n ? MIN : roundToNew(Math.log(asF64(num(x))) / Math.log(asF64(b))));
+  const fact = (x) => {
+    if (num(x) < 0n) return MIN;
+    let v = x / 1000n;
+    let i = 0n;
+    let result = 1n;
+    while (v > 0n) {
+      i += 1n;
+      v -= 1n;
+      result = wrap(result * i);
+    }
+    return wrap(result * 1000n);
+  };
+  const sin = (x) => fromF64(Math.sin(f32(asF32(num(x)) * RAD)));
+  const cos = (x) => fromF64(Math.cos(f32(asF32(num(x)) * RAD)));
+  const tan = (x) => fromF64(Math.tan(f32(asF32(num(x)) * RAD)));
+  const asin = (x) => fromF32(f32(f32(Math.asin(asF32(num(x)))) * DEG));
+  const acos = (x) => fromF32(f32(f32(Math.acos(asF32(num(x)))) * DEG));
+  const atan = (x) => {
+    let a = f32(f32(Math.atan(asF32(num(x)))) * DEG);
+    return fromF32(a === -90 ? 90 : a);
+  };
+  const inc = (v, k) => {
+    const x = get(v, k);
+    return (v[k] = isNum(x) ? wrap(x + 1000n) : cap(x + " "));
+  };
+  const dec = (v, k) => {
+    const x = get(v, k);
+    return (v[k] = isNum(x) ? wrap(x - 1000n) : x === "" ? fail() : x.slice(0, -1));
+  };
+  const gotoLine = (x) => Math.min(Math.max(Math.trunc(asF32(num(x))), 1), lines.length) - 1;
+"#;
+
+const LUA_HELPERS: &str = r#"
+  local MIN = math.mininteger
+  local MAX_F = 9223372036854775807 / 1000
+  local MIN_F = math.mininteger / 1000
+  local MAX_LEN = 1024
+  local function f32(x) return (string.unpack("f", string.pack("f", x))) end
+  local RAD = f32(f32(math.pi) / 180)
+  local DEG = f32(180 / f32(math.pi))
+  local YololError = {}
+  local function fail() error(YololError) end
+  local function is_num(x) return type(x) == "number" end
+  local function num(x) if is_num(x) then return x end fail() end
+  local function bool(b) if b then return 1000 end return 0 end
+  local function trunc(x) if x < 0 then return math.ceil(x) end return math.floor(x) end
+  local function tdiv(a, b)
+    local q = a // b
+    if a % b ~= 0 and (a < 0) ~= (b < 0) then q = q + 1 end
+    return q
+  end
+  local function from_f64(n)
+    n = n * 1000
+    if n == n and n >= MIN_F and n <= MAX_F then return trunc(n) end
+    return MIN
+  end
+  local function from_f32(n)
+    n = f32(n * 1000)
+    if n == n and n >= f32(MIN_F) and n <= f32(MAX_F) then return trunc(n) end
+    return MIN
+  end
+  local function round_to_new(v)
+    if v < 0 or 1 / v < 0 then return from_f64(v - 5e-5) end
+    return from_f64(v + 5e-5)
+  end
+  local function as_f64(n) return n / 1000 end
+  local function as_f32(n) return f32(f32(n + 0.0) / 1000) end
+  local function stringify(n)
+    local int, dec = tdiv(n, 1000), math.fmod(n, 1000)
+    if dec == 0 then return tostring(int) end
+    local sign = (n < 0 and int == 0) and "-" or ""
+    local digits = string.format("%03d", math.abs(dec)):gsub("0+$", "")
+    return sign .. tostring(int) .. "." .. digits
+  end
+  local function str(x) if is_num(x) then return stringify(x) end return x end
+  local function cap(s) if #s > MAX_LEN then return s:sub(1, MAX_LEN) end return s end
+  local function get(v, k) return v[k] or 0 end
+  local function add(l, r)
+    if is_num(l) and is_num(r) then return l + r end
+    return cap(str(l) .. str(r))
+  end
+  local function sub(l, r)
+    if is_num(l) and is_num(r) then return l - r end
+    l, r = str(l), str(r)
+    if r == "" then return l end
+    local last, i = nil, l:find(r, 1, true)
+    while i do
+      last = i
+      i = l:find(r, i + 1, true)
+    end
+    if not last then return l end
+    return l:sub(1, last - 1) .. l:sub(last + #r)
+  end
+  local function mul(l, r) return tdiv(num(l) * num(r), 1000) end
+  local function div(l, r)
+    l, r = num(l), num(r)
+    if r == 0 then fail() end
+    return tdiv(l * 1000, r)
+  end
+  local function mod(l, r)
+    l, r = num(l), num(r)
+    if r == 0 then fail() end
+    return math.fmod(l, r)
+  end
+  local function pow(l, r) return round_to_new(as_f64(num(l)) ^ as_f64(num(r))) end
+  local function truthy(x) return is_num(x) and x ~= 0 end
+  local function yand(l, r) return bool(truthy(l) and truthy(r)) end
+  local function yor(l, r) return bool(truthy(l) or truthy(r)) end
+  local function same(l, r) return is_num(l) == is_num(r) and l == r end
+  local function eq(l, r) return bool(same(l, r)) end
+  local function ne(l, r) return bool(not same(l, r)) end
+  local function lt(l, r)
+    if is_num(l) and is_num(r) then return bool(l < r) end
+    return bool(str(l) < str(r))
+  end
+  local function le(l, r)
+    if is_num(l) and is_num(r) then return bool(l <= r) end
+    return bool(str(l) <= str(r))
+  end
+  local function gt(l, r) return lt(r, l) end
+  local function ge(l, r) return le(r, l) end
+  local function ynot(x) return bool(is_num(x) and x == 0) end
+  local function neg(x) return -num(x) end
+  local function abs(x) if num(x) < 0 then return -x end return x end
+  local function sqrt(x)
+    if num(x) < 0 or x >= 9223372036854775000 then return MIN end
+    return round_to_new(math.sqrt(as_f64(x)))
+  end
+  local function exp(x) return round_to_new(math.exp(as_f64(num(x)))) end
+  local function ln(x) return round_to_new(math.log(as_f64(num(x)))) end
+  local function log10(x) return round_to_new(math.log(as_f64(num(x)), 10)) end
+  local function log(x, b)
+    if num(b) <= 0 or b == 1000 then return MIN end
+    return round_to_new(math.log(as_f64(num(x))) / math.log(as_f64(b)))
+  end
+  local function fact(x)
+    if num(x) < 0 then return MIN end
+    local v, i, result = tdiv(x, 1000), 0, 1
+    while v > 0 do
+      i, v = i + 1, v - 1
+      result = result * i
+    end
+    return result * 1000
+  end
+  local function sin(x) return from_f64(math.sin(f32(as_f32(num(x)) * RAD))) end
+  local function cos(x) return from_f64(math.cos(f32(as_f32(num(x)) * RAD))) end
+  local function tan(x) return from_f64(math.tan(f32(as_f32(num(x)) * RAD))) end
+  local function asin(x) return from_f32(f32(f32(math.asin(as_f32(num(x)))) * DEG)) end
+  local function acos(x) return from_f32(f32(f32(math.acos(as_f32(num(x)))) * DEG)) end
+  local function atan(x)
+    local a = f32(f32(math.atan(as_f32(num(x)))) * DEG)
+    if a == -90 then a = 90 end
+    return from_f32(a)
+  end
+  local function inc(v, k)
+    local x = get(v, k)
+    if is_num(x) then v[k] = x + 1000 else v[k] = cap(x .. " ") end
+    return v[k]
+  end
+  local function dec(v, k)
+    local x = get(v, k)
+    if is_num(x) then
+      v[k] = x - 1000
+    elseif x == "" then
+      fail()
+    else
+      v[k] = x:sub(1, -2)
+    end
+    return v[k]
+  end
+  local lines
+  local function goto_line(x)
+    return math.min(math.max(trunc(as_f32(num(x))), 1), #lines)
+  end
+"#;
+
+struct Transpiler {
+    target: Target,
+    out: String,
+    indent: usize,
+}
+
+impl Transpiler {
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    fn quote(&self, s: &str) -> String {
+        let mut quoted = String::with_capacity(s.len() + 2);
+        quoted.push('"');
+        for c in s.chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '"' => quoted.push_str("\\\""),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => match self.target {
+                    Target::JavaScript => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+                    Target::Lua => write!(quoted, "\\{}", c as u32).unwrap(),
+                },
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn var(&self, ident: &Ident) -> String {
+        self.quote(&ident.to_string())
+    }
+
+    fn number(&self, n: Number) -> String {
+        match self.target {
+            Target::JavaScript => format!("{}n", n.0),
+            Target::Lua if n == Number::MIN => "math.mininteger".to_string(),
+            Target::Lua => n.0.to_string(),
+        }
+    }
+
+    // Yolol evaluates the right operand before the left one.
+    fn call_rtl(&self, f: &str, l: String, r: String) -> String {
+        match self.target {
+            Target::JavaScript => format!("((r) => {}({}, r))({})", f, l, r),
+            Target::Lua => format!("(function(r) return {}({}, r) end)({})", f, l, r),
+        }
+    }
+
+    fn binop_fn(&self, op: Binop) -> &'static str {
+        match (op, self.target) {
+            (Binop::And, Target::JavaScript) => "and",
+            (Binop::And, Target::Lua) => "yand",
+            (Binop::Or, Target::JavaScript) => "or",
+            (Binop::Or, Target::Lua) => "yor",
+            (Binop::Add, _) => "add",
+            (Binop::Sub, _) => "sub",
+            (Binop::Mul, _) => "mul",
+            (Binop::Div, _) => "div",
+            (Binop::Mod, _) => "mod",
+            (Binop::Pow, _) => "pow",
+            (Binop::Eq, _) => "eq",
+            (Binop::Ne, _) => "ne",
+            (Binop::Le, _) => "le",
+            (Binop::Lt, _) => "lt",
+            (Binop::Ge, _) => "ge",
+            (Binop::Gt, _) => "gt",
+            (Binop::Log, _) => "log",
+        }
+    }
+
+    fn unop_fn(&self, op: Unop) -> &'static str {
+        match (op, self.target) {
+            (Unop::Not, Target::JavaScript) => "not",
+            (Unop::Not, Target::Lua) => "ynot",
+            (Unop::Neg, _) => "neg",
+            (Unop::Abs, _) => "abs",
+            (Unop::Sqrt, _) => "sqrt",
+            (Unop::Fact, _) => "fact",
+            (Unop::Sin, _) => "sin",
+            (Unop::Cos, _) => "cos",
+            (Unop::Tan, _) => "tan",
+            (Unop::Asin, _) => "asin",
+            (Unop::Acos, _) => "acos",
+            (Unop::Atan, _) => "atan",
+            (Unop::Exp, _) => "exp",
+            (Unop::Ln, _) => "ln",
+            (Unop::Log10, _) => "log10",
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binop(l, op, r) => self.call_rtl(self.binop_fn(*op), self.expr(l), self.expr(r)),
+            Expr::Unop(op, e) => format!("{}({})", self.unop_fn(*op), self.expr(e)),
+            Expr::Incdec(incdec) => format!(
+                "{}(v, {})",
+                if incdec.inc { "inc" } else { "dec" },
+                self.var(&incdec.ident),
+            ),
+            Expr::Ident(ident) => format!("get(v, {})", self.var(ident)),
+            Expr::Number(n) => self.number(*n),
+            Expr::String(s) => self.quote(&s.to_string()),
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Statement) {
+        match (stmt, self.target) {
+            (Statement::Goto(e), Target::JavaScript) => {
+                let line = format!("return gotoLine({});", self.expr(e));
+                self.line(&line);
+            },
+            (Statement::Goto(e), Target::Lua) => {
+                let line = format!("do return goto_line({}) end", self.expr(e));
+                self.line(&line);
+            },
+            (Statement::Ite(c, t, e), target) => {
+                let c = self.expr(c);
+                self.line(&match target {
+                    Target::JavaScript => format!("if (truthy({})) {{", c),
+                    Target::Lua => format!("if truthy({}) then", c),
+                });
+                self.indent += 1;
+                self.stmts(t);
+                self.indent -= 1;
+                if !e.is_empty() {
+                    self.line(match target {
+                        Target::JavaScript => "} else {",
+                        Target::Lua => "else",
+                    });
+                    self.indent += 1;
+                    self.stmts(e);
+                    self.indent -= 1;
+                }
+                self.line(match target {
+                    Target::JavaScript => "}",
+                    Target::Lua => "end",
+                });
+            },
+            (Statement::Incdec(incdec), target) => {
+                let line = self.expr(&Expr::Incdec(incdec.clone())) + match target {
+                    Target::JavaScript => ";",
+                    Target::Lua => "",
+                };
+                self.line(&line);
+            },
+            (Statement::Assign(ident, None, e), target) => {
+                let line = format!(
+                    "v[{}] = {}{}",
+                    self.var(ident),
+                    self.expr(e),
+                    if target == Target::JavaScript { ";" } else { "" },
+                );
+                self.line(&line);
+            },
+            (Statement::Assign(ident, Some(op), e), target) => {
+                let var = self.var(ident);
+                let f = self.binop_fn((*op).into());
+                let line = match target {
+                    Target::JavaScript => format!(
+                        "{{ const e = {}; v[{1:}] = {2:}(get(v, {1:}), e); }}",
+                        self.expr(e),
+                        var,
+                        f,
+                    ),
+                    Target::Lua => format!(
+                        "do local e = {}; v[{1:}] = {2:}(get(v, {1:}), e) end",
+                        self.expr(e),
+                        var,
+                        f,
+                    ),
+                };
+                self.line(&line);
+            },
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        match self.target {
+            Target::JavaScript => {
+                self.line("function createChip() {");
+                self.out.push_str(JS_HELPERS);
+                self.indent += 1;
+                self.line("const lines = [");
+                self.indent += 1;
+                for line in program.iter() {
+                    self.line("(v) => {");
+                    self.indent += 1;
+                    self.stmts(line);
+                    self.indent -= 1;
+                    self.line("},");
+                }
+                self.indent -= 1;
+                self.line("];");
+                self.line("const chip = { line: 0, vars: Object.create(null) };");
+                self.line("chip.step = () => {");
+                self.line("  let next = (chip.line + 1) % lines.length;");
+                self.line("  try {");
+                self.line("    const target = lines[chip.line](chip.vars);");
+                self.line("    if (target !== undefined) next = target;");
+                self.line("  } catch (e) {");
+                self.line("    if (!(e instanceof YololError)) throw e;");
+                self.line("  }");
+                self.line("  chip.line = next;");
+                self.line("};");
+                self.line("return chip;");
+                self.indent -= 1;
+                self.line("}");
+            },
+            Target::Lua => {
+                self.line("local function create_chip()");
+                self.out.push_str(LUA_HELPERS);
+                self.indent += 1;
+                self.line("lines = {");
+                self.indent += 1;
+                for line in program.iter() {
+                    self.line("function(v)");
+                    self.indent += 1;
+                    self.stmts(line);
+                    self.indent -= 1;
+                    self.line("end,");
+                }
+                self.indent -= 1;
+                self.line("}");
+                self.line("local chip = { line = 1, vars = {} }");
+                self.line("function chip.step()");
+                self.line("  local next = chip.line % #lines + 1");
+                self.line("  local ok, target = pcall(lines[chip.line], chip.vars)");
+                self.line("  if not ok then");
+                self.line("    if target ~= YololError then error(target, 0) end");
+                self.line("  elseif target ~= nil then");
+                self.line("    next = target");
+                self.line("  end");
+                self.line("  chip.line = next");
+                self.line("end");
+                self.line("return chip");
+                self.indent -= 1;
+                self.line("end");
+                self.line("return create_chip");
+            },
+        }
+    }
+}
+
+/// Converts a program into source for `target` implementing the same semantics, including
+/// Yolol's fixed point numbers. The chip's variables are keyed by name, with a `:` in front
+/// of globals, and `step()` runs a single line.
+pub fn transpile(program: &Program, target: Target) -> String {
+    let mut transpiler = Transpiler {
+        target,
+        out: String::with_capacity(8192),
+        indent: 0,
+    };
+    transpiler.program(program);
+    transpiler.out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use simple_interp::SimpleInterp;
+    use super::*;
+
+    #[test]
+    fn transpile_shape() -> anyhow::Result<()> {
+        let src = "a=1.5 :b=\"x\"+a\nif a then goto 1 end a-=2\n";
+        let program = YololParser::default().parse(src)?;
+
+        let js = transpile(&program, Target::JavaScript);
+        assert!(js.starts_with("function createChip() {"));
+        assert!(js.contains("v[\"a\"] = 1500n;"));
+        assert!(js.contains("v[\":b\"] = ((r) => add(\"x\", r))(get(v, \"a\"));"));
+        assert!(js.contains("return gotoLine(1000n);"));
+        assert!(js.contains("{ const e = 2000n; v[\"a\"] = sub(get(v, \"a\"), e); }"));
+        assert_eq!(js.matches("(v) => {").count(), 20);
+
+        let lua = transpile(&program, Target::Lua);
+        assert!(lua.ends_with("return create_chip\n"));
+        assert!(lua.contains("if truthy(get(v, \"a\")) then"));
+        assert!(lua.contains("do return goto_line(1000) end"));
+        assert_eq!(lua.matches("function(v)").count(), 20);
+        Ok(())
+    }
+
+    const RUN_SRC: &str = "a=1.5 :b=\"x\"+a c=a*3/2 d=sqrt 2 s=\"hello\" s-=\"l\" s++\n\
+                           e=a/0 f=9\ni++ if i<5 then goto 1 end :done=i%4 s+=i";
+
+    /// Run `script` with `interpreter`, which should print each variable as `name\t[n|s]value`,
+    /// and check the result against the simple interpreter after 40 lines.
+    fn runs_like_simple_interp(
+        program: Program,
+        interpreter: &str,
+        script: &str,
+    ) -> anyhow::Result<()> {
+        let output = Command::new(interpreter)
+            .arg("-e")
+            .arg(script)
+            .output()
+            .map_err(|e| anyhow::anyhow!("couldn't run `{}`: {}", interpreter, e))?;
+        anyhow::ensure!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let mut ran: Vec<_> = String::from_utf8(output.stdout)?.lines().map(String::from).collect();
+        ran.sort();
+
+        let mut simple = SimpleInterp::new(program);
+        simple.step_lines(40);
+        let mut expected: Vec<_> = simple
+            .values()
+            .iter()
+            .map(|(ident, value)| match value {
+                Value::Num(n) => format!("{}\tn{}", ident, n.0),
+                Value::Str(s) => format!("{}\ts{}", ident, s),
+            })
+            .collect();
+        expected.sort();
+        assert_eq!(ran, expected);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "needs `node` on the path"]
+    fn javascript_runs_like_simple_interp() -> anyhow::Result<()> {
+        let program = YololParser::default().parse(RUN_SRC)?;
+        let script = format!(
+            "{}const chip = createChip();\nfor (let i = 0; i < 40; i++) chip.step();\n\
+             for (const [k, x] of Object.entries(chip.vars))\n  \
+             console.log(k + \"\\t\" + (typeof x === \"bigint\" ? \"n\" : \"s\") + x);\n",
+            transpile(&program, Target::JavaScript),
+        );
+        runs_like_simple_interp(program, "node", &script)
+    }
+
+    #[test]
+    #[ignore = "needs Lua 5.3 or later on the path as `lua`"]
+    fn lua_runs_like_simple_interp() -> anyhow::Result<()> {
+        let program = YololParser::default().parse(RUN_SRC)?;
+        let script = format!(
+            "local create_chip = (function()\n{}end)()\nlocal chip = create_chip()\n\
+             for i = 1, 40 do chip.step() end\nfor k, x in pairs(chip.vars) do\n  \
+             print(k .. \"\\t\" .. (math.type(x) == \"integer\" and \"n\" or \"s\") .. x)\nend\n",
+            transpile(&program, Target::Lua),
+        );
+        runs_like_simple_interp(program, "lua", &script)
+    }
+}
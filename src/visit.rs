@@ -0,0 +1,195 @@
+//! Walking the AST, for analyses and rewrites which only care about some kinds of node.
+//!
+//! Implement [`Visit`] or [`VisitMut`], overriding the methods for the nodes of interest. The
+//! defaults walk into every child, through the `walk_*` functions, which an override can call
+//! to carry on into the children itself, before or after handling the node. Lines are walked
+//! through their statements, then their assertion and field annotation, if they have them.
+
+use arith::{Number, YString};
+use parser::*;
+use super::*;
+
+pub trait Visit {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_line(&mut self, line: &Line) {
+        walk_line(self, line);
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement) {
+        walk_statement(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    /// Every variable, whether it's read or written.
+    fn visit_ident(&mut self, _ident: &Ident) {}
+
+    fn visit_number(&mut self, _number: &Number) {}
+
+    fn visit_string(&mut self, _string: &YString) {}
+}
+
+pub fn walk_program<V: Visit + ?Sized>(v: &mut V, program: &Program) {
+    for line in program.iter() {
+        v.visit_line(line);
+    }
+}
+
+pub fn walk_line<V: Visit + ?Sized>(v: &mut V, line: &Line) {
+    for stmt in line.iter() {
+        v.visit_statement(stmt);
+    }
+    if let Some(assert) = &line.assert {
+        v.visit_expr(assert);
+    }
+    if let Some(annotation) = &line.annotation {
+        v.visit_ident(&annotation.field);
+    }
+}
+
+pub fn walk_statement<V: Visit + ?Sized>(v: &mut V, stmt: &Statement) {
+    match stmt {
+        Statement::Goto(e) => v.visit_expr(e),
+        Statement::Ite(c, t, e) => {
+            v.visit_expr(c);
+            t.iter().chain(e.iter()).for_each(|s| v.visit_statement(s));
+        },
+        Statement::Incdec(Incdec { ident, .. }) => v.visit_ident(ident),
+        Statement::Assign(ident, _, e) => {
+            v.visit_ident(ident);
+            v.visit_expr(e);
+        },
+    }
+}
+
+pub fn walk_expr<V: Visit + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binop(l, _, r) => {
+            v.visit_expr(l);
+            v.visit_expr(r);
+        },
+        Expr::Unop(_, e) => v.visit_expr(e),
+        Expr::Incdec(Incdec { ident, .. }) | Expr::Ident(ident) => v.visit_ident(ident),
+        Expr::Number(n) => v.visit_number(n),
+        Expr::String(s) => v.visit_string(s),
+    }
+}
+
+/// Like [`Visit`], but able to change nodes, or replace them outright.
+pub trait VisitMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_line_mut(&mut self, line: &mut Line) {
+        walk_line_mut(self, line);
+    }
+
+    fn visit_statement_mut(&mut self, stmt: &mut Statement) {
+        walk_statement_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+
+    fn visit_number_mut(&mut self, _number: &mut Number) {}
+
+    fn visit_string_mut(&mut self, _string: &mut YString) {}
+}
+
+pub fn walk_program_mut<V: VisitMut + ?Sized>(v: &mut V, program: &mut Program) {
+    for line in program.iter_mut() {
+        v.visit_line_mut(line);
+    }
+}
+
+pub fn walk_line_mut<V: VisitMut + ?Sized>(v: &mut V, line: &mut Line) {
+    for stmt in line.iter_mut() {
+        v.visit_statement_mut(stmt);
+    }
+    if let Some(assert) = &mut line.assert {
+        v.visit_expr_mut(assert);
+    }
+    if let Some(annotation) = &mut line.annotation {
+        v.visit_ident_mut(&mut annotation.field);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitMut + ?Sized>(v: &mut V, stmt: &mut Statement) {
+    match stmt {
+        Statement::Goto(e) => v.visit_expr_mut(e),
+        Statement::Ite(c, t, e) => {
+            v.visit_expr_mut(c);
+            t.iter_mut().chain(e.iter_mut()).for_each(|s| v.visit_statement_mut(s));
+        },
+        Statement::Incdec(Incdec { ident, .. }) => v.visit_ident_mut(ident),
+        Statement::Assign(ident, _, e) => {
+            v.visit_ident_mut(ident);
+            v.visit_expr_mut(e);
+        },
+    }
+}
+
+pub fn walk_expr_mut<V: VisitMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Binop(l, _, r) => {
+            v.visit_expr_mut(l);
+            v.visit_expr_mut(r);
+        },
+        Expr::Unop(_, e) => v.visit_expr_mut(e),
+        Expr::Incdec(Incdec { ident, .. }) | Expr::Ident(ident) => v.visit_ident_mut(ident),
+        Expr::Number(n) => v.visit_number_mut(n),
+        Expr::String(s) => v.visit_string_mut(s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visits_and_rewrites() {
+        let src = "a=:b*1 c=\"s\"\nif a then :d=a*(c*1) end // assert: a*1 < 5\n// :d (m) out";
+        let mut program = YololParser::default().parse(src).unwrap();
+
+        #[derive(Default)]
+        struct Names(Vec<String>);
+
+        impl Visit for Names {
+            fn visit_ident(&mut self, ident: &Ident) {
+                self.0.push(ident.to_string());
+            }
+        }
+
+        let mut names = Names::default();
+        names.visit_program(&program);
+        assert_eq!(names.0, ["a", ":b", "c", "a", ":d", "a", "c", "a", ":d"]);
+
+        /// Turns `x * 1` into `x`, innermost first.
+        struct TimesOne;
+
+        impl VisitMut for TimesOne {
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                walk_expr_mut(self, expr);
+                if let Expr::Binop(l, Binop::Mul, r) = expr {
+                    if **r == Expr::from(1) {
+                        *expr = std::mem::replace(&mut **l, Expr::from(0));
+                    }
+                }
+            }
+        }
+
+        TimesOne.visit_program_mut(&mut program);
+        assert_eq!(program[0].to_string(), "a = :b c = \"s\"");
+        assert_eq!(program[1].to_string(), "if a then :d = a * c end");
+        assert_eq!(program[1].assert.as_ref().unwrap().to_string(), "a < 5");
+    }
+}
@@ -0,0 +1,107 @@
+//! Bindings for running chips from JavaScript. wasm-pack needs a `cdylib`, so build a crate
+//! which depends on this one with the `wasm` feature and does `pub use yogi::wasm::*;`.
+
+use wasm_bindgen::prelude::*;
+use arith::{Number, Value};
+use ir::IRMachine;
+use parser::{Ident, YololParser};
+use super::*;
+
+/// A compiled chip, with every global protected so it can be read and written.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct Chip {
+    machine: IRMachine,
+}
+
+/// `name` with or without its leading `:`.
+fn global(name: &str) -> Ident {
+    Ident::global(name.strip_prefix(':').unwrap_or(name))
+}
+
+#[wasm_bindgen]
+impl Chip {
+    /// Throws the parse error as a string if `source` isn't valid Yolol.
+    #[wasm_bindgen(constructor)]
+    pub fn compile(source: &str) -> Result<Chip, String> {
+        let program = YololParser::default().parse(source).map_err(|e| format!("{:#}", e))?;
+        Ok(Chip { machine: IRMachine::from_ast(Default::default(), program) })
+    }
+
+    /// Runs one line.
+    pub fn step(&mut self) {
+        self.machine.step();
+    }
+
+    /// Runs `lines` lines.
+    pub fn run(&mut self, lines: usize) {
+        self.machine.step_repeat(lines);
+    }
+
+    /// The 1-based line which runs next.
+    #[wasm_bindgen(js_name = currentLine)]
+    pub fn current_line(&self) -> usize {
+        self.machine.get_current_line().map_or(1, |line| line + 1)
+    }
+
+    /// The global's number, or `undefined` if it holds a string.
+    #[wasm_bindgen(js_name = getNumber)]
+    pub fn get_number(&self, name: &str) -> Option<f64> {
+        match self.machine.get_ident_value(&global(name)) {
+            Value::Num(n) => Some(n.as_f64()),
+            Value::Str(_) => None,
+        }
+    }
+
+    /// The global's string, or `undefined` if it holds a number.
+    #[wasm_bindgen(js_name = getString)]
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        match self.machine.get_ident_value(&global(name)) {
+            Value::Str(s) => Some(s.to_string()),
+            Value::Num(_) => None,
+        }
+    }
+
+    /// Numbers are cut to thousandths, as Yolol keeps them. Globals the program doesn't use
+    /// are ignored.
+    #[wasm_bindgen(js_name = setNumber)]
+    pub fn set_number(&mut self, name: &str, value: f64) {
+        // the shortest decimal form, so 1.005 isn't 1.00499999...
+        let n = value.to_string().parse().unwrap_or_else(|_| Number::new(value));
+        self.set(name, Value::Num(n));
+    }
+
+    #[wasm_bindgen(js_name = setString)]
+    pub fn set_string(&mut self, name: &str, value: &str) {
+        self.set(name, Value::Str(value.into()));
+    }
+
+    fn set(&mut self, name: &str, value: Value) {
+        let ident = global(name);
+        if self.machine.ident_register(&ident).is_some() {
+            self.machine.set_ident(&ident, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip_bindings() {
+        assert!(Chip::compile("a = = 1").is_err());
+        let mut chip = Chip::compile(":msg=\"n=\"+:in :out=:in*2\ngoto 1").unwrap();
+        chip.set_number(":in", 1.005);
+        chip.set_number("unused", 1.0);
+        chip.step();
+        assert_eq!(chip.current_line(), 2);
+        assert_eq!(chip.get_number("out"), Some(2.01));
+        assert_eq!(chip.get_string(":msg").as_deref(), Some("n=1.005"));
+        assert_eq!(chip.get_number("msg"), None);
+
+        chip.set_string("in", "x");
+        chip.run(2);
+        assert_eq!(chip.get_string("msg").as_deref(), Some("n=x"));
+    }
+}